@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS data_v2_topic_timestamp_idx ON data_v2 (kafka_topic, timestamp);
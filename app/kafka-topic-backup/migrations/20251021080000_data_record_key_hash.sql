@@ -0,0 +1,5 @@
+-- Hash of the record key, so all records of a key can be found without storing the key
+-- in an index. Existing rows are filled in by the backfill-key-hashes command, since the
+-- hash is keyed with a secret the database does not have.
+ALTER TABLE data_v2 ADD COLUMN IF NOT EXISTS record_key_hash BYTEA;
+CREATE INDEX IF NOT EXISTS data_v2_topic_record_key_hash_idx ON data_v2 (kafka_topic, record_key_hash);
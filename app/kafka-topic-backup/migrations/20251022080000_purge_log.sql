@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS purge_log (
+    id BIGSERIAL PRIMARY KEY,
+    purged_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    kafka_topic VARCHAR(255) NOT NULL,
+    kafka_partition SMALLINT NOT NULL,
+    cutoff TIMESTAMPTZ NOT NULL,
+    row_count BIGINT NOT NULL,
+    min_offset BIGINT NOT NULL,
+    max_offset BIGINT NOT NULL,
+    min_timestamp TIMESTAMPTZ(3) NOT NULL,
+    max_timestamp TIMESTAMPTZ(3) NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS purge_log_topic_partition_idx ON purge_log (kafka_topic, kafka_partition);
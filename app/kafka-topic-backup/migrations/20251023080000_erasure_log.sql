@@ -0,0 +1,26 @@
+-- Every erasure of a key, hash-chained so that edited or deleted entries are detected.
+-- Personal data is never stored here, only the key hash and hashes of filter values.
+CREATE TABLE IF NOT EXISTS erasure_log (
+    id BIGSERIAL PRIMARY KEY,
+    erased_at TIMESTAMPTZ(3) NOT NULL,
+    kafka_topic VARCHAR(255) NOT NULL,
+    record_key_hash BYTEA NOT NULL,
+    filter JSONB,
+    requested_by VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    row_count BIGINT NOT NULL,
+    prev_hash BYTEA NOT NULL,
+    entry_hash BYTEA NOT NULL UNIQUE
+);
+
+-- The erased records, which are never stored again when the topic is consumed from
+-- before the erasure
+CREATE TABLE IF NOT EXISTS erased_offsets (
+    kafka_topic VARCHAR(255) NOT NULL,
+    kafka_partition SMALLINT NOT NULL,
+    kafka_offset BIGINT NOT NULL,
+    erasure_id BIGINT NOT NULL REFERENCES erasure_log (id),
+    PRIMARY KEY (kafka_topic, kafka_partition, kafka_offset)
+);
+
+CREATE INDEX IF NOT EXISTS erased_offsets_erasure_id_idx ON erased_offsets (erasure_id);
@@ -0,0 +1,10 @@
+-- Data encryption keys, each wrapped with the master key identified by master_key_id
+CREATE TABLE IF NOT EXISTS encryption_keys (
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    master_key_id VARCHAR(64) NOT NULL,
+    wrapped_key BYTEA NOT NULL
+);
+
+-- The key the record key and value are encrypted with, NULL for plaintext rows
+ALTER TABLE data_v2 ADD COLUMN IF NOT EXISTS key_id INTEGER REFERENCES encryption_keys (id);
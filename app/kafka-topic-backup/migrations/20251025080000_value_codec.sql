@@ -0,0 +1,2 @@
+-- Codec the record value is compressed with, NULL for uncompressed values
+ALTER TABLE data_v2 ADD COLUMN IF NOT EXISTS value_codec VARCHAR(16);
@@ -0,0 +1,29 @@
+-- Records partitioned by month on the record timestamp, so old months can be dropped
+-- instead of deleted row by row. Unique constraints on a partitioned table must include
+-- the partition key, but a record always has the same timestamp, so each offset is
+-- still stored only once.
+CREATE TABLE IF NOT EXISTS data_v3 (
+    id BIGSERIAL NOT NULL,
+    kafka_topic VARCHAR(255) NOT NULL,
+    kafka_partition SMALLINT NOT NULL,
+    kafka_offset BIGINT NOT NULL,
+    timestamp TIMESTAMP(3) WITH TIME ZONE NOT NULL,
+    headers JSONB,
+    record_key BYTEA,
+    record_value BYTEA,
+    record_key_hash BYTEA,
+    key_id INTEGER REFERENCES encryption_keys (id),
+    value_codec VARCHAR(16),
+    PRIMARY KEY (id, timestamp),
+    UNIQUE (kafka_topic, kafka_partition, kafka_offset, timestamp)
+) PARTITION BY RANGE (timestamp);
+
+-- Catches records for months without a partition, the partition maintenance moves them
+-- to a monthly partition
+CREATE TABLE IF NOT EXISTS data_v3_default PARTITION OF data_v3 DEFAULT;
+
+CREATE INDEX IF NOT EXISTS data_v3_topic_timestamp_idx ON data_v3 (kafka_topic, timestamp);
+CREATE INDEX IF NOT EXISTS data_v3_topic_record_key_hash_idx ON data_v3 (kafka_topic, record_key_hash);
+
+-- Records copied from data_v2 keep their id, new records get ids above them
+SELECT setval(pg_get_serial_sequence('data_v3', 'id'), COALESCE((SELECT MAX(id) FROM data_v2), 0) + 1, false);
@@ -0,0 +1,17 @@
+-- Catalog of the segments archived to object storage, one per topic and day
+CREATE TABLE IF NOT EXISTS archive_segments (
+    id BIGSERIAL PRIMARY KEY,
+    kafka_topic VARCHAR(255) NOT NULL,
+    window_start TIMESTAMPTZ NOT NULL,
+    window_end TIMESTAMPTZ NOT NULL,
+    object_key TEXT NOT NULL UNIQUE,
+    row_count BIGINT NOT NULL,
+    bytes BIGINT NOT NULL,
+    sha256 BYTEA NOT NULL,
+    -- Set when the uploaded object was read back and matched the checksum
+    verified_at TIMESTAMPTZ NOT NULL,
+    -- Set when the archived records were deleted from the data table
+    rows_deleted_at TIMESTAMPTZ,
+    deleted_rows BIGINT,
+    UNIQUE (kafka_topic, window_start)
+);
@@ -0,0 +1,15 @@
+-- Latest record of every key for topics with the current_state policy, stored like the
+-- records in the data table. Keys whose latest record is a tombstone have no row.
+CREATE TABLE IF NOT EXISTS current_state (
+    kafka_topic VARCHAR(255) NOT NULL,
+    record_key_hash BYTEA NOT NULL,
+    kafka_partition SMALLINT NOT NULL,
+    kafka_offset BIGINT NOT NULL,
+    timestamp TIMESTAMPTZ(3) NOT NULL,
+    headers JSONB,
+    record_key BYTEA,
+    record_value BYTEA,
+    key_id INTEGER REFERENCES encryption_keys (id),
+    value_codec VARCHAR(16),
+    PRIMARY KEY (kafka_topic, record_key_hash)
+);
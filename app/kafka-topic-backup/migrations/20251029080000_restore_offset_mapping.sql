@@ -0,0 +1,13 @@
+-- Where each restored record ended up, so positions committed against the source topic
+-- can be translated to the target topic. A later restore to the same target replaces
+-- the mapping.
+CREATE TABLE IF NOT EXISTS restore_offset_mapping (
+    source_topic VARCHAR(255) NOT NULL,
+    source_partition SMALLINT NOT NULL,
+    source_offset BIGINT NOT NULL,
+    target_topic VARCHAR(255) NOT NULL,
+    target_partition SMALLINT NOT NULL,
+    target_offset BIGINT NOT NULL,
+    restored_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (target_topic, source_topic, source_partition, source_offset)
+);
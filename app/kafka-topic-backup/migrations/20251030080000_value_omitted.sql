@@ -0,0 +1,5 @@
+-- Records of keys_only topics are stored without their value. The marker tells them
+-- apart from tombstones, which also have no value.
+ALTER TABLE data_v2 ADD COLUMN IF NOT EXISTS value_omitted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE data_v3 ADD COLUMN IF NOT EXISTS value_omitted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE current_state ADD COLUMN IF NOT EXISTS value_omitted BOOLEAN NOT NULL DEFAULT FALSE;
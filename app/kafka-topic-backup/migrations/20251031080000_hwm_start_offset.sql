@@ -0,0 +1,3 @@
+-- First offset the backup of the partition started from, offsets below it were never
+-- read. NULL for partitions that started before it was recorded.
+ALTER TABLE hwm ADD COLUMN IF NOT EXISTS start_offset BIGINT;
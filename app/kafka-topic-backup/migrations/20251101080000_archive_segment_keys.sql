@@ -0,0 +1,12 @@
+-- Key hashes of the records in each archived segment, so an erasure finds the segments
+-- holding a key without reading the whole archive
+CREATE TABLE IF NOT EXISTS archive_segment_keys (
+    record_key_hash BYTEA NOT NULL,
+    segment_id BIGINT NOT NULL REFERENCES archive_segments (id) ON DELETE CASCADE,
+    PRIMARY KEY (record_key_hash, segment_id)
+);
+
+CREATE INDEX IF NOT EXISTS archive_segment_keys_segment_id_idx ON archive_segment_keys (segment_id);
+
+-- Object keys of the archived segments an erasure removed records from
+ALTER TABLE erasure_log ADD COLUMN IF NOT EXISTS archive_segments TEXT[] NOT NULL DEFAULT '{}';
@@ -1,10 +1,21 @@
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 
 #[derive(Debug)]
 pub struct AppState {
     pub is_alive: AtomicBool,
     pub is_ready: AtomicBool,
     pub has_started: AtomicBool,
+    /// Assigned partitions sent by the rebalance callback that the positioner has not
+    /// received yet
+    pub queued_partitions: AtomicUsize,
+    /// Partitions the positioner has received but not yet positioned at their HWM
+    pub positioning_partitions: AtomicUsize,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
@@ -13,6 +24,8 @@ impl AppState {
             is_alive: AtomicBool::new(true),
             is_ready: AtomicBool::new(true),
             has_started: AtomicBool::new(true),
+            queued_partitions: AtomicUsize::new(0),
+            positioning_partitions: AtomicUsize::new(0),
         }
     }
 
@@ -21,17 +34,47 @@ impl AppState {
             .store(value, std::sync::atomic::Ordering::Relaxed);
     }
 
+    pub fn set_is_ready(&self, value: bool) {
+        self.is_ready
+            .store(value, std::sync::atomic::Ordering::Relaxed);
+    }
+
     pub fn set_has_started(&self, value: bool) {
         self.has_started
             .store(value, std::sync::atomic::Ordering::Relaxed);
     }
 
+    pub fn add_queued_partitions(&self, count: usize) {
+        self.queued_partitions
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn remove_queued_partitions(&self, count: usize) {
+        self.queued_partitions
+            .fetch_sub(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn set_positioning_partitions(&self, count: usize) {
+        self.positioning_partitions
+            .store(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
     pub fn get_is_alive(&self) -> bool {
         self.is_alive.load(std::sync::atomic::Ordering::Relaxed)
     }
 
     pub fn get_is_ready(&self) -> bool {
         self.is_ready.load(std::sync::atomic::Ordering::Relaxed)
+            && self.get_pending_partitions() == 0
+    }
+
+    /// Assigned partitions that are not yet positioned at their HWM, queued or not
+    pub fn get_pending_partitions(&self) -> usize {
+        self.queued_partitions
+            .load(std::sync::atomic::Ordering::Relaxed)
+            + self
+                .positioning_partitions
+                .load(std::sync::atomic::Ordering::Relaxed)
     }
 
     pub fn get_has_started(&self) -> bool {
@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use futures::StreamExt;
+use log::{info, warn};
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectStore, WriteMultipart};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::compression::ZSTD_LEVEL;
+use crate::config::ArchiveConfig;
+use crate::database::archive_catalog::{
+    ArchiveSegmentRow, NewArchiveSegment, RewrittenSegment, delete_archive_segment,
+    delete_archived_records, get_archived_topics, get_expired_archive_segments,
+    get_last_archived_window_end, get_next_record_timestamp, insert_archive_segment,
+    insert_archive_segment_keys, update_archive_segment_deleted,
+};
+use crate::database::purge::get_hwm_topics;
+use crate::database::read_data::{DataRow, RecordCursor, RecordQuery, query_records};
+use crate::encoding::ValueEncoding;
+use crate::encryption::data_cipher::DataCipher;
+use crate::encryption::key_hash::record_key_hash;
+use crate::kafka::topic_subscription::TopicSelector;
+use crate::metrics;
+use crate::query_api::records::RecordResponse;
+
+pub const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+pub const SEGMENT_EXTENSION: &str = "jsonl.zst";
+const READ_BATCH_SIZE: i64 = 1000;
+/// Offsets per delete statement
+const DELETE_BATCH_SIZE: usize = 5000;
+/// Parts of a segment uploaded at the same time
+const UPLOAD_CONCURRENCY: usize = 4;
+
+/// A window archived by [`Archiver::archive_topic`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedWindow {
+    pub topic: String,
+    pub day: NaiveDate,
+    pub object_key: String,
+    pub row_count: i64,
+    /// Records deleted from the database, `None` when archived records are kept
+    pub deleted_rows: Option<i64>,
+}
+
+/// The segment as written, before it is verified
+struct WrittenSegment {
+    rows: i64,
+    bytes: i64,
+    sha256: Vec<u8>,
+    offsets: Vec<(i16, i64)>,
+    key_hashes: HashSet<Vec<u8>>,
+}
+
+/// The records of an archived segment that match an erasure, found by
+/// [`Archiver::find_erased_records`]
+#[derive(Debug, Clone)]
+pub struct SegmentErasure {
+    pub segment: ArchiveSegmentRow,
+    /// Offsets of the matching records
+    pub offsets: Vec<(i16, i64)>,
+    /// The lines of the other records, written again by [`Archiver::rewrite_segment`]
+    kept_lines: Vec<Vec<u8>>,
+    /// Whether one of the other records has the same key hash, so the segment stays in
+    /// the key index
+    pub key_hash_kept: bool,
+}
+
+/// Archives the records of closed days to zstd-compressed JSON Lines segments in object
+/// storage, in the same format as the JSON Lines export
+pub struct Archiver {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    delete_archived_rows: bool,
+}
+
+impl Archiver {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str, delete_archived_rows: bool) -> Self {
+        Archiver {
+            store,
+            prefix: prefix.trim_matches('/').to_string(),
+            delete_archived_rows,
+        }
+    }
+
+    pub fn from_config(config: &ArchiveConfig) -> Result<Self, Box<dyn Error>> {
+        Ok(Archiver::new(
+            create_object_store(config)?,
+            &config.prefix,
+            *config.delete_archived_rows,
+        ))
+    }
+
+    /// `{prefix}/{topic}/{YYYY}/{MM}/{topic}-{YYYY-MM-DD}.jsonl.zst`
+    pub fn object_key(&self, topic: &str, day: NaiveDate) -> Path {
+        let file = format!("{}-{}.{}", topic, day.format("%Y-%m-%d"), SEGMENT_EXTENSION);
+        let key = format!("{}/{}/{}", topic, day.format("%Y/%m"), file);
+        match self.prefix.as_str() {
+            "" => Path::from(key),
+            prefix => Path::from(format!("{}/{}", prefix, key)),
+        }
+    }
+
+    /// The object a segment is written to when an erasure removes records from it,
+    /// `{prefix}/{topic}/{YYYY}/{MM}/{topic}-{YYYY-MM-DD}-erasure-{id}.jsonl.zst`
+    pub fn rewritten_object_key(&self, topic: &str, day: NaiveDate, erasure_id: i64) -> Path {
+        let key = self.object_key(topic, day).to_string();
+        let stem = key
+            .strip_suffix(&format!(".{}", SEGMENT_EXTENSION))
+            .unwrap_or(&key);
+        Path::from(format!(
+            "{}-erasure-{}.{}",
+            stem, erasure_id, SEGMENT_EXTENSION
+        ))
+    }
+
+    /// Archives the closed days of every topic with `archive_after_days` in its policy
+    pub async fn archive_closed_windows(
+        &self,
+        pg_pool: &PgPool,
+        selector: &TopicSelector,
+        now: DateTime<Utc>,
+        cipher: Option<&DataCipher>,
+    ) -> Result<Vec<ArchivedWindow>, Box<dyn Error>> {
+        let mut archived = Vec::new();
+        for topic in get_hwm_topics(pg_pool).await? {
+            let Some(days) = selector.policy_for(&topic).archive_after_days else {
+                continue;
+            };
+            let before = (now - TimeDelta::days(days as i64)).date_naive();
+            archived.extend(self.archive_topic(pg_pool, &topic, before, cipher).await?);
+        }
+        Ok(archived)
+    }
+
+    /// Archives every day before `before` with records, continuing after the last
+    /// archived day. Records stored later for a day that is already archived stay in the
+    /// database.
+    pub async fn archive_topic(
+        &self,
+        pg_pool: &PgPool,
+        topic: &str,
+        before: NaiveDate,
+        cipher: Option<&DataCipher>,
+    ) -> Result<Vec<ArchivedWindow>, Box<dyn Error>> {
+        let mut from = get_last_archived_window_end(pg_pool, topic)
+            .await?
+            .unwrap_or(DateTime::UNIX_EPOCH);
+        let mut archived = Vec::new();
+        while let Some(next) = get_next_record_timestamp(pg_pool, topic, from).await? {
+            let day = next.date_naive();
+            if day >= before {
+                break;
+            }
+            archived.push(self.archive_window(pg_pool, topic, day, cipher).await?);
+            from = day_bounds(day).1;
+        }
+        Ok(archived)
+    }
+
+    /// Uploads the segment of the day, verifies it by reading it back and adds it to the
+    /// catalog. The archived records are deleted in the same transaction, so a failed
+    /// run leaves the window to be archived again.
+    async fn archive_window(
+        &self,
+        pg_pool: &PgPool,
+        topic: &str,
+        day: NaiveDate,
+        cipher: Option<&DataCipher>,
+    ) -> Result<ArchivedWindow, Box<dyn Error>> {
+        let (window_start, window_end) = day_bounds(day);
+        let key = self.object_key(topic, day);
+        let query = RecordQuery {
+            kafka_topic: topic.to_string(),
+            from_timestamp: Some(window_start),
+            to_timestamp: Some(window_end),
+            ..Default::default()
+        };
+        let mut upload = WriteMultipart::new(self.store.put_multipart(&key).await?);
+        // Only the message of a failed write is kept, the error is not Send and the
+        // upload is aborted before returning
+        let written = match write_segment(pg_pool, &query, cipher, &mut upload)
+            .await
+            .map_err(|e| e.to_string())
+        {
+            Ok(written) => written,
+            Err(message) => {
+                if let Err(abort_error) = upload.abort().await {
+                    warn!("Kunne ikke avbryte opplasting av {}: {}", key, abort_error);
+                }
+                return Err(message.into());
+            }
+        };
+        upload.finish().await?;
+        self.verify_segment(&key, &written).await?;
+
+        let segment = NewArchiveSegment {
+            kafka_topic: topic.to_string(),
+            window_start,
+            window_end,
+            object_key: key.to_string(),
+            row_count: written.rows,
+            bytes: written.bytes,
+            sha256: written.sha256,
+        };
+        let mut tx = pg_pool.begin().await?;
+        let id = insert_archive_segment(&mut tx, &segment).await?;
+        let key_hashes: Vec<Vec<u8>> = written.key_hashes.into_iter().collect();
+        insert_archive_segment_keys(&mut tx, id, &key_hashes).await?;
+        let mut deleted = Vec::new();
+        if self.delete_archived_rows {
+            for offsets in written.offsets.chunks(DELETE_BATCH_SIZE) {
+                deleted.extend(
+                    delete_archived_records(&mut tx, topic, window_start, window_end, offsets)
+                        .await?,
+                );
+            }
+            let deleted_rows = deleted.iter().map(|(_, count)| count).sum();
+            update_archive_segment_deleted(&mut tx, id, deleted_rows).await?;
+        }
+        tx.commit().await?;
+
+        metrics::increment_archived_records(topic, segment.row_count);
+        for (partition, count) in &deleted {
+            metrics::increment_retention_rows_deleted(topic, *partition as i32, *count);
+        }
+        let deleted_rows = self
+            .delete_archived_rows
+            .then(|| deleted.iter().map(|(_, count)| count).sum());
+        info!(
+            "Arkiverte {} meldinger fra {} for {} til {}, slettet {:?}",
+            segment.row_count, topic, day, segment.object_key, deleted_rows
+        );
+        Ok(ArchivedWindow {
+            topic: segment.kafka_topic,
+            day,
+            object_key: segment.object_key,
+            row_count: segment.row_count,
+            deleted_rows,
+        })
+    }
+
+    /// Reads the segment and returns the records that match, `None` when none do since
+    /// the key index only holds key hashes. The segment is checked against its checksum
+    /// in the catalog first.
+    pub async fn find_erased_records(
+        &self,
+        segment: &ArchiveSegmentRow,
+        matches: impl Fn(&DataRow) -> bool,
+    ) -> Result<Option<SegmentErasure>, Box<dyn Error + Send + Sync>> {
+        let key = Path::from(segment.object_key.as_str());
+        let content = self.store.get(&key).await?.bytes().await?;
+        if content.len() as i64 != segment.bytes
+            || Sha256::digest(&content).as_slice() != segment.sha256
+        {
+            return Err(format!("Archive segment {} does not match the catalog", key).into());
+        }
+        let content = zstd::decode_all(content.as_ref())?;
+        let mut offsets = Vec::new();
+        let mut kept_lines = Vec::new();
+        let mut key_hashes = HashSet::new();
+        let mut erased_key_hashes = HashSet::new();
+        for line in content.split(|byte| *byte == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let row = serde_json::from_slice::<RecordResponse>(line)?.into_row()?;
+            let key_hash = row.record_key.as_deref().map(record_key_hash);
+            if matches(&row) {
+                offsets.push((row.kafka_partition, row.kafka_offset));
+                erased_key_hashes.extend(key_hash);
+            } else {
+                kept_lines.push(line.to_vec());
+                key_hashes.extend(key_hash);
+            }
+        }
+        if offsets.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(SegmentErasure {
+            segment: segment.clone(),
+            offsets,
+            kept_lines,
+            key_hash_kept: erased_key_hashes.is_subset(&key_hashes),
+        }))
+    }
+
+    /// Writes the records the erasure keeps to a new object and verifies it. The catalog
+    /// is pointed to it in the erasure transaction, the old object is deleted after the
+    /// commit with [`Archiver::delete_object`], so a failed erasure leaves the catalog
+    /// and the old object as they were.
+    pub async fn rewrite_segment(
+        &self,
+        erasure: &SegmentErasure,
+        erasure_id: i64,
+    ) -> Result<RewrittenSegment, Box<dyn Error + Send + Sync>> {
+        let segment = &erasure.segment;
+        let key = self.rewritten_object_key(
+            &segment.kafka_topic,
+            segment.window_start.date_naive(),
+            erasure_id,
+        );
+        let mut content = Vec::new();
+        for line in &erasure.kept_lines {
+            content.extend_from_slice(line);
+            content.push(b'\n');
+        }
+        let compressed = zstd::encode_all(content.as_slice(), ZSTD_LEVEL)?;
+        let written = WrittenSegment {
+            rows: erasure.kept_lines.len() as i64,
+            bytes: compressed.len() as i64,
+            sha256: Sha256::digest(&compressed).to_vec(),
+            offsets: Vec::new(),
+            key_hashes: HashSet::new(),
+        };
+        self.store.put(&key, compressed.into()).await?;
+        self.verify_segment(&key, &written)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(RewrittenSegment {
+            object_key: key.to_string(),
+            row_count: written.rows,
+            bytes: written.bytes,
+            sha256: written.sha256,
+        })
+    }
+
+    pub async fn delete_object(&self, object_key: &str) -> Result<(), object_store::Error> {
+        self.store.delete(&Path::from(object_key)).await
+    }
+
+    /// Deletes the segments of every archived topic with `retention_days` in its policy
+    /// once their whole window is older than the retention, the object first so a failed
+    /// run leaves the catalog entry to be deleted again. Returns the deleted object keys.
+    pub async fn expire_segments(
+        &self,
+        pg_pool: &PgPool,
+        selector: &TopicSelector,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut expired = Vec::new();
+        for topic in get_archived_topics(pg_pool).await? {
+            let Some(days) = selector.policy_for(&topic).retention_days else {
+                continue;
+            };
+            let cutoff = now - TimeDelta::days(days as i64);
+            for segment in get_expired_archive_segments(pg_pool, &topic, cutoff).await? {
+                self.delete_object(&segment.object_key).await?;
+                delete_archive_segment(pg_pool, segment.id).await?;
+                info!(
+                    "Slettet arkivert segment {} med {} meldinger, eldre enn {}",
+                    segment.object_key, segment.row_count, cutoff
+                );
+                expired.push(segment.object_key);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Reads the uploaded object back and compares its size and checksum with what was
+    /// written
+    async fn verify_segment(
+        &self,
+        key: &Path,
+        written: &WrittenSegment,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut stream = self.store.get(key).await?.into_stream();
+        let mut hasher = Sha256::new();
+        let mut bytes = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            bytes += chunk.len() as i64;
+        }
+        if bytes != written.bytes || hasher.finalize().as_slice() != written.sha256 {
+            return Err(format!(
+                "Archive segment {} does not match the uploaded data, expected {} bytes, found {}",
+                key, written.bytes, bytes
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// The bucket of the archive, with credentials from the AWS environment variables
+pub fn create_object_store(config: &ArchiveConfig) -> Result<Arc<dyn ObjectStore>, Box<dyn Error>> {
+    let mut builder = AmazonS3Builder::from_env()
+        .with_bucket_name(config.bucket.as_str())
+        .with_region(config.region.as_str())
+        .with_allow_http(*config.allow_http);
+    if let Some(endpoint) = &config.endpoint {
+        builder = builder.with_endpoint(endpoint.as_str());
+    }
+    Ok(Arc::new(builder.build()?))
+}
+
+/// Start of the day, inclusive, and start of the next day, exclusive, in UTC
+fn day_bounds(day: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = day.and_hms_opt(0, 0, 0).expect("Valid time").and_utc();
+    (start, start + TimeDelta::days(1))
+}
+
+/// Streams the records of the query to the upload as compressed JSON Lines, hashing the
+/// compressed bytes on the way
+async fn write_segment(
+    pg_pool: &PgPool,
+    query: &RecordQuery,
+    cipher: Option<&DataCipher>,
+    upload: &mut WriteMultipart,
+) -> Result<WrittenSegment, Box<dyn Error>> {
+    let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?;
+    let mut hasher = Sha256::new();
+    let mut bytes = 0;
+    let mut offsets = Vec::new();
+    let mut key_hashes = HashSet::new();
+    let mut cursor = RecordCursor::start();
+    loop {
+        let rows = query_records(pg_pool, query, cursor, READ_BATCH_SIZE, cipher).await?;
+        let Some(last_row) = rows.last() else {
+            break;
+        };
+        cursor = RecordCursor::after(last_row, query);
+        for row in rows {
+            offsets.push((row.kafka_partition, row.kafka_offset));
+            key_hashes.extend(row.record_key.as_deref().map(record_key_hash));
+            let record = RecordResponse::from_row(row, ValueEncoding::Utf8)?;
+            serde_json::to_writer(&mut encoder, &record)?;
+            encoder.write_all(b"\n")?;
+        }
+        let compressed = std::mem::take(encoder.get_mut());
+        hasher.update(&compressed);
+        bytes += compressed.len() as i64;
+        upload.write(&compressed);
+        upload.wait_for_capacity(UPLOAD_CONCURRENCY).await?;
+    }
+    let compressed = encoder.finish()?;
+    hasher.update(&compressed);
+    bytes += compressed.len() as i64;
+    upload.write(&compressed);
+    Ok(WrittenSegment {
+        rows: offsets.len() as i64,
+        bytes,
+        sha256: hasher.finalize().to_vec(),
+        offsets,
+        key_hashes,
+    })
+}
+
+/// Runs the archiver and the retention of archived segments periodically, a failed run
+/// is logged and retried at the next interval
+pub async fn run_archiver(
+    pg_pool: PgPool,
+    selector: Arc<TopicSelector>,
+    archiver: Arc<Archiver>,
+    cipher: Option<Arc<DataCipher>>,
+    interval: Duration,
+) {
+    loop {
+        if let Err(e) = archiver
+            .archive_closed_windows(&pg_pool, &selector, Utc::now(), cipher.as_deref())
+            .await
+        {
+            warn!("Arkivering feilet: {}", e);
+        }
+        if let Err(e) = archiver
+            .expire_segments(&pg_pool, &selector, Utc::now())
+            .await
+        {
+            warn!("Sletting av utløpte arkiverte segmenter feilet: {}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::error::KafkaError;
+use rdkafka::{Message, Offset, TopicPartitionList};
+
+use crate::kafka::config::ApplicationKafkaConfig;
+
+/// What the audit needs to know from the broker to classify an offset gap
+pub trait BrokerOffsets: Send + Sync {
+    /// The lowest offset the broker still has for the partition
+    fn low_watermark(&self, topic: &str, partition: i32) -> Result<i64, Box<dyn Error>>;
+
+    /// The offset of the first record at or after `offset`, `None` when there are no
+    /// more records in the partition
+    fn first_offset_from(
+        &self,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> Result<Option<i64>, Box<dyn Error>>;
+}
+
+/// Looks up offsets with a separate consumer that is assigned partitions manually, so
+/// it never joins the consumer group and never commits offsets.
+pub struct KafkaBrokerOffsets {
+    consumer: BaseConsumer,
+    timeout: Duration,
+}
+
+impl KafkaBrokerOffsets {
+    pub fn new(app_config: ApplicationKafkaConfig) -> Result<Self, Box<dyn Error>> {
+        let consumer: BaseConsumer = app_config
+            .rdkafka_config()?
+            .set("enable.partition.eof", "true")
+            .create()?;
+        Ok(KafkaBrokerOffsets {
+            consumer,
+            timeout: Duration::from_secs(10),
+        })
+    }
+}
+
+impl BrokerOffsets for KafkaBrokerOffsets {
+    fn low_watermark(&self, topic: &str, partition: i32) -> Result<i64, Box<dyn Error>> {
+        let (low, _high) = self
+            .consumer
+            .fetch_watermarks(topic, partition, self.timeout)?;
+        Ok(low)
+    }
+
+    fn first_offset_from(
+        &self,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> Result<Option<i64>, Box<dyn Error>> {
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+        self.consumer.unassign()?;
+        self.consumer.assign(&assignment)?;
+
+        let deadline = Instant::now() + self.timeout;
+        let result = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break Err(format!(
+                    "Timeout reading topic={}, partition={} from offset {}",
+                    topic, partition, offset
+                )
+                .into());
+            }
+            match self.consumer.poll(remaining) {
+                Some(Ok(msg))
+                    if msg.topic() == topic
+                        && msg.partition() == partition
+                        && msg.offset() >= offset =>
+                {
+                    break Ok(Some(msg.offset()));
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(KafkaError::PartitionEOF(eof_partition)))
+                    if eof_partition == partition =>
+                {
+                    break Ok(None);
+                }
+                Some(Err(e)) => break Err(e.into()),
+                None => continue,
+            }
+        };
+        self.consumer.unassign()?;
+        result
+    }
+}
@@ -0,0 +1,2 @@
+pub mod broker_offsets;
+pub mod offset_audit;
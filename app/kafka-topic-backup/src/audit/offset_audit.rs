@@ -0,0 +1,346 @@
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::audit::broker_offsets::BrokerOffsets;
+use crate::database::erasure::get_erased_ranges;
+use crate::database::hwm_statements::get_all_hwms;
+use crate::database::offset_gaps::{OffsetRange, find_offset_gaps};
+use crate::database::purge::get_purged_ranges;
+use crate::metrics;
+
+/// The audit runs at startup and then with this interval
+pub const AUDIT_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Upper bound on reads from the broker per audit run, gaps beyond it are unverified
+pub const MAX_VERIFICATIONS_PER_RUN: usize = 100;
+
+/// The latest completed audit, `None` until the first run completes
+pub type SharedAuditReport = Arc<RwLock<Option<AuditReport>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapKind {
+    /// Expected, the partition was started from a later offset by its start position
+    BeforeStart,
+    /// Expected, deleted by the retention job
+    Purged,
+    /// Expected, deleted by an erasure request
+    Erased,
+    /// Expected, the broker has already deleted these offsets
+    BelowLowWatermark,
+    /// Expected, the broker has no records in the range, which happens with compacted
+    /// topics, transaction markers and aborted transactions
+    NoRecordsOnBroker,
+    /// The broker still has at least one record in the range that is not stored
+    Missing,
+    /// The broker could not be asked, or the verification budget for the run was used up
+    Unverified,
+}
+
+impl GapKind {
+    pub const ALL: [GapKind; 7] = [
+        GapKind::BeforeStart,
+        GapKind::Purged,
+        GapKind::Erased,
+        GapKind::BelowLowWatermark,
+        GapKind::NoRecordsOnBroker,
+        GapKind::Missing,
+        GapKind::Unverified,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GapKind::BeforeStart => "before_start",
+            GapKind::Purged => "purged",
+            GapKind::Erased => "erased",
+            GapKind::BelowLowWatermark => "below_low_watermark",
+            GapKind::NoRecordsOnBroker => "no_records_on_broker",
+            GapKind::Missing => "missing",
+            GapKind::Unverified => "unverified",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffsetGap {
+    /// First offset that is not stored
+    pub start: i64,
+    /// Last offset that is not stored, inclusive
+    pub end: i64,
+    pub kind: GapKind,
+}
+
+impl OffsetGap {
+    pub fn offsets(&self) -> i64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionAudit {
+    pub topic: String,
+    pub partition: i32,
+    pub hwm: i64,
+    /// `None` when the broker could not be asked
+    pub low_watermark: Option<i64>,
+    pub gaps: Vec<OffsetGap>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditReport {
+    pub completed_at: DateTime<Utc>,
+    pub partitions: Vec<PartitionAudit>,
+}
+
+impl AuditReport {
+    pub fn missing_gaps(&self) -> usize {
+        self.partitions
+            .iter()
+            .flat_map(|partition| &partition.gaps)
+            .filter(|gap| gap.kind == GapKind::Missing)
+            .count()
+    }
+}
+
+/// Scans the stored offsets of every partition with a HWM and classifies the gaps.
+/// Without a broker every gap is reported as [`GapKind::Unverified`].
+pub async fn audit_offsets(
+    pg_pool: &PgPool,
+    broker: Option<Arc<dyn BrokerOffsets>>,
+    max_verifications: usize,
+) -> Result<AuditReport, Box<dyn Error>> {
+    let mut remaining_verifications = max_verifications;
+    let mut partitions = Vec::new();
+    for hwm in get_all_hwms(pg_pool).await? {
+        let partition = hwm.partition as i32;
+        let gaps = find_offset_gaps(pg_pool, &hwm.topic, partition, hwm.hwm).await?;
+        let (mut expected, gaps) = split_below(
+            gaps,
+            hwm.start_offset.map(|start_offset| start_offset - 1),
+            GapKind::BeforeStart,
+        );
+        let purged_ranges = get_purged_ranges(pg_pool, &hwm.topic, partition, hwm.hwm).await?;
+        let (purged, gaps) = split_ranges(gaps, &purged_ranges, GapKind::Purged);
+        expected.extend(purged);
+        let erased_ranges = get_erased_ranges(pg_pool, &hwm.topic, partition, hwm.hwm).await?;
+        let (erased, gaps) = split_ranges(gaps, &erased_ranges, GapKind::Erased);
+        expected.extend(erased);
+        let (low_watermark, gaps) = match (&broker, gaps.is_empty()) {
+            (Some(broker), false) => {
+                let broker = broker.clone();
+                let topic = hwm.topic.clone();
+                let budget = remaining_verifications;
+                let (low_watermark, gaps, used) = tokio::task::spawn_blocking(move || {
+                    classify_gaps(broker.as_ref(), &topic, partition, gaps, budget)
+                })
+                .await?;
+                remaining_verifications -= used;
+                (low_watermark, gaps)
+            }
+            _ => (None, unverified(gaps)),
+        };
+        expected.extend(gaps);
+        expected.sort_by_key(|gap| gap.start);
+        partitions.push(PartitionAudit {
+            topic: hwm.topic,
+            partition,
+            hwm: hwm.hwm,
+            low_watermark,
+            gaps: expected,
+        });
+    }
+    Ok(AuditReport {
+        completed_at: Utc::now(),
+        partitions,
+    })
+}
+
+/// Splits off the parts of the gaps at or below `last` as expected gaps of the kind, like
+/// the offsets before the partition was started from
+fn split_below(
+    gaps: Vec<OffsetRange>,
+    last: Option<i64>,
+    kind: GapKind,
+) -> (Vec<OffsetGap>, Vec<OffsetRange>) {
+    let Some(last) = last else {
+        return (Vec::new(), gaps);
+    };
+    let mut below = Vec::new();
+    let mut remaining = Vec::new();
+    for gap in gaps {
+        if gap.start <= last {
+            below.push(OffsetGap {
+                start: gap.start,
+                end: gap.end.min(last),
+                kind,
+            });
+        }
+        if gap.end > last {
+            remaining.push(OffsetRange {
+                start: gap.start.max(last + 1),
+                end: gap.end,
+            });
+        }
+    }
+    (below, remaining)
+}
+
+/// Splits off the parts of the gaps within `ranges` as expected gaps of the kind, like
+/// the offsets that were purged or erased. `ranges` must be ordered and disjoint.
+fn split_ranges(
+    gaps: Vec<OffsetRange>,
+    ranges: &[OffsetRange],
+    kind: GapKind,
+) -> (Vec<OffsetGap>, Vec<OffsetRange>) {
+    let mut expected = Vec::new();
+    let mut remaining = Vec::new();
+    for gap in gaps {
+        let mut start = gap.start;
+        for range in ranges
+            .iter()
+            .filter(|range| range.end >= gap.start && range.start <= gap.end)
+        {
+            if range.start > start {
+                remaining.push(OffsetRange {
+                    start,
+                    end: range.start - 1,
+                });
+            }
+            expected.push(OffsetGap {
+                start: range.start.max(start),
+                end: range.end.min(gap.end),
+                kind,
+            });
+            start = range.end + 1;
+        }
+        if start <= gap.end {
+            remaining.push(OffsetRange {
+                start,
+                end: gap.end,
+            });
+        }
+    }
+    (expected, remaining)
+}
+
+/// Returns the low watermark, the classified gaps and the number of reads used
+fn classify_gaps(
+    broker: &dyn BrokerOffsets,
+    topic: &str,
+    partition: i32,
+    gaps: Vec<OffsetRange>,
+    budget: usize,
+) -> (Option<i64>, Vec<OffsetGap>, usize) {
+    let low_watermark = match broker.low_watermark(topic, partition) {
+        Ok(low_watermark) => low_watermark,
+        Err(e) => {
+            warn!(
+                "Klarte ikke å hente low watermark for topic={}, partition={}: {}",
+                topic, partition, e
+            );
+            return (None, unverified(gaps), 0);
+        }
+    };
+
+    let mut used = 0;
+    let mut classified = Vec::new();
+    for gap in gaps {
+        let mut start = gap.start;
+        if start < low_watermark {
+            let end = gap.end.min(low_watermark - 1);
+            classified.push(OffsetGap {
+                start,
+                end,
+                kind: GapKind::BelowLowWatermark,
+            });
+            if end == gap.end {
+                continue;
+            }
+            start = low_watermark;
+        }
+        let kind = if used >= budget {
+            GapKind::Unverified
+        } else {
+            used += 1;
+            match broker.first_offset_from(topic, partition, start) {
+                Ok(Some(first)) if first <= gap.end => GapKind::Missing,
+                Ok(_) => GapKind::NoRecordsOnBroker,
+                Err(e) => {
+                    warn!(
+                        "Klarte ikke å verifisere hull i offsets for topic={}, partition={}, offsets={}..={}: {}",
+                        topic, partition, start, gap.end, e
+                    );
+                    GapKind::Unverified
+                }
+            }
+        };
+        classified.push(OffsetGap {
+            start,
+            end: gap.end,
+            kind,
+        });
+    }
+    (Some(low_watermark), classified, used)
+}
+
+fn unverified(gaps: Vec<OffsetRange>) -> Vec<OffsetGap> {
+    gaps.into_iter()
+        .map(|gap| OffsetGap {
+            start: gap.start,
+            end: gap.end,
+            kind: GapKind::Unverified,
+        })
+        .collect()
+}
+
+fn update_metrics(report: &AuditReport) {
+    for partition in &report.partitions {
+        for kind in GapKind::ALL {
+            let gaps: Vec<&OffsetGap> = partition
+                .gaps
+                .iter()
+                .filter(|gap| gap.kind == kind)
+                .collect();
+            metrics::set_offset_gaps(
+                &partition.topic,
+                partition.partition,
+                kind.as_str(),
+                gaps.len(),
+                gaps.iter().map(|gap| gap.offsets()).sum(),
+            );
+        }
+    }
+    metrics::set_offset_audit_completed(report.completed_at.timestamp());
+}
+
+/// Runs the audit periodically and publishes the result as metrics and in `report`.
+/// A failed run is logged and retried at the next interval.
+pub async fn run_offset_audit(
+    pg_pool: PgPool,
+    broker: Option<Arc<dyn BrokerOffsets>>,
+    report: SharedAuditReport,
+    interval: Duration,
+) {
+    loop {
+        match audit_offsets(&pg_pool, broker.clone(), MAX_VERIFICATIONS_PER_RUN).await {
+            Ok(completed) => {
+                update_metrics(&completed);
+                info!(
+                    "Revisjon av offsets fullført: {} partisjoner, {} hull med manglende meldinger",
+                    completed.partitions.len(),
+                    completed.missing_gaps()
+                );
+                if let Ok(mut latest) = report.write() {
+                    *latest = Some(completed);
+                }
+            }
+            Err(e) => warn!("Revisjon av offsets feilet: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+
+use crate::encoding::ValueEncoding;
+use crate::export::jsonl_export::ExportRequest;
+use crate::export::parquet_export::{JsonColumn, ParquetExportRequest};
+use crate::export::state_export::StateExportRequest;
+use crate::migration::migrate_data_table::MIGRATION_BATCH_SIZE;
+use crate::restore::restore_topic::{RestoreCutoff, RestoreRequest};
+
+#[derive(Debug, Parser)]
+#[command(name = "paw-kafka-topic-backup", version)]
+pub struct Cli {
+    /// TOML config file, overrides the CONFIG_FILE env var and the embedded config
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Back up the configured topics to Postgres (default when no command is given)
+    Backup,
+    /// Produce backed up records from Postgres to the same partitions of a Kafka topic
+    Restore(RestoreArgs),
+    /// Re-wrap the data encryption keys with the current master key
+    RotateKeys(RotateKeysArgs),
+    /// Copy the records from the old data_v2 table to the partitioned data table, which
+    /// the backup also does at startup
+    MigrateDataTable(MigrateDataTableArgs),
+    /// Fill in the key hash of records stored without one
+    BackfillKeyHashes(BackfillKeyHashesArgs),
+    /// Export backed up records of a topic to JSON Lines files
+    Export(ExportArgs),
+    /// Export backed up records of a topic to one Parquet file per day
+    ExportParquet(ExportParquetArgs),
+    /// Import records from a JSON Lines export or the archive to Postgres or Kafka
+    Import(ImportArgs),
+    /// Export the latest record of each key of a topic to JSON Lines files
+    ExportState(ExportStateArgs),
+    /// Rebuild the current state of a topic from its stored records
+    RebuildState(RebuildStateArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// Topic to read backed up records for
+    #[arg(long)]
+    pub source_topic: String,
+    /// Topic to produce the records to
+    #[arg(long)]
+    pub target_topic: String,
+    /// Comma separated list of partitions to restore, all partitions when omitted
+    #[arg(long, value_delimiter = ',')]
+    pub partitions: Option<Vec<i32>>,
+    /// Number of records read from the database per batch
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: i64,
+    /// Only restore records with a timestamp at or before this, like 2025-01-01T14:00:00Z
+    #[arg(long)]
+    pub until_timestamp: Option<DateTime<Utc>>,
+    /// Last offset to restore of a partition, as partition=offset, comma separated
+    #[arg(long, value_delimiter = ',', value_parser = parse_partition_offset)]
+    pub until_offsets: Vec<(i32, i64)>,
+    /// Skip records with a timestamp at or after this, up to --exclude-to
+    #[arg(long, requires = "exclude_to")]
+    pub exclude_from: Option<DateTime<Utc>>,
+    /// Skip records with a timestamp before this, from --exclude-from
+    #[arg(long, requires = "exclude_from")]
+    pub exclude_to: Option<DateTime<Utc>>,
+    /// Write the replayed and skipped offsets to this file as JSON
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+impl RestoreArgs {
+    pub fn restore_request(&self) -> RestoreRequest {
+        RestoreRequest {
+            source_topic: self.source_topic.clone(),
+            target_topic: self.target_topic.clone(),
+            partitions: self.partitions.clone(),
+            batch_size: self.batch_size,
+            cutoff: RestoreCutoff {
+                until_timestamp: self.until_timestamp,
+                until_offsets: self.until_offsets.iter().copied().collect(),
+                exclude_window: self.exclude_from.zip(self.exclude_to),
+            },
+        }
+    }
+}
+
+fn parse_partition_offset(value: &str) -> Result<(i32, i64), String> {
+    let invalid = || format!("Invalid offset '{}', expected partition=offset", value);
+    let (partition, offset) = value.split_once('=').ok_or_else(invalid)?;
+    Ok((
+        partition.trim().parse().map_err(|_| invalid())?,
+        offset.trim().parse().map_err(|_| invalid())?,
+    ))
+}
+
+#[derive(Debug, Args)]
+pub struct RotateKeysArgs {
+    /// Also create a new data encryption key, used for all records stored from now on
+    #[arg(long)]
+    pub new_data_key: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateDataTableArgs {
+    /// Number of records copied per statement
+    #[arg(long, default_value_t = MIGRATION_BATCH_SIZE)]
+    pub batch_size: i64,
+}
+
+#[derive(Debug, Args)]
+pub struct BackfillKeyHashesArgs {
+    /// Number of records updated per statement
+    #[arg(long, default_value_t = 5000)]
+    pub batch_size: i64,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(long)]
+    pub topic: String,
+    /// Only records with a timestamp at or after this, like 2025-01-01T00:00:00Z
+    #[arg(long)]
+    pub from_timestamp: Option<DateTime<Utc>>,
+    /// Only records with a timestamp before this
+    #[arg(long)]
+    pub to_timestamp: Option<DateTime<Utc>>,
+    /// Directory to write the segments and the manifest to
+    #[arg(long)]
+    pub output_dir: PathBuf,
+    /// Segments are split before they get larger than this
+    #[arg(long, default_value_t = 128 * 1024 * 1024)]
+    pub max_segment_bytes: u64,
+    /// Number of records read from the database per batch
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: i64,
+    /// Preferred encoding of keys and values, values that are not UTF-8 are always base64
+    #[arg(long, value_enum, default_value_t = ValueEncoding::Utf8)]
+    pub encoding: ValueEncoding,
+}
+
+impl ExportArgs {
+    pub fn export_request(&self) -> ExportRequest {
+        ExportRequest {
+            topic: self.topic.clone(),
+            from_timestamp: self.from_timestamp,
+            to_timestamp: self.to_timestamp,
+            output_dir: self.output_dir.clone(),
+            max_segment_bytes: self.max_segment_bytes,
+            batch_size: self.batch_size,
+            encoding: self.encoding,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ExportParquetArgs {
+    #[arg(long)]
+    pub topic: String,
+    /// Only records with a timestamp at or after this, like 2025-01-01T00:00:00Z
+    #[arg(long)]
+    pub from_timestamp: Option<DateTime<Utc>>,
+    /// Only records with a timestamp before this
+    #[arg(long)]
+    pub to_timestamp: Option<DateTime<Utc>>,
+    /// Directory to write the files and the manifest to
+    #[arg(long)]
+    pub output_dir: PathBuf,
+    /// Number of records read from the database per batch
+    #[arg(long, default_value_t = 5000)]
+    pub batch_size: i64,
+    /// Field of the JSON value to add as a column, as name=/pointer or /pointer, can be
+    /// repeated
+    #[arg(long = "json-field")]
+    pub json_fields: Vec<JsonColumn>,
+}
+
+impl ExportParquetArgs {
+    pub fn export_request(&self) -> ParquetExportRequest {
+        ParquetExportRequest {
+            topic: self.topic.clone(),
+            from_timestamp: self.from_timestamp,
+            to_timestamp: self.to_timestamp,
+            output_dir: self.output_dir.clone(),
+            batch_size: self.batch_size,
+            json_columns: self.json_fields.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ExportStateArgs {
+    /// Topic that keeps its current state
+    #[arg(long)]
+    pub topic: String,
+    /// Directory to write the segments and the manifest to
+    #[arg(long)]
+    pub output_dir: PathBuf,
+    /// Segments are split before they get larger than this
+    #[arg(long, default_value_t = 128 * 1024 * 1024)]
+    pub max_segment_bytes: u64,
+    /// Number of keys read from the database per batch
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: i64,
+    /// Preferred encoding of keys and values, values that are not UTF-8 are always base64
+    #[arg(long, value_enum, default_value_t = ValueEncoding::Utf8)]
+    pub encoding: ValueEncoding,
+}
+
+impl ExportStateArgs {
+    pub fn export_request(&self) -> StateExportRequest {
+        StateExportRequest {
+            topic: self.topic.clone(),
+            output_dir: self.output_dir.clone(),
+            max_segment_bytes: self.max_segment_bytes,
+            batch_size: self.batch_size,
+            encoding: self.encoding,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RebuildStateArgs {
+    /// Topic to rebuild the current state of, typically after enabling `current_state`
+    #[arg(long)]
+    pub topic: String,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("source").required(true).args(["export_dir", "archive_topic"])))]
+pub struct ImportArgs {
+    /// Directory with a JSON Lines export and its manifest
+    #[arg(long)]
+    pub export_dir: Option<PathBuf>,
+    /// Topic to import the archived segments of, read from the configured archive
+    #[arg(long)]
+    pub archive_topic: Option<String>,
+    /// Only records with a timestamp at or after this, like 2025-01-01T00:00:00Z
+    #[arg(long)]
+    pub from_timestamp: Option<DateTime<Utc>>,
+    /// Only records with a timestamp before this
+    #[arg(long)]
+    pub to_timestamp: Option<DateTime<Utc>>,
+    /// Produce the records to this topic instead of storing them in Postgres
+    #[arg(long)]
+    pub target_topic: Option<String>,
+    /// Number of records stored or produced per batch
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: usize,
+}
@@ -0,0 +1,93 @@
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use flate2::Compression as GzipLevel;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+
+pub(crate) const ZSTD_LEVEL: i32 = 3;
+
+/// How record values are compressed before they are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Gzip,
+}
+
+/// The codec of a compressed value, stored per row in `value_codec`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    Gzip,
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Gzip => "gzip",
+        }
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zstd => zstd::encode_all(bytes, ZSTD_LEVEL),
+            Codec::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zstd => zstd::decode_all(bytes),
+            Codec::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "zstd" => Ok(Codec::Zstd),
+            "gzip" => Ok(Codec::Gzip),
+            _ => Err(format!("Unknown codec '{}'", value)),
+        }
+    }
+}
+
+impl Compression {
+    pub fn codec(&self) -> Option<Codec> {
+        match self {
+            Compression::None => None,
+            Compression::Zstd => Some(Codec::Zstd),
+            Compression::Gzip => Some(Codec::Gzip),
+        }
+    }
+
+    /// Returns the value to store and its codec. Values that do not get smaller are
+    /// stored uncompressed, so short values are not penalised.
+    pub fn apply(&self, value: Vec<u8>) -> io::Result<(Vec<u8>, Option<Codec>)> {
+        let Some(codec) = self.codec() else {
+            return Ok((value, None));
+        };
+        let compressed = codec.compress(&value)?;
+        if compressed.len() < value.len() {
+            Ok((compressed, Some(codec)))
+        } else {
+            Ok((value, None))
+        }
+    }
+}
@@ -1,24 +1,215 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use log::info;
 use serde::Deserialize;
-use serde_env_field::env_field_wrap;
+use serde_env_field::{EnvField, env_field_wrap};
+
+use crate::compression::Compression;
+use crate::config_utils::get_env::get_env;
+use crate::kafka::topic_policy::{StartPosition, TopicPolicy};
+
+/// Path to a TOML config file, used when no path is given on the command line
+pub const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
 
 #[env_field_wrap]
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub topics: Vec<String>,
+    /// Topic names, or regular expressions when starting with `^`, optionally as a
+    /// table with the backup policy for the topic
+    #[env_field_wrap(skip)]
+    pub topics: Vec<TopicEntry>,
+    /// Topics that are never backed up, even when matched by a pattern. Names or
+    /// regular expressions starting with `^`
+    #[serde(default)]
+    pub exclude_topics: Vec<String>,
+    #[env_field_wrap(skip)]
+    #[serde(default)]
+    pub kafka: KafkaConfig,
+    /// Object storage for archived records, topics are only archived when this is set
+    #[env_field_wrap(skip)]
+    pub archive: Option<ArchiveConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TopicEntry {
+    Name(EnvField<String>),
+    WithPolicy(TopicTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopicTable {
+    pub name: EnvField<String>,
+    #[serde(default)]
+    pub start_position: StartPosition,
+    #[serde(default = "default_store_headers")]
+    pub store_headers: bool,
+    #[serde(default)]
+    pub keys_only: bool,
+    pub retention_days: Option<u32>,
+    #[serde(default)]
+    pub compression: Compression,
+    pub archive_after_days: Option<u32>,
+    #[serde(default)]
+    pub current_state: bool,
+}
+
+fn default_store_headers() -> bool {
+    TopicPolicy::default().store_headers
+}
+
+impl TopicEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            TopicEntry::Name(name) => name,
+            TopicEntry::WithPolicy(table) => &table.name,
+        }
+    }
+
+    pub fn policy(&self) -> TopicPolicy {
+        match self {
+            TopicEntry::Name(_) => TopicPolicy::default(),
+            TopicEntry::WithPolicy(table) => TopicPolicy {
+                start_position: table.start_position,
+                store_headers: table.store_headers,
+                keys_only: table.keys_only,
+                retention_days: table.retention_days,
+                compression: table.compression,
+                archive_after_days: table.archive_after_days,
+                current_state: table.current_state,
+            },
+        }
+    }
+}
+
+#[env_field_wrap]
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct KafkaConfig {
+    pub group_id: String,
+    pub auto_offset_reset: String,
+    pub security_protocol: String,
+    /// rdkafka properties, applied after the built-in defaults of both the consumers and
+    /// the restore and import producers, so they can override fetch and buffer sizes
+    /// as well as the security settings
+    #[env_field_wrap(skip)]
+    pub properties: BTreeMap<String, EnvField<String>>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        KafkaConfig {
+            group_id: "hedelselogg_backup2_v1".to_string().into(),
+            auto_offset_reset: "earliest".to_string().into(),
+            security_protocol: "ssl".to_string().into(),
+            properties: BTreeMap::new(),
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        self.properties
+            .iter()
+            .map(|(key, value)| (key.clone(), value.to_string()))
+            .collect()
+    }
+}
+
+/// S3-compatible bucket for archived segments. Credentials are read from the standard
+/// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
+#[env_field_wrap]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub bucket: String,
+    /// Endpoint of an S3-compatible service like MinIO, AWS when not set
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default = "default_archive_region")]
+    pub region: String,
+    /// Object keys start with this prefix
+    #[serde(default = "default_archive_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub allow_http: bool,
+    /// Deletes the archived records from the database once the segment is verified
+    #[serde(default)]
+    pub delete_archived_rows: bool,
+}
+
+fn default_archive_region() -> EnvField<String> {
+    "us-east-1".to_string().into()
+}
+
+fn default_archive_prefix() -> EnvField<String> {
+    "kafka-topic-backup".to_string().into()
 }
 
 impl Config {
     pub fn from_string(file_content: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let config: Config = toml::from_str(file_content)?;
+        config.validate()?;
         Ok(config)
     }
 
+    /// Rejects policies that cannot be combined
+    fn validate(&self) -> Result<(), String> {
+        for topic in &self.topics {
+            let policy = topic.policy();
+            if policy.keys_only && policy.current_state {
+                return Err(format!(
+                    "Topic {}: keys_only and current_state cannot be combined, the values needed for the current state are not stored",
+                    topic.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn from_default_file() -> Result<Self, Box<dyn std::error::Error>> {
         let file_content = include_str!("../config/config.toml");
+        Self::from_string(file_content)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file_content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
         Self::from_string(&file_content)
     }
 
+    /// Reads the file given on the command line, then the file in [`CONFIG_FILE_ENV`],
+    /// and falls back to the config embedded in the binary
+    pub fn load(cli_path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let env_path = get_env(CONFIG_FILE_ENV)
+            .ok()
+            .filter(|path| !path.is_empty());
+        match cli_path.or(env_path.as_deref().map(Path::new)) {
+            Some(path) => {
+                info!("Leser konfigurasjon fra {}", path.display());
+                Self::from_file(path)
+            }
+            None => {
+                info!("Bruker innebygd konfigurasjon");
+                Self::from_default_file()
+            }
+        }
+    }
+
     pub fn topics_as_str_slice(&self) -> Vec<&str> {
-        self.topics.iter().map(|s| s.as_str()).collect()
+        self.topics.iter().map(TopicEntry::name).collect()
+    }
+
+    pub fn topic_policies(&self) -> Vec<(&str, TopicPolicy)> {
+        self.topics
+            .iter()
+            .map(|topic| (topic.name(), topic.policy()))
+            .collect()
+    }
+
+    pub fn exclude_topics_as_str_slice(&self) -> Vec<&str> {
+        self.exclude_topics.iter().map(|s| s.as_str()).collect()
     }
 }
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+
+use crate::database::{
+    DELETE_ARCHIVE_SEGMENT, DELETE_ARCHIVE_SEGMENT_KEY, DELETE_ARCHIVED_RECORDS,
+    INSERT_ARCHIVE_SEGMENT, INSERT_ARCHIVE_SEGMENT_KEYS, QUERY_ARCHIVE_SEGMENTS,
+    QUERY_ARCHIVE_SEGMENTS_WITH_KEY, QUERY_ARCHIVED_TOPICS, QUERY_EXPIRED_ARCHIVE_SEGMENTS,
+    QUERY_LAST_ARCHIVED_WINDOW_END, QUERY_NEXT_RECORD_TIMESTAMP, UPDATE_ARCHIVE_SEGMENT_DELETED,
+    UPDATE_ARCHIVE_SEGMENT_REWRITTEN,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct ArchiveSegmentRow {
+    pub id: i64,
+    pub kafka_topic: String,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub object_key: String,
+    pub row_count: i64,
+    pub bytes: i64,
+    pub sha256: Vec<u8>,
+    pub verified_at: DateTime<Utc>,
+    pub rows_deleted_at: Option<DateTime<Utc>>,
+    pub deleted_rows: Option<i64>,
+}
+
+/// A verified segment to add to the catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewArchiveSegment {
+    pub kafka_topic: String,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub object_key: String,
+    pub row_count: i64,
+    pub bytes: i64,
+    pub sha256: Vec<u8>,
+}
+
+pub async fn get_last_archived_window_end(
+    pg_pool: &PgPool,
+    topic: &str,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_LAST_ARCHIVED_WINDOW_END)
+        .bind(topic)
+        .fetch_one(pg_pool)
+        .await
+}
+
+/// Timestamp of the oldest record of the topic at or after `from`
+pub async fn get_next_record_timestamp(
+    pg_pool: &PgPool,
+    topic: &str,
+    from: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_NEXT_RECORD_TIMESTAMP)
+        .bind(topic)
+        .bind(from)
+        .fetch_one(pg_pool)
+        .await
+}
+
+pub async fn insert_archive_segment(
+    tx: &mut Transaction<'_, Postgres>,
+    segment: &NewArchiveSegment,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(INSERT_ARCHIVE_SEGMENT)
+        .bind(&segment.kafka_topic)
+        .bind(segment.window_start)
+        .bind(segment.window_end)
+        .bind(&segment.object_key)
+        .bind(segment.row_count)
+        .bind(segment.bytes)
+        .bind(&segment.sha256)
+        .fetch_one(&mut **tx)
+        .await
+}
+
+pub async fn update_archive_segment_deleted(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i64,
+    deleted_rows: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(UPDATE_ARCHIVE_SEGMENT_DELETED)
+        .bind(id)
+        .bind(deleted_rows)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_archive_segments(
+    pg_pool: &PgPool,
+    topic: &str,
+) -> Result<Vec<ArchiveSegmentRow>, sqlx::Error> {
+    sqlx::query_as(QUERY_ARCHIVE_SEGMENTS)
+        .bind(topic)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Deletes the given offsets of the topic within the window and returns the number of
+/// deleted records per partition
+pub async fn delete_archived_records(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+    window_start: DateTime<Utc>,
+    window_end: DateTime<Utc>,
+    offsets: &[(i16, i64)],
+) -> Result<Vec<(i16, i64)>, sqlx::Error> {
+    let (partitions, offsets): (Vec<i16>, Vec<i64>) = offsets.iter().copied().unzip();
+    sqlx::query_as(DELETE_ARCHIVED_RECORDS)
+        .bind(topic)
+        .bind(window_start)
+        .bind(window_end)
+        .bind(partitions)
+        .bind(offsets)
+        .fetch_all(&mut **tx)
+        .await
+}
+
+/// Indexes the key hashes of the records in the segment
+pub async fn insert_archive_segment_keys(
+    tx: &mut Transaction<'_, Postgres>,
+    segment_id: i64,
+    key_hashes: &[Vec<u8>],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(INSERT_ARCHIVE_SEGMENT_KEYS)
+        .bind(segment_id)
+        .bind(key_hashes)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Segments of the topic that hold records with the key hash, locked until the
+/// transaction ends
+pub async fn get_archive_segments_with_key(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+    key_hash: &[u8],
+) -> Result<Vec<ArchiveSegmentRow>, sqlx::Error> {
+    sqlx::query_as(QUERY_ARCHIVE_SEGMENTS_WITH_KEY)
+        .bind(topic)
+        .bind(key_hash)
+        .fetch_all(&mut **tx)
+        .await
+}
+
+/// A segment written again without some of its records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewrittenSegment {
+    pub object_key: String,
+    pub row_count: i64,
+    pub bytes: i64,
+    pub sha256: Vec<u8>,
+}
+
+pub async fn update_archive_segment_rewritten(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i64,
+    segment: &RewrittenSegment,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(UPDATE_ARCHIVE_SEGMENT_REWRITTEN)
+        .bind(id)
+        .bind(&segment.object_key)
+        .bind(segment.row_count)
+        .bind(segment.bytes)
+        .bind(&segment.sha256)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_archive_segment_key(
+    tx: &mut Transaction<'_, Postgres>,
+    segment_id: i64,
+    key_hash: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(DELETE_ARCHIVE_SEGMENT_KEY)
+        .bind(segment_id)
+        .bind(key_hash)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_archived_topics(pg_pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_ARCHIVED_TOPICS)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Segments of the topic whose window ended at or before `cutoff`
+pub async fn get_expired_archive_segments(
+    pg_pool: &PgPool,
+    topic: &str,
+    cutoff: DateTime<Utc>,
+) -> Result<Vec<ArchiveSegmentRow>, sqlx::Error> {
+    sqlx::query_as(QUERY_EXPIRED_ARCHIVE_SEGMENTS)
+        .bind(topic)
+        .bind(cutoff)
+        .fetch_all(pg_pool)
+        .await
+}
+
+pub async fn delete_archive_segment(pg_pool: &PgPool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(DELETE_ARCHIVE_SEGMENT)
+        .bind(id)
+        .execute(pg_pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::database::read_data::{DataRow, decode_rows};
+use crate::database::{
+    DELETE_CURRENT_STATE, DELETE_ERASED_CURRENT_STATE, INSERT_CURRENT_STATE_FROM_DATA,
+    QUERY_CURRENT_STATE, QUERY_TOPIC_HWMS, UPSERT_CURRENT_STATE,
+};
+use crate::encryption::data_cipher::DataCipher;
+
+/// Updates the current state with the records just stored for the partition, given as
+/// offset and timestamp, in the transaction that stored them. The timestamps limit the
+/// read to the matching partitions of the data table.
+pub async fn upsert_current_state(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+    partition: i32,
+    records: &[(i64, DateTime<Utc>)],
+) -> Result<(), sqlx::Error> {
+    let (offsets, timestamps): (Vec<i64>, Vec<DateTime<Utc>>) = records.iter().copied().unzip();
+    let (Some(from), Some(to)) = (timestamps.iter().min(), timestamps.iter().max()) else {
+        return Ok(());
+    };
+    sqlx::query(UPSERT_CURRENT_STATE)
+        .bind(topic)
+        .bind(partition)
+        .bind(offsets)
+        .bind(from)
+        .bind(to)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Removes the state of the key when its record was erased and replaces it with the
+/// latest record of the key that is still stored, if any. Called after the erased
+/// records are deleted from the data table.
+pub async fn erase_current_state(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+    record_key_hash: &[u8],
+    erased_offsets: &[(i16, i64)],
+) -> Result<(), sqlx::Error> {
+    let (partitions, offsets): (Vec<i16>, Vec<i64>) = erased_offsets.iter().copied().unzip();
+    let removed = sqlx::query(DELETE_ERASED_CURRENT_STATE)
+        .bind(topic)
+        .bind(record_key_hash)
+        .bind(partitions)
+        .bind(offsets)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+    if removed > 0 {
+        sqlx::query(INSERT_CURRENT_STATE_FROM_DATA)
+            .bind(topic)
+            .bind(record_key_hash)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Builds the current state of the topic from the stored records, for topics that had
+/// records before the policy was enabled. Returns the number of keys in the state.
+pub async fn rebuild_current_state(pg_pool: &PgPool, topic: &str) -> Result<u64, sqlx::Error> {
+    let mut tx = pg_pool.begin().await?;
+    sqlx::query(DELETE_CURRENT_STATE)
+        .bind(topic)
+        .bind(None::<Vec<u8>>)
+        .execute(&mut *tx)
+        .await?;
+    let keys = sqlx::query(INSERT_CURRENT_STATE_FROM_DATA)
+        .bind(topic)
+        .bind(None::<Vec<u8>>)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+    Ok(keys)
+}
+
+/// A read-only transaction that sees the HWMs and the current state as of its start
+pub async fn begin_snapshot(
+    pg_pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pg_pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+/// The HWM of every partition of the topic
+pub async fn get_topic_hwms(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+) -> Result<BTreeMap<i32, i64>, sqlx::Error> {
+    let hwms: Vec<(i16, i64)> = sqlx::query_as(QUERY_TOPIC_HWMS)
+        .bind(topic)
+        .fetch_all(&mut **tx)
+        .await?;
+    Ok(hwms
+        .into_iter()
+        .map(|(partition, hwm)| (partition as i32, hwm))
+        .collect())
+}
+
+/// The latest record of each key, ordered by the hash of the key, starting after the
+/// key with the given hash
+pub async fn query_current_state(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+    after_key_hash: Option<&[u8]>,
+    limit: i64,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, DataRow>(QUERY_CURRENT_STATE)
+        .bind(topic)
+        .bind(after_key_hash)
+        .bind(limit)
+        .fetch_all(&mut **tx)
+        .await?;
+    decode_rows(rows, cipher)
+}
@@ -0,0 +1,45 @@
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+
+use crate::database::{INSERT_ENCRYPTION_KEY, QUERY_ENCRYPTION_KEYS, UPDATE_WRAPPED_KEY};
+
+/// A data encryption key as stored, wrapped with a master key
+#[derive(Debug, Clone, FromRow)]
+pub struct EncryptionKeyRow {
+    pub id: i32,
+    pub master_key_id: String,
+    pub wrapped_key: Vec<u8>,
+}
+
+pub async fn get_encryption_keys(pg_pool: &PgPool) -> Result<Vec<EncryptionKeyRow>, sqlx::Error> {
+    sqlx::query_as(QUERY_ENCRYPTION_KEYS)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Inserts a wrapped key and returns its id
+pub async fn insert_encryption_key(
+    tx: &mut Transaction<'_, Postgres>,
+    master_key_id: &str,
+    wrapped_key: &[u8],
+) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(INSERT_ENCRYPTION_KEY)
+        .bind(master_key_id)
+        .bind(wrapped_key)
+        .fetch_one(&mut **tx)
+        .await
+}
+
+pub async fn update_wrapped_key(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    master_key_id: &str,
+    wrapped_key: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(UPDATE_WRAPPED_KEY)
+        .bind(id)
+        .bind(master_key_id)
+        .bind(wrapped_key)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
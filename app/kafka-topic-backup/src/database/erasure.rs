@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+
+use crate::database::key_hashes::has_missing_key_hashes;
+use crate::database::offset_gaps::OffsetRange;
+use crate::database::read_data::{DataRow, decode_rows};
+use crate::database::{
+    DELETE_RECORDS_BY_OFFSET, INSERT_ERASED_OFFSETS, INSERT_ERASURE_LOG, LOCK_ERASURE_LOG,
+    QUERY_ERASED_OFFSETS_BY_ERASURE, QUERY_ERASED_OFFSETS_IN_RANGE, QUERY_ERASED_RANGES,
+    QUERY_ERASURE_LOG, QUERY_LAST_ERASURE_HASH, QUERY_LEGACY_RECORDS_FOR_ERASURE,
+    QUERY_RECORDS_FOR_ERASURE,
+};
+use crate::encryption::data_cipher::DataCipher;
+use crate::encryption::key_hash::record_key_hash;
+
+/// One entry in the erasure log, without the id assigned by the database
+#[derive(Debug, Clone, FromRow)]
+pub struct ErasureLogEntry {
+    pub erased_at: DateTime<Utc>,
+    pub kafka_topic: String,
+    pub record_key_hash: Vec<u8>,
+    /// The header and payload field filters, with the values replaced by their hashes
+    pub filter: Option<Value>,
+    pub requested_by: String,
+    pub reason: String,
+    pub row_count: i64,
+    /// Object keys of the archived segments the records were also erased from
+    pub archive_segments: Vec<String>,
+    pub prev_hash: Vec<u8>,
+    pub entry_hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ErasureLogRow {
+    pub id: i64,
+    #[sqlx(flatten)]
+    pub entry: ErasureLogEntry,
+}
+
+/// Held until the transaction ends, so concurrent erasures are chained one after another
+pub async fn lock_erasure_log(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query(LOCK_ERASURE_LOG).execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// All records with the key in the topic, including those still only in the legacy
+/// table, locked until the transaction ends, decrypted and decompressed so the filters
+/// see the original values. Rows are found by the key hash and then compared with the
+/// full key. Fails while the topic has records without a key hash, since those would
+/// not be erased.
+pub async fn query_records_for_erasure(
+    tx: &mut Transaction<'_, Postgres>,
+    kafka_topic: &str,
+    record_key: &[u8],
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    if has_missing_key_hashes(tx, kafka_topic).await? {
+        return Err(sqlx::Error::Configuration(
+            format!(
+                "Topic {} has records without a key hash, run backfill-key-hashes first",
+                kafka_topic
+            )
+            .into(),
+        ));
+    }
+    let key_hash = record_key_hash(record_key);
+    let mut rows = sqlx::query_as::<_, DataRow>(QUERY_RECORDS_FOR_ERASURE)
+        .bind(kafka_topic)
+        .bind(&key_hash)
+        .fetch_all(&mut **tx)
+        .await?;
+    let copied: HashSet<(i16, i64)> = rows
+        .iter()
+        .map(|row| (row.kafka_partition, row.kafka_offset))
+        .collect();
+    let legacy_rows = sqlx::query_as::<_, DataRow>(QUERY_LEGACY_RECORDS_FOR_ERASURE)
+        .bind(kafka_topic)
+        .bind(&key_hash)
+        .fetch_all(&mut **tx)
+        .await?;
+    rows.extend(
+        legacy_rows
+            .into_iter()
+            .filter(|row| !copied.contains(&(row.kafka_partition, row.kafka_offset))),
+    );
+    rows.sort_by_key(|row| (row.kafka_partition, row.kafka_offset));
+    Ok(decode_rows(rows, cipher)?
+        .into_iter()
+        .filter(|row| row.record_key.as_deref() == Some(record_key))
+        .collect())
+}
+
+/// Deletes the records at the given (partition, offset) positions from the data table
+/// and the legacy table, returns the number of positions deleted
+pub async fn delete_records(
+    tx: &mut Transaction<'_, Postgres>,
+    kafka_topic: &str,
+    offsets: &[(i16, i64)],
+) -> Result<u64, sqlx::Error> {
+    let (partitions, offsets): (Vec<i16>, Vec<i64>) = offsets.iter().copied().unzip();
+    let deleted: i64 = sqlx::query_scalar(DELETE_RECORDS_BY_OFFSET)
+        .bind(kafka_topic)
+        .bind(partitions)
+        .bind(offsets)
+        .fetch_one(&mut **tx)
+        .await?;
+    Ok(deleted as u64)
+}
+
+pub async fn get_last_erasure_hash(
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_LAST_ERASURE_HASH)
+        .fetch_optional(&mut **tx)
+        .await
+}
+
+/// Inserts the entry and returns its id
+pub async fn insert_erasure_log(
+    tx: &mut Transaction<'_, Postgres>,
+    entry: &ErasureLogEntry,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(INSERT_ERASURE_LOG)
+        .bind(entry.erased_at)
+        .bind(&entry.kafka_topic)
+        .bind(&entry.record_key_hash)
+        .bind(&entry.filter)
+        .bind(&entry.requested_by)
+        .bind(&entry.reason)
+        .bind(entry.row_count)
+        .bind(&entry.archive_segments)
+        .bind(&entry.prev_hash)
+        .bind(&entry.entry_hash)
+        .fetch_one(&mut **tx)
+        .await
+}
+
+pub async fn insert_erased_offsets(
+    tx: &mut Transaction<'_, Postgres>,
+    kafka_topic: &str,
+    offsets: &[(i16, i64)],
+    erasure_id: i64,
+) -> Result<(), sqlx::Error> {
+    let (partitions, offsets): (Vec<i16>, Vec<i64>) = offsets.iter().copied().unzip();
+    sqlx::query(INSERT_ERASED_OFFSETS)
+        .bind(kafka_topic)
+        .bind(partitions)
+        .bind(offsets)
+        .bind(erasure_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// The whole erasure log in chain order
+pub async fn get_erasure_log(pg_pool: &PgPool) -> Result<Vec<ErasureLogRow>, sqlx::Error> {
+    sqlx::query_as(QUERY_ERASURE_LOG).fetch_all(pg_pool).await
+}
+
+/// The (partition, offset) positions erased by one erasure, in order
+pub async fn get_erased_offsets_by_erasure(
+    pg_pool: &PgPool,
+    erasure_id: i64,
+) -> Result<Vec<(i16, i64)>, sqlx::Error> {
+    sqlx::query_as(QUERY_ERASED_OFFSETS_BY_ERASURE)
+        .bind(erasure_id)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Erased offsets of the partition between `from` and `to`, inclusive
+pub async fn get_erased_offsets_in_range(
+    tx: &mut Transaction<'_, Postgres>,
+    kafka_topic: &str,
+    kafka_partition: i32,
+    from: i64,
+    to: i64,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_ERASED_OFFSETS_IN_RANGE)
+        .bind(kafka_topic)
+        .bind(kafka_partition)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut **tx)
+        .await
+}
+
+/// Erased offsets of the partition up to and including the HWM, as ordered ranges
+pub async fn get_erased_ranges(
+    pg_pool: &PgPool,
+    kafka_topic: &str,
+    kafka_partition: i32,
+    hwm: i64,
+) -> Result<Vec<OffsetRange>, sqlx::Error> {
+    let ranges: Vec<(i64, i64)> = sqlx::query_as(QUERY_ERASED_RANGES)
+        .bind(kafka_topic)
+        .bind(kafka_partition)
+        .bind(hwm)
+        .fetch_all(pg_pool)
+        .await?;
+    Ok(ranges
+        .into_iter()
+        .map(|(start, end)| OffsetRange { start, end })
+        .collect())
+}
@@ -1,6 +1,18 @@
-use sqlx::{Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 
-use crate::database::{INSERT_HWM, QUERY_HWM, UPDATE_HWM};
+use crate::database::{INSERT_HWM, QUERY_ALL_HWMS, QUERY_HWM, QUERY_HWM_FOR_UPDATE, UPDATE_HWM};
+
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct HwmRow {
+    pub topic: String,
+    pub partition: i16,
+    pub hwm: i64,
+    /// First offset the backup of the partition started from, `None` when not recorded
+    pub start_offset: Option<i64>,
+    /// Timestamp of the record stored at the HWM, `None` when nothing is stored there
+    pub last_timestamp: Option<DateTime<Utc>>,
+}
 
 pub async fn update_hwm(
     tx: &mut Transaction<'_, Postgres>,
@@ -9,7 +21,7 @@ pub async fn update_hwm(
     new_hwm: i64,
 ) -> Result<bool, Box<dyn std::error::Error>> {
     let result = sqlx::query(UPDATE_HWM)
-        .bind(&topic)
+        .bind(topic)
         .bind(partition)
         .bind(new_hwm)
         .execute(&mut **tx)
@@ -44,3 +56,22 @@ pub async fn get_hwm(
         .await?;
     Ok(hwm)
 }
+
+/// Reads the HWM and locks its row until the transaction ends. A concurrent writer for
+/// the same partition waits here and then sees the HWM committed by this transaction.
+pub async fn lock_hwm(
+    tx: &mut Transaction<'_, Postgres>,
+    topic: &str,
+    partition: i32,
+) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    let hwm: Option<i64> = sqlx::query_scalar(QUERY_HWM_FOR_UPDATE)
+        .bind(topic)
+        .bind(partition)
+        .fetch_optional(&mut **tx)
+        .await?;
+    Ok(hwm)
+}
+
+pub async fn get_all_hwms(pg_pool: &PgPool) -> Result<Vec<HwmRow>, sqlx::Error> {
+    sqlx::query_as(QUERY_ALL_HWMS).fetch_all(pg_pool).await
+}
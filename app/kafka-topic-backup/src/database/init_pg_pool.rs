@@ -29,6 +29,6 @@ pub async fn init_db() -> Result<PgPool, Box<dyn Error>> {
     info!("Database config: {:?}", db_config);
     let pg_pool = get_pg_pool(&db_config).await?;
     info!("Postgres pool opprettet");
-    let _ = sqlx::migrate!("./migrations").run(&pg_pool).await?;
+    sqlx::migrate!("./migrations").run(&pg_pool).await?;
     Ok(pg_pool)
 }
@@ -3,26 +3,93 @@ use serde_json::Value;
 use sqlx::Postgres;
 use sqlx::Transaction;
 
-use crate::database::INSERT_DATA;
+use crate::compression::Codec;
+use crate::database::{IMPORT_DATA_BATCH, INSERT_DATA_BATCH};
+use crate::encryption::data_cipher::DataCipher;
 
-pub async fn insert_data(
+#[derive(Debug, Clone)]
+pub struct InsertDataRow {
+    pub kafka_topic: String,
+    pub kafka_partition: i32,
+    pub kafka_offset: i64,
+    pub timestamp: DateTime<Utc>,
+    pub headers: Option<Value>,
+    pub record_key: Option<Vec<u8>>,
+    pub record_value: Option<Vec<u8>>,
+    /// Codec the value is compressed with, `None` when stored as is
+    pub value_codec: Option<Codec>,
+    /// The record had a value that was not stored, see
+    /// [`DataRow::value_omitted`](crate::database::read_data::DataRow::value_omitted)
+    pub value_omitted: bool,
+    /// Keyed hash of the key, see [`crate::encryption::key_hash::record_key_hash`]
+    pub record_key_hash: Option<Vec<u8>>,
+}
+
+/// Inserts all rows with a single statement, the rows are passed as one array per column.
+/// With a cipher the record keys and values are encrypted, the key hash is not.
+pub async fn insert_data_batch(
+    tx: &mut Transaction<'_, Postgres>,
+    rows: Vec<InsertDataRow>,
+    cipher: Option<&DataCipher>,
+) -> Result<u64, sqlx::Error> {
+    execute_data_batch(tx, INSERT_DATA_BATCH, rows, cipher).await
+}
+
+/// Like [`insert_data_batch`], but records that are already stored are skipped and not
+/// counted
+pub async fn import_data_batch(
+    tx: &mut Transaction<'_, Postgres>,
+    rows: Vec<InsertDataRow>,
+    cipher: Option<&DataCipher>,
+) -> Result<u64, sqlx::Error> {
+    execute_data_batch(tx, IMPORT_DATA_BATCH, rows, cipher).await
+}
+
+async fn execute_data_batch(
     tx: &mut Transaction<'_, Postgres>,
-    kafka_topic: &str,
-    kafka_partition: i32,
-    kafka_offset: i64,
-    timestamp: DateTime<Utc>,
-    headers: Option<Value>,
-    record_key: Vec<u8>,
-    record_value: Vec<u8>,
+    sql: &str,
+    rows: Vec<InsertDataRow>,
+    cipher: Option<&DataCipher>,
 ) -> Result<u64, sqlx::Error> {
-    let result = sqlx::query(INSERT_DATA)
-        .bind(kafka_topic)
-        .bind(kafka_partition)
-        .bind(kafka_offset)
-        .bind(timestamp)
+    let mut kafka_topics = Vec::with_capacity(rows.len());
+    let mut kafka_partitions = Vec::with_capacity(rows.len());
+    let mut kafka_offsets = Vec::with_capacity(rows.len());
+    let mut timestamps = Vec::with_capacity(rows.len());
+    let mut headers = Vec::with_capacity(rows.len());
+    let mut record_keys = Vec::with_capacity(rows.len());
+    let mut record_values = Vec::with_capacity(rows.len());
+    let mut key_ids = Vec::with_capacity(rows.len());
+    let mut value_codecs = Vec::with_capacity(rows.len());
+    let mut values_omitted = Vec::with_capacity(rows.len());
+    let mut record_key_hashes = Vec::with_capacity(rows.len());
+    for mut row in rows {
+        let key_id = cipher
+            .map(|cipher| cipher.encrypt_row(&mut row))
+            .transpose()?;
+        kafka_topics.push(row.kafka_topic);
+        kafka_partitions.push(row.kafka_partition);
+        kafka_offsets.push(row.kafka_offset);
+        timestamps.push(row.timestamp);
+        headers.push(row.headers);
+        record_keys.push(row.record_key);
+        record_values.push(row.record_value);
+        key_ids.push(key_id);
+        value_codecs.push(row.value_codec.map(|codec| codec.as_str()));
+        values_omitted.push(row.value_omitted);
+        record_key_hashes.push(row.record_key_hash);
+    }
+    let result = sqlx::query(sql)
+        .bind(kafka_topics)
+        .bind(kafka_partitions)
+        .bind(kafka_offsets)
+        .bind(timestamps)
         .bind(headers)
-        .bind(record_key)
-        .bind(record_value)
+        .bind(record_keys)
+        .bind(record_values)
+        .bind(key_ids)
+        .bind(value_codecs)
+        .bind(values_omitted)
+        .bind(record_key_hashes)
         .execute(&mut **tx)
         .await?;
     Ok(result.rows_affected())
@@ -0,0 +1,70 @@
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+
+use crate::database::read_data::DataRow;
+use crate::database::{
+    QUERY_LEGACY_MISSING_KEY_HASHES, QUERY_MISSING_KEY_HASHES, QUERY_TOPIC_MISSING_KEY_HASHES,
+    UPDATE_KEY_HASHES, UPDATE_LEGACY_KEY_HASHES,
+};
+
+/// The table a key hash backfill runs against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataTable {
+    Current,
+    Legacy,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct MissingKeyHashRow {
+    pub id: i64,
+    #[sqlx(flatten)]
+    pub row: DataRow,
+}
+
+/// The next rows after `after_id` that have a key but no key hash, in id order. The rows
+/// are returned as stored, encrypted keys are not decrypted.
+pub async fn query_missing_key_hashes(
+    pg_pool: &PgPool,
+    table: DataTable,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<MissingKeyHashRow>, sqlx::Error> {
+    let sql = match table {
+        DataTable::Current => QUERY_MISSING_KEY_HASHES,
+        DataTable::Legacy => QUERY_LEGACY_MISSING_KEY_HASHES,
+    };
+    sqlx::query_as(sql)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Sets the key hash of rows that still have none, returns the number of rows updated
+pub async fn update_key_hashes(
+    pg_pool: &PgPool,
+    table: DataTable,
+    ids: &[i64],
+    key_hashes: &[Vec<u8>],
+) -> Result<u64, sqlx::Error> {
+    let sql = match table {
+        DataTable::Current => UPDATE_KEY_HASHES,
+        DataTable::Legacy => UPDATE_LEGACY_KEY_HASHES,
+    };
+    let result = sqlx::query(sql)
+        .bind(ids)
+        .bind(key_hashes)
+        .execute(pg_pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Whether the topic has records with a key but no key hash in either data table
+pub async fn has_missing_key_hashes(
+    tx: &mut Transaction<'_, Postgres>,
+    kafka_topic: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(QUERY_TOPIC_MISSING_KEY_HASHES)
+        .bind(kafka_topic)
+        .fetch_one(&mut **tx)
+        .await
+}
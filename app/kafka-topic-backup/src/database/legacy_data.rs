@@ -0,0 +1,58 @@
+use chrono::NaiveDate;
+use sqlx::PgPool;
+
+use crate::database::{
+    COPY_LEGACY_DATA_BATCH, QUERY_LEGACY_DATA_AFTER_ID, QUERY_LEGACY_DATA_COPIED_ID,
+    QUERY_LEGACY_DATA_MONTHS,
+};
+
+/// The months of the oldest and newest record in data_v2, `None` when it is empty
+pub async fn get_legacy_data_months(
+    pg_pool: &PgPool,
+) -> Result<Option<(NaiveDate, NaiveDate)>, sqlx::Error> {
+    let (first, last): (Option<NaiveDate>, Option<NaiveDate>) =
+        sqlx::query_as(QUERY_LEGACY_DATA_MONTHS)
+            .fetch_one(pg_pool)
+            .await?;
+    Ok(first.zip(last))
+}
+
+/// The highest data_v2 id already copied, 0 when nothing is copied
+pub async fn get_copied_legacy_id(pg_pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(QUERY_LEGACY_DATA_COPIED_ID)
+        .fetch_one(pg_pool)
+        .await
+}
+
+/// Whether data_v2 has records with a higher id, a single index lookup
+pub async fn has_legacy_data_after(pg_pool: &PgPool, after_id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(QUERY_LEGACY_DATA_AFTER_ID)
+        .bind(after_id)
+        .fetch_one(pg_pool)
+        .await
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopiedBatch {
+    /// `None` when there was nothing left to copy
+    pub last_id: Option<i64>,
+    pub rows_read: i64,
+    pub rows_copied: i64,
+}
+
+pub async fn copy_legacy_data_batch(
+    pg_pool: &PgPool,
+    after_id: i64,
+    limit: i64,
+) -> Result<CopiedBatch, sqlx::Error> {
+    let (last_id, rows_read, rows_copied) = sqlx::query_as(COPY_LEGACY_DATA_BATCH)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_one(pg_pool)
+        .await?;
+    Ok(CopiedBatch {
+        last_id,
+        rows_read,
+        rows_copied,
+    })
+}
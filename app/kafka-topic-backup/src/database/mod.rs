@@ -1,7 +1,18 @@
+pub mod archive_catalog;
+pub mod current_state;
 pub mod database_config;
+pub mod encryption_keys;
+pub mod erasure;
 pub mod hwm_statements;
 pub mod init_pg_pool;
 pub mod insert_data;
+pub mod key_hashes;
+pub mod legacy_data;
+pub mod offset_gaps;
+pub mod partitions;
+pub mod purge;
+pub mod read_data;
+pub mod restore_offsets;
 pub mod sqls;
 
 // Re-export commonly used items for easier access
@@ -0,0 +1,59 @@
+use sqlx::PgPool;
+
+use crate::database::{QUERY_OFFSET_GAPS, QUERY_OFFSET_RANGE};
+
+/// An inclusive range of offsets that are not stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// Finds all offsets from 0 up to and including the HWM that are not stored for the
+/// partition, including the ranges before the first and after the last stored offset.
+pub async fn find_offset_gaps(
+    pg_pool: &PgPool,
+    topic: &str,
+    partition: i32,
+    hwm: i64,
+) -> Result<Vec<OffsetRange>, sqlx::Error> {
+    if hwm < 0 {
+        return Ok(Vec::new());
+    }
+    let (min_offset, max_offset): (Option<i64>, Option<i64>) = sqlx::query_as(QUERY_OFFSET_RANGE)
+        .bind(topic)
+        .bind(partition)
+        .bind(hwm)
+        .fetch_one(pg_pool)
+        .await?;
+    let (min_offset, max_offset) = match (min_offset, max_offset) {
+        (Some(min_offset), Some(max_offset)) => (min_offset, max_offset),
+        _ => return Ok(vec![OffsetRange { start: 0, end: hwm }]),
+    };
+
+    let mut gaps = Vec::new();
+    if min_offset > 0 {
+        gaps.push(OffsetRange {
+            start: 0,
+            end: min_offset - 1,
+        });
+    }
+    let between: Vec<(i64, i64)> = sqlx::query_as(QUERY_OFFSET_GAPS)
+        .bind(topic)
+        .bind(partition)
+        .bind(hwm)
+        .fetch_all(pg_pool)
+        .await?;
+    gaps.extend(
+        between
+            .into_iter()
+            .map(|(start, end)| OffsetRange { start, end }),
+    );
+    if max_offset < hwm {
+        gaps.push(OffsetRange {
+            start: max_offset + 1,
+            end: hwm,
+        });
+    }
+    Ok(gaps)
+}
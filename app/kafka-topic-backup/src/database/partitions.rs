@@ -0,0 +1,139 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::database::{
+    DATA_DEFAULT_PARTITION, DATA_TABLE, DELETE_CURRENT_STATE_IN_RANGE, LOG_PURGED_RANGE,
+    QUERY_DATA_PARTITIONS, QUERY_DEFAULT_PARTITION_MONTHS, QUERY_TOPICS_IN_RANGE,
+};
+
+/// The first day of the month the timestamp is in, in UTC
+pub fn month_of(timestamp: DateTime<Utc>) -> NaiveDate {
+    timestamp
+        .date_naive()
+        .with_day(1)
+        .expect("Every month has a first day")
+}
+
+pub fn next_month(month: NaiveDate) -> NaiveDate {
+    month + Months::new(1)
+}
+
+/// Start of the month, inclusive, and start of the next month, exclusive
+pub fn month_bounds(month: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = |day: NaiveDate| day.and_hms_opt(0, 0, 0).expect("Valid time").and_utc();
+    (start(month), start(next_month(month)))
+}
+
+pub fn partition_name(month: NaiveDate) -> String {
+    format!("{}_p{}", DATA_TABLE, month.format("%Y%m"))
+}
+
+/// The month of a monthly partition, `None` for the default partition
+pub fn partition_month(name: &str) -> Option<NaiveDate> {
+    let month = name.strip_prefix(DATA_TABLE)?.strip_prefix("_p")?;
+    NaiveDate::parse_from_str(&format!("{}01", month), "%Y%m%d").ok()
+}
+
+/// Months with a partition, oldest first
+pub async fn get_partition_months(pg_pool: &PgPool) -> Result<Vec<NaiveDate>, sqlx::Error> {
+    let names: Vec<String> = sqlx::query_scalar(QUERY_DATA_PARTITIONS)
+        .fetch_all(pg_pool)
+        .await?;
+    let mut months: Vec<NaiveDate> = names
+        .iter()
+        .filter_map(|name| partition_month(name))
+        .collect();
+    months.sort();
+    Ok(months)
+}
+
+/// Months of the records that ended up in the default partition
+pub async fn get_default_partition_months(pg_pool: &PgPool) -> Result<Vec<NaiveDate>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_DEFAULT_PARTITION_MONTHS)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Creates the partition for the month and moves its records out of the default
+/// partition, returning the number of moved records. A partition cannot be attached
+/// while the default partition has records for it, so the table is created detached.
+pub async fn create_month_partition(
+    pg_pool: &PgPool,
+    month: NaiveDate,
+) -> Result<u64, sqlx::Error> {
+    let name = partition_name(month);
+    let (from, to) = month_bounds(month);
+    let mut tx = pg_pool.begin().await?;
+    sqlx::query(&format!(
+        "CREATE TABLE {} (LIKE {} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)",
+        name, DATA_TABLE
+    ))
+    .execute(&mut *tx)
+    .await?;
+    let moved = sqlx::query(&format!(
+        "WITH moved AS (DELETE FROM {} WHERE timestamp >= $1 AND timestamp < $2 RETURNING *) INSERT INTO {} SELECT * FROM moved",
+        DATA_DEFAULT_PARTITION, name
+    ))
+    .bind(from)
+    .bind(to)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query(&format!(
+        "ALTER TABLE {} ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+        DATA_TABLE,
+        name,
+        from.to_rfc3339(),
+        to.to_rfc3339()
+    ))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(moved)
+}
+
+/// Blocks writes to the data table until the transaction ends, reads can continue
+/// until the partition is dropped
+pub async fn lock_data_table(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("LOCK TABLE {} IN SHARE MODE", DATA_TABLE))
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// The topics with records in the month
+pub async fn get_topics_in_month(
+    tx: &mut Transaction<'_, Postgres>,
+    month: NaiveDate,
+) -> Result<Vec<String>, sqlx::Error> {
+    let (from, to) = month_bounds(month);
+    sqlx::query_scalar(QUERY_TOPICS_IN_RANGE)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut **tx)
+        .await
+}
+
+/// Logs the records of the partition in the purge log, like the retention job does for
+/// the records it deletes, removes them from the current state and drops the partition.
+/// Returns the number of dropped records per topic and partition.
+pub async fn drop_month_partition(
+    tx: &mut Transaction<'_, Postgres>,
+    month: NaiveDate,
+) -> Result<Vec<(String, i16, i64)>, sqlx::Error> {
+    let (from, to) = month_bounds(month);
+    let dropped = sqlx::query_as(LOG_PURGED_RANGE)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut **tx)
+        .await?;
+    sqlx::query(DELETE_CURRENT_STATE_IN_RANGE)
+        .bind(from)
+        .bind(to)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(&format!("DROP TABLE {}", partition_name(month)))
+        .execute(&mut **tx)
+        .await?;
+    Ok(dropped)
+}
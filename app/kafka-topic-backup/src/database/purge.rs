@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::database::offset_gaps::OffsetRange;
+use crate::database::{
+    PURGE_DATA_BATCH, QUERY_HWM_TOPICS, QUERY_OLDEST_TIMESTAMP, QUERY_PURGED_RANGES,
+};
+
+/// Topics with a HWM, which are all topics that can have stored records
+pub async fn get_hwm_topics(pg_pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_HWM_TOPICS)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Deletes up to `limit` of the oldest records of the topic with a timestamp before
+/// `cutoff` and returns the number of deleted records per partition
+pub async fn purge_data_batch(
+    pg_pool: &PgPool,
+    topic: &str,
+    cutoff: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<(i16, i64)>, sqlx::Error> {
+    sqlx::query_as(PURGE_DATA_BATCH)
+        .bind(topic)
+        .bind(cutoff)
+        .bind(limit)
+        .fetch_all(pg_pool)
+        .await
+}
+
+pub async fn get_oldest_timestamp(
+    pg_pool: &PgPool,
+    topic: &str,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_OLDEST_TIMESTAMP)
+        .bind(topic)
+        .fetch_one(pg_pool)
+        .await
+}
+
+/// The offsets removed by retention or archiving up to and including the HWM, as ordered
+/// ranges. Each purged batch is logged with its lowest and highest offset, overlapping
+/// and adjacent batches are merged.
+pub async fn get_purged_ranges(
+    pg_pool: &PgPool,
+    topic: &str,
+    partition: i32,
+    hwm: i64,
+) -> Result<Vec<OffsetRange>, sqlx::Error> {
+    let ranges: Vec<(i64, i64)> = sqlx::query_as(QUERY_PURGED_RANGES)
+        .bind(topic)
+        .bind(partition)
+        .bind(hwm)
+        .fetch_all(pg_pool)
+        .await?;
+    let mut merged: Vec<OffsetRange> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.end + 1 => last.end = last.end.max(end),
+            _ => merged.push(OffsetRange { start, end }),
+        }
+    }
+    Ok(merged)
+}
@@ -0,0 +1,227 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+
+use crate::compression::Codec;
+use crate::database::{
+    QUERY_DATA_BATCH, QUERY_RECORDS_BY_KEY_HASH, QUERY_RECORDS_BY_OFFSET,
+    QUERY_RECORDS_BY_TIMESTAMP, QUERY_TOPIC_PARTITIONS,
+};
+use crate::encryption::data_cipher::{DataCipher, decrypt_rows};
+use crate::encryption::key_hash::record_key_hash;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct DataRow {
+    pub kafka_topic: String,
+    pub kafka_partition: i16,
+    pub kafka_offset: i64,
+    pub timestamp: DateTime<Utc>,
+    pub headers: Option<Value>,
+    pub record_key: Option<Vec<u8>>,
+    pub record_value: Option<Vec<u8>>,
+    /// The data encryption key of the key and value, `None` when stored in plaintext
+    pub key_id: Option<i32>,
+    /// Codec the value is compressed with, `None` when stored as is
+    pub value_codec: Option<String>,
+    /// The value was left out by a `keys_only` policy, so a missing value is not a
+    /// tombstone and the record cannot be replayed
+    pub value_omitted: bool,
+}
+
+/// Position in a topic, rows are read in (partition, offset) order starting after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataPosition {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl DataPosition {
+    pub fn start() -> Self {
+        DataPosition {
+            partition: -1,
+            offset: -1,
+        }
+    }
+
+    pub fn of(row: &DataRow) -> Self {
+        DataPosition {
+            partition: row.kafka_partition as i32,
+            offset: row.kafka_offset,
+        }
+    }
+}
+
+pub async fn read_data_batch(
+    pg_pool: &PgPool,
+    kafka_topic: &str,
+    partitions: Option<&[i32]>,
+    after: DataPosition,
+    limit: i64,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, DataRow>(QUERY_DATA_BATCH)
+        .bind(kafka_topic)
+        .bind(partitions)
+        .bind(after.partition)
+        .bind(after.offset)
+        .bind(limit)
+        .fetch_all(pg_pool)
+        .await?;
+    decode_rows(rows, cipher)
+}
+
+/// The partitions of the topic with stored records or a HWM, in order
+pub async fn get_topic_partitions(
+    pg_pool: &PgPool,
+    kafka_topic: &str,
+) -> Result<Vec<i32>, sqlx::Error> {
+    sqlx::query_scalar(QUERY_TOPIC_PARTITIONS)
+        .bind(kafka_topic)
+        .fetch_all(pg_pool)
+        .await
+}
+
+/// Decrypts and then decompresses the rows, so callers only see the original key and
+/// value. Every read of `data_v3` rows goes through here.
+pub fn decode_rows(
+    rows: Vec<DataRow>,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    decrypt_rows(rows, cipher)?
+        .into_iter()
+        .map(decompress_row)
+        .collect()
+}
+
+fn decompress_row(mut row: DataRow) -> Result<DataRow, sqlx::Error> {
+    let Some(codec) = row.value_codec.take() else {
+        return Ok(row);
+    };
+    let codec: Codec = codec
+        .parse()
+        .map_err(|e: String| sqlx::Error::Decode(e.into()))?;
+    row.record_value = row
+        .record_value
+        .as_deref()
+        .map(|value| codec.decompress(value))
+        .transpose()
+        .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+    Ok(row)
+}
+
+/// Filters for browsing stored records, all bounds are optional
+#[derive(Debug, Clone, Default)]
+pub struct RecordQuery {
+    pub kafka_topic: String,
+    pub kafka_partition: Option<i32>,
+    pub from_offset: Option<i64>,
+    pub to_offset: Option<i64>,
+    pub from_timestamp: Option<DateTime<Utc>>,
+    pub to_timestamp: Option<DateTime<Utc>>,
+}
+
+impl RecordQuery {
+    /// Records are ordered by timestamp when a timestamp range is given, otherwise by
+    /// partition and offset.
+    pub fn ordered_by_timestamp(&self) -> bool {
+        self.from_timestamp.is_some() || self.to_timestamp.is_some()
+    }
+}
+
+/// Position after the last returned record, in the order used by the query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordCursor {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl RecordCursor {
+    pub fn start() -> Self {
+        RecordCursor {
+            timestamp: None,
+            partition: -1,
+            offset: -1,
+        }
+    }
+
+    pub fn after(row: &DataRow, query: &RecordQuery) -> Self {
+        RecordCursor {
+            timestamp: query.ordered_by_timestamp().then_some(row.timestamp),
+            partition: row.kafka_partition as i32,
+            offset: row.kafka_offset,
+        }
+    }
+}
+
+pub async fn query_records(
+    pg_pool: &PgPool,
+    query: &RecordQuery,
+    cursor: RecordCursor,
+    limit: i64,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    let sql = if query.ordered_by_timestamp() {
+        QUERY_RECORDS_BY_TIMESTAMP
+    } else {
+        QUERY_RECORDS_BY_OFFSET
+    };
+    let rows = sqlx::query_as::<_, DataRow>(sql)
+        .bind(&query.kafka_topic)
+        .bind(query.kafka_partition)
+        .bind(query.from_offset)
+        .bind(query.to_offset)
+        .bind(query.from_timestamp)
+        .bind(query.to_timestamp)
+        .bind(cursor.timestamp)
+        .bind(cursor.partition)
+        .bind(cursor.offset)
+        .bind(limit)
+        .fetch_all(pg_pool)
+        .await?;
+    decode_rows(rows, cipher)
+}
+
+/// All records with the given key in a topic, ordered by timestamp, partition and offset.
+/// Rows are found by the key hash and then compared with the full key. Pages of hash
+/// matches are read until `limit` rows with the full key are found, so rows with a
+/// colliding hash never make a page come back short.
+pub async fn query_records_by_key(
+    pg_pool: &PgPool,
+    kafka_topic: &str,
+    record_key: &[u8],
+    mut cursor: RecordCursor,
+    limit: i64,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    let key_hash = record_key_hash(record_key);
+    let mut matching = Vec::new();
+    loop {
+        let rows = sqlx::query_as::<_, DataRow>(QUERY_RECORDS_BY_KEY_HASH)
+            .bind(kafka_topic)
+            .bind(&key_hash)
+            .bind(cursor.timestamp)
+            .bind(cursor.partition)
+            .bind(cursor.offset)
+            .bind(limit)
+            .fetch_all(pg_pool)
+            .await?;
+        let exhausted = (rows.len() as i64) < limit;
+        for row in decode_rows(rows, cipher)? {
+            cursor = RecordCursor {
+                timestamp: Some(row.timestamp),
+                partition: row.kafka_partition as i32,
+                offset: row.kafka_offset,
+            };
+            if row.record_key.as_deref() == Some(record_key) {
+                matching.push(row);
+                if matching.len() as i64 == limit {
+                    return Ok(matching);
+                }
+            }
+        }
+        if exhausted {
+            return Ok(matching);
+        }
+    }
+}
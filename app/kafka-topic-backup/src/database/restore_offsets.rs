@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::database::{
+    DELETE_RESTORE_OFFSET_MAPPING, INSERT_RESTORE_OFFSET_MAPPING, QUERY_RESTORE_OFFSET_MAPPING,
+};
+
+/// Where a restored record ended up in the target topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffsetMapping {
+    pub source_partition: i32,
+    pub source_offset: i64,
+    pub target_partition: i32,
+    pub target_offset: i64,
+}
+
+pub async fn insert_offset_mappings(
+    pg_pool: &PgPool,
+    source_topic: &str,
+    target_topic: &str,
+    mappings: &[OffsetMapping],
+) -> Result<(), sqlx::Error> {
+    if mappings.is_empty() {
+        return Ok(());
+    }
+    let mut source_partitions = Vec::with_capacity(mappings.len());
+    let mut source_offsets = Vec::with_capacity(mappings.len());
+    let mut target_partitions = Vec::with_capacity(mappings.len());
+    let mut target_offsets = Vec::with_capacity(mappings.len());
+    for mapping in mappings {
+        source_partitions.push(mapping.source_partition as i16);
+        source_offsets.push(mapping.source_offset);
+        target_partitions.push(mapping.target_partition as i16);
+        target_offsets.push(mapping.target_offset);
+    }
+    sqlx::query(INSERT_RESTORE_OFFSET_MAPPING)
+        .bind(source_topic)
+        .bind(target_topic)
+        .bind(source_partitions)
+        .bind(source_offsets)
+        .bind(target_partitions)
+        .bind(target_offsets)
+        .execute(pg_pool)
+        .await?;
+    Ok(())
+}
+
+/// Removes the mappings of an earlier restore of the partitions from the source to the
+/// target topic, all partitions when `partitions` is `None`
+pub async fn delete_offset_mappings(
+    pg_pool: &PgPool,
+    source_topic: &str,
+    target_topic: &str,
+    partitions: Option<&[i32]>,
+) -> Result<u64, sqlx::Error> {
+    let partitions: Option<Vec<i16>> =
+        partitions.map(|partitions| partitions.iter().map(|p| *p as i16).collect());
+    let result = sqlx::query(DELETE_RESTORE_OFFSET_MAPPING)
+        .bind(target_topic)
+        .bind(source_topic)
+        .bind(partitions)
+        .execute(pg_pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Translates a position committed against the source partition, the offset of the next
+/// record to read, to the target topic. That is the mapping of the first restored record
+/// at or after the offset, `None` when every restored record is before it and the
+/// consumer should start at the end of the target partition.
+pub async fn translate_position(
+    pg_pool: &PgPool,
+    source_topic: &str,
+    target_topic: &str,
+    partition: i32,
+    offset: i64,
+) -> Result<Option<OffsetMapping>, sqlx::Error> {
+    let mapping: Option<(i16, i64, i16, i64)> = sqlx::query_as(QUERY_RESTORE_OFFSET_MAPPING)
+        .bind(target_topic)
+        .bind(source_topic)
+        .bind(partition as i16)
+        .bind(offset)
+        .fetch_optional(pg_pool)
+        .await?;
+    Ok(mapping.map(
+        |(source_partition, source_offset, target_partition, target_offset)| OffsetMapping {
+            source_partition: source_partition as i32,
+            source_offset,
+            target_partition: target_partition as i32,
+            target_offset,
+        },
+    ))
+}
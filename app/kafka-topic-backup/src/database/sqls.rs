@@ -1,21 +1,149 @@
 macro_rules! data_table {
+    () => {
+        "data_v3"
+    };
+}
+/// The unpartitioned table used before data_v3, read when migrating. Erasures delete
+/// from it as well, so erased records are not copied to data_v3 later.
+macro_rules! legacy_data_table {
     () => {
         "data_v2"
     };
 }
+macro_rules! data_columns {
+    () => {
+        "kafka_topic, kafka_partition, kafka_offset, timestamp, headers, record_key, record_value, key_id, value_codec, value_omitted"
+    };
+}
 macro_rules! hwm_table {
     () => {
         "hwm"
     };
 }
+macro_rules! purge_log_table {
+    () => {
+        "purge_log"
+    };
+}
+macro_rules! encryption_keys_table {
+    () => {
+        "encryption_keys"
+    };
+}
+macro_rules! archive_segments_table {
+    () => {
+        "archive_segments"
+    };
+}
+macro_rules! archive_segment_keys_table {
+    () => {
+        "archive_segment_keys"
+    };
+}
+macro_rules! archive_segment_columns {
+    () => {
+        "id, kafka_topic, window_start, window_end, object_key, row_count, bytes, sha256, verified_at, rows_deleted_at, deleted_rows"
+    };
+}
+macro_rules! current_state_table {
+    () => {
+        "current_state"
+    };
+}
+macro_rules! restore_offset_mapping_table {
+    () => {
+        "restore_offset_mapping"
+    };
+}
+macro_rules! erasure_log_table {
+    () => {
+        "erasure_log"
+    };
+}
+macro_rules! erased_offsets_table {
+    () => {
+        "erased_offsets"
+    };
+}
 
-pub const INSERT_DATA: &str = concat!(
-    "INSERT INTO ",
+macro_rules! insert_data_batch {
+    () => {
+        concat!(
+            "INSERT INTO ",
+            data_table!(),
+            " (",
+            data_columns!(),
+            ", record_key_hash",
+            ") SELECT * FROM UNNEST(",
+            "$1::VARCHAR[], $2::INTEGER[], $3::BIGINT[], ",
+            "$4::TIMESTAMPTZ[], $5::JSONB[], $6::BYTEA[], $7::BYTEA[], $8::INTEGER[], $9::VARCHAR[], $10::BOOLEAN[], $11::BYTEA[])"
+        )
+    };
+}
+
+pub const INSERT_DATA_BATCH: &str = insert_data_batch!();
+
+/// Records that are already stored are skipped, so an import can be repeated
+pub const IMPORT_DATA_BATCH: &str = concat!(insert_data_batch!(), " ON CONFLICT DO NOTHING");
+
+pub const QUERY_DATA_BATCH: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
     data_table!(),
-    " (",
-    "kafka_topic, kafka_partition, kafka_offset, ",
-    "timestamp, headers, record_key, record_value",
-    ") VALUES ($1, $2, $3, $4, $5, $6, $7)"
+    " WHERE kafka_topic = $1 AND ($2::INTEGER[] IS NULL OR kafka_partition = ANY($2))",
+    " AND (kafka_partition, kafka_offset) > ($3, $4)",
+    " ORDER BY kafka_partition, kafka_offset LIMIT $5"
+);
+
+/// The partitions of a topic with stored records, including records still only in the
+/// legacy table, and those with a HWM
+pub const QUERY_TOPIC_PARTITIONS: &str = concat!(
+    "SELECT kafka_partition::INTEGER FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 UNION SELECT kafka_partition::INTEGER FROM ",
+    legacy_data_table!(),
+    " WHERE kafka_topic = $1 UNION SELECT partition::INTEGER FROM ",
+    hwm_table!(),
+    " WHERE topic = $1 ORDER BY 1"
+);
+
+pub const QUERY_RECORDS_BY_OFFSET: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND ($2::INTEGER IS NULL OR kafka_partition = $2)",
+    " AND ($3::BIGINT IS NULL OR kafka_offset >= $3)",
+    " AND ($4::BIGINT IS NULL OR kafka_offset <= $4)",
+    " AND ($5::TIMESTAMPTZ IS NULL OR timestamp >= $5)",
+    " AND ($6::TIMESTAMPTZ IS NULL OR timestamp < $6)",
+    " AND (kafka_partition, kafka_offset) > ($8, $9)",
+    " ORDER BY kafka_partition, kafka_offset LIMIT $10"
+);
+
+pub const QUERY_RECORDS_BY_TIMESTAMP: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND ($2::INTEGER IS NULL OR kafka_partition = $2)",
+    " AND ($3::BIGINT IS NULL OR kafka_offset >= $3)",
+    " AND ($4::BIGINT IS NULL OR kafka_offset <= $4)",
+    " AND ($5::TIMESTAMPTZ IS NULL OR timestamp >= $5)",
+    " AND ($6::TIMESTAMPTZ IS NULL OR timestamp < $6)",
+    " AND ($7::TIMESTAMPTZ IS NULL OR (timestamp, kafka_partition, kafka_offset) > ($7, $8, $9))",
+    " ORDER BY timestamp, kafka_partition, kafka_offset LIMIT $10"
+);
+
+pub const QUERY_RECORDS_BY_KEY_HASH: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND record_key_hash = $2",
+    " AND ($3::TIMESTAMPTZ IS NULL OR (timestamp, kafka_partition, kafka_offset) > ($3, $4, $5))",
+    " ORDER BY timestamp, kafka_partition, kafka_offset LIMIT $6"
 );
 
 pub const QUERY_HWM: &str = concat!(
@@ -24,11 +152,19 @@ pub const QUERY_HWM: &str = concat!(
     " WHERE topic = $1 AND partition = $2"
 );
 
+/// Locks the HWM row, so batches for the same partition are stored one at a time
+pub const QUERY_HWM_FOR_UPDATE: &str = concat!(
+    "SELECT hwm FROM ",
+    hwm_table!(),
+    " WHERE topic = $1 AND partition = $2 FOR UPDATE"
+);
+
+/// The HWM a partition starts from, the backup of the partition starts at the next offset
 pub const INSERT_HWM: &str = concat!(
     "INSERT INTO ",
     hwm_table!(),
-    " (topic, partition, hwm) ",
-    "VALUES ($1, $2, $3)"
+    " (topic, partition, hwm, start_offset) ",
+    "VALUES ($1, $2, $3, $3 + 1)"
 );
 
 pub const UPDATE_HWM: &str = concat!(
@@ -36,3 +172,524 @@ pub const UPDATE_HWM: &str = concat!(
     hwm_table!(),
     " SET hwm = $3 WHERE topic = $1 AND partition = $2 AND hwm < $3"
 );
+
+pub const QUERY_ALL_HWMS: &str = concat!(
+    "SELECT h.topic, h.partition, h.hwm, h.start_offset, d.timestamp AS last_timestamp FROM ",
+    hwm_table!(),
+    " h LEFT JOIN ",
+    data_table!(),
+    " d ON d.kafka_topic = h.topic AND d.kafka_partition = h.partition AND d.kafka_offset = h.hwm",
+    " ORDER BY h.topic, h.partition"
+);
+
+pub const QUERY_OFFSET_RANGE: &str = concat!(
+    "SELECT MIN(kafka_offset), MAX(kafka_offset) FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND kafka_partition = $2 AND kafka_offset <= $3"
+);
+
+pub const QUERY_OFFSET_GAPS: &str = concat!(
+    "SELECT gap_start, gap_end FROM (",
+    "SELECT kafka_offset + 1 AS gap_start,",
+    " LEAD(kafka_offset) OVER (ORDER BY kafka_offset) - 1 AS gap_end FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND kafka_partition = $2 AND kafka_offset <= $3",
+    ") AS offsets WHERE gap_end >= gap_start ORDER BY gap_start"
+);
+
+pub const QUERY_HWM_TOPICS: &str = concat!(
+    "SELECT DISTINCT topic FROM ",
+    hwm_table!(),
+    " ORDER BY topic"
+);
+
+/// Deletes up to $3 rows older than $2 and logs the deleted ranges per partition in the
+/// same statement, so the purge log is always in sync with what was deleted. Deleted
+/// records are removed from the current state as well.
+pub const PURGE_DATA_BATCH: &str = concat!(
+    "WITH deleted AS (DELETE FROM ",
+    data_table!(),
+    " WHERE id IN (SELECT id FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND timestamp < $2 ORDER BY timestamp LIMIT $3)",
+    " RETURNING kafka_partition, kafka_offset, timestamp),",
+    " state AS (DELETE FROM ",
+    current_state_table!(),
+    " s USING deleted d WHERE s.kafka_topic = $1 AND s.kafka_partition = d.kafka_partition",
+    " AND s.kafka_offset = d.kafka_offset)",
+    " INSERT INTO ",
+    purge_log_table!(),
+    " (kafka_topic, kafka_partition, cutoff, row_count, min_offset, max_offset, min_timestamp, max_timestamp)",
+    " SELECT $1, kafka_partition, $2, COUNT(*), MIN(kafka_offset), MAX(kafka_offset), MIN(timestamp), MAX(timestamp)",
+    " FROM deleted GROUP BY kafka_partition",
+    " RETURNING kafka_partition, row_count"
+);
+
+pub const QUERY_OLDEST_TIMESTAMP: &str = concat!(
+    "SELECT MIN(timestamp) FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1"
+);
+
+/// The offset ranges removed by retention and archiving up to $3, ordered by their start
+pub const QUERY_PURGED_RANGES: &str = concat!(
+    "SELECT min_offset, max_offset FROM ",
+    purge_log_table!(),
+    " WHERE kafka_topic = $1 AND kafka_partition = $2 AND min_offset <= $3",
+    " ORDER BY min_offset, max_offset"
+);
+
+/// Serializes erasures so that every entry is chained to the one before it
+pub const LOCK_ERASURE_LOG: &str = concat!(
+    "LOCK TABLE ",
+    erasure_log_table!(),
+    " IN SHARE ROW EXCLUSIVE MODE"
+);
+
+pub const QUERY_RECORDS_FOR_ERASURE: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND record_key_hash = $2",
+    " ORDER BY kafka_partition, kafka_offset FOR UPDATE"
+);
+
+/// The records of the key that are not yet copied from the legacy table are erased too
+pub const QUERY_LEGACY_RECORDS_FOR_ERASURE: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
+    legacy_data_table!(),
+    " WHERE kafka_topic = $1 AND record_key_hash = $2",
+    " ORDER BY kafka_partition, kafka_offset FOR UPDATE"
+);
+
+/// Deletes the positions from both the data table and the legacy table and returns the
+/// number of positions deleted, a record copied to data_v3 is counted once
+pub const DELETE_RECORDS_BY_OFFSET: &str = concat!(
+    "WITH positions AS (SELECT * FROM UNNEST($2::SMALLINT[], $3::BIGINT[])),",
+    " deleted AS (DELETE FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND (kafka_partition, kafka_offset) IN (SELECT * FROM positions)",
+    " RETURNING kafka_partition, kafka_offset),",
+    " legacy_deleted AS (DELETE FROM ",
+    legacy_data_table!(),
+    " WHERE kafka_topic = $1 AND (kafka_partition, kafka_offset) IN (SELECT * FROM positions)",
+    " RETURNING kafka_partition, kafka_offset)",
+    " SELECT COUNT(*) FROM (SELECT * FROM deleted UNION SELECT * FROM legacy_deleted) AS erased"
+);
+
+pub const QUERY_LAST_ERASURE_HASH: &str = concat!(
+    "SELECT entry_hash FROM ",
+    erasure_log_table!(),
+    " ORDER BY id DESC LIMIT 1"
+);
+
+pub const INSERT_ERASURE_LOG: &str = concat!(
+    "INSERT INTO ",
+    erasure_log_table!(),
+    " (erased_at, kafka_topic, record_key_hash, filter, requested_by, reason, row_count, archive_segments, prev_hash, entry_hash)",
+    " VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id"
+);
+
+pub const INSERT_ERASED_OFFSETS: &str = concat!(
+    "INSERT INTO ",
+    erased_offsets_table!(),
+    " (kafka_topic, kafka_partition, kafka_offset, erasure_id)",
+    " SELECT $1, partition, kafka_offset, $4 FROM UNNEST($2::SMALLINT[], $3::BIGINT[])",
+    " AS erased (partition, kafka_offset)"
+);
+
+pub const QUERY_ERASURE_LOG: &str = concat!(
+    "SELECT id, erased_at, kafka_topic, record_key_hash, filter, requested_by, reason,",
+    " row_count, archive_segments, prev_hash, entry_hash FROM ",
+    erasure_log_table!(),
+    " ORDER BY id"
+);
+
+pub const QUERY_ERASED_OFFSETS_BY_ERASURE: &str = concat!(
+    "SELECT kafka_partition, kafka_offset FROM ",
+    erased_offsets_table!(),
+    " WHERE erasure_id = $1 ORDER BY kafka_partition, kafka_offset"
+);
+
+pub const QUERY_ERASED_OFFSETS_IN_RANGE: &str = concat!(
+    "SELECT kafka_offset FROM ",
+    erased_offsets_table!(),
+    " WHERE kafka_topic = $1 AND kafka_partition = $2 AND kafka_offset BETWEEN $3 AND $4"
+);
+
+/// Consecutive erased offsets collapsed into ranges, an offset minus its row number is
+/// the same for every offset in a run
+pub const QUERY_ERASED_RANGES: &str = concat!(
+    "SELECT MIN(kafka_offset), MAX(kafka_offset) FROM (",
+    "SELECT kafka_offset, kafka_offset - ROW_NUMBER() OVER (ORDER BY kafka_offset) AS run FROM ",
+    erased_offsets_table!(),
+    " WHERE kafka_topic = $1 AND kafka_partition = $2 AND kafka_offset <= $3",
+    ") AS erased GROUP BY run ORDER BY MIN(kafka_offset)"
+);
+
+pub const QUERY_ENCRYPTION_KEYS: &str = concat!(
+    "SELECT id, master_key_id, wrapped_key FROM ",
+    encryption_keys_table!(),
+    " ORDER BY id"
+);
+
+pub const INSERT_ENCRYPTION_KEY: &str = concat!(
+    "INSERT INTO ",
+    encryption_keys_table!(),
+    " (master_key_id, wrapped_key) VALUES ($1, $2) RETURNING id"
+);
+
+pub const UPDATE_WRAPPED_KEY: &str = concat!(
+    "UPDATE ",
+    encryption_keys_table!(),
+    " SET master_key_id = $2, wrapped_key = $3 WHERE id = $1"
+);
+
+/// Name of the data table, for DDL that cannot be a constant
+pub const DATA_TABLE: &str = data_table!();
+
+pub const DATA_DEFAULT_PARTITION: &str = concat!(data_table!(), "_default");
+
+pub const QUERY_DATA_PARTITIONS: &str = concat!(
+    "SELECT c.relname::TEXT FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid",
+    " WHERE i.inhparent = '",
+    data_table!(),
+    "'::regclass ORDER BY c.relname"
+);
+
+pub const QUERY_DEFAULT_PARTITION_MONTHS: &str = concat!(
+    "SELECT DISTINCT DATE_TRUNC('month', timestamp AT TIME ZONE 'UTC')::DATE AS month FROM ",
+    data_table!(),
+    "_default ORDER BY month"
+);
+
+/// The topics of $1 with records from $2 up to $3, one index lookup per topic
+pub const QUERY_TOPICS_IN_RANGE: &str = concat!(
+    "SELECT DISTINCT kafka_topic FROM ",
+    data_table!(),
+    " WHERE timestamp >= $1 AND timestamp < $2 ORDER BY kafka_topic"
+);
+
+/// Logs the records of a month in the purge log before its partition is dropped, $2 is
+/// the end of the month and used as the cutoff
+pub const LOG_PURGED_RANGE: &str = concat!(
+    "INSERT INTO ",
+    purge_log_table!(),
+    " (kafka_topic, kafka_partition, cutoff, row_count, min_offset, max_offset, min_timestamp, max_timestamp)",
+    " SELECT kafka_topic, kafka_partition, $2, COUNT(*), MIN(kafka_offset), MAX(kafka_offset), MIN(timestamp), MAX(timestamp)",
+    " FROM ",
+    data_table!(),
+    " WHERE timestamp >= $1 AND timestamp < $2 GROUP BY kafka_topic, kafka_partition",
+    " RETURNING kafka_topic, kafka_partition, row_count"
+);
+
+pub const QUERY_LEGACY_DATA_MONTHS: &str = concat!(
+    "SELECT DATE_TRUNC('month', MIN(timestamp) AT TIME ZONE 'UTC')::DATE,",
+    " DATE_TRUNC('month', MAX(timestamp) AT TIME ZONE 'UTC')::DATE FROM ",
+    legacy_data_table!()
+);
+
+/// Where to resume copying, the copied ids are kept and new records get higher ids
+pub const QUERY_LEGACY_DATA_COPIED_ID: &str = concat!(
+    "SELECT COALESCE(MAX(id), 0) FROM ",
+    data_table!(),
+    " WHERE id <= (SELECT COALESCE(MAX(id), 0) FROM ",
+    legacy_data_table!(),
+    ")"
+);
+
+pub const QUERY_LEGACY_DATA_AFTER_ID: &str = concat!(
+    "SELECT EXISTS (SELECT 1 FROM ",
+    legacy_data_table!(),
+    " WHERE id > $1)"
+);
+
+/// Copies the next $2 rows after id $1 and returns the last id read, the number of rows
+/// read and the number of rows copied. Erased records are not copied, and neither are
+/// records that are already there.
+pub const COPY_LEGACY_DATA_BATCH: &str = concat!(
+    "WITH batch AS (SELECT id, ",
+    data_columns!(),
+    ", record_key_hash FROM ",
+    legacy_data_table!(),
+    " WHERE id > $1 ORDER BY id LIMIT $2),",
+    " copied AS (INSERT INTO ",
+    data_table!(),
+    " (id, ",
+    data_columns!(),
+    ", record_key_hash) SELECT * FROM batch b WHERE NOT EXISTS (SELECT 1 FROM ",
+    erased_offsets_table!(),
+    " e WHERE e.kafka_topic = b.kafka_topic AND e.kafka_partition = b.kafka_partition",
+    " AND e.kafka_offset = b.kafka_offset) ON CONFLICT DO NOTHING RETURNING 1)",
+    " SELECT MAX(id), COUNT(*), (SELECT COUNT(*) FROM copied) FROM batch"
+);
+
+macro_rules! query_missing_key_hashes {
+    ($table:expr) => {
+        concat!(
+            "SELECT id, ",
+            data_columns!(),
+            " FROM ",
+            $table,
+            " WHERE id > $1 AND record_key IS NOT NULL AND record_key_hash IS NULL",
+            " ORDER BY id LIMIT $2"
+        )
+    };
+}
+
+macro_rules! update_key_hashes {
+    ($table:expr) => {
+        concat!(
+            "UPDATE ",
+            $table,
+            " d SET record_key_hash = u.record_key_hash",
+            " FROM UNNEST($1::BIGINT[], $2::BYTEA[]) AS u(id, record_key_hash)",
+            " WHERE d.id = u.id AND d.record_key_hash IS NULL"
+        )
+    };
+}
+
+/// The next $2 rows after id $1 with a key but no key hash, stored before the hash was
+/// added or copied from data_v2 before it was backfilled
+pub const QUERY_MISSING_KEY_HASHES: &str = query_missing_key_hashes!(data_table!());
+pub const QUERY_LEGACY_MISSING_KEY_HASHES: &str = query_missing_key_hashes!(legacy_data_table!());
+
+/// Sets the key hash of the rows in $1 to the hashes in $2
+pub const UPDATE_KEY_HASHES: &str = update_key_hashes!(data_table!());
+pub const UPDATE_LEGACY_KEY_HASHES: &str = update_key_hashes!(legacy_data_table!());
+
+/// Whether the topic has records that can not be found by their key until the key hashes
+/// are backfilled
+pub const QUERY_TOPIC_MISSING_KEY_HASHES: &str = concat!(
+    "SELECT EXISTS (SELECT 1 FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND record_key_hash IS NULL AND record_key IS NOT NULL)",
+    " OR EXISTS (SELECT 1 FROM ",
+    legacy_data_table!(),
+    " WHERE kafka_topic = $1 AND record_key_hash IS NULL AND record_key IS NOT NULL)"
+);
+
+pub const QUERY_LAST_ARCHIVED_WINDOW_END: &str = concat!(
+    "SELECT MAX(window_end) FROM ",
+    archive_segments_table!(),
+    " WHERE kafka_topic = $1"
+);
+
+pub const QUERY_NEXT_RECORD_TIMESTAMP: &str = concat!(
+    "SELECT MIN(timestamp) FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND timestamp >= $2"
+);
+
+pub const INSERT_ARCHIVE_SEGMENT: &str = concat!(
+    "INSERT INTO ",
+    archive_segments_table!(),
+    " (kafka_topic, window_start, window_end, object_key, row_count, bytes, sha256, verified_at)",
+    " VALUES ($1, $2, $3, $4, $5, $6, $7, NOW()) RETURNING id"
+);
+
+pub const UPDATE_ARCHIVE_SEGMENT_DELETED: &str = concat!(
+    "UPDATE ",
+    archive_segments_table!(),
+    " SET rows_deleted_at = NOW(), deleted_rows = $2 WHERE id = $1"
+);
+
+pub const QUERY_ARCHIVE_SEGMENTS: &str = concat!(
+    "SELECT ",
+    archive_segment_columns!(),
+    " FROM ",
+    archive_segments_table!(),
+    " WHERE kafka_topic = $1 ORDER BY window_start"
+);
+
+pub const INSERT_ARCHIVE_SEGMENT_KEYS: &str = concat!(
+    "INSERT INTO ",
+    archive_segment_keys_table!(),
+    " (record_key_hash, segment_id) SELECT UNNEST($2::BYTEA[]), $1 ON CONFLICT DO NOTHING"
+);
+
+/// Segments of the topic with records of the key hash, locked until the transaction ends
+pub const QUERY_ARCHIVE_SEGMENTS_WITH_KEY: &str = concat!(
+    "SELECT ",
+    archive_segment_columns!(),
+    " FROM ",
+    archive_segments_table!(),
+    " WHERE kafka_topic = $1 AND id IN (SELECT segment_id FROM ",
+    archive_segment_keys_table!(),
+    " WHERE record_key_hash = $2) ORDER BY window_start FOR UPDATE"
+);
+
+/// Points the segment to the object it was rewritten to
+pub const UPDATE_ARCHIVE_SEGMENT_REWRITTEN: &str = concat!(
+    "UPDATE ",
+    archive_segments_table!(),
+    " SET object_key = $2, row_count = $3, bytes = $4, sha256 = $5, verified_at = NOW()",
+    " WHERE id = $1"
+);
+
+pub const DELETE_ARCHIVE_SEGMENT_KEY: &str = concat!(
+    "DELETE FROM ",
+    archive_segment_keys_table!(),
+    " WHERE segment_id = $1 AND record_key_hash = $2"
+);
+
+pub const QUERY_ARCHIVED_TOPICS: &str = concat!(
+    "SELECT DISTINCT kafka_topic FROM ",
+    archive_segments_table!(),
+    " ORDER BY kafka_topic"
+);
+
+/// Segments of the topic whose whole window is before $2
+pub const QUERY_EXPIRED_ARCHIVE_SEGMENTS: &str = concat!(
+    "SELECT ",
+    archive_segment_columns!(),
+    " FROM ",
+    archive_segments_table!(),
+    " WHERE kafka_topic = $1 AND window_end <= $2 ORDER BY window_start"
+);
+
+/// The key hashes of the segment are deleted with it
+pub const DELETE_ARCHIVE_SEGMENT: &str =
+    concat!("DELETE FROM ", archive_segments_table!(), " WHERE id = $1");
+
+/// Deletes the archived records of a window by offset, so records stored after the
+/// segment was written are kept, and logs the deleted ranges and removes the current
+/// state of the records like the retention job
+pub const DELETE_ARCHIVED_RECORDS: &str = concat!(
+    "WITH deleted AS (DELETE FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND timestamp >= $2 AND timestamp < $3",
+    " AND (kafka_partition, kafka_offset) IN (SELECT * FROM UNNEST($4::SMALLINT[], $5::BIGINT[]))",
+    " RETURNING kafka_partition, kafka_offset, timestamp),",
+    " state AS (DELETE FROM ",
+    current_state_table!(),
+    " s USING deleted d WHERE s.kafka_topic = $1 AND s.kafka_partition = d.kafka_partition",
+    " AND s.kafka_offset = d.kafka_offset)",
+    " INSERT INTO ",
+    purge_log_table!(),
+    " (kafka_topic, kafka_partition, cutoff, row_count, min_offset, max_offset, min_timestamp, max_timestamp)",
+    " SELECT $1, kafka_partition, $3, COUNT(*), MIN(kafka_offset), MAX(kafka_offset), MIN(timestamp), MAX(timestamp)",
+    " FROM deleted GROUP BY kafka_partition",
+    " RETURNING kafka_partition, row_count"
+);
+
+/// Removes the current state of the records in a month whose partition is dropped
+pub const DELETE_CURRENT_STATE_IN_RANGE: &str = concat!(
+    "DELETE FROM ",
+    current_state_table!(),
+    " WHERE timestamp >= $1 AND timestamp < $2"
+);
+
+pub const QUERY_TOPIC_HWMS: &str = concat!(
+    "SELECT partition, hwm FROM ",
+    hwm_table!(),
+    " WHERE topic = $1 ORDER BY partition"
+);
+
+/// Updates the current state with the latest record per key among the given offsets of
+/// a partition, which are read back from the data table so they are stored the same way.
+/// A newer record of the key in the same partition is never replaced, and a tombstone
+/// deletes the key.
+pub const UPSERT_CURRENT_STATE: &str = concat!(
+    "WITH latest AS (SELECT DISTINCT ON (record_key_hash) record_key_hash, ",
+    data_columns!(),
+    " FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND kafka_partition = $2 AND kafka_offset = ANY($3)",
+    " AND timestamp >= $4 AND timestamp <= $5 AND record_key_hash IS NOT NULL",
+    " ORDER BY record_key_hash, kafka_offset DESC),",
+    " removed AS (DELETE FROM ",
+    current_state_table!(),
+    " s USING latest l WHERE l.record_value IS NULL AND s.kafka_topic = l.kafka_topic",
+    " AND s.record_key_hash = l.record_key_hash",
+    " AND NOT (s.kafka_partition = l.kafka_partition AND s.kafka_offset > l.kafka_offset))",
+    " INSERT INTO ",
+    current_state_table!(),
+    " (record_key_hash, ",
+    data_columns!(),
+    ") SELECT * FROM latest WHERE record_value IS NOT NULL",
+    " ON CONFLICT (kafka_topic, record_key_hash) DO UPDATE SET",
+    " kafka_partition = EXCLUDED.kafka_partition, kafka_offset = EXCLUDED.kafka_offset,",
+    " timestamp = EXCLUDED.timestamp, headers = EXCLUDED.headers,",
+    " record_key = EXCLUDED.record_key, record_value = EXCLUDED.record_value,",
+    " key_id = EXCLUDED.key_id, value_codec = EXCLUDED.value_codec",
+    " WHERE ",
+    current_state_table!(),
+    ".kafka_partition <> EXCLUDED.kafka_partition OR ",
+    current_state_table!(),
+    ".kafka_offset < EXCLUDED.kafka_offset"
+);
+
+pub const DELETE_CURRENT_STATE: &str = concat!(
+    "DELETE FROM ",
+    current_state_table!(),
+    " WHERE kafka_topic = $1 AND ($2::BYTEA IS NULL OR record_key_hash = $2)"
+);
+
+pub const DELETE_ERASED_CURRENT_STATE: &str = concat!(
+    "DELETE FROM ",
+    current_state_table!(),
+    " WHERE kafka_topic = $1 AND record_key_hash = $2 AND (kafka_partition, kafka_offset) IN",
+    " (SELECT * FROM UNNEST($3::SMALLINT[], $4::BIGINT[]))"
+);
+
+/// Builds the current state of the topic, or of one key, from the stored records. The
+/// latest record of a key is the one with the highest offset in its partition, and the
+/// newest of those when the key is in several partitions.
+pub const INSERT_CURRENT_STATE_FROM_DATA: &str = concat!(
+    "INSERT INTO ",
+    current_state_table!(),
+    " (record_key_hash, ",
+    data_columns!(),
+    ") SELECT * FROM (SELECT DISTINCT ON (record_key_hash) * FROM",
+    " (SELECT DISTINCT ON (record_key_hash, kafka_partition) record_key_hash, ",
+    data_columns!(),
+    " FROM ",
+    data_table!(),
+    " WHERE kafka_topic = $1 AND ($2::BYTEA IS NULL OR record_key_hash = $2)",
+    " AND record_key_hash IS NOT NULL",
+    " ORDER BY record_key_hash, kafka_partition, kafka_offset DESC) per_partition",
+    " ORDER BY record_key_hash, timestamp DESC) latest WHERE record_value IS NOT NULL"
+);
+
+pub const QUERY_CURRENT_STATE: &str = concat!(
+    "SELECT ",
+    data_columns!(),
+    " FROM ",
+    current_state_table!(),
+    " WHERE kafka_topic = $1 AND ($2::BYTEA IS NULL OR record_key_hash > $2)",
+    " ORDER BY record_key_hash LIMIT $3"
+);
+
+/// Maps the restored offsets of the source topic to the offsets they got in the target
+/// topic, the offsets are given as arrays of equal length
+pub const INSERT_RESTORE_OFFSET_MAPPING: &str = concat!(
+    "INSERT INTO ",
+    restore_offset_mapping_table!(),
+    " (source_topic, target_topic, source_partition, source_offset, target_partition, target_offset, restored_at)",
+    " SELECT $1, $2, source_partition, source_offset, target_partition, target_offset, NOW()",
+    " FROM UNNEST($3::SMALLINT[], $4::BIGINT[], $5::SMALLINT[], $6::BIGINT[])",
+    " AS mapping(source_partition, source_offset, target_partition, target_offset)",
+    " ON CONFLICT (target_topic, source_topic, source_partition, source_offset) DO UPDATE SET",
+    " target_partition = EXCLUDED.target_partition, target_offset = EXCLUDED.target_offset,",
+    " restored_at = EXCLUDED.restored_at"
+);
+
+/// Mappings of an earlier restore of the partitions, all partitions when $3 is NULL
+pub const DELETE_RESTORE_OFFSET_MAPPING: &str = concat!(
+    "DELETE FROM ",
+    restore_offset_mapping_table!(),
+    " WHERE target_topic = $1 AND source_topic = $2",
+    " AND ($3::SMALLINT[] IS NULL OR source_partition = ANY($3))"
+);
+
+/// The first restored record of the source partition at or after the offset
+pub const QUERY_RESTORE_OFFSET_MAPPING: &str = concat!(
+    "SELECT source_partition, source_offset, target_partition, target_offset FROM ",
+    restore_offset_mapping_table!(),
+    " WHERE target_topic = $1 AND source_topic = $2 AND source_partition = $3",
+    " AND source_offset >= $4 ORDER BY source_offset LIMIT 1"
+);
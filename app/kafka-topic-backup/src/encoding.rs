@@ -0,0 +1,49 @@
+use base64::{Engine as _, engine::general_purpose};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// How bytes are represented as a JSON string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueEncoding {
+    Utf8,
+    Base64,
+}
+
+/// Bytes represented as a JSON string together with the encoding used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodedBytes {
+    pub encoding: ValueEncoding,
+    pub data: String,
+}
+
+impl EncodedBytes {
+    /// Encodes with the preferred encoding, bytes that are not valid UTF-8 or contain
+    /// NUL are always base64-encoded.
+    pub fn encode(bytes: &[u8], preferred: ValueEncoding) -> Self {
+        let (data, encoding) = encode_bytes(bytes, preferred);
+        EncodedBytes { encoding, data }
+    }
+
+    pub fn decode(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        decode_bytes(&self.data, self.encoding)
+    }
+}
+
+/// UTF-8 with NUL is base64-encoded too, since Postgres rejects `\u0000` in JSONB
+pub fn encode_bytes(bytes: &[u8], preferred: ValueEncoding) -> (String, ValueEncoding) {
+    match (preferred, std::str::from_utf8(bytes)) {
+        (ValueEncoding::Utf8, Ok(s)) if !s.contains('\0') => (s.to_string(), ValueEncoding::Utf8),
+        _ => (
+            general_purpose::STANDARD.encode(bytes),
+            ValueEncoding::Base64,
+        ),
+    }
+}
+
+pub fn decode_bytes(data: &str, encoding: ValueEncoding) -> Result<Vec<u8>, base64::DecodeError> {
+    match encoding {
+        ValueEncoding::Utf8 => Ok(data.as_bytes().to_vec()),
+        ValueEncoding::Base64 => general_purpose::STANDARD.decode(data),
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use sqlx::PgPool;
+
+use crate::database::encryption_keys::get_encryption_keys;
+use crate::database::insert_data::InsertDataRow;
+use crate::database::read_data::DataRow;
+use crate::encryption::master_key::{MasterKeys, NONCE_LEN};
+
+/// Encrypts and decrypts record keys and values with the unwrapped data encryption
+/// keys. New rows are encrypted with the newest key, older keys are kept for reading.
+pub struct DataCipher {
+    keys: BTreeMap<i32, Aes256Gcm>,
+}
+
+impl fmt::Debug for DataCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataCipher")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl DataCipher {
+    /// Unwraps every data encryption key, failing if one is wrapped with a master key
+    /// that is not configured
+    pub async fn load(pg_pool: &PgPool, master_keys: &MasterKeys) -> Result<Self, Box<dyn Error>> {
+        let mut keys = BTreeMap::new();
+        for row in get_encryption_keys(pg_pool).await? {
+            let master_key = master_keys.by_id(&row.master_key_id).ok_or_else(|| {
+                format!(
+                    "Data key {} is wrapped with master key {}, which is not configured",
+                    row.id, row.master_key_id
+                )
+            })?;
+            let data_key = master_key
+                .unwrap(&row.wrapped_key)
+                .map_err(|_| format!("Failed to unwrap data key {}", row.id))?;
+            keys.insert(
+                row.id,
+                Aes256Gcm::new_from_slice(&data_key).map_err(|e| e.to_string())?,
+            );
+        }
+        Ok(DataCipher { keys })
+    }
+
+    /// The newest key, used for all new rows
+    pub fn active_key_id(&self) -> Option<i32> {
+        self.keys.keys().next_back().copied()
+    }
+
+    /// Encrypts the key and value of the row in place and returns the id of the key used
+    pub fn encrypt_row(&self, row: &mut InsertDataRow) -> Result<i32, sqlx::Error> {
+        let (key_id, cipher) = self
+            .keys
+            .last_key_value()
+            .ok_or_else(|| sqlx::Error::Encode("No data encryption key is loaded".into()))?;
+        let encrypt = |field: &str, plaintext: &[u8]| {
+            encrypt(
+                cipher,
+                plaintext,
+                &aad(
+                    &row.kafka_topic,
+                    row.kafka_partition,
+                    row.kafka_offset,
+                    field,
+                ),
+            )
+            .map_err(|_| sqlx::Error::Encode(format!("Failed to encrypt record {}", field).into()))
+        };
+        let record_key = row
+            .record_key
+            .as_deref()
+            .map(|key| encrypt("key", key))
+            .transpose()?;
+        let record_value = row
+            .record_value
+            .as_deref()
+            .map(|value| encrypt("value", value))
+            .transpose()?;
+        row.record_key = record_key;
+        row.record_value = record_value;
+        Ok(*key_id)
+    }
+
+    fn decrypt_row(&self, key_id: i32, mut row: DataRow) -> Result<DataRow, sqlx::Error> {
+        let cipher = self.keys.get(&key_id).ok_or_else(|| {
+            sqlx::Error::Decode(format!("Unknown data encryption key {}", key_id).into())
+        })?;
+        let partition = row.kafka_partition as i32;
+        let decrypt = |field: &str, ciphertext: &[u8]| {
+            decrypt(
+                cipher,
+                ciphertext,
+                &aad(&row.kafka_topic, partition, row.kafka_offset, field),
+            )
+            .map_err(|_| {
+                sqlx::Error::Decode(
+                    format!(
+                        "Failed to decrypt record {} of {}::{}::{}",
+                        field, row.kafka_topic, row.kafka_partition, row.kafka_offset
+                    )
+                    .into(),
+                )
+            })
+        };
+        let record_key = row
+            .record_key
+            .as_deref()
+            .map(|key| decrypt("key", key))
+            .transpose()?;
+        let record_value = row
+            .record_value
+            .as_deref()
+            .map(|value| decrypt("value", value))
+            .transpose()?;
+        row.record_key = record_key;
+        row.record_value = record_value;
+        row.key_id = None;
+        Ok(row)
+    }
+}
+
+/// Decrypts the encrypted rows, plaintext rows are returned as they are. Fails for an
+/// encrypted row when no cipher is given.
+pub fn decrypt_rows(
+    rows: Vec<DataRow>,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<DataRow>, sqlx::Error> {
+    rows.into_iter()
+        .map(|row| match (row.key_id, cipher) {
+            (None, _) => Ok(row),
+            (Some(key_id), Some(cipher)) => cipher.decrypt_row(key_id, row),
+            (Some(_), None) => Err(sqlx::Error::Decode(
+                "Record is encrypted, but no master key is configured".into(),
+            )),
+        })
+        .collect()
+}
+
+/// Binds the ciphertext to the position and field of the record, so it cannot be moved
+/// to another row or swapped between key and value
+fn aad(topic: &str, partition: i32, offset: i64, field: &str) -> Vec<u8> {
+    format!("{}:{}:{}:{}", topic, partition, offset, field).into_bytes()
+}
+
+/// The result is the nonce followed by the ciphertext
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(
+        &nonce,
+        Payload {
+            msg: plaintext,
+            aad,
+        },
+    )?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(cipher: &Aes256Gcm, encrypted: &[u8], aad: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+    if encrypted.len() < NONCE_LEN {
+        return Err(aes_gcm::Error);
+    }
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+    cipher.decrypt(
+        Nonce::from_slice(nonce),
+        Payload {
+            msg: ciphertext,
+            aad,
+        },
+    )
+}
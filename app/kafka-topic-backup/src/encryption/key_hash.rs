@@ -0,0 +1,71 @@
+use std::error::Error;
+use std::sync::OnceLock;
+
+use base64::{Engine as _, engine::general_purpose};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config_utils::get_env::get_env;
+
+/// Base64-encoded secret the record key hashes are keyed with, required by all commands
+/// that store or look up records by key
+pub const KEY_HASH_SECRET_ENV: &str = "RECORD_KEY_HASH_SECRET";
+/// Path to a file with the base64-encoded secret, used when the secret itself is not set
+pub const KEY_HASH_SECRET_FILE_ENV: &str = "RECORD_KEY_HASH_SECRET_FILE";
+
+const MIN_SECRET_LEN: usize = 32;
+
+static KEY_HASH_SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Sets the secret used by [`record_key_hash`] for the rest of the process. Setting the
+/// same secret again is a no-op, a different secret is rejected since the stored hashes
+/// would no longer match.
+pub fn init_key_hash_secret(secret: Vec<u8>) -> Result<(), Box<dyn Error>> {
+    if secret.len() < MIN_SECRET_LEN {
+        return Err(format!(
+            "Key hash secret must be at least {} bytes, got {}",
+            MIN_SECRET_LEN,
+            secret.len()
+        )
+        .into());
+    }
+    if KEY_HASH_SECRET.get_or_init(|| secret.clone()) != &secret {
+        return Err("A different key hash secret is already set".into());
+    }
+    Ok(())
+}
+
+/// Reads the secret from [`KEY_HASH_SECRET_ENV`] or the file in [`KEY_HASH_SECRET_FILE_ENV`]
+pub fn key_hash_secret_from_env() -> Result<Vec<u8>, Box<dyn Error>> {
+    let encoded = match get_env(KEY_HASH_SECRET_ENV) {
+        Ok(encoded) if !encoded.is_empty() => encoded,
+        _ => match get_env(KEY_HASH_SECRET_FILE_ENV) {
+            Ok(path) if !path.is_empty() => std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read key hash secret file {}: {}", path, e))?,
+            _ => {
+                return Err(format!(
+                    "{} or {} must be set",
+                    KEY_HASH_SECRET_ENV, KEY_HASH_SECRET_FILE_ENV
+                )
+                .into());
+            }
+        },
+    };
+    general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| format!("Invalid key hash secret: {}", e).into())
+}
+
+/// HMAC-SHA256 of the key, used to look up all records for a key without storing the key
+/// in the clear. Keyed so the hashes can not be matched against guessed keys without the
+/// secret.
+///
+/// Panics when [`init_key_hash_secret`] has not been called.
+pub fn record_key_hash(record_key: &[u8]) -> Vec<u8> {
+    let secret = KEY_HASH_SECRET
+        .get()
+        .expect("The key hash secret is set at startup");
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(record_key);
+    mac.finalize().into_bytes().to_vec()
+}
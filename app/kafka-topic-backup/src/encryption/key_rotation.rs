@@ -0,0 +1,80 @@
+use std::error::Error;
+
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{KeyInit, OsRng};
+use log::info;
+use sqlx::PgPool;
+
+use crate::database::encryption_keys::{
+    get_encryption_keys, insert_encryption_key, update_wrapped_key,
+};
+use crate::encryption::master_key::{MasterKey, MasterKeys};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RotationSummary {
+    /// Data keys that were wrapped with another master key and now use the current one
+    pub rewrapped_key_ids: Vec<i32>,
+    pub new_data_key_id: Option<i32>,
+}
+
+/// Creates the first data key when encryption is enabled for the first time
+pub async fn ensure_data_key(
+    pg_pool: &PgPool,
+    master_keys: &MasterKeys,
+) -> Result<(), Box<dyn Error>> {
+    if get_encryption_keys(pg_pool).await?.is_empty() {
+        let mut tx = pg_pool.begin().await?;
+        let id = insert_new_data_key(&mut tx, &master_keys.current).await?;
+        tx.commit().await?;
+        info!("Opprettet første nøkkel for kryptering av data, id={}", id);
+    }
+    Ok(())
+}
+
+/// Re-wraps every data key with the current master key, so the previous master key can
+/// be retired. The data itself is not touched. With `new_data_key` a new data key is
+/// created as well, which is used for all rows stored from then on.
+pub async fn rotate_keys(
+    pg_pool: &PgPool,
+    master_keys: &MasterKeys,
+    new_data_key: bool,
+) -> Result<RotationSummary, Box<dyn Error>> {
+    let current = &master_keys.current;
+    let mut summary = RotationSummary::default();
+    let mut tx = pg_pool.begin().await?;
+    for row in get_encryption_keys(pg_pool).await? {
+        if row.master_key_id == current.id() {
+            continue;
+        }
+        let master_key = master_keys.by_id(&row.master_key_id).ok_or_else(|| {
+            format!(
+                "Data key {} is wrapped with master key {}, which is not configured",
+                row.id, row.master_key_id
+            )
+        })?;
+        let data_key = master_key
+            .unwrap(&row.wrapped_key)
+            .map_err(|_| format!("Failed to unwrap data key {}", row.id))?;
+        let wrapped_key = current
+            .wrap(&data_key)
+            .map_err(|_| format!("Failed to wrap data key {}", row.id))?;
+        update_wrapped_key(&mut tx, row.id, current.id(), &wrapped_key).await?;
+        summary.rewrapped_key_ids.push(row.id);
+    }
+    if new_data_key {
+        summary.new_data_key_id = Some(insert_new_data_key(&mut tx, current).await?);
+    }
+    tx.commit().await?;
+    Ok(summary)
+}
+
+async fn insert_new_data_key(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    master_key: &MasterKey,
+) -> Result<i32, Box<dyn Error>> {
+    let data_key = Aes256Gcm::generate_key(OsRng);
+    let wrapped_key = master_key
+        .wrap(&data_key)
+        .map_err(|_| "Failed to wrap new data key")?;
+    Ok(insert_encryption_key(tx, master_key.id(), &wrapped_key).await?)
+}
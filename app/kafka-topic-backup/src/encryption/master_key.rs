@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{Engine as _, engine::general_purpose};
+use sha2::{Digest, Sha256};
+
+use crate::config_utils::get_env::get_env;
+
+/// Base64-encoded 32 byte master key, encryption at rest is enabled when it is set
+pub const MASTER_KEY_ENV: &str = "ENCRYPTION_MASTER_KEY";
+/// Path to a file with the base64-encoded master key, used when the key itself is not set
+pub const MASTER_KEY_FILE_ENV: &str = "ENCRYPTION_MASTER_KEY_FILE";
+/// The master key being rotated away from, only needed until `rotate-keys` has run
+pub const PREVIOUS_MASTER_KEY_ENV: &str = "ENCRYPTION_PREVIOUS_MASTER_KEY";
+pub const PREVIOUS_MASTER_KEY_FILE_ENV: &str = "ENCRYPTION_PREVIOUS_MASTER_KEY_FILE";
+
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// Key encryption key, only used to wrap and unwrap the data encryption keys
+pub struct MasterKey {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey").field("id", &self.id).finish()
+    }
+}
+
+impl MasterKey {
+    pub fn from_bytes(key: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if key.len() != 32 {
+            return Err(format!("Master key must be 32 bytes, got {}", key.len()).into());
+        }
+        Ok(MasterKey {
+            id: master_key_id(key),
+            cipher: Aes256Gcm::new_from_slice(key).map_err(|e| e.to_string())?,
+        })
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_bytes(&general_purpose::STANDARD.decode(encoded.trim())?)
+    }
+
+    /// Derived from the key, so the id stored with a wrapped key identifies the master
+    /// key without revealing it
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Encrypts a data encryption key, the result is the nonce followed by the ciphertext
+    pub fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: data_key,
+                aad: self.id.as_bytes(),
+            },
+        )?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn unwrap(&self, wrapped_key: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+        if wrapped_key.len() < NONCE_LEN {
+            return Err(aes_gcm::Error);
+        }
+        let (nonce, ciphertext) = wrapped_key.split_at(NONCE_LEN);
+        self.cipher.decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: self.id.as_bytes(),
+            },
+        )
+    }
+}
+
+fn master_key_id(key: &[u8]) -> String {
+    Sha256::digest(key)[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The current master key wraps new and rotated data keys, the previous one can still
+/// unwrap keys that have not been rotated yet
+#[derive(Debug)]
+pub struct MasterKeys {
+    pub current: MasterKey,
+    pub previous: Option<MasterKey>,
+}
+
+impl MasterKeys {
+    /// `None` when no master key is configured, which leaves encryption disabled
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(current) = read_master_key(MASTER_KEY_ENV, MASTER_KEY_FILE_ENV)? else {
+            return Ok(None);
+        };
+        let previous = read_master_key(PREVIOUS_MASTER_KEY_ENV, PREVIOUS_MASTER_KEY_FILE_ENV)?;
+        Ok(Some(MasterKeys { current, previous }))
+    }
+
+    pub fn by_id(&self, id: &str) -> Option<&MasterKey> {
+        std::iter::once(&self.current)
+            .chain(self.previous.as_ref())
+            .find(|master_key| master_key.id() == id)
+    }
+}
+
+fn read_master_key(key_env: &str, file_env: &str) -> Result<Option<MasterKey>, Box<dyn Error>> {
+    if let Ok(encoded) = get_env(key_env)
+        && !encoded.is_empty()
+    {
+        return MasterKey::from_base64(&encoded)
+            .map(Some)
+            .map_err(|e| format!("Invalid master key in {}: {}", key_env, e).into());
+    }
+    match get_env(file_env) {
+        Ok(path) if !path.is_empty() => {
+            let encoded = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read master key file {}: {}", path, e))?;
+            MasterKey::from_base64(&encoded)
+                .map(Some)
+                .map_err(|e| format!("Invalid master key in {}: {}", path, e).into())
+        }
+        _ => Ok(None),
+    }
+}
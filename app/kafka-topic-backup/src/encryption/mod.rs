@@ -0,0 +1,4 @@
+pub mod data_cipher;
+pub mod key_hash;
+pub mod key_rotation;
+pub mod master_key;
@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+use chrono::{SubsecRound, Utc};
+use log::{info, warn};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::archive::archiver::{Archiver, SegmentErasure};
+use crate::database::archive_catalog::{
+    delete_archive_segment_key, get_archive_segments_with_key, update_archive_segment_rewritten,
+};
+use crate::database::current_state::erase_current_state;
+use crate::database::erasure::{
+    ErasureLogEntry, delete_records, get_last_erasure_hash, insert_erased_offsets,
+    insert_erasure_log, lock_erasure_log, query_records_for_erasure,
+};
+use crate::database::read_data::DataRow;
+use crate::encryption::data_cipher::DataCipher;
+use crate::encryption::key_hash::record_key_hash;
+use crate::erasure::hash_chain::{GENESIS_HASH, entry_hash, to_hex};
+use crate::kafka::headers::headers_from_json;
+use crate::metrics;
+
+/// A header the record must have, with exactly this value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderMatch {
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+/// A field in the JSON payload, given as a JSON pointer like `/person/ident`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonFieldMatch {
+    pub pointer: String,
+    pub value: Value,
+}
+
+/// Narrows an erasure to the records with the key that also match every given filter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErasureFilter {
+    pub header: Option<HeaderMatch>,
+    pub json_field: Option<JsonFieldMatch>,
+}
+
+impl ErasureFilter {
+    /// Records without stored headers, or with a value that is not JSON, never match a
+    /// filter on them
+    pub fn matches(&self, row: &DataRow) -> bool {
+        let header_matches = self.header.as_ref().is_none_or(|header| {
+            row.headers
+                .as_ref()
+                .and_then(|headers| headers_from_json(headers).ok())
+                .is_some_and(|headers| {
+                    headers.iter().any(|stored| {
+                        stored.key == header.key && stored.value.as_ref() == Some(&header.value)
+                    })
+                })
+        });
+        let json_field_matches = self.json_field.as_ref().is_none_or(|field| {
+            row.record_value
+                .as_deref()
+                .and_then(|value| serde_json::from_slice::<Value>(value).ok())
+                .is_some_and(|value| value.pointer(&field.pointer) == Some(&field.value))
+        });
+        header_matches && json_field_matches
+    }
+
+    /// The filter as written to the erasure log, values are hashed since they are
+    /// personal data themselves
+    fn to_log_json(&self) -> Option<Value> {
+        if self.header.is_none() && self.json_field.is_none() {
+            return None;
+        }
+        let mut filter = serde_json::Map::new();
+        if let Some(header) = &self.header {
+            filter.insert(
+                "header".to_string(),
+                json!({ "key": header.key, "value_sha256": to_hex(&Sha256::digest(&header.value)) }),
+            );
+        }
+        if let Some(field) = &self.json_field {
+            filter.insert(
+                "json_field".to_string(),
+                json!({
+                    "pointer": field.pointer,
+                    "value_sha256": to_hex(&Sha256::digest(field.value.to_string())),
+                }),
+            );
+        }
+        Some(Value::Object(filter))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErasureRequest {
+    pub topic: String,
+    pub key: Vec<u8>,
+    pub filter: ErasureFilter,
+    pub requested_by: String,
+    pub reason: String,
+    /// Only count the matching records, nothing is deleted or logged
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErasureResult {
+    /// `None` for a dry run
+    pub erasure_id: Option<i64>,
+    pub rows_per_partition: BTreeMap<i32, i64>,
+    pub entry_hash: Option<Vec<u8>>,
+    /// Object keys of the archived segments with matching records, as they were before
+    /// the erasure rewrote them
+    pub archive_segments: Vec<String>,
+}
+
+impl ErasureResult {
+    pub fn total(&self) -> i64 {
+        self.rows_per_partition.values().sum()
+    }
+}
+
+/// Deletes every record of the topic with the key that matches the filter, logs the
+/// erasure in the hash-chained erasure log and records the erased offsets, so they are
+/// skipped if the topic is consumed again from before the erasure. An erased record in
+/// the current state is replaced by the latest record of the key that is left. All in
+/// one transaction. An erasure that matches nothing is logged as well.
+///
+/// Archived segments with the key are rewritten without the matching records and logged
+/// with the erasure, the old objects are deleted once it is committed. Fails when the
+/// catalog has segments with the key but no archiver is given.
+pub async fn erase_key(
+    pg_pool: &PgPool,
+    request: &ErasureRequest,
+    cipher: Option<&DataCipher>,
+    archiver: Option<&Archiver>,
+) -> Result<ErasureResult, Box<dyn Error + Send + Sync>> {
+    let mut tx = pg_pool.begin().await?;
+    lock_erasure_log(&mut tx).await?;
+
+    let key_hash = record_key_hash(&request.key);
+    let matches = |row: &DataRow| {
+        row.record_key.as_deref() == Some(&request.key) && request.filter.matches(row)
+    };
+    let mut erased_offsets: BTreeSet<(i16, i64)> =
+        query_records_for_erasure(&mut tx, &request.topic, &request.key, cipher)
+            .await?
+            .iter()
+            .filter(|row| request.filter.matches(row))
+            .map(|row| (row.kafka_partition, row.kafka_offset))
+            .collect();
+    let segments = get_archive_segments_with_key(&mut tx, &request.topic, &key_hash).await?;
+    let mut segment_erasures: Vec<SegmentErasure> = Vec::new();
+    if !segments.is_empty() {
+        let archiver = archiver.ok_or_else(|| {
+            format!(
+                "{} archived segments of {} have records with the key, but the archive is not configured",
+                segments.len(),
+                request.topic
+            )
+        })?;
+        for segment in &segments {
+            segment_erasures.extend(archiver.find_erased_records(segment, matches).await?);
+        }
+    }
+    // Archived records that are still stored are only counted once
+    for segment_erasure in &segment_erasures {
+        erased_offsets.extend(&segment_erasure.offsets);
+    }
+    let erased_offsets: Vec<(i16, i64)> = erased_offsets.into_iter().collect();
+    let archive_segments: Vec<String> = segment_erasures
+        .iter()
+        .map(|segment_erasure| segment_erasure.segment.object_key.clone())
+        .collect();
+    let mut rows_per_partition = BTreeMap::new();
+    for (partition, _) in &erased_offsets {
+        *rows_per_partition.entry(*partition as i32).or_default() += 1;
+    }
+    if request.dry_run {
+        tx.rollback().await?;
+        return Ok(ErasureResult {
+            erasure_id: None,
+            rows_per_partition,
+            entry_hash: None,
+            archive_segments,
+        });
+    }
+
+    delete_records(&mut tx, &request.topic, &erased_offsets).await?;
+    erase_current_state(&mut tx, &request.topic, &key_hash, &erased_offsets).await?;
+    let prev_hash = get_last_erasure_hash(&mut tx)
+        .await?
+        .unwrap_or_else(|| GENESIS_HASH.to_vec());
+    let row_count = erased_offsets.len() as i64;
+    let mut entry = ErasureLogEntry {
+        erased_at: Utc::now().trunc_subsecs(3),
+        kafka_topic: request.topic.clone(),
+        record_key_hash: key_hash.clone(),
+        filter: request.filter.to_log_json(),
+        requested_by: request.requested_by.clone(),
+        reason: request.reason.clone(),
+        row_count,
+        archive_segments: archive_segments.clone(),
+        prev_hash,
+        entry_hash: Vec::new(),
+    };
+    entry.entry_hash = entry_hash(&entry, &erased_offsets);
+    let erasure_id = insert_erasure_log(&mut tx, &entry).await?;
+    insert_erased_offsets(&mut tx, &request.topic, &erased_offsets, erasure_id).await?;
+    if let Some(archiver) = archiver {
+        for segment_erasure in &segment_erasures {
+            let rewritten = archiver
+                .rewrite_segment(segment_erasure, erasure_id)
+                .await?;
+            update_archive_segment_rewritten(&mut tx, segment_erasure.segment.id, &rewritten)
+                .await?;
+            if !segment_erasure.key_hash_kept {
+                delete_archive_segment_key(&mut tx, segment_erasure.segment.id, &key_hash).await?;
+            }
+        }
+    }
+    tx.commit().await?;
+    if let Some(archiver) = archiver {
+        for object_key in &archive_segments {
+            if let Err(e) = archiver.delete_object(object_key).await {
+                warn!(
+                    "Sletting {}: kunne ikke slette det gamle arkiverte segmentet {}: {}",
+                    erasure_id, object_key, e
+                );
+            }
+        }
+    }
+
+    for (partition, count) in &rows_per_partition {
+        metrics::increment_erased_records(&request.topic, *partition, *count);
+    }
+    info!(
+        "Sletting {} fullført: slettet {} meldinger fra {} for én nøkkel, {} arkiverte segmenter skrevet på nytt, forespurt av {}, hash={}",
+        erasure_id,
+        row_count,
+        request.topic,
+        archive_segments.len(),
+        request.requested_by,
+        to_hex(&entry.entry_hash)
+    );
+    Ok(ErasureResult {
+        erasure_id: Some(erasure_id),
+        rows_per_partition,
+        entry_hash: Some(entry.entry_hash),
+        archive_segments,
+    })
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::database::erasure::{ErasureLogEntry, get_erased_offsets_by_erasure, get_erasure_log};
+
+/// The previous hash of the first entry in the log
+pub const GENESIS_HASH: [u8; 32] = [0; 32];
+
+/// Hash of an entry, covering the previous hash, every field except the hash itself
+/// and the erased offsets, so changing, removing or reordering entries breaks the chain.
+/// Each field is prefixed with its length, so content cannot move between fields. The
+/// archived segments are only hashed when there are any, so entries logged before they
+/// were recorded keep their hash.
+pub fn entry_hash(entry: &ErasureLogEntry, erased_offsets: &[(i16, i64)]) -> Vec<u8> {
+    let filter = entry
+        .filter
+        .as_ref()
+        .map(|filter| filter.to_string())
+        .unwrap_or_default();
+    let mut offsets = Vec::with_capacity(erased_offsets.len() * 10);
+    for (partition, offset) in erased_offsets {
+        offsets.extend_from_slice(&partition.to_be_bytes());
+        offsets.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    let mut hasher = Sha256::new();
+    for field in [
+        entry.prev_hash.as_slice(),
+        &entry.erased_at.timestamp_millis().to_be_bytes(),
+        entry.kafka_topic.as_bytes(),
+        &entry.record_key_hash,
+        filter.as_bytes(),
+        entry.requested_by.as_bytes(),
+        entry.reason.as_bytes(),
+        &entry.row_count.to_be_bytes(),
+        &offsets,
+    ] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field);
+    }
+    for segment in &entry.archive_segments {
+        hasher.update((segment.len() as u64).to_be_bytes());
+        hasher.update(segment.as_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainVerification {
+    pub entries: usize,
+    /// The first entry whose hash or link to the previous entry does not match
+    pub first_invalid_id: Option<i64>,
+    /// Hex-encoded hash of the last entry, compare it with a copy kept outside the
+    /// database to detect entries removed from the end of the log
+    pub last_entry_hash: Option<String>,
+}
+
+/// Recomputes every hash in the log, including the erased offsets of each entry
+pub async fn verify_erasure_log(pg_pool: &PgPool) -> Result<ChainVerification, sqlx::Error> {
+    let log = get_erasure_log(pg_pool).await?;
+    let mut prev_hash = GENESIS_HASH.to_vec();
+    let mut first_invalid_id = None;
+    for row in &log {
+        let erased_offsets = get_erased_offsets_by_erasure(pg_pool, row.id).await?;
+        let valid = row.entry.prev_hash == prev_hash
+            && entry_hash(&row.entry, &erased_offsets) == row.entry.entry_hash;
+        if !valid {
+            first_invalid_id = Some(row.id);
+            break;
+        }
+        prev_hash = row.entry.entry_hash.clone();
+    }
+    Ok(ChainVerification {
+        entries: log.len(),
+        first_invalid_id,
+        last_entry_hash: log.last().map(|row| to_hex(&row.entry.entry_hash)),
+    })
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
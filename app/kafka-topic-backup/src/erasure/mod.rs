@@ -0,0 +1,2 @@
+pub mod erase_key;
+pub mod hash_chain;
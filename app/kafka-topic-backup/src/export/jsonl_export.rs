@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use log::info;
+use sqlx::PgPool;
+
+use crate::database::read_data::{RecordCursor, RecordQuery, query_records};
+use crate::encoding::ValueEncoding;
+use crate::encryption::data_cipher::DataCipher;
+use crate::export::manifest::{ExportManifest, prepare_output_dir};
+use crate::export::segment_writer::SegmentWriter;
+use crate::query_api::records::RecordResponse;
+
+pub const JSONL_FORMAT: &str = "jsonl";
+
+#[derive(Debug, Clone)]
+pub struct ExportRequest {
+    pub topic: String,
+    pub from_timestamp: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub to_timestamp: Option<DateTime<Utc>>,
+    pub output_dir: PathBuf,
+    pub max_segment_bytes: u64,
+    pub batch_size: i64,
+    /// Preferred encoding of keys and values, values that are not UTF-8 are always base64
+    pub encoding: ValueEncoding,
+}
+
+/// Writes the records of the topic to JSON Lines segments in the output directory, one
+/// record per line in the same format as the query API. Records are in timestamp order
+/// when a time range is given, otherwise in partition and offset order. The manifest is
+/// written last, so an export without a manifest is incomplete.
+pub async fn export_jsonl(
+    pg_pool: &PgPool,
+    request: &ExportRequest,
+    cipher: Option<&DataCipher>,
+) -> Result<ExportManifest, Box<dyn Error>> {
+    prepare_output_dir(&request.output_dir)?;
+    let query = RecordQuery {
+        kafka_topic: request.topic.clone(),
+        from_timestamp: request.from_timestamp,
+        to_timestamp: request.to_timestamp,
+        ..Default::default()
+    };
+    let mut writer = SegmentWriter::new(
+        &request.output_dir,
+        &request.topic,
+        JSONL_FORMAT,
+        request.max_segment_bytes,
+    );
+    let mut cursor = RecordCursor::start();
+    let mut rows_written = 0;
+    loop {
+        let rows = query_records(pg_pool, &query, cursor, request.batch_size, cipher).await?;
+        let Some(last_row) = rows.last() else {
+            break;
+        };
+        cursor = RecordCursor::after(last_row, &query);
+        for row in rows {
+            let timestamp = row.timestamp;
+            let record = RecordResponse::from_row(row, request.encoding)?;
+            writer.write_line(&serde_json::to_vec(&record)?, timestamp)?;
+            rows_written += 1;
+        }
+        info!(
+            "Eksport av {}: {} meldinger skrevet",
+            request.topic, rows_written
+        );
+    }
+
+    let segments = writer.finish()?;
+    let manifest = ExportManifest {
+        topic: request.topic.clone(),
+        format: JSONL_FORMAT.to_string(),
+        from_timestamp: request.from_timestamp,
+        to_timestamp: request.to_timestamp,
+        exported_at: Utc::now(),
+        rows: segments.iter().map(|segment| segment.rows).sum(),
+        segments,
+        high_watermarks: None,
+    };
+    manifest.write(&request.output_dir)?;
+    Ok(manifest)
+}
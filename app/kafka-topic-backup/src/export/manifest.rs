@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::erasure::hash_chain::to_hex;
+
+/// Written next to the segments once every segment is complete
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub topic: String,
+    /// File format of the segments, like `jsonl`
+    pub format: String,
+    pub from_timestamp: Option<DateTime<Utc>>,
+    pub to_timestamp: Option<DateTime<Utc>>,
+    pub exported_at: DateTime<Utc>,
+    pub rows: u64,
+    pub segments: Vec<SegmentInfo>,
+    /// HWM of each partition a current state export corresponds to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high_watermarks: Option<BTreeMap<i32, i64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    /// File name, relative to the manifest
+    pub file: String,
+    pub rows: u64,
+    pub bytes: u64,
+    /// Hex-encoded SHA-256 of the file
+    pub sha256: String,
+    pub min_timestamp: Option<DateTime<Utc>>,
+    pub max_timestamp: Option<DateTime<Utc>>,
+}
+
+impl ExportManifest {
+    pub fn write(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(dir.join(MANIFEST_FILE), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_slice(&fs::read(dir.join(MANIFEST_FILE))?)?)
+    }
+}
+
+/// Creates the output directory, failing if it already holds a complete export
+pub fn prepare_output_dir(dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    if dir.join(MANIFEST_FILE).exists() {
+        return Err(format!("{} already contains an export", dir.display()).into());
+    }
+    Ok(())
+}
+
+/// Hex-encoded SHA-256 of the file
+pub fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
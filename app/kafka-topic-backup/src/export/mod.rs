@@ -0,0 +1,5 @@
+pub mod jsonl_export;
+pub mod manifest;
+pub mod parquet_export;
+pub mod segment_writer;
+pub mod state_export;
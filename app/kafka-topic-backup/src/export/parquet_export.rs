@@ -0,0 +1,295 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BinaryArray, BooleanArray, Int32Array, Int64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::info;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::database::read_data::{DataRow, RecordCursor, RecordQuery, query_records};
+use crate::encryption::data_cipher::DataCipher;
+use crate::export::manifest::{ExportManifest, SegmentInfo, file_sha256, prepare_output_dir};
+
+pub const PARQUET_FORMAT: &str = "parquet";
+const BASE_COLUMNS: [&str; 8] = [
+    "topic",
+    "partition",
+    "offset",
+    "timestamp",
+    "key",
+    "value",
+    "headers",
+    "value_omitted",
+];
+
+/// A field of the JSON value exported as a column of its own, given as `name=/pointer`
+/// or just `/pointer`, which is named after the pointer like `person.ident`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonColumn {
+    pub name: String,
+    pub pointer: String,
+}
+
+impl FromStr for JsonColumn {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, pointer) = match value.split_once('=') {
+            Some((name, pointer)) => (name.to_string(), pointer),
+            None => (value.trim_start_matches('/').replace('/', "."), value),
+        };
+        if !pointer.starts_with('/') || name.is_empty() {
+            return Err(format!(
+                "Invalid JSON field '{}', expected name=/pointer or /pointer",
+                value
+            ));
+        }
+        Ok(JsonColumn {
+            name,
+            pointer: pointer.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParquetExportRequest {
+    pub topic: String,
+    pub from_timestamp: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub to_timestamp: Option<DateTime<Utc>>,
+    pub output_dir: PathBuf,
+    pub batch_size: i64,
+    pub json_columns: Vec<JsonColumn>,
+}
+
+/// The Parquet file of one day, UTC
+struct DayFile {
+    day: NaiveDate,
+    file: String,
+    writer: ArrowWriter<File>,
+    rows: u64,
+    min_timestamp: DateTime<Utc>,
+    max_timestamp: DateTime<Utc>,
+}
+
+/// Writes the records of the topic to one zstd-compressed Parquet file per day. Keys
+/// and values are binary columns, headers are JSON text, `value_omitted` marks records
+/// stored without their value and the JSON fields are text columns that are null when
+/// the value is not JSON or has no such field. The manifest
+/// is written last, like for the JSON Lines export.
+pub async fn export_parquet(
+    pg_pool: &PgPool,
+    request: &ParquetExportRequest,
+    cipher: Option<&DataCipher>,
+) -> Result<ExportManifest, Box<dyn Error>> {
+    for (i, column) in request.json_columns.iter().enumerate() {
+        if BASE_COLUMNS.contains(&column.name.as_str())
+            || request.json_columns[..i]
+                .iter()
+                .any(|other| other.name == column.name)
+        {
+            return Err(format!("Column name '{}' is already used", column.name).into());
+        }
+    }
+    prepare_output_dir(&request.output_dir)?;
+    let schema = schema(&request.json_columns);
+    // Timestamps are never before the epoch, the lower bound makes the records come in
+    // timestamp order so each day is written in one go
+    let query = RecordQuery {
+        kafka_topic: request.topic.clone(),
+        from_timestamp: Some(request.from_timestamp.unwrap_or(DateTime::UNIX_EPOCH)),
+        to_timestamp: request.to_timestamp,
+        ..Default::default()
+    };
+    let mut segments = Vec::new();
+    let mut current: Option<DayFile> = None;
+    let mut cursor = RecordCursor::start();
+    loop {
+        let rows = query_records(pg_pool, &query, cursor, request.batch_size, cipher).await?;
+        let Some(last_row) = rows.last() else {
+            break;
+        };
+        cursor = RecordCursor::after(last_row, &query);
+        for day_rows in rows.chunk_by(|a, b| a.timestamp.date_naive() == b.timestamp.date_naive()) {
+            let day = day_rows[0].timestamp.date_naive();
+            if current.as_ref().is_some_and(|file| file.day != day) {
+                segments.extend(
+                    current
+                        .take()
+                        .map(|file| close_day_file(request, file))
+                        .transpose()?,
+                );
+            }
+            let file = match current.as_mut() {
+                Some(file) => file,
+                None => current.insert(open_day_file(request, &schema, day)?),
+            };
+            file.writer
+                .write(&record_batch(&schema, day_rows, &request.json_columns)?)?;
+            file.rows += day_rows.len() as u64;
+            file.min_timestamp = file.min_timestamp.min(day_rows[0].timestamp);
+            file.max_timestamp = file
+                .max_timestamp
+                .max(day_rows[day_rows.len() - 1].timestamp);
+        }
+        info!(
+            "Parquet-eksport av {}: til og med {}",
+            request.topic, last_row.timestamp
+        );
+    }
+    segments.extend(
+        current
+            .map(|file| close_day_file(request, file))
+            .transpose()?,
+    );
+
+    let manifest = ExportManifest {
+        topic: request.topic.clone(),
+        format: PARQUET_FORMAT.to_string(),
+        from_timestamp: request.from_timestamp,
+        to_timestamp: request.to_timestamp,
+        exported_at: Utc::now(),
+        rows: segments.iter().map(|segment| segment.rows).sum(),
+        segments,
+        high_watermarks: None,
+    };
+    manifest.write(&request.output_dir)?;
+    Ok(manifest)
+}
+
+fn schema(json_columns: &[JsonColumn]) -> SchemaRef {
+    let mut fields = vec![
+        Field::new("topic", DataType::Utf8, false),
+        Field::new("partition", DataType::Int32, false),
+        Field::new("offset", DataType::Int64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("key", DataType::Binary, true),
+        Field::new("value", DataType::Binary, true),
+        Field::new("headers", DataType::Utf8, true),
+        Field::new("value_omitted", DataType::Boolean, false),
+    ];
+    fields.extend(
+        json_columns
+            .iter()
+            .map(|column| Field::new(&column.name, DataType::Utf8, true)),
+    );
+    Arc::new(Schema::new(fields))
+}
+
+fn record_batch(
+    schema: &SchemaRef,
+    rows: &[DataRow],
+    json_columns: &[JsonColumn],
+) -> Result<RecordBatch, Box<dyn Error>> {
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.kafka_topic),
+        )),
+        Arc::new(Int32Array::from_iter_values(
+            rows.iter().map(|row| row.kafka_partition as i32),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            rows.iter().map(|row| row.kafka_offset),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                rows.iter().map(|row| row.timestamp.timestamp_millis()),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(BinaryArray::from_iter(
+            rows.iter().map(|row| row.record_key.as_deref()),
+        )),
+        Arc::new(BinaryArray::from_iter(
+            rows.iter().map(|row| row.record_value.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            rows.iter()
+                .map(|row| row.headers.as_ref().map(Value::to_string)),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|row| Some(row.value_omitted)),
+        )),
+    ];
+    if !json_columns.is_empty() {
+        let values: Vec<Option<Value>> = rows
+            .iter()
+            .map(|row| {
+                row.record_value
+                    .as_deref()
+                    .and_then(|value| serde_json::from_slice(value).ok())
+            })
+            .collect();
+        for column in json_columns {
+            columns.push(Arc::new(StringArray::from_iter(values.iter().map(
+                |value| match value.as_ref()?.pointer(&column.pointer)? {
+                    Value::Null => None,
+                    Value::String(text) => Some(text.clone()),
+                    other => Some(other.to_string()),
+                },
+            ))));
+        }
+    }
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn open_day_file(
+    request: &ParquetExportRequest,
+    schema: &SchemaRef,
+    day: NaiveDate,
+) -> Result<DayFile, Box<dyn Error>> {
+    let file = format!(
+        "{}-{}.{}",
+        request.topic,
+        day.format("%Y-%m-%d"),
+        PARQUET_FORMAT
+    );
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let writer = ArrowWriter::try_new(
+        File::create_new(request.output_dir.join(&file))?,
+        schema.clone(),
+        Some(properties),
+    )?;
+    Ok(DayFile {
+        day,
+        file,
+        writer,
+        rows: 0,
+        min_timestamp: DateTime::<Utc>::MAX_UTC,
+        max_timestamp: DateTime::<Utc>::MIN_UTC,
+    })
+}
+
+fn close_day_file(
+    request: &ParquetExportRequest,
+    day_file: DayFile,
+) -> Result<SegmentInfo, Box<dyn Error>> {
+    let file = day_file.writer.into_inner()?;
+    file.sync_all()?;
+    let path = request.output_dir.join(&day_file.file);
+    Ok(SegmentInfo {
+        bytes: file.metadata()?.len(),
+        sha256: file_sha256(&path)?,
+        file: day_file.file,
+        rows: day_file.rows,
+        min_timestamp: Some(day_file.min_timestamp),
+        max_timestamp: Some(day_file.max_timestamp),
+    })
+}
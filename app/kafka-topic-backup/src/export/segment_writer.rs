@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::erasure::hash_chain::to_hex;
+use crate::export::manifest::SegmentInfo;
+
+/// Writes lines to numbered segment files, starting a new segment when the next line
+/// would make the current one larger than `max_bytes`. A single line larger than
+/// `max_bytes` gets a segment of its own.
+pub struct SegmentWriter {
+    dir: PathBuf,
+    prefix: String,
+    extension: &'static str,
+    max_bytes: u64,
+    current: Option<OpenSegment>,
+    segments: Vec<SegmentInfo>,
+}
+
+struct OpenSegment {
+    writer: BufWriter<File>,
+    hasher: Sha256,
+    info: SegmentInfo,
+}
+
+impl SegmentWriter {
+    pub fn new(dir: &Path, prefix: &str, extension: &'static str, max_bytes: u64) -> Self {
+        SegmentWriter {
+            dir: dir.to_path_buf(),
+            prefix: prefix.to_string(),
+            extension,
+            max_bytes,
+            current: None,
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn write_line(&mut self, line: &[u8], timestamp: DateTime<Utc>) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.current.as_ref().is_some_and(|segment| {
+            segment.info.bytes > 0 && segment.info.bytes + len > self.max_bytes
+        }) {
+            self.close_segment()?;
+        }
+        let segment = match self.current.as_mut() {
+            Some(segment) => segment,
+            None => self.current.insert(self.open_segment()?),
+        };
+        segment.writer.write_all(line)?;
+        segment.writer.write_all(b"\n")?;
+        segment.hasher.update(line);
+        segment.hasher.update(b"\n");
+        segment.info.rows += 1;
+        segment.info.bytes += len;
+        segment.info.min_timestamp = Some(
+            segment
+                .info
+                .min_timestamp
+                .map_or(timestamp, |min| min.min(timestamp)),
+        );
+        segment.info.max_timestamp = Some(
+            segment
+                .info
+                .max_timestamp
+                .map_or(timestamp, |max| max.max(timestamp)),
+        );
+        Ok(())
+    }
+
+    /// Flushes the last segment and returns all written segments in order
+    pub fn finish(mut self) -> io::Result<Vec<SegmentInfo>> {
+        self.close_segment()?;
+        Ok(self.segments)
+    }
+
+    fn open_segment(&self) -> io::Result<OpenSegment> {
+        let file = format!(
+            "{}-{:05}.{}",
+            self.prefix,
+            self.segments.len(),
+            self.extension
+        );
+        Ok(OpenSegment {
+            writer: BufWriter::new(File::create_new(self.dir.join(&file))?),
+            hasher: Sha256::new(),
+            info: SegmentInfo {
+                file,
+                rows: 0,
+                bytes: 0,
+                sha256: String::new(),
+                min_timestamp: None,
+                max_timestamp: None,
+            },
+        })
+    }
+
+    fn close_segment(&mut self) -> io::Result<()> {
+        if let Some(mut segment) = self.current.take() {
+            segment.writer.flush()?;
+            segment.writer.get_ref().sync_all()?;
+            segment.info.sha256 = to_hex(&segment.hasher.finalize());
+            self.segments.push(segment.info);
+        }
+        Ok(())
+    }
+}
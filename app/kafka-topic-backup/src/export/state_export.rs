@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use log::info;
+use sqlx::PgPool;
+
+use crate::database::current_state::{begin_snapshot, get_topic_hwms, query_current_state};
+use crate::encoding::ValueEncoding;
+use crate::encryption::data_cipher::DataCipher;
+use crate::encryption::key_hash::record_key_hash;
+use crate::export::jsonl_export::JSONL_FORMAT;
+use crate::export::manifest::{ExportManifest, prepare_output_dir};
+use crate::export::segment_writer::SegmentWriter;
+use crate::query_api::records::RecordResponse;
+
+#[derive(Debug, Clone)]
+pub struct StateExportRequest {
+    pub topic: String,
+    pub output_dir: PathBuf,
+    pub max_segment_bytes: u64,
+    pub batch_size: i64,
+    /// Preferred encoding of keys and values, values that are not UTF-8 are always base64
+    pub encoding: ValueEncoding,
+}
+
+/// Writes the current state of the topic, the latest record of each key, to JSON Lines
+/// segments like the regular export. The state and the HWMs in the manifest are read in
+/// one snapshot, so the export is the compacted topic as of those HWMs.
+pub async fn export_current_state(
+    pg_pool: &PgPool,
+    request: &StateExportRequest,
+    cipher: Option<&DataCipher>,
+) -> Result<ExportManifest, Box<dyn Error>> {
+    prepare_output_dir(&request.output_dir)?;
+    let mut writer = SegmentWriter::new(
+        &request.output_dir,
+        &request.topic,
+        JSONL_FORMAT,
+        request.max_segment_bytes,
+    );
+    let mut tx = begin_snapshot(pg_pool).await?;
+    let high_watermarks = get_topic_hwms(&mut tx, &request.topic).await?;
+    let mut after_key_hash = None;
+    let mut rows_written = 0;
+    loop {
+        let rows = query_current_state(
+            &mut tx,
+            &request.topic,
+            after_key_hash.as_deref(),
+            request.batch_size,
+            cipher,
+        )
+        .await?;
+        let Some(last_key) = rows.last().and_then(|row| row.record_key.as_deref()) else {
+            break;
+        };
+        after_key_hash = Some(record_key_hash(last_key));
+        for row in rows {
+            let timestamp = row.timestamp;
+            let record = RecordResponse::from_row(row, request.encoding)?;
+            writer.write_line(&serde_json::to_vec(&record)?, timestamp)?;
+            rows_written += 1;
+        }
+        info!(
+            "Eksport av gjeldende tilstand for {}: {} nøkler skrevet",
+            request.topic, rows_written
+        );
+    }
+    tx.commit().await?;
+
+    let segments = writer.finish()?;
+    let manifest = ExportManifest {
+        topic: request.topic.clone(),
+        format: JSONL_FORMAT.to_string(),
+        from_timestamp: None,
+        to_timestamp: None,
+        exported_at: Utc::now(),
+        rows: segments.iter().map(|segment| segment.rows).sum(),
+        segments,
+        high_watermarks: Some(high_watermarks),
+    };
+    manifest.write(&request.output_dir)?;
+    Ok(manifest)
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use log::info;
+use rdkafka::producer::FutureProducer;
+use sqlx::PgPool;
+
+use crate::database::current_state::upsert_current_state;
+use crate::database::erasure::get_erased_offsets_in_range;
+use crate::database::insert_data::{InsertDataRow, import_data_batch};
+use crate::database::read_data::DataRow;
+use crate::encryption::data_cipher::DataCipher;
+use crate::encryption::key_hash::record_key_hash;
+use crate::import::segment_source::SegmentSource;
+use crate::kafka::topic_subscription::TopicSelector;
+use crate::query_api::records::RecordResponse;
+use crate::restore::restore_topic::send_row;
+
+pub struct ImportRequest {
+    pub source: SegmentSource,
+    /// Only records with a timestamp at or after this
+    pub from_timestamp: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub to_timestamp: Option<DateTime<Utc>>,
+    pub batch_size: usize,
+}
+
+/// Where imported records go
+pub enum ImportTarget<'a> {
+    /// Back into the data table of the topic they were backed up from, compressed with
+    /// the policy of the topic and encrypted like records consumed from Kafka. The current
+    /// state is updated for topics that keep it. Records without their value keep the
+    /// `value_omitted` marker.
+    Database {
+        selector: &'a TopicSelector,
+        cipher: Option<&'a DataCipher>,
+    },
+    /// Produced to the topic with the original key, value, headers and timestamp
+    Kafka {
+        producer: &'a FutureProducer,
+        topic: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub segments: usize,
+    /// Records in the segments within the time range
+    pub records_read: u64,
+    pub records_imported: u64,
+    /// Records that were already stored, only counted when importing to the database
+    pub records_existing: u64,
+    /// Records erased after they were exported or archived, which are never imported
+    pub records_erased: u64,
+    /// Records stored without their value by a `keys_only` policy, which are not
+    /// produced to Kafka since they would become tombstones
+    pub records_value_omitted: u64,
+}
+
+/// Imports the records of every segment of the source in the time range. Each segment
+/// is checked against the checksum in its manifest or catalog entry before any of its
+/// records are imported, so a corrupt segment stops the import at a segment boundary.
+/// Importing to the database can be repeated, records that are already stored are
+/// skipped.
+pub async fn import_segments(
+    pg_pool: &PgPool,
+    request: &ImportRequest,
+    target: &ImportTarget<'_>,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    let mut summary = ImportSummary::default();
+    let segments = request.source.segments(pg_pool).await?;
+    for segment in segments
+        .iter()
+        .filter(|segment| segment.overlaps(request.from_timestamp, request.to_timestamp))
+    {
+        let content = request.source.read_segment(segment).await?;
+        let mut rows = Vec::new();
+        for line in content.split(|byte| *byte == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let row = serde_json::from_slice::<RecordResponse>(line)?.into_row()?;
+            if request
+                .from_timestamp
+                .is_none_or(|from| row.timestamp >= from)
+                && request.to_timestamp.is_none_or(|to| row.timestamp < to)
+            {
+                rows.push(row);
+            }
+        }
+        for batch in rows.chunks(request.batch_size.max(1)) {
+            summary.records_read += batch.len() as u64;
+            let batch = without_erased(pg_pool, batch, &mut summary).await?;
+            match target {
+                ImportTarget::Database { selector, cipher } => {
+                    let imported = insert_rows(pg_pool, &batch, selector, *cipher).await?;
+                    summary.records_imported += imported;
+                    summary.records_existing += batch.len() as u64 - imported;
+                }
+                ImportTarget::Kafka { producer, topic } => {
+                    let (omitted, batch): (Vec<&DataRow>, Vec<&DataRow>) =
+                        batch.into_iter().partition(|row| row.value_omitted);
+                    produce_rows(producer, topic, &batch).await?;
+                    summary.records_imported += batch.len() as u64;
+                    summary.records_value_omitted += omitted.len() as u64;
+                }
+            }
+        }
+        summary.segments += 1;
+        info!(
+            "Importerte segmentet {}: {} av {} meldinger importert så langt",
+            segment.name, summary.records_imported, summary.records_read
+        );
+    }
+    Ok(summary)
+}
+
+/// The rows of the batch that have not been erased
+async fn without_erased<'a>(
+    pg_pool: &PgPool,
+    batch: &'a [DataRow],
+    summary: &mut ImportSummary,
+) -> Result<Vec<&'a DataRow>, Box<dyn Error>> {
+    let mut ranges: BTreeMap<(&str, i16), (i64, i64)> = BTreeMap::new();
+    for row in batch {
+        ranges
+            .entry((&row.kafka_topic, row.kafka_partition))
+            .and_modify(|(min, max)| {
+                *min = (*min).min(row.kafka_offset);
+                *max = (*max).max(row.kafka_offset);
+            })
+            .or_insert((row.kafka_offset, row.kafka_offset));
+    }
+    let mut erased = HashSet::new();
+    let mut tx = pg_pool.begin().await?;
+    for ((topic, partition), (min, max)) in ranges {
+        for offset in
+            get_erased_offsets_in_range(&mut tx, topic, partition as i32, min, max).await?
+        {
+            erased.insert((topic, partition, offset));
+        }
+    }
+    tx.commit().await?;
+    let rows: Vec<&DataRow> = batch
+        .iter()
+        .filter(|row| {
+            !erased.contains(&(
+                row.kafka_topic.as_str(),
+                row.kafka_partition,
+                row.kafka_offset,
+            ))
+        })
+        .collect();
+    summary.records_erased += (batch.len() - rows.len()) as u64;
+    Ok(rows)
+}
+
+async fn insert_rows(
+    pg_pool: &PgPool,
+    rows: &[&DataRow],
+    selector: &TopicSelector,
+    cipher: Option<&DataCipher>,
+) -> Result<u64, Box<dyn Error>> {
+    let mut insert_rows = Vec::with_capacity(rows.len());
+    let mut state_records: BTreeMap<(&str, i32), Vec<_>> = BTreeMap::new();
+    for row in rows {
+        let policy = selector.policy_for(&row.kafka_topic);
+        if policy.current_state && !policy.keys_only && row.record_key.is_some() {
+            state_records
+                .entry((&row.kafka_topic, row.kafka_partition as i32))
+                .or_default()
+                .push((row.kafka_offset, row.timestamp));
+        }
+        let (record_value, value_codec) = match row.record_value.clone() {
+            Some(value) => {
+                let (stored, codec) = policy.compression.apply(value)?;
+                (Some(stored), codec)
+            }
+            None => (None, None),
+        };
+        insert_rows.push(InsertDataRow {
+            kafka_topic: row.kafka_topic.clone(),
+            kafka_partition: row.kafka_partition as i32,
+            kafka_offset: row.kafka_offset,
+            timestamp: row.timestamp,
+            headers: row.headers.clone(),
+            record_key_hash: row.record_key.as_deref().map(record_key_hash),
+            record_key: row.record_key.clone(),
+            record_value,
+            value_codec,
+            value_omitted: row.value_omitted,
+        });
+    }
+    let mut tx = pg_pool.begin().await?;
+    let imported = import_data_batch(&mut tx, insert_rows, cipher).await?;
+    // Records that were already stored are upserted again, which changes nothing
+    for ((topic, partition), records) in &state_records {
+        upsert_current_state(&mut tx, topic, *partition, records).await?;
+    }
+    tx.commit().await?;
+    Ok(imported)
+}
+
+/// Produces the rows and waits until the broker has acknowledged all of them
+async fn produce_rows(
+    producer: &FutureProducer,
+    topic: &str,
+    rows: &[&DataRow],
+) -> Result<(), Box<dyn Error>> {
+    let mut deliveries = Vec::with_capacity(rows.len());
+    for row in rows {
+        deliveries.push(send_row(producer, topic, None, row).await?);
+    }
+    for (row, delivery) in rows.iter().zip(deliveries) {
+        if let Err((e, _)) = delivery.await? {
+            return Err(format!(
+                "Failed to import {}::{}::{} to {}: {}",
+                row.kafka_topic, row.kafka_partition, row.kafka_offset, topic, e
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
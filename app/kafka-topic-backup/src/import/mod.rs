@@ -0,0 +1,2 @@
+pub mod import_segments;
+pub mod segment_source;
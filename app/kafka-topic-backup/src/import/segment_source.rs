@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use object_store::ObjectStore;
+use object_store::path::Path;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::database::archive_catalog::get_archive_segments;
+use crate::erasure::hash_chain::to_hex;
+use crate::export::jsonl_export::JSONL_FORMAT;
+use crate::export::manifest::ExportManifest;
+
+/// Where segments are imported from
+pub enum SegmentSource {
+    /// A JSON Lines export, its manifest lists the segments and their checksums
+    ExportDir(PathBuf),
+    /// Segments archived for the topic, the catalog lists them and their checksums
+    Archive {
+        store: Arc<dyn ObjectStore>,
+        topic: String,
+    },
+}
+
+/// A segment with the size and checksum it was written with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentEntry {
+    /// File name in the export directory or object key in the archive
+    pub name: String,
+    pub bytes: u64,
+    /// Hex-encoded SHA-256 of the segment as written
+    pub sha256: String,
+    pub min_timestamp: Option<DateTime<Utc>>,
+    /// Exclusive for archived segments, which cover a whole day
+    pub max_timestamp: Option<DateTime<Utc>>,
+}
+
+impl SegmentEntry {
+    /// Archived segments are zstd-compressed
+    pub fn is_compressed(&self) -> bool {
+        self.name.ends_with(".zst")
+    }
+
+    /// Whether the segment can have records in the time range, `to` is exclusive
+    pub fn overlaps(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+        let starts_before_end = match (self.min_timestamp, to) {
+            (Some(min), Some(to)) => min < to,
+            _ => true,
+        };
+        let ends_after_start = match (self.max_timestamp, from) {
+            (Some(max), Some(from)) => max >= from,
+            _ => true,
+        };
+        starts_before_end && ends_after_start
+    }
+}
+
+impl SegmentSource {
+    pub async fn segments(&self, pg_pool: &PgPool) -> Result<Vec<SegmentEntry>, Box<dyn Error>> {
+        match self {
+            SegmentSource::ExportDir(dir) => {
+                let manifest = ExportManifest::read(dir)?;
+                if manifest.format != JSONL_FORMAT {
+                    return Err(format!(
+                        "Only {} exports can be imported, {} is {}",
+                        JSONL_FORMAT,
+                        dir.display(),
+                        manifest.format
+                    )
+                    .into());
+                }
+                Ok(manifest
+                    .segments
+                    .into_iter()
+                    .map(|segment| SegmentEntry {
+                        name: segment.file,
+                        bytes: segment.bytes,
+                        sha256: segment.sha256,
+                        min_timestamp: segment.min_timestamp,
+                        max_timestamp: segment.max_timestamp,
+                    })
+                    .collect())
+            }
+            SegmentSource::Archive { topic, .. } => Ok(get_archive_segments(pg_pool, topic)
+                .await?
+                .into_iter()
+                .map(|segment| SegmentEntry {
+                    name: segment.object_key,
+                    bytes: segment.bytes as u64,
+                    sha256: to_hex(&segment.sha256),
+                    min_timestamp: Some(segment.window_start),
+                    max_timestamp: Some(segment.window_end),
+                })
+                .collect()),
+        }
+    }
+
+    /// Reads the segment and checks its size and checksum, compressed segments are
+    /// returned decompressed
+    pub async fn read_segment(&self, segment: &SegmentEntry) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = match self {
+            SegmentSource::ExportDir(dir) => fs::read(dir.join(&segment.name))?,
+            SegmentSource::Archive { store, .. } => store
+                .get(&Path::from(segment.name.as_str()))
+                .await?
+                .bytes()
+                .await?
+                .to_vec(),
+        };
+        let sha256 = to_hex(&Sha256::digest(&content));
+        if content.len() as u64 != segment.bytes || sha256 != segment.sha256 {
+            return Err(format!(
+                "Segment {} does not match its checksum, expected {} bytes with SHA-256 {}, found {} bytes with {}",
+                segment.name,
+                segment.bytes,
+                segment.sha256,
+                content.len(),
+                sha256
+            )
+            .into());
+        }
+        if segment.is_compressed() {
+            Ok(zstd::decode_all(content.as_slice())?)
+        } else {
+            Ok(content)
+        }
+    }
+}
@@ -1,24 +1,49 @@
+use crate::config::KafkaConfig;
 use crate::config_utils::get_env::get_env;
 use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
-use std::{error::Error, time::SystemTime};
+use std::{collections::BTreeMap, error::Error, time::SystemTime};
+
+/// Settings shared by every client we create: brokers, client id and security.
+/// The SSL files are only required when the security protocol is `ssl`, which
+/// lets us run against a plaintext broker locally and in tests.
+fn get_connection_config(
+    application_kafka_config: &ApplicationKafkaConfig,
+) -> Result<ClientConfig, Box<dyn Error>> {
+    let brokers = get_env("KAFKA_BROKERS")?;
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", brokers)
+        .set("client.id", &application_kafka_config.client_id)
+        .set(
+            "security.protocol",
+            &application_kafka_config.security_protocol,
+        );
+    if application_kafka_config
+        .security_protocol
+        .eq_ignore_ascii_case("ssl")
+    {
+        config
+            .set("ssl.key.location", get_env("KAFKA_PRIVATE_KEY_PATH")?)
+            .set(
+                "ssl.certificate.location",
+                get_env("KAFKA_CERTIFICATE_PATH")?,
+            )
+            .set("ssl.ca.location", get_env("KAFKA_CA_PATH")?);
+    }
+    Ok(config)
+}
 
 fn get_kafka_config(
     application_kafka_config: ApplicationKafkaConfig,
 ) -> Result<ClientConfig, Box<dyn Error>> {
-    let brokers = get_env("KAFKA_BROKERS")?;
-    let kafka_private_key_path = get_env("KAFKA_PRIVATE_KEY_PATH")?;
-    let kafka_certificate_path = get_env("KAFKA_CERTIFICATE_PATH")?;
-    let kafka_ca_path = get_env("KAFKA_CA_PATH")?;
     let auto_commit = if application_kafka_config.auto_commit {
         "true"
     } else {
         "false"
     };
-    let mut config = ClientConfig::new();
+    let mut config = get_connection_config(&application_kafka_config)?;
     config
-        .set("bootstrap.servers", brokers)
         .set("group.id", application_kafka_config.group_id)
-        .set("client.id", application_kafka_config.client_id)
         .set(
             "session.timeout.ms",
             application_kafka_config.session_timeout_ms.to_string(),
@@ -28,13 +53,6 @@ fn get_kafka_config(
             application_kafka_config.auto_offset_reset,
         )
         .set("enable.auto.commit", auto_commit)
-        .set(
-            "security.protocol",
-            application_kafka_config.security_protocol,
-        )
-        .set("ssl.key.location", kafka_private_key_path)
-        .set("ssl.certificate.location", kafka_certificate_path)
-        .set("ssl.ca.location", kafka_ca_path)
         // Memory-constrained settings using only valid rdkafka properties
         // Note: fetch.max.bytes must be >= message.max.bytes (default 1MB)
         .set("message.max.bytes", "65536") // 64KB max message size
@@ -48,6 +66,24 @@ fn get_kafka_config(
         .set("fetch.wait.max.ms", "100") // Don't wait long for data
         .set("receive.message.max.bytes", "200000") // 200KB max response (must be > fetch.max.bytes + 512)
         .set_log_level(RDKafkaLogLevel::Info);
+    for (key, value) in &application_kafka_config.properties {
+        config.set(key, value);
+    }
+    Ok(config)
+}
+
+fn get_kafka_producer_config(
+    application_kafka_config: ApplicationKafkaConfig,
+) -> Result<ClientConfig, Box<dyn Error>> {
+    let mut config = get_connection_config(&application_kafka_config)?;
+    config
+        // Idempotence keeps the per-partition order intact when the producer retries
+        .set("enable.idempotence", "true")
+        .set("message.timeout.ms", "60000")
+        .set_log_level(RDKafkaLogLevel::Info);
+    for (key, value) in &application_kafka_config.properties {
+        config.set(key, value);
+    }
     Ok(config)
 }
 
@@ -59,6 +95,9 @@ pub struct ApplicationKafkaConfig {
     pub security_protocol: String,
     pub auto_offset_reset: String,
     pub session_timeout_ms: i64,
+    /// rdkafka properties from `[kafka.properties]`, applied last to every consumer and
+    /// producer so they override the defaults above
+    pub properties: BTreeMap<String, String>,
 }
 
 impl Default for ApplicationKafkaConfig {
@@ -69,11 +108,12 @@ impl Default for ApplicationKafkaConfig {
         );
         Self {
             group_id: "default-group".to_string(),
-            client_id: client_id,
+            client_id,
             auto_commit: false,
             security_protocol: "ssl".to_string(),
             auto_offset_reset: "earliest".to_string(),
             session_timeout_ms: 6000,
+            properties: BTreeMap::new(),
         }
     }
 }
@@ -86,9 +126,23 @@ impl ApplicationKafkaConfig {
             ..Default::default()
         }
     }
+
+    pub fn from_config(kafka_config: &KafkaConfig) -> Self {
+        ApplicationKafkaConfig {
+            group_id: kafka_config.group_id.to_string(),
+            security_protocol: kafka_config.security_protocol.to_string(),
+            auto_offset_reset: kafka_config.auto_offset_reset.to_string(),
+            properties: kafka_config.properties(),
+            ..Default::default()
+        }
+    }
+
     pub fn rdkafka_config(&self) -> Result<ClientConfig, Box<dyn Error>> {
         get_kafka_config(self.clone())
     }
+    pub fn rdkafka_producer_config(&self) -> Result<ClientConfig, Box<dyn Error>> {
+        get_kafka_producer_config(self.clone())
+    }
 }
 
 fn unix_timestamp_millis() -> Result<u128, Box<dyn Error>> {
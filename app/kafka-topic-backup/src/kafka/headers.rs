@@ -1,38 +1,111 @@
-use base64::{Engine as _, engine::general_purpose};
+use crate::encoding::{ValueEncoding, decode_bytes, encode_bytes};
 use rdkafka::{
     Message,
-    message::{BorrowedMessage, Headers},
+    message::{BorrowedMessage, Header, Headers, OwnedHeaders},
 };
-use serde_json::{Map, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::error::Error;
 
-/// Converts Kafka message headers to JSON format
+/// A single Kafka header, the value is `None` when the header has no value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaHeader {
+    pub key: String,
+    pub value: Option<Vec<u8>>,
+}
+
+/// The shape of one header entry in the `headers` JSONB column
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredHeader {
+    key: String,
+    value: Option<String>,
+    encoding: ValueEncoding,
+}
+
+/// Extracts the headers of a Kafka message in their original order
 ///
-/// Returns None if the message has no headers, otherwise returns a JSON object
-/// where keys are header names and values are either strings (for UTF-8 data)
-/// or base64-encoded strings (for binary data)
-pub fn extract_headers_as_json(msg: &BorrowedMessage<'_>) -> Result<Option<Value>, Box<dyn Error>> {
-    match msg.headers() {
-        Some(headers) => {
-            let mut header_map = Map::new();
+/// Returns None if the message has no headers. Repeated keys are kept as separate entries.
+pub fn extract_headers(msg: &BorrowedMessage<'_>) -> Option<Vec<KafkaHeader>> {
+    msg.headers().map(|headers| {
+        headers
+            .iter()
+            .map(|header| KafkaHeader {
+                key: header.key.to_string(),
+                value: header.value.map(|value| value.to_vec()),
+            })
+            .collect()
+    })
+}
 
-            for header in headers.iter() {
-                let key = header.key;
-                let value = match header.value {
-                    Some(val) => {
-                        // Try to decode as UTF-8, fallback to base64 for binary data
-                        match std::str::from_utf8(val) {
-                            Ok(s) => Value::String(s.to_string()),
-                            Err(_) => Value::String(general_purpose::STANDARD.encode(val)),
-                        }
-                    }
-                    None => Value::Null,
-                };
-                header_map.insert(key.to_string(), value);
+/// Converts headers to the JSON array stored in the database
+///
+/// Each entry holds the key, the value and the encoding of the value: UTF-8 data is
+/// stored as is, binary data and UTF-8 with NUL are base64-encoded.
+pub fn headers_to_json(headers: &[KafkaHeader]) -> Value {
+    let stored: Vec<StoredHeader> = headers
+        .iter()
+        .map(|header| {
+            let (value, encoding) = match header.value.as_deref() {
+                Some(value) => {
+                    let (value, encoding) = encode_bytes(value, ValueEncoding::Utf8);
+                    (Some(value), encoding)
+                }
+                None => (None, ValueEncoding::Utf8),
+            };
+            StoredHeader {
+                key: header.key.clone(),
+                value,
+                encoding,
             }
+        })
+        .collect();
+    serde_json::to_value(stored).expect("Stored headers are always valid JSON")
+}
 
-            Ok(Some(serde_json::to_value(header_map)?))
-        }
-        None => Ok(None),
+/// Reads headers from the `headers` JSONB column
+///
+/// Rows written before headers were stored as an ordered list contain a JSON object
+/// of key to value. Those values carry no encoding marker, so they are read back as
+/// the UTF-8 bytes of the stored string, and the original order is unknown.
+pub fn headers_from_json(headers: &Value) -> Result<Vec<KafkaHeader>, Box<dyn Error>> {
+    match headers {
+        Value::Array(entries) => entries
+            .iter()
+            .map(|entry| {
+                let stored: StoredHeader = serde_json::from_value(entry.clone())?;
+                let value = match stored.value {
+                    Some(value) => Some(decode_bytes(&value, stored.encoding)?),
+                    None => None,
+                };
+                Ok(KafkaHeader {
+                    key: stored.key,
+                    value,
+                })
+            })
+            .collect(),
+        Value::Object(legacy_map) => Ok(legacy_map
+            .iter()
+            .map(|(key, value)| KafkaHeader {
+                key: key.clone(),
+                value: match value {
+                    Value::Null => None,
+                    Value::String(s) => Some(s.as_bytes().to_vec()),
+                    other => Some(other.to_string().into_bytes()),
+                },
+            })
+            .collect()),
+        other => Err(format!("Unexpected headers format: {}", other).into()),
     }
 }
+
+pub fn to_kafka_headers(headers: &[KafkaHeader]) -> OwnedHeaders {
+    headers.iter().fold(
+        OwnedHeaders::new_with_capacity(headers.len()),
+        |kafka_headers, header| {
+            kafka_headers.insert(Header {
+                key: &header.key,
+                value: header.value.as_deref(),
+            })
+        },
+    )
+}
@@ -1,25 +1,33 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use crate::{
     app_state::AppState,
     database::hwm_statements::{get_hwm, insert_hwm},
+    kafka::topic_policy::StartPosition,
 };
 use log::{error, info};
 use rdkafka::{
-    ClientContext, Offset,
-    consumer::{BaseConsumer, Consumer, ConsumerContext, Rebalance},
+    ClientContext, Offset, TopicPartitionList,
+    consumer::{BaseConsumer, Consumer, ConsumerContext, Rebalance, StreamConsumer},
     topic_partition_list::TopicPartitionListElem,
 };
 use sqlx::PgPool;
+use tokio::sync::mpsc::UnboundedSender;
+
+const WATERMARK_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct Hwm {
-    topic: String,
-    partition: i32,
-    hwm: i64,
+    pub topic: String,
+    pub partition: i32,
+    pub hwm: i64,
 }
 
 impl Hwm {
-    fn seek_to_rdkafka_offset(&self) -> Offset {
+    pub fn seek_to_rdkafka_offset(&self) -> Offset {
         match self.hwm {
             -1 => Offset::Beginning,
             _ => Offset::Offset(self.hwm + 1), //HWM er sist leste melding, i seek_to skal vi ha neste melding vi vil lese.
@@ -27,14 +35,35 @@ impl Hwm {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Topic {
-    name: String,
-    partition: i32,
+    pub name: String,
+    pub partition: i32,
+}
+
+/// Changes in the partitions assigned to the consumer, sent from the rebalance callback
+#[derive(Debug)]
+pub enum AssignmentEvent {
+    Assigned(Vec<Topic>),
+    Revoked(Vec<Topic>),
 }
 
+/// Pauses newly assigned partitions and hands them over to the
+/// [`PartitionPositioner`](crate::kafka::partition_positioner::PartitionPositioner),
+/// which looks up the HWMs and seeks outside the rdkafka callback.
 pub struct HwmRebalanceHandler {
-    pub pg_pool: PgPool,
     pub app_state: Arc<AppState>,
+    pub assignments: UnboundedSender<AssignmentEvent>,
+    /// Partitions revoked since the consumer loop last checked, see [`Self::take_revoked`]
+    pub revoked: Mutex<Vec<Topic>>,
+}
+
+impl HwmRebalanceHandler {
+    /// The partitions revoked since the last call, their pending batches must be dropped
+    /// so they do not overwrite what the new owner of the partition stores
+    pub fn take_revoked(&self) -> Vec<Topic> {
+        std::mem::take(&mut *self.revoked.lock().unwrap_or_else(|e| e.into_inner()))
+    }
 }
 
 impl Default for HwmRebalanceHandler {
@@ -42,67 +71,137 @@ impl Default for HwmRebalanceHandler {
         panic!("Default not implemented for HwmRebalanceHandler");
     }
 }
-const DEFAULT_HWM: i64 = -1;
-impl HwmRebalanceHandler {
-    async fn get_hwms(&self, topics: Vec<Topic>) -> Result<Vec<Hwm>, Box<dyn Error>> {
-        let mut tx = self.pg_pool.begin().await?;
-        let mut hwms = Vec::new();
-        for topic in topics {
-            let hwm = get_hwm(&mut tx, &topic.name, topic.partition).await?;
-            let hwm = if hwm.is_none() {
-                info!(
-                    "HWM for {}::{} not found, inserting {} as HWM in DB",
-                    topic.name, topic.partition, DEFAULT_HWM
-                );
-                insert_hwm(&mut tx, &topic.name, topic.partition, DEFAULT_HWM).await?;
-                DEFAULT_HWM
-            } else {
-                hwm.unwrap()
-            };
-            hwms.push(Hwm {
-                topic: topic.name,
+/// HWM of a partition where nothing has been read, the partition is read from the beginning
+pub const DEFAULT_HWM: i64 = -1;
+
+/// Returns the stored HWMs and the partitions that have no HWM yet
+pub async fn get_hwms(
+    pg_pool: &PgPool,
+    topics: &[Topic],
+) -> Result<(Vec<Hwm>, Vec<Topic>), Box<dyn Error>> {
+    let mut tx = pg_pool.begin().await?;
+    let mut hwms = Vec::new();
+    let mut missing = Vec::new();
+    for topic in topics {
+        match get_hwm(&mut tx, &topic.name, topic.partition).await? {
+            Some(hwm) => hwms.push(Hwm {
+                topic: topic.name.clone(),
                 partition: topic.partition,
-                hwm: hwm,
-            });
+                hwm,
+            }),
+            None => missing.push(topic.clone()),
+        }
+    }
+    tx.commit().await?;
+    Ok((hwms, missing))
+}
+
+pub async fn insert_start_hwms(pg_pool: &PgPool, hwms: &[Hwm]) -> Result<(), Box<dyn Error>> {
+    let mut tx = pg_pool.begin().await?;
+    for hwm in hwms {
+        info!(
+            "HWM for {}::{} not found, inserting {} as HWM in DB",
+            hwm.topic, hwm.partition, hwm.hwm
+        );
+        insert_hwm(&mut tx, &hwm.topic, hwm.partition, hwm.hwm).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The HWM to start a partition without a stored HWM from, blocks while the broker is asked
+pub fn start_hwm<C: ConsumerContext>(
+    consumer: &StreamConsumer<C>,
+    topic: &Topic,
+    start_position: StartPosition,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    match start_position {
+        StartPosition::Earliest => Ok(DEFAULT_HWM),
+        StartPosition::Latest => latest_hwm(consumer, topic),
+        StartPosition::Timestamp(timestamp) => {
+            let mut timestamps = TopicPartitionList::new();
+            timestamps.add_partition_offset(
+                &topic.name,
+                topic.partition,
+                Offset::Offset(timestamp.timestamp_millis()),
+            )?;
+            let offsets = consumer.offsets_for_times(timestamps, WATERMARK_TIMEOUT)?;
+            let offset = offsets
+                .find_partition(&topic.name, topic.partition)
+                .map(|elem| elem.offset());
+            match offset {
+                // The first record at or after the timestamp is the next one to read
+                Some(Offset::Offset(offset)) => Ok(offset - 1),
+                // No record is that new yet, so only later records are read
+                Some(Offset::End) => latest_hwm(consumer, topic),
+                other => Err(format!(
+                    "Unexpected offset {:?} for timestamp {} on {}::{}",
+                    other, timestamp, topic.name, topic.partition
+                )
+                .into()),
+            }
         }
-        tx.commit().await?;
-        Ok(hwms)
     }
 }
 
+fn latest_hwm<C: ConsumerContext>(
+    consumer: &StreamConsumer<C>,
+    topic: &Topic,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    let (_low, high) =
+        consumer.fetch_watermarks(&topic.name, topic.partition, WATERMARK_TIMEOUT)?;
+    // The high watermark is the offset of the next record
+    Ok(high - 1)
+}
+
+fn to_topics(topic_partitions: &TopicPartitionList) -> Vec<Topic> {
+    topic_partitions
+        .elements()
+        .iter()
+        .map(|elem: &TopicPartitionListElem| Topic {
+            name: elem.topic().to_string(),
+            partition: elem.partition(),
+        })
+        .collect()
+}
+
 impl ClientContext for HwmRebalanceHandler {}
 
 impl ConsumerContext for HwmRebalanceHandler {
     fn post_rebalance(&self, base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
         match rebalance {
             Rebalance::Assign(topic_partitions) => {
-                let topics = topic_partitions
-                    .elements()
-                    .iter()
-                    .map(|elem: &TopicPartitionListElem| Topic {
-                        name: elem.topic().to_string(),
-                        partition: elem.partition(),
-                    })
-                    .collect();
-
-                let hwm = futures::executor::block_on(self.get_hwms(topics)).unwrap();
-                hwm.iter().for_each(|hwm| {
+                let topics = to_topics(topic_partitions);
+                // Nothing is consumed from the new partitions until they have been seeked to the HWM
+                if let Err(e) = base_consumer.pause(topic_partitions) {
+                    error!("Klarte ikke å pause tildelte partisjoner: {}", e);
+                }
+                let count = topics.len();
+                self.app_state.add_queued_partitions(count);
+                topics.iter().for_each(|topic| {
                     info!(
-                        "Assigned: topic: {}, partition: {}, hwm: {}",
-                        hwm.topic, hwm.partition, hwm.hwm
+                        "Assigned: topic: {}, partition: {}, venter på HWM",
+                        topic.name, topic.partition
                     );
-                    base_consumer
-                        .seek(
-                            &hwm.topic,
-                            hwm.partition,
-                            hwm.seek_to_rdkafka_offset(),
-                            std::time::Duration::from_secs(10),
-                        )
-                        .unwrap();
                 });
+                if self
+                    .assignments
+                    .send(AssignmentEvent::Assigned(topics))
+                    .is_err()
+                {
+                    error!("Partition positioner har stoppet, kan ikke posisjonere partisjoner");
+                    self.app_state.remove_queued_partitions(count);
+                    self.app_state.set_is_alive(false);
+                }
             }
-            Rebalance::Revoke(_) => {
-                info!("Topic partitions revoked")
+            Rebalance::Revoke(topic_partitions) => {
+                info!("Topic partitions revoked");
+                let topics = to_topics(topic_partitions);
+                self.revoked
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .extend(topics.iter().cloned());
+                let _ = self.assignments.send(AssignmentEvent::Revoked(topics));
             }
             Rebalance::Error(e) => {
                 error!("Rebalance error: {}", e);
@@ -1,22 +1,70 @@
 use std::{error::Error, sync::Arc};
 
 use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::producer::FutureProducer;
 use sqlx::PgPool;
+use tokio::sync::mpsc;
 
 use crate::{
     app_state::AppState,
-    kafka::{config::ApplicationKafkaConfig, hwm::HwmRebalanceHandler},
+    kafka::{
+        config::ApplicationKafkaConfig,
+        hwm::HwmRebalanceHandler,
+        partition_positioner::PartitionPositioner,
+        topic_subscription::{
+            TOPIC_REFRESH_INTERVAL, TopicSelector, log_selected_topics, run_topic_subscription,
+            select_topics,
+        },
+    },
 };
 
+/// Creates the consumer and starts the task that positions assigned partitions at
+/// their HWM. When the selector has patterns, a second task picks up new matching
+/// topics. The tasks stop when the returned consumer is dropped.
 pub fn create_kafka_consumer(
     app_state: Arc<AppState>,
     pg_pool: PgPool,
     app_config: ApplicationKafkaConfig,
-    topics: &[&str],
-) -> Result<StreamConsumer<HwmRebalanceHandler>, Box<dyn Error>> {
+    selector: Arc<TopicSelector>,
+) -> Result<Arc<StreamConsumer<HwmRebalanceHandler>>, Box<dyn Error>> {
     let config = app_config.rdkafka_config()?;
-    let context = HwmRebalanceHandler { pg_pool, app_state };
-    let consumer: StreamConsumer<HwmRebalanceHandler> = config.create_with_context(context)?;
-    consumer.subscribe(topics)?;
+    let (assignments, events) = mpsc::unbounded_channel();
+    let context = HwmRebalanceHandler {
+        app_state: app_state.clone(),
+        assignments,
+        revoked: Default::default(),
+    };
+    let consumer: Arc<StreamConsumer<HwmRebalanceHandler>> =
+        Arc::new(config.create_with_context(context)?);
+    let positioner = PartitionPositioner::new(
+        pg_pool,
+        app_state,
+        Arc::downgrade(&consumer),
+        events,
+        selector.clone(),
+    );
+    tokio::spawn(positioner.run());
+
+    let topics = select_topics(&consumer, &selector)?;
+    if topics.is_empty() {
+        return Err("Ingen topics matcher konfigurasjonen".into());
+    }
+    log_selected_topics(&topics);
+    consumer.subscribe(&topics.iter().map(String::as_str).collect::<Vec<_>>())?;
+    if selector.has_patterns() {
+        tokio::spawn(run_topic_subscription(
+            Arc::downgrade(&consumer),
+            selector,
+            topics,
+            TOPIC_REFRESH_INTERVAL,
+        ));
+    }
     Ok(consumer)
 }
+
+pub fn create_kafka_producer(
+    app_config: ApplicationKafkaConfig,
+) -> Result<FutureProducer, Box<dyn Error>> {
+    let producer: FutureProducer = app_config.rdkafka_producer_config()?.create()?;
+    Ok(producer)
+}
@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use sqlx::PgPool;
+
+use crate::database::hwm_statements::{HwmRow, get_all_hwms};
+use crate::kafka::hwm::HwmRebalanceHandler;
+use crate::metrics;
+
+pub const LAG_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const WATERMARK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: i32,
+    pub high_watermark: i64,
+    pub stored_hwm: i64,
+    /// Offsets on the broker above the stored HWM. Transaction markers count as well,
+    /// so a transactional topic can show a small lag even when everything is stored.
+    pub lag: i64,
+    pub seconds_since_last_record: Option<f64>,
+}
+
+impl PartitionLag {
+    pub fn new(hwm: &HwmRow, high_watermark: i64, now: DateTime<Utc>) -> Self {
+        PartitionLag {
+            topic: hwm.topic.clone(),
+            partition: hwm.partition as i32,
+            high_watermark,
+            stored_hwm: hwm.hwm,
+            // The high watermark is the next offset, the HWM is the last stored offset
+            lag: (high_watermark - 1 - hwm.hwm).max(0),
+            seconds_since_last_record: hwm
+                .last_timestamp
+                .map(|timestamp| (now - timestamp).num_milliseconds() as f64 / 1000.0),
+        }
+    }
+}
+
+/// Refreshes the lag gauges until the consumer is dropped
+pub async fn run_lag_monitor(
+    pg_pool: PgPool,
+    consumer: Weak<StreamConsumer<HwmRebalanceHandler>>,
+    interval: Duration,
+) {
+    loop {
+        let Some(consumer) = consumer.upgrade() else {
+            return;
+        };
+        match refresh_lag_metrics(&pg_pool, consumer).await {
+            Ok(partitions) => debug!("Lag oppdatert for {} partisjoner", partitions.len()),
+            Err(e) => warn!("Klarte ikke å oppdatere lag: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Compares the stored HWM of every partition with the high watermark on the broker.
+/// Partitions where the watermarks cannot be fetched are logged and left out.
+pub async fn refresh_lag_metrics(
+    pg_pool: &PgPool,
+    consumer: Arc<StreamConsumer<HwmRebalanceHandler>>,
+) -> Result<Vec<PartitionLag>, Box<dyn Error>> {
+    let hwms = get_all_hwms(pg_pool).await?;
+    let partitions = tokio::task::spawn_blocking(move || {
+        let now = Utc::now();
+        hwms.iter()
+            .filter_map(|hwm| {
+                match consumer.fetch_watermarks(&hwm.topic, hwm.partition as i32, WATERMARK_TIMEOUT)
+                {
+                    Ok((_low, high)) => Some(PartitionLag::new(hwm, high, now)),
+                    Err(e) => {
+                        warn!(
+                            "Klarte ikke å hente watermarks for topic={}, partition={}: {}",
+                            hwm.topic, hwm.partition, e
+                        );
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    for partition in &partitions {
+        metrics::set_partition_lag(
+            &partition.topic,
+            partition.partition,
+            partition.high_watermark,
+            partition.stored_hwm,
+            partition.lag,
+            partition.seconds_since_last_record,
+        );
+    }
+    Ok(partitions)
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::kafka::hwm::Topic;
+use crate::kafka::message_processor::KafkaMessage;
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Max number of messages in one partition batch
+    pub max_messages: usize,
+    /// Max size of keys, payloads and headers in one partition batch
+    pub max_bytes: usize,
+    /// Max time the first message of a batch waits before the batch is written
+    pub linger: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 500,
+            max_bytes: 256 * 1024,
+            linger: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Messages from one topic partition, in the order they were consumed
+#[derive(Debug)]
+pub struct MessageBatch {
+    pub topic: String,
+    pub partition: i32,
+    pub messages: Vec<KafkaMessage>,
+}
+
+struct PartitionBatch {
+    messages: Vec<KafkaMessage>,
+    bytes: usize,
+    deadline: Instant,
+}
+
+/// Accumulates consumed messages per topic partition until a batch is full or has
+/// waited for the configured linger time.
+pub struct MessageBatcher {
+    config: BatchConfig,
+    batches: HashMap<(String, i32), PartitionBatch>,
+}
+
+impl MessageBatcher {
+    pub fn new(config: BatchConfig) -> Self {
+        MessageBatcher {
+            config,
+            batches: HashMap::new(),
+        }
+    }
+
+    /// Adds a message, returns the batch for its partition when the batch is full
+    pub fn add(&mut self, msg: KafkaMessage) -> Option<MessageBatch> {
+        let key = (msg.topic.clone(), msg.partition);
+        let bytes = msg.size_in_bytes();
+        let linger = self.config.linger;
+        let batch = self
+            .batches
+            .entry(key.clone())
+            .or_insert_with(|| PartitionBatch {
+                messages: Vec::new(),
+                bytes: 0,
+                deadline: Instant::now() + linger,
+            });
+        batch.messages.push(msg);
+        batch.bytes += bytes;
+        if batch.messages.len() >= self.config.max_messages || batch.bytes >= self.config.max_bytes
+        {
+            self.take(key)
+        } else {
+            None
+        }
+    }
+
+    /// The earliest time a pending batch should be written
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.batches.values().map(|batch| batch.deadline).min()
+    }
+
+    /// Removes and returns all batches that have waited for the linger time
+    pub fn take_expired(&mut self, now: Instant) -> Vec<MessageBatch> {
+        let expired: Vec<(String, i32)> = self
+            .batches
+            .iter()
+            .filter(|(_, batch)| batch.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.take(key))
+            .collect()
+    }
+
+    /// Discards the pending batches of partitions that are no longer assigned, their
+    /// messages are stored by the consumer that takes over the partitions.
+    /// Returns the number of discarded messages.
+    pub fn drop_partitions(&mut self, topics: &[Topic]) -> usize {
+        topics
+            .iter()
+            .filter_map(|topic| self.batches.remove(&(topic.name.clone(), topic.partition)))
+            .map(|batch| batch.messages.len())
+            .sum()
+    }
+
+    fn take(&mut self, key: (String, i32)) -> Option<MessageBatch> {
+        self.batches.remove(&key).map(|batch| MessageBatch {
+            topic: key.0,
+            partition: key.1,
+            messages: batch.messages,
+        })
+    }
+}
@@ -1,12 +1,19 @@
-use crate::database::hwm_statements::update_hwm;
-use crate::database::insert_data;
-use crate::kafka::headers::extract_headers_as_json;
+use crate::database::current_state::upsert_current_state;
+use crate::database::erasure::get_erased_offsets_in_range;
+use crate::database::hwm_statements::{lock_hwm, update_hwm};
+use crate::database::insert_data::{InsertDataRow, insert_data_batch};
+use crate::encryption::data_cipher::DataCipher;
+use crate::encryption::key_hash::record_key_hash;
+use crate::kafka::headers::{KafkaHeader, extract_headers, headers_to_json};
+use crate::kafka::message_batcher::MessageBatch;
+use crate::kafka::topic_policy::TopicPolicy;
 use crate::metrics;
 use chrono::{DateTime, Utc};
 use log::{info, trace};
 use rdkafka::Message;
 use rdkafka::message::BorrowedMessage;
 use sqlx::PgPool;
+use std::collections::HashSet;
 use std::error::Error;
 
 #[derive(Debug, Clone)]
@@ -14,9 +21,11 @@ pub struct KafkaMessage {
     pub topic: String,
     pub partition: i32,
     pub offset: i64,
-    pub headers: Option<serde_json::Value>,
-    pub key: Vec<u8>,
-    pub payload: Vec<u8>,
+    pub headers: Option<Vec<KafkaHeader>>,
+    /// `None` when the record has no key, which is different from an empty key
+    pub key: Option<Vec<u8>>,
+    /// `None` for tombstones, which is different from an empty payload
+    pub payload: Option<Vec<u8>>,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -30,45 +39,151 @@ impl KafkaMessage {
             topic: msg.topic().to_string(),
             partition: msg.partition(),
             offset: msg.offset(),
-            headers: extract_headers_as_json(&msg)?,
-            key: msg.key().unwrap_or(&[]).to_vec(),
-            payload: msg.payload().unwrap_or(&[]).to_vec(),
+            headers: extract_headers(&msg),
+            key: msg.key().map(|key| key.to_vec()),
+            payload: msg.payload().map(|payload| payload.to_vec()),
             timestamp,
         })
     }
+
+    /// Approximate number of bytes held by the key, payload and headers
+    pub fn size_in_bytes(&self) -> usize {
+        let headers_size: usize = self
+            .headers
+            .iter()
+            .flatten()
+            .map(|header| header.key.len() + header.value.as_ref().map_or(0, Vec::len))
+            .sum();
+        self.key.as_ref().map_or(0, Vec::len)
+            + self.payload.as_ref().map_or(0, Vec::len)
+            + headers_size
+    }
 }
 
 pub async fn prosesser_melding(pg_pool: PgPool, msg: KafkaMessage) -> Result<(), Box<dyn Error>> {
-    let mut tx = pg_pool.begin().await?;
-    let topic = &msg.topic;
+    prosesser_batch(
+        pg_pool,
+        MessageBatch {
+            topic: msg.topic.clone(),
+            partition: msg.partition,
+            messages: vec![msg],
+        },
+        &TopicPolicy::default(),
+        None,
+    )
+    .await
+}
 
-    let hwm_ok = update_hwm(&mut tx, topic, msg.partition, msg.offset).await?;
+/// Stores all messages above the current HWM for the partition and advances the HWM
+/// to the last consumed offset, all in one transaction. Headers and values are left out
+/// when the topic policy says so, and erased records are never stored again. Values are
+/// compressed with the codec of the policy, and with a cipher the record keys and values
+/// are encrypted after compression. For topics with the current state policy the latest
+/// record of each key is updated in the same transaction.
+pub async fn prosesser_batch(
+    pg_pool: PgPool,
+    batch: MessageBatch,
+    policy: &TopicPolicy,
+    cipher: Option<&DataCipher>,
+) -> Result<(), Box<dyn Error>> {
+    let topic = &batch.topic;
+    let partition = batch.partition;
+    let mut tx = pg_pool.begin().await?;
 
-    if hwm_ok {
-        let _ = insert_data::insert_data(
-            &mut tx,
-            topic,
-            msg.partition,
-            msg.offset,
-            msg.timestamp,
-            msg.headers,
-            msg.key,
-            msg.payload,
-        )
-        .await?;
-        tx.commit().await?;
+    let current_hwm = lock_hwm(&mut tx, topic, partition).await?;
+    let (above_hwm, below_hwm): (Vec<KafkaMessage>, Vec<KafkaMessage>) = batch
+        .messages
+        .into_iter()
+        .partition(|msg| current_hwm.is_some_and(|hwm| msg.offset > hwm));
 
-        trace!(
-            "Message processed: topic={}, partition={}, offset={}",
-            topic, msg.partition, msg.offset
-        );
-    } else {
+    if let (Some(first_below), Some(last_below)) = (below_hwm.first(), below_hwm.last()) {
         info!(
-            "Below HWM, skipping insert: topic={}, partition={}, offset={}",
-            topic, msg.partition, msg.offset
+            "Below HWM, skipping insert: topic={}, partition={}, offsets={}..={}, count={}, hwm={:?}",
+            topic,
+            partition,
+            first_below.offset,
+            last_below.offset,
+            below_hwm.len(),
+            current_hwm
         );
-        tx.rollback().await?;
     }
-    metrics::increment_kafka_messages_processed(hwm_ok, topic.clone(), msg.partition);
+
+    // Only the records actually inserted, erased records are counted on their own
+    let mut stored_count = 0;
+    match above_hwm.iter().map(|msg| msg.offset).max() {
+        Some(new_hwm) => {
+            let first_offset = above_hwm
+                .iter()
+                .map(|msg| msg.offset)
+                .min()
+                .unwrap_or(new_hwm);
+            let erased: HashSet<i64> =
+                get_erased_offsets_in_range(&mut tx, topic, partition, first_offset, new_hwm)
+                    .await?
+                    .into_iter()
+                    .collect();
+            let (erased_messages, to_store): (Vec<KafkaMessage>, Vec<KafkaMessage>) = above_hwm
+                .into_iter()
+                .partition(|msg| erased.contains(&msg.offset));
+            if !erased_messages.is_empty() {
+                info!(
+                    "Hopper over {} slettede meldinger: topic={}, partition={}",
+                    erased_messages.len(),
+                    topic,
+                    partition
+                );
+                metrics::increment_erased_records_skipped(topic, partition, erased_messages.len());
+            }
+            let mut raw_bytes = 0;
+            let mut stored_bytes = 0;
+            let mut rows = Vec::with_capacity(to_store.len());
+            for msg in to_store {
+                let value_omitted = policy.keys_only && msg.payload.is_some();
+                let (record_value, value_codec) = match msg.payload.filter(|_| !policy.keys_only) {
+                    Some(value) => {
+                        raw_bytes += value.len();
+                        let (stored, codec) = policy.compression.apply(value)?;
+                        stored_bytes += stored.len();
+                        (Some(stored), codec)
+                    }
+                    None => (None, None),
+                };
+                rows.push(InsertDataRow {
+                    headers: msg
+                        .headers
+                        .as_deref()
+                        .filter(|_| policy.store_headers)
+                        .map(headers_to_json),
+                    record_key_hash: msg.key.as_deref().map(record_key_hash),
+                    kafka_topic: msg.topic,
+                    kafka_partition: msg.partition,
+                    kafka_offset: msg.offset,
+                    timestamp: msg.timestamp,
+                    record_key: msg.key,
+                    record_value,
+                    value_codec,
+                    value_omitted,
+                });
+            }
+            let state_records: Vec<(i64, DateTime<Utc>)> = rows
+                .iter()
+                .filter(|row| policy.current_state && !policy.keys_only && row.record_key.is_some())
+                .map(|row| (row.kafka_offset, row.timestamp))
+                .collect();
+            stored_count = insert_data_batch(&mut tx, rows, cipher).await? as usize;
+            upsert_current_state(&mut tx, topic, partition, &state_records).await?;
+            update_hwm(&mut tx, topic, partition, new_hwm).await?;
+            tx.commit().await?;
+            metrics::increment_value_bytes(topic, raw_bytes, stored_bytes);
+            trace!(
+                "Batch processed: topic={}, partition={}, count={}, hwm={}",
+                topic, partition, stored_count, new_hwm
+            );
+        }
+        None => tx.rollback().await?,
+    }
+
+    metrics::increment_kafka_messages_processed(true, topic, partition, stored_count);
+    metrics::increment_kafka_messages_processed(false, topic, partition, below_hwm.len());
     Ok(())
 }
@@ -2,4 +2,9 @@ pub mod config;
 pub mod headers;
 pub mod hwm;
 pub mod kafka_connection;
+pub mod lag_monitor;
+pub mod message_batcher;
 pub mod message_processor;
+pub mod partition_positioner;
+pub mod topic_policy;
+pub mod topic_subscription;
@@ -0,0 +1,193 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use log::{info, warn};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::{Offset, TopicPartitionList};
+use sqlx::PgPool;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::app_state::AppState;
+use crate::kafka::hwm::{
+    AssignmentEvent, Hwm, HwmRebalanceHandler, Topic, get_hwms, insert_start_hwms, start_hwm,
+};
+use crate::kafka::topic_subscription::TopicSelector;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const SEEK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Positions newly assigned partitions at their HWM and resumes them.
+///
+/// The rebalance callback only pauses the partitions and sends an [`AssignmentEvent`],
+/// the database lookup and seek happen here so a slow database never blocks the
+/// consumer thread. Partitions without a HWM start where the topic policy says.
+/// Failures are retried with exponential backoff, and the app is not ready while
+/// partitions are waiting to be positioned.
+pub struct PartitionPositioner {
+    pg_pool: PgPool,
+    app_state: Arc<AppState>,
+    consumer: Weak<StreamConsumer<HwmRebalanceHandler>>,
+    events: UnboundedReceiver<AssignmentEvent>,
+    selector: Arc<TopicSelector>,
+    pending: BTreeSet<Topic>,
+}
+
+impl PartitionPositioner {
+    pub fn new(
+        pg_pool: PgPool,
+        app_state: Arc<AppState>,
+        consumer: Weak<StreamConsumer<HwmRebalanceHandler>>,
+        events: UnboundedReceiver<AssignmentEvent>,
+        selector: Arc<TopicSelector>,
+    ) -> Self {
+        PartitionPositioner {
+            pg_pool,
+            app_state,
+            consumer,
+            events,
+            selector,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    /// Runs until the consumer, and with it the sending side of the events, is dropped
+    pub async fn run(mut self) {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            if self.pending.is_empty() {
+                match self.events.recv().await {
+                    Some(event) => self.apply(event),
+                    None => return,
+                }
+                continue;
+            }
+            while let Ok(event) = self.events.try_recv() {
+                self.apply(event);
+            }
+            let result = self.position_pending().await.map_err(|e| e.to_string());
+            self.app_state
+                .set_positioning_partitions(self.pending.len());
+            match result {
+                Ok(()) => backoff = INITIAL_BACKOFF,
+                Err(e) => {
+                    warn!(
+                        "Klarte ikke å posisjonere {} partisjoner, prøver igjen om {:?}: {}",
+                        self.pending.len(),
+                        backoff,
+                        e
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        event = self.events.recv() => match event {
+                            Some(event) => self.apply(event),
+                            None => return,
+                        }
+                    }
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Received partitions count as positioning before they stop counting as queued, so
+    /// the app never looks ready in between
+    fn apply(&mut self, event: AssignmentEvent) {
+        match event {
+            AssignmentEvent::Assigned(topics) => {
+                let count = topics.len();
+                self.pending.extend(topics);
+                self.app_state
+                    .set_positioning_partitions(self.pending.len());
+                self.app_state.remove_queued_partitions(count);
+            }
+            AssignmentEvent::Revoked(topics) => {
+                topics.iter().for_each(|topic| {
+                    self.pending.remove(topic);
+                });
+                self.app_state
+                    .set_positioning_partitions(self.pending.len());
+            }
+        }
+    }
+
+    async fn position_pending(&mut self) -> Result<(), Box<dyn Error>> {
+        let topics: Vec<Topic> = self.pending.iter().cloned().collect();
+        let (mut hwms, missing) = get_hwms(&self.pg_pool, &topics).await?;
+        let Some(consumer) = self.consumer.upgrade() else {
+            self.pending.clear();
+            return Ok(());
+        };
+        if !missing.is_empty() {
+            let start_hwms =
+                resolve_start_hwms(consumer.clone(), self.selector.clone(), missing).await?;
+            insert_start_hwms(&self.pg_pool, &start_hwms).await?;
+            hwms.extend(start_hwms);
+        }
+        let mut failed = Vec::new();
+        for hwm in hwms {
+            let topic = Topic {
+                name: hwm.topic.clone(),
+                partition: hwm.partition,
+            };
+            let offset = hwm.seek_to_rdkafka_offset();
+            match seek_and_resume(consumer.clone(), topic.clone(), offset).await {
+                Ok(()) => {
+                    info!(
+                        "Posisjonert: topic: {}, partition: {}, hwm: {}",
+                        hwm.topic, hwm.partition, hwm.hwm
+                    );
+                    self.pending.remove(&topic);
+                }
+                Err(e) => failed.push(format!("{}::{}: {}", topic.name, topic.partition, e)),
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Seek feilet for {}", failed.join(", ")).into())
+        }
+    }
+}
+
+async fn resolve_start_hwms(
+    consumer: Arc<StreamConsumer<HwmRebalanceHandler>>,
+    selector: Arc<TopicSelector>,
+    topics: Vec<Topic>,
+) -> Result<Vec<Hwm>, Box<dyn Error>> {
+    let hwms = tokio::task::spawn_blocking(move || {
+        topics
+            .into_iter()
+            .map(|topic| {
+                let start_position = selector.policy_for(&topic.name).start_position;
+                start_hwm(&consumer, &topic, start_position)
+                    .map(|hwm| Hwm {
+                        topic: topic.name.clone(),
+                        partition: topic.partition,
+                        hwm,
+                    })
+                    .map_err(|e| format!("{}::{}: {}", topic.name, topic.partition, e))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await??;
+    Ok(hwms)
+}
+
+/// Seek blocks until the fetcher has acknowledged it, so it runs on the blocking pool
+async fn seek_and_resume(
+    consumer: Arc<StreamConsumer<HwmRebalanceHandler>>,
+    topic: Topic,
+    offset: Offset,
+) -> Result<(), Box<dyn Error>> {
+    tokio::task::spawn_blocking(move || {
+        consumer.seek(&topic.name, topic.partition, offset, SEEK_TIMEOUT)?;
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition(&topic.name, topic.partition);
+        consumer.resume(&partitions)
+    })
+    .await??;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::compression::Compression;
+
+/// Where to start reading a partition that has no HWM yet. In the config it is
+/// `earliest`, `latest` or an RFC 3339 timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum StartPosition {
+    #[default]
+    Earliest,
+    /// Only records produced after the partition was first assigned are backed up
+    Latest,
+    /// Records with a timestamp at or after this are backed up
+    Timestamp(DateTime<Utc>),
+}
+
+impl FromStr for StartPosition {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "earliest" => Ok(StartPosition::Earliest),
+            "latest" => Ok(StartPosition::Latest),
+            _ => DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| StartPosition::Timestamp(timestamp.with_timezone(&Utc)))
+                .map_err(|_| {
+                    format!(
+                        "Invalid start position '{}', expected earliest, latest or an RFC 3339 timestamp",
+                        value
+                    )
+                }),
+        }
+    }
+}
+
+impl TryFrom<String> for StartPosition {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// How records from a topic are backed up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicPolicy {
+    pub start_position: StartPosition,
+    pub store_headers: bool,
+    /// Values are left out and the records are marked `value_omitted`, which restore and
+    /// import skip instead of replaying them as tombstones
+    pub keys_only: bool,
+    /// Stored records older than this many days are deleted, `None` keeps them forever
+    pub retention_days: Option<u32>,
+    pub compression: Compression,
+    /// Records are archived to object storage in daily segments once the day is this
+    /// many days old, `None` never archives them
+    pub archive_after_days: Option<u32>,
+    /// Keeps the latest record of every key in the current state table, for compacted
+    /// topics. Rejected together with `keys_only`, where no record has its value
+    pub current_state: bool,
+}
+
+impl Default for TopicPolicy {
+    fn default() -> Self {
+        TopicPolicy {
+            start_position: StartPosition::Earliest,
+            store_headers: true,
+            keys_only: false,
+            retention_days: None,
+            compression: Compression::None,
+            archive_after_days: None,
+            current_state: false,
+        }
+    }
+}
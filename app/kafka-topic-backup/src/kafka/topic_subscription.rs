@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use log::{info, warn};
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use regex::Regex;
+
+use crate::kafka::hwm::HwmRebalanceHandler;
+use crate::kafka::topic_policy::TopicPolicy;
+use crate::metrics;
+
+pub const TOPIC_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decides which topics to back up and with which policy. Entries starting with `^`
+/// are regular expressions, the same convention librdkafka uses for subscriptions,
+/// everything else is a topic name. Excludes win over includes.
+#[derive(Debug, Clone)]
+pub struct TopicSelector {
+    names: BTreeMap<String, TopicPolicy>,
+    patterns: Vec<(Regex, TopicPolicy)>,
+    exclude_names: BTreeSet<String>,
+    exclude_patterns: Vec<Regex>,
+    default_policy: TopicPolicy,
+}
+
+impl TopicSelector {
+    /// Selects the topics with the default policy
+    pub fn new(topics: &[&str], exclude: &[&str]) -> Result<Self, regex::Error> {
+        Self::with_policies(
+            topics.iter().map(|topic| (*topic, TopicPolicy::default())),
+            exclude,
+        )
+    }
+
+    pub fn with_policies<'a>(
+        topics: impl IntoIterator<Item = (&'a str, TopicPolicy)>,
+        exclude: &[&str],
+    ) -> Result<Self, regex::Error> {
+        let mut names = BTreeMap::new();
+        let mut patterns = Vec::new();
+        for (topic, policy) in topics {
+            if is_pattern(topic) {
+                patterns.push((Regex::new(topic)?, policy));
+            } else {
+                names.entry(topic.to_string()).or_insert(policy);
+            }
+        }
+        let mut exclude_names = BTreeSet::new();
+        let mut exclude_patterns = Vec::new();
+        for topic in exclude {
+            if is_pattern(topic) {
+                exclude_patterns.push(Regex::new(topic)?);
+            } else {
+                exclude_names.insert(topic.to_string());
+            }
+        }
+        Ok(TopicSelector {
+            names,
+            patterns,
+            exclude_names,
+            exclude_patterns,
+            default_policy: TopicPolicy::default(),
+        })
+    }
+
+    /// Without patterns the selection never changes and no metadata is needed
+    pub fn has_patterns(&self) -> bool {
+        !self.patterns.is_empty()
+    }
+
+    fn is_excluded(&self, topic: &str) -> bool {
+        self.exclude_names.contains(topic)
+            || self
+                .exclude_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(topic))
+    }
+
+    /// Topic names are selected whether they exist yet or not, patterns only match
+    /// existing topics. Internal topics like `__consumer_offsets` never match a pattern.
+    pub fn select<'a>(
+        &self,
+        existing_topics: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeSet<String> {
+        let matched = existing_topics
+            .into_iter()
+            .filter(|topic| !topic.starts_with("__"))
+            .filter(|topic| {
+                self.patterns
+                    .iter()
+                    .any(|(pattern, _)| pattern.is_match(topic))
+            })
+            .map(str::to_string);
+        self.names
+            .keys()
+            .cloned()
+            .chain(matched)
+            .filter(|topic| !self.is_excluded(topic))
+            .collect()
+    }
+
+    /// The policy of the topic name, or else of the first matching pattern
+    pub fn policy_for(&self, topic: &str) -> &TopicPolicy {
+        self.names
+            .get(topic)
+            .or_else(|| {
+                self.patterns
+                    .iter()
+                    .find(|(pattern, _)| pattern.is_match(topic))
+                    .map(|(_, policy)| policy)
+            })
+            .unwrap_or(&self.default_policy)
+    }
+}
+
+fn is_pattern(topic: &str) -> bool {
+    topic.starts_with('^')
+}
+
+/// Names of all topics in the cluster, blocks until the broker answers
+pub fn fetch_topic_names<C: ConsumerContext>(
+    consumer: &StreamConsumer<C>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let metadata = consumer.fetch_metadata(None, METADATA_TIMEOUT)?;
+    Ok(metadata
+        .topics()
+        .iter()
+        .map(|topic| topic.name().to_string())
+        .collect())
+}
+
+/// Selects the topics to subscribe to, asking the broker for existing topics only when
+/// the selector has patterns
+pub fn select_topics<C: ConsumerContext>(
+    consumer: &StreamConsumer<C>,
+    selector: &TopicSelector,
+) -> Result<BTreeSet<String>, Box<dyn Error>> {
+    if selector.has_patterns() {
+        let existing = fetch_topic_names(consumer)?;
+        Ok(selector.select(existing.iter().map(String::as_str)))
+    } else {
+        Ok(selector.select([]))
+    }
+}
+
+pub fn log_selected_topics(topics: &BTreeSet<String>) {
+    info!(
+        "Abonnerer på {} topics: {}",
+        topics.len(),
+        topics.iter().cloned().collect::<Vec<_>>().join(", ")
+    );
+    metrics::set_subscribed_topics(topics);
+}
+
+/// Refreshes the metadata periodically and subscribes again when the set of matching
+/// topics has changed. Runs until the consumer is dropped.
+pub async fn run_topic_subscription(
+    consumer: Weak<StreamConsumer<HwmRebalanceHandler>>,
+    selector: Arc<TopicSelector>,
+    mut subscribed: BTreeSet<String>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(consumer) = consumer.upgrade() else {
+            return;
+        };
+        match refresh_subscription(consumer, selector.clone(), &subscribed).await {
+            Ok(Some(selected)) => subscribed = selected,
+            Ok(None) => {}
+            Err(e) => warn!("Klarte ikke å oppdatere abonnement på topics: {}", e),
+        }
+    }
+}
+
+/// Returns the new selection when the subscription was changed
+async fn refresh_subscription(
+    consumer: Arc<StreamConsumer<HwmRebalanceHandler>>,
+    selector: Arc<TopicSelector>,
+    subscribed: &BTreeSet<String>,
+) -> Result<Option<BTreeSet<String>>, Box<dyn Error>> {
+    let current = subscribed.clone();
+    let selected = tokio::task::spawn_blocking(move || {
+        let selected = select_topics(&consumer, &selector).map_err(|e| e.to_string())?;
+        if selected != current && !selected.is_empty() {
+            let topics: Vec<&str> = selected.iter().map(String::as_str).collect();
+            consumer.subscribe(&topics).map_err(|e| e.to_string())?;
+        }
+        Ok::<_, String>(selected)
+    })
+    .await??;
+
+    if selected == *subscribed {
+        log_selected_topics(&selected);
+        return Ok(None);
+    }
+    if selected.is_empty() {
+        warn!(
+            "Ingen topics matcher lenger, beholder abonnementet på {:?}",
+            subscribed
+        );
+        return Ok(None);
+    }
+    info!(
+        "Endret abonnement, nye topics: {:?}, fjernede topics: {:?}",
+        selected.difference(subscribed).collect::<Vec<_>>(),
+        subscribed.difference(&selected).collect::<Vec<_>>()
+    );
+    log_selected_topics(&selected);
+    Ok(Some(selected))
+}
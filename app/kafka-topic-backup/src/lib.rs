@@ -1,12 +1,25 @@
 pub mod app_state;
+pub mod archive;
+pub mod audit;
+pub mod cli;
+pub mod compression;
 pub mod config;
 pub mod config_utils;
 pub mod database;
+pub mod encoding;
+pub mod encryption;
+pub mod erasure;
 pub mod errors;
+pub mod export;
 pub mod kafka;
+pub mod import;
 pub mod logging;
 pub mod metrics;
+pub mod migration;
 pub mod nais_http_apis;
+pub mod query_api;
+pub mod restore;
+pub mod retention;
 
 // Re-export the functions we want to test from their proper location
 pub use kafka::message_processor::{KafkaMessage, prosesser_melding};
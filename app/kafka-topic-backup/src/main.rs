@@ -1,29 +1,67 @@
-mod app_state;
-mod config;
-mod config_utils;
-mod database;
-mod errors;
-mod kafka;
-mod logging;
-mod metrics;
-mod nais_http_apis;
-
-use crate::app_state::AppState;
-use crate::database::init_pg_pool::init_db;
-use crate::kafka::config::ApplicationKafkaConfig;
-use crate::kafka::hwm::HwmRebalanceHandler;
-use crate::kafka::kafka_connection::create_kafka_consumer;
-use crate::kafka::message_processor::KafkaMessage;
-use crate::kafka::message_processor::prosesser_melding;
-use crate::logging::init_log;
-use crate::nais_http_apis::register_nais_http_apis;
+use clap::Parser;
 use log::error;
 use log::info;
-use rdkafka::consumer::StreamConsumer;
+use log::warn;
+use paw_kafka_topic_backup::app_state::AppState;
+use paw_kafka_topic_backup::archive::archiver::{
+    ARCHIVE_INTERVAL, Archiver, create_object_store, run_archiver,
+};
+use paw_kafka_topic_backup::audit::broker_offsets::{BrokerOffsets, KafkaBrokerOffsets};
+use paw_kafka_topic_backup::audit::offset_audit::{
+    AUDIT_INTERVAL, SharedAuditReport, run_offset_audit,
+};
+use paw_kafka_topic_backup::cli::{
+    BackfillKeyHashesArgs, Cli, Command, ExportArgs, ExportParquetArgs, ExportStateArgs,
+    ImportArgs, MigrateDataTableArgs, RebuildStateArgs, RestoreArgs, RotateKeysArgs,
+};
+use paw_kafka_topic_backup::config;
+use paw_kafka_topic_backup::config::KafkaConfig;
+use paw_kafka_topic_backup::database::current_state::rebuild_current_state;
+use paw_kafka_topic_backup::database::init_pg_pool::init_db;
+use paw_kafka_topic_backup::encryption::data_cipher::DataCipher;
+use paw_kafka_topic_backup::encryption::key_hash::{
+    init_key_hash_secret, key_hash_secret_from_env,
+};
+use paw_kafka_topic_backup::encryption::key_rotation::{ensure_data_key, rotate_keys};
+use paw_kafka_topic_backup::encryption::master_key::{MASTER_KEY_ENV, MasterKeys};
+use paw_kafka_topic_backup::export::jsonl_export::export_jsonl;
+use paw_kafka_topic_backup::export::parquet_export::export_parquet;
+use paw_kafka_topic_backup::export::state_export::export_current_state;
+use paw_kafka_topic_backup::import::import_segments::{
+    ImportRequest, ImportTarget, import_segments,
+};
+use paw_kafka_topic_backup::import::segment_source::SegmentSource;
+use paw_kafka_topic_backup::kafka::config::ApplicationKafkaConfig;
+use paw_kafka_topic_backup::kafka::hwm::HwmRebalanceHandler;
+use paw_kafka_topic_backup::kafka::kafka_connection::{
+    create_kafka_consumer, create_kafka_producer,
+};
+use paw_kafka_topic_backup::kafka::lag_monitor::{LAG_REFRESH_INTERVAL, run_lag_monitor};
+use paw_kafka_topic_backup::kafka::message_batcher::{BatchConfig, MessageBatcher};
+use paw_kafka_topic_backup::kafka::message_processor::KafkaMessage;
+use paw_kafka_topic_backup::kafka::message_processor::prosesser_batch;
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+use paw_kafka_topic_backup::logging::init_log;
+use paw_kafka_topic_backup::migration::backfill_key_hashes::backfill_key_hashes;
+use paw_kafka_topic_backup::migration::migrate_data_table::{
+    MIGRATION_BATCH_SIZE, migrate_data_table,
+};
+use paw_kafka_topic_backup::nais_http_apis::{self, QueryApiRoutes, register_nais_http_apis};
+use paw_kafka_topic_backup::query_api::QueryApiState;
+use paw_kafka_topic_backup::restore::restore_topic::restore_topic;
+use paw_kafka_topic_backup::retention::partition_maintenance::{
+    PARTITION_MAINTENANCE_INTERVAL, run_partition_maintenance,
+};
+use paw_kafka_topic_backup::retention::purge_job::{RETENTION_INTERVAL, run_retention};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::producer::Producer;
 use sqlx::PgPool;
 use std::error::Error;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::Instant;
 
 #[tokio::main]
 async fn main() {
@@ -45,8 +83,21 @@ async fn main() {
         }
     }));
 
+    let cli = Cli::parse();
     info!("Starter applikasjon");
-    let _ = match run_app().await {
+    let result = match cli.command.unwrap_or(Command::Backup) {
+        Command::Backup => run_app(cli.config.as_deref()).await,
+        Command::Restore(args) => run_restore(cli.config.as_deref(), args).await,
+        Command::RotateKeys(args) => run_rotate_keys(args).await,
+        Command::MigrateDataTable(args) => run_migrate_data_table(args).await,
+        Command::BackfillKeyHashes(args) => run_backfill_key_hashes(args).await,
+        Command::Export(args) => run_export(args).await,
+        Command::ExportParquet(args) => run_export_parquet(args).await,
+        Command::Import(args) => run_import(cli.config.as_deref(), args).await,
+        Command::ExportState(args) => run_export_state(args).await,
+        Command::RebuildState(args) => run_rebuild_state(args).await,
+    };
+    match result {
         Ok(_) => {
             info!("Applikasjonen avsluttet uten feil");
         }
@@ -62,29 +113,97 @@ async fn main() {
                 level += 1;
             }
         }
-    };
+    }
     info!("Main funksjon ferdig, applikasjon avsluttet");
 }
 
-async fn run_app() -> Result<(), Box<dyn std::error::Error>> {
+async fn run_app(config_path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
     init_log();
-    let config = config::Config::from_default_file()?;
+    let config = config::Config::load(config_path)?;
     info!("Konfigurasjon lastet: {:?}", config);
+    init_key_hash_secret(key_hash_secret_from_env()?)?;
     // Initialize Prometheus metrics
-    crate::metrics::init_metrics();
+    paw_kafka_topic_backup::metrics::init_metrics();
     info!("Prometheus metrics initialized");
 
     let app_state = Arc::new(AppState::new());
-    let http_server_task = register_nais_http_apis(app_state.clone());
+    // Not ready until the database and data_v2 are migrated and the query API is in place
+    app_state.set_is_ready(false);
+    let query_api_routes = QueryApiRoutes::default();
+    let http_server_task = register_nais_http_apis(app_state.clone(), query_api_routes.clone());
     info!("HTTP server startet");
     let pg_pool = init_db().await?;
+    let cipher = load_cipher(&pg_pool, true).await?.map(Arc::new);
+    let audit_report = SharedAuditReport::default();
+    let archiver = config
+        .archive
+        .as_ref()
+        .map(Archiver::from_config)
+        .transpose()?
+        .map(Arc::new);
+    // Everything reads data_v3, so records still only in data_v2 are copied before the
+    // query API, the audit and the consumer start
+    let migrated = migrate_data_table(&pg_pool, MIGRATION_BATCH_SIZE).await?;
+    if migrated.rows_read > 0 {
+        info!(
+            "Migrering av data_v2 ferdig, {} av {} meldinger kopiert",
+            migrated.rows_copied, migrated.rows_read
+        );
+    }
+    let _ = query_api_routes.set(nais_http_apis::query_api_routes(QueryApiState::from_env(
+        pg_pool.clone(),
+        audit_report.clone(),
+        cipher.clone(),
+        archiver.clone(),
+    )));
+    app_state.set_is_ready(true);
+    let selector = Arc::new(TopicSelector::with_policies(
+        config.topic_policies(),
+        &config.exclude_topics_as_str_slice(),
+    )?);
     let stream = create_kafka_consumer(
         app_state.clone(),
         pg_pool.clone(),
-        ApplicationKafkaConfig::new("hedelselogg_backup2_v1", "ssl"),
-        &config.topics_as_str_slice(),
+        ApplicationKafkaConfig::from_config(&config.kafka),
+        selector.clone(),
     )?;
-    let reader = read_all(pg_pool.clone(), stream);
+    tokio::spawn(run_lag_monitor(
+        pg_pool.clone(),
+        Arc::downgrade(&stream),
+        LAG_REFRESH_INTERVAL,
+    ));
+    tokio::spawn(run_retention(
+        pg_pool.clone(),
+        selector.clone(),
+        RETENTION_INTERVAL,
+    ));
+    tokio::spawn(run_partition_maintenance(
+        pg_pool.clone(),
+        selector.clone(),
+        PARTITION_MAINTENANCE_INTERVAL,
+    ));
+    if let Some(archiver) = archiver {
+        tokio::spawn(run_archiver(
+            pg_pool.clone(),
+            selector.clone(),
+            archiver,
+            cipher.clone(),
+            ARCHIVE_INTERVAL,
+        ));
+    }
+    tokio::spawn(run_offset_audit(
+        pg_pool.clone(),
+        create_broker_offsets(&config.kafka),
+        audit_report,
+        AUDIT_INTERVAL,
+    ));
+    let reader = read_all(
+        pg_pool.clone(),
+        stream,
+        selector,
+        cipher,
+        BatchConfig::default(),
+    );
     let signal = await_signal();
     app_state.set_has_started(true);
     info!("Alle tjenester startet, applikasjon kjører");
@@ -105,7 +224,7 @@ async fn run_app() -> Result<(), Box<dyn std::error::Error>> {
         result = signal => {
             match result {
                 Ok(signal) => info!("Signal '{}' mottatt, avslutter....", signal),
-                Err(e) => return Err(e.into()),
+                Err(e) => return Err(e),
             }
         }
     }
@@ -115,14 +234,313 @@ async fn run_app() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+async fn run_restore(
+    config_path: Option<&Path>,
+    args: RestoreArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let config = config::Config::load(config_path)?;
+    let request = args.restore_request();
+    info!("Starter restore: {:?}", request);
+    let pg_pool = init_db().await?;
+    let cipher = load_cipher(&pg_pool, false).await?;
+    let producer = create_kafka_producer(ApplicationKafkaConfig::from_config(&config.kafka))?;
+    let summary = restore_topic(&pg_pool, &producer, &request, cipher.as_ref()).await?;
+    producer.flush(Duration::from_secs(30))?;
+    info!(
+        "Restore ferdig, {} meldinger produsert: {:?}, {} hoppet over",
+        summary.total(),
+        summary.records_per_partition,
+        summary.skipped_total()
+    );
+    if let Some(report) = &args.report {
+        std::fs::write(report, serde_json::to_vec_pretty(&summary)?)?;
+        info!(
+            "Rapport over produserte og utelatte offsets skrevet til {}",
+            report.display()
+        );
+    }
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_rotate_keys(args: RotateKeysArgs) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let master_keys = MasterKeys::from_env()?
+        .ok_or_else(|| format!("{} må være satt for å rotere nøkler", MASTER_KEY_ENV))?;
+    let pg_pool = init_db().await?;
+    let summary = rotate_keys(&pg_pool, &master_keys, args.new_data_key).await?;
+    info!(
+        "Nøkler rotert til hovednøkkel {}: {} nøkler pakket inn på nytt {:?}, ny nøkkel: {:?}",
+        master_keys.current.id(),
+        summary.rewrapped_key_ids.len(),
+        summary.rewrapped_key_ids,
+        summary.new_data_key_id
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_migrate_data_table(
+    args: MigrateDataTableArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let pg_pool = init_db().await?;
+    let summary = migrate_data_table(&pg_pool, args.batch_size).await?;
+    info!(
+        "Migrering av data_v2 ferdig, {} av {} meldinger kopiert, resten var slettet eller allerede kopiert",
+        summary.rows_copied, summary.rows_read
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_backfill_key_hashes(
+    args: BackfillKeyHashesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    init_key_hash_secret(key_hash_secret_from_env()?)?;
+    let pg_pool = init_db().await?;
+    let cipher = load_cipher(&pg_pool, false).await?;
+    let summary = backfill_key_hashes(&pg_pool, args.batch_size, cipher.as_ref()).await?;
+    info!(
+        "Nøkkel-hash fylt inn for {} meldinger i data_v2 og {} i data_v3",
+        summary.legacy_rows_updated, summary.data_rows_updated
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_export(args: ExportArgs) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let request = args.export_request();
+    info!("Starter eksport: {:?}", request);
+    let pg_pool = init_db().await?;
+    let cipher = load_cipher(&pg_pool, false).await?;
+    let manifest = export_jsonl(&pg_pool, &request, cipher.as_ref()).await?;
+    info!(
+        "Eksport ferdig, {} meldinger skrevet til {} segmenter i {}",
+        manifest.rows,
+        manifest.segments.len(),
+        request.output_dir.display()
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_export_parquet(args: ExportParquetArgs) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let request = args.export_request();
+    info!("Starter Parquet-eksport: {:?}", request);
+    let pg_pool = init_db().await?;
+    let cipher = load_cipher(&pg_pool, false).await?;
+    let manifest = export_parquet(&pg_pool, &request, cipher.as_ref()).await?;
+    info!(
+        "Parquet-eksport ferdig, {} meldinger skrevet til {} filer i {}",
+        manifest.rows,
+        manifest.segments.len(),
+        request.output_dir.display()
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_export_state(args: ExportStateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let request = args.export_request();
+    info!("Starter eksport av gjeldende tilstand: {:?}", request);
+    init_key_hash_secret(key_hash_secret_from_env()?)?;
+    let pg_pool = init_db().await?;
+    let cipher = load_cipher(&pg_pool, false).await?;
+    let manifest = export_current_state(&pg_pool, &request, cipher.as_ref()).await?;
+    info!(
+        "Eksport av gjeldende tilstand ferdig, {} nøkler skrevet til {} segmenter i {}",
+        manifest.rows,
+        manifest.segments.len(),
+        request.output_dir.display()
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_rebuild_state(args: RebuildStateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let pg_pool = init_db().await?;
+    let keys = rebuild_current_state(&pg_pool, &args.topic).await?;
+    info!(
+        "Gjeldende tilstand for {} bygget på nytt, {} nøkler",
+        args.topic, keys
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+async fn run_import(
+    config_path: Option<&Path>,
+    args: ImportArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    init_log();
+    let config = config::Config::load(config_path)?;
+    init_key_hash_secret(key_hash_secret_from_env()?)?;
+    let source = match (args.export_dir, args.archive_topic) {
+        (Some(dir), _) => SegmentSource::ExportDir(dir),
+        (None, Some(topic)) => {
+            let archive = config
+                .archive
+                .as_ref()
+                .ok_or("Arkivet er ikke konfigurert, [archive] mangler i konfigurasjonen")?;
+            SegmentSource::Archive {
+                store: create_object_store(archive)?,
+                topic,
+            }
+        }
+        (None, None) => return Err("Enten --export-dir eller --archive-topic må angis".into()),
+    };
+    let request = ImportRequest {
+        source,
+        from_timestamp: args.from_timestamp,
+        to_timestamp: args.to_timestamp,
+        batch_size: args.batch_size,
+    };
+    let pg_pool = init_db().await?;
+    let summary = match args.target_topic {
+        Some(topic) => {
+            info!("Starter import til topic {}", topic);
+            let producer =
+                create_kafka_producer(ApplicationKafkaConfig::from_config(&config.kafka))?;
+            let summary = import_segments(
+                &pg_pool,
+                &request,
+                &ImportTarget::Kafka {
+                    producer: &producer,
+                    topic,
+                },
+            )
+            .await?;
+            producer.flush(Duration::from_secs(30))?;
+            summary
+        }
+        None => {
+            info!("Starter import til databasen");
+            let cipher = load_cipher(&pg_pool, true).await?;
+            let selector = TopicSelector::with_policies(
+                config.topic_policies(),
+                &config.exclude_topics_as_str_slice(),
+            )?;
+            import_segments(
+                &pg_pool,
+                &request,
+                &ImportTarget::Database {
+                    selector: &selector,
+                    cipher: cipher.as_ref(),
+                },
+            )
+            .await?
+        }
+    };
+    info!(
+        "Import ferdig fra {} segmenter: {} av {} meldinger importert, {} fantes fra før, {} var slettet, {} manglet verdi",
+        summary.segments,
+        summary.records_imported,
+        summary.records_read,
+        summary.records_existing,
+        summary.records_erased,
+        summary.records_value_omitted
+    );
+    pg_pool.close().await;
+    Ok(())
+}
+
+/// `None` when no master key is configured. With `create_key` the first data key is
+/// created when there is none, which is only wanted when storing records.
+async fn load_cipher(
+    pg_pool: &PgPool,
+    create_key: bool,
+) -> Result<Option<DataCipher>, Box<dyn Error>> {
+    let Some(master_keys) = MasterKeys::from_env()? else {
+        info!("{} er ikke satt, data lagres ukryptert", MASTER_KEY_ENV);
+        return Ok(None);
+    };
+    if create_key {
+        ensure_data_key(pg_pool, &master_keys).await?;
+    }
+    let cipher = DataCipher::load(pg_pool, &master_keys).await?;
+    info!(
+        "Kryptering av data aktivert med hovednøkkel {}, aktiv nøkkel: {:?}",
+        master_keys.current.id(),
+        cipher.active_key_id()
+    );
+    Ok(Some(cipher))
+}
+
+/// Consumes all assigned partitions and writes the messages in batches per partition.
+/// Messages still waiting in a batch on shutdown are not stored, but since the HWM is
+/// not advanced they are consumed again on the next start.
 async fn read_all(
     pg_pool: PgPool,
-    stream: StreamConsumer<HwmRebalanceHandler>,
+    stream: Arc<StreamConsumer<HwmRebalanceHandler>>,
+    selector: Arc<TopicSelector>,
+    cipher: Option<Arc<DataCipher>>,
+    batch_config: BatchConfig,
 ) -> Result<(), Box<dyn Error>> {
+    let mut batcher = MessageBatcher::new(batch_config);
     loop {
-        let msg = stream.recv().await?;
-        let msg = KafkaMessage::from_borrowed_message(msg)?;
-        prosesser_melding(pg_pool.clone(), msg).await?;
+        let deadline = batcher.next_deadline();
+        tokio::select! {
+            msg = stream.recv() => {
+                let msg = KafkaMessage::from_borrowed_message(msg?)?;
+                drop_revoked_batches(&stream, &mut batcher);
+                if let Some(batch) = batcher.add(msg) {
+                    let policy = selector.policy_for(&batch.topic);
+                    prosesser_batch(pg_pool.clone(), batch, policy, cipher.as_deref()).await?;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                drop_revoked_batches(&stream, &mut batcher);
+                for batch in batcher.take_expired(Instant::now()) {
+                    let policy = selector.policy_for(&batch.topic);
+                    prosesser_batch(pg_pool.clone(), batch, policy, cipher.as_deref()).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Revocations happen while the stream is polled, so pending batches of revoked
+/// partitions are dropped before anything else is written
+fn drop_revoked_batches(
+    stream: &StreamConsumer<HwmRebalanceHandler>,
+    batcher: &mut MessageBatcher,
+) {
+    let revoked = stream.context().take_revoked();
+    if revoked.is_empty() {
+        return;
+    }
+    let dropped = batcher.drop_partitions(&revoked);
+    if dropped > 0 {
+        info!(
+            "Forkaster {} meldinger fra {} partisjoner som ikke lenger er tildelt",
+            dropped,
+            revoked.len()
+        );
+    }
+}
+
+/// The audit still runs without a broker connection, but cannot classify the gaps
+fn create_broker_offsets(kafka_config: &KafkaConfig) -> Option<Arc<dyn BrokerOffsets>> {
+    let app_config = ApplicationKafkaConfig::from_config(kafka_config);
+    match KafkaBrokerOffsets::new(ApplicationKafkaConfig {
+        group_id: format!("{}_audit", app_config.group_id),
+        ..app_config
+    }) {
+        Ok(broker) => Some(Arc::new(broker)),
+        Err(e) => {
+            warn!(
+                "Klarte ikke å opprette consumer for revisjon av offsets: {}",
+                e
+            );
+            None
+        }
     }
 }
 
@@ -1,7 +1,26 @@
-use prometheus::{CounterVec, register_counter_vec};
+use prometheus::{
+    CounterVec, Gauge, GaugeVec, register_counter_vec, register_gauge, register_gauge_vec,
+};
+use std::collections::BTreeSet;
 use std::sync::OnceLock;
 
 static KAFKA_MESSAGES_PROCESSED: OnceLock<CounterVec> = OnceLock::new();
+static OFFSET_GAPS: OnceLock<GaugeVec> = OnceLock::new();
+static OFFSET_GAP_OFFSETS: OnceLock<GaugeVec> = OnceLock::new();
+static OFFSET_AUDIT_COMPLETED: OnceLock<Gauge> = OnceLock::new();
+static BROKER_HIGH_WATERMARK: OnceLock<GaugeVec> = OnceLock::new();
+static STORED_HWM: OnceLock<GaugeVec> = OnceLock::new();
+static BACKUP_LAG: OnceLock<GaugeVec> = OnceLock::new();
+static SECONDS_SINCE_LAST_STORED_RECORD: OnceLock<GaugeVec> = OnceLock::new();
+static SUBSCRIBED_TOPICS: OnceLock<Gauge> = OnceLock::new();
+static RETENTION_ROWS_DELETED: OnceLock<CounterVec> = OnceLock::new();
+static OLDEST_STORED_RECORD: OnceLock<GaugeVec> = OnceLock::new();
+static ERASED_RECORDS: OnceLock<CounterVec> = OnceLock::new();
+static ERASED_RECORDS_SKIPPED: OnceLock<CounterVec> = OnceLock::new();
+static VALUE_RAW_BYTES: OnceLock<CounterVec> = OnceLock::new();
+static VALUE_STORED_BYTES: OnceLock<CounterVec> = OnceLock::new();
+static TOPIC_SUBSCRIBED: OnceLock<GaugeVec> = OnceLock::new();
+static ARCHIVED_RECORDS: OnceLock<CounterVec> = OnceLock::new();
 
 pub fn init_metrics() {
     KAFKA_MESSAGES_PROCESSED.get_or_init(|| {
@@ -12,12 +31,256 @@ pub fn init_metrics() {
         )
         .expect("Failed to register kafka_messages_processed_total counter")
     });
+    OFFSET_GAPS.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_offset_gaps",
+            "Number of offset gaps below the HWM found by the last audit",
+            &["topic", "partition", "kind"]
+        )
+        .expect("Failed to register kafka_backup_offset_gaps gauge")
+    });
+    OFFSET_GAP_OFFSETS.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_offset_gap_offsets",
+            "Number of offsets in the gaps found by the last audit",
+            &["topic", "partition", "kind"]
+        )
+        .expect("Failed to register kafka_backup_offset_gap_offsets gauge")
+    });
+    OFFSET_AUDIT_COMPLETED.get_or_init(|| {
+        register_gauge!(
+            "kafka_backup_offset_audit_completed_timestamp_seconds",
+            "Unix time of the last completed offset audit"
+        )
+        .expect("Failed to register kafka_backup_offset_audit_completed_timestamp_seconds gauge")
+    });
+    BROKER_HIGH_WATERMARK.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_broker_high_watermark",
+            "High watermark on the broker, the offset of the next record to be written",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_broker_high_watermark gauge")
+    });
+    STORED_HWM.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_stored_hwm",
+            "Offset of the last stored record from the hwm table",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_stored_hwm gauge")
+    });
+    BACKUP_LAG.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_lag",
+            "Number of offsets on the broker above the stored HWM",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_lag gauge")
+    });
+    SECONDS_SINCE_LAST_STORED_RECORD.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_seconds_since_last_stored_record",
+            "Seconds since the timestamp of the record stored at the HWM",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_seconds_since_last_stored_record gauge")
+    });
+    SUBSCRIBED_TOPICS.get_or_init(|| {
+        register_gauge!(
+            "kafka_backup_subscribed_topics",
+            "Number of topics the backup consumer is subscribed to"
+        )
+        .expect("Failed to register kafka_backup_subscribed_topics gauge")
+    });
+    TOPIC_SUBSCRIBED.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_topic_subscribed",
+            "Set to 1 for every topic matched at the last metadata refresh",
+            &["topic"]
+        )
+        .expect("Failed to register kafka_backup_topic_subscribed gauge")
+    });
+    ARCHIVED_RECORDS.get_or_init(|| {
+        register_counter_vec!(
+            "kafka_backup_archived_records_total",
+            "Number of records written to verified archive segments",
+            &["topic"]
+        )
+        .expect("Failed to register kafka_backup_archived_records_total counter")
+    });
+    RETENTION_ROWS_DELETED.get_or_init(|| {
+        register_counter_vec!(
+            "kafka_backup_retention_rows_deleted_total",
+            "Number of stored records deleted by the retention job",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_retention_rows_deleted_total counter")
+    });
+    OLDEST_STORED_RECORD.get_or_init(|| {
+        register_gauge_vec!(
+            "kafka_backup_oldest_record_timestamp_seconds",
+            "Unix time of the oldest stored record per topic",
+            &["topic"]
+        )
+        .expect("Failed to register kafka_backup_oldest_record_timestamp_seconds gauge")
+    });
+    ERASED_RECORDS.get_or_init(|| {
+        register_counter_vec!(
+            "kafka_backup_erased_records_total",
+            "Number of stored records deleted by erasure requests",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_erased_records_total counter")
+    });
+    ERASED_RECORDS_SKIPPED.get_or_init(|| {
+        register_counter_vec!(
+            "kafka_backup_erased_records_skipped_total",
+            "Number of consumed records not stored because they were erased earlier",
+            &["topic", "partition"]
+        )
+        .expect("Failed to register kafka_backup_erased_records_skipped_total counter")
+    });
+    VALUE_RAW_BYTES.get_or_init(|| {
+        register_counter_vec!(
+            "kafka_backup_value_raw_bytes_total",
+            "Bytes of record values as consumed, before compression",
+            &["topic"]
+        )
+        .expect("Failed to register kafka_backup_value_raw_bytes_total counter")
+    });
+    VALUE_STORED_BYTES.get_or_init(|| {
+        register_counter_vec!(
+            "kafka_backup_value_stored_bytes_total",
+            "Bytes of record values after compression, before encryption",
+            &["topic"]
+        )
+        .expect("Failed to register kafka_backup_value_stored_bytes_total counter")
+    });
 }
 
-pub fn increment_kafka_messages_processed(above_hwm: bool, topic: String, partition: i32) {
+pub fn increment_kafka_messages_processed(
+    above_hwm: bool,
+    topic: &str,
+    partition: i32,
+    count: usize,
+) {
     if let Some(counter_vec) = KAFKA_MESSAGES_PROCESSED.get() {
         counter_vec
-            .with_label_values(&[&above_hwm.to_string(), &topic, &partition.to_string()])
-            .inc();
+            .with_label_values(&[&above_hwm.to_string(), topic, &partition.to_string()])
+            .inc_by(count as f64);
+    }
+}
+
+pub fn set_offset_gaps(topic: &str, partition: i32, kind: &str, gaps: usize, offsets: i64) {
+    let partition = partition.to_string();
+    if let Some(gauge_vec) = OFFSET_GAPS.get() {
+        gauge_vec
+            .with_label_values(&[topic, &partition, kind])
+            .set(gaps as f64);
+    }
+    if let Some(gauge_vec) = OFFSET_GAP_OFFSETS.get() {
+        gauge_vec
+            .with_label_values(&[topic, &partition, kind])
+            .set(offsets as f64);
+    }
+}
+
+pub fn set_offset_audit_completed(unix_seconds: i64) {
+    if let Some(gauge) = OFFSET_AUDIT_COMPLETED.get() {
+        gauge.set(unix_seconds as f64);
+    }
+}
+
+pub fn set_partition_lag(
+    topic: &str,
+    partition: i32,
+    high_watermark: i64,
+    stored_hwm: i64,
+    lag: i64,
+    seconds_since_last_record: Option<f64>,
+) {
+    let partition = partition.to_string();
+    let labels = [topic, partition.as_str()];
+    if let Some(gauge_vec) = BROKER_HIGH_WATERMARK.get() {
+        gauge_vec
+            .with_label_values(&labels)
+            .set(high_watermark as f64);
+    }
+    if let Some(gauge_vec) = STORED_HWM.get() {
+        gauge_vec.with_label_values(&labels).set(stored_hwm as f64);
+    }
+    if let Some(gauge_vec) = BACKUP_LAG.get() {
+        gauge_vec.with_label_values(&labels).set(lag as f64);
+    }
+    if let Some(gauge_vec) = SECONDS_SINCE_LAST_STORED_RECORD.get() {
+        match seconds_since_last_record {
+            Some(seconds) => gauge_vec.with_label_values(&labels).set(seconds),
+            None => {
+                let _ = gauge_vec.remove_label_values(&labels);
+            }
+        }
+    }
+}
+
+pub fn set_subscribed_topics(topics: &BTreeSet<String>) {
+    if let Some(gauge) = SUBSCRIBED_TOPICS.get() {
+        gauge.set(topics.len() as f64);
+    }
+    if let Some(gauge_vec) = TOPIC_SUBSCRIBED.get() {
+        gauge_vec.reset();
+        for topic in topics {
+            gauge_vec.with_label_values(&[topic.as_str()]).set(1.0);
+        }
+    }
+}
+
+pub fn increment_retention_rows_deleted(topic: &str, partition: i32, count: i64) {
+    if let Some(counter_vec) = RETENTION_ROWS_DELETED.get() {
+        counter_vec
+            .with_label_values(&[topic, &partition.to_string()])
+            .inc_by(count as f64);
+    }
+}
+
+pub fn increment_erased_records(topic: &str, partition: i32, count: i64) {
+    if let Some(counter_vec) = ERASED_RECORDS.get() {
+        counter_vec
+            .with_label_values(&[topic, &partition.to_string()])
+            .inc_by(count as f64);
+    }
+}
+
+pub fn increment_erased_records_skipped(topic: &str, partition: i32, count: usize) {
+    if let Some(counter_vec) = ERASED_RECORDS_SKIPPED.get() {
+        counter_vec
+            .with_label_values(&[topic, &partition.to_string()])
+            .inc_by(count as f64);
+    }
+}
+
+pub fn increment_archived_records(topic: &str, count: i64) {
+    if let Some(counter_vec) = ARCHIVED_RECORDS.get() {
+        counter_vec.with_label_values(&[topic]).inc_by(count as f64);
+    }
+}
+
+pub fn increment_value_bytes(topic: &str, raw_bytes: usize, stored_bytes: usize) {
+    if let (Some(raw), Some(stored)) = (VALUE_RAW_BYTES.get(), VALUE_STORED_BYTES.get()) {
+        raw.with_label_values(&[topic]).inc_by(raw_bytes as f64);
+        stored
+            .with_label_values(&[topic])
+            .inc_by(stored_bytes as f64);
+    }
+}
+
+pub fn set_oldest_stored_record(topic: &str, unix_seconds: Option<i64>) {
+    if let Some(gauge_vec) = OLDEST_STORED_RECORD.get() {
+        match unix_seconds {
+            Some(seconds) => gauge_vec.with_label_values(&[topic]).set(seconds as f64),
+            None => {
+                let _ = gauge_vec.remove_label_values(&[topic]);
+            }
+        }
     }
 }
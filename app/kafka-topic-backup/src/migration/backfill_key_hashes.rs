@@ -0,0 +1,67 @@
+use std::error::Error;
+
+use log::info;
+use sqlx::PgPool;
+
+use crate::database::key_hashes::{DataTable, query_missing_key_hashes, update_key_hashes};
+use crate::database::read_data::DataRow;
+use crate::encryption::data_cipher::{DataCipher, decrypt_rows};
+use crate::encryption::key_hash::record_key_hash;
+
+/// Batches between each progress log line
+const LOG_EVERY_BATCHES: u64 = 100;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackfillSummary {
+    pub data_rows_updated: u64,
+    pub legacy_rows_updated: u64,
+}
+
+/// Computes the key hash of stored records that have none, in batches of `batch_size`,
+/// first in data_v2 and then in data_v3. The hash is keyed with a secret, so it is
+/// computed here rather than in the database. Encrypted keys are decrypted with the
+/// cipher first. Only rows without a hash are touched, so an interrupted backfill
+/// continues where it stopped when started again.
+pub async fn backfill_key_hashes(
+    pg_pool: &PgPool,
+    batch_size: i64,
+    cipher: Option<&DataCipher>,
+) -> Result<BackfillSummary, Box<dyn Error>> {
+    Ok(BackfillSummary {
+        legacy_rows_updated: backfill_table(pg_pool, DataTable::Legacy, batch_size, cipher).await?,
+        data_rows_updated: backfill_table(pg_pool, DataTable::Current, batch_size, cipher).await?,
+    })
+}
+
+async fn backfill_table(
+    pg_pool: &PgPool,
+    table: DataTable,
+    batch_size: i64,
+    cipher: Option<&DataCipher>,
+) -> Result<u64, Box<dyn Error>> {
+    info!("Fyller inn nøkkel-hash i {:?}", table);
+    let mut updated = 0;
+    let mut after_id = 0;
+    let mut batches = 0;
+    loop {
+        let batch = query_missing_key_hashes(pg_pool, table, after_id, batch_size).await?;
+        let Some(last) = batch.last() else {
+            return Ok(updated);
+        };
+        after_id = last.id;
+        let (ids, rows): (Vec<i64>, Vec<DataRow>) =
+            batch.into_iter().map(|row| (row.id, row.row)).unzip();
+        let key_hashes: Vec<Vec<u8>> = decrypt_rows(rows, cipher)?
+            .iter()
+            .map(|row| record_key_hash(row.record_key.as_deref().unwrap_or_default()))
+            .collect();
+        updated += update_key_hashes(pg_pool, table, &ids, &key_hashes).await?;
+        batches += 1;
+        if batches % LOG_EVERY_BATCHES == 0 {
+            info!(
+                "Fylt inn {} nøkkel-hasher i {:?}, til og med id {}",
+                updated, table, after_id
+            );
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::error::Error;
+
+use log::info;
+use sqlx::PgPool;
+
+use crate::database::legacy_data::{
+    copy_legacy_data_batch, get_copied_legacy_id, get_legacy_data_months, has_legacy_data_after,
+};
+use crate::database::partitions::{create_month_partition, get_partition_months, next_month};
+
+/// Records copied per statement when the migration runs at startup
+pub const MIGRATION_BATCH_SIZE: i64 = 5000;
+/// Batches between each progress log line
+const LOG_EVERY_BATCHES: u64 = 100;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationSummary {
+    pub rows_read: i64,
+    pub rows_copied: i64,
+}
+
+/// Copies the records from data_v2 to the partitioned data_v3 in batches of
+/// `batch_size`, creating the monthly partitions first so nothing ends up in the default
+/// partition. Records keep their id, so an interrupted migration continues after the last
+/// copied record when started again. Erased records are not copied. Returns right away
+/// when everything is copied, so it is cheap to run at every startup.
+pub async fn migrate_data_table(
+    pg_pool: &PgPool,
+    batch_size: i64,
+) -> Result<MigrationSummary, Box<dyn Error>> {
+    let mut summary = MigrationSummary::default();
+    let mut after_id = get_copied_legacy_id(pg_pool).await?;
+    if !has_legacy_data_after(pg_pool, after_id).await? {
+        return Ok(summary);
+    }
+    if let Some((first, last)) = get_legacy_data_months(pg_pool).await? {
+        let existing = get_partition_months(pg_pool).await?;
+        let mut month = first;
+        while month <= last {
+            if !existing.contains(&month) {
+                create_month_partition(pg_pool, month).await?;
+            }
+            month = next_month(month);
+        }
+    }
+
+    info!("Kopierer meldinger fra data_v2 etter id {}", after_id);
+    let mut batches = 0;
+    loop {
+        let batch = copy_legacy_data_batch(pg_pool, after_id, batch_size).await?;
+        let Some(last_id) = batch.last_id else {
+            return Ok(summary);
+        };
+        summary.rows_read += batch.rows_read;
+        summary.rows_copied += batch.rows_copied;
+        after_id = last_id;
+        batches += 1;
+        if batches % LOG_EVERY_BATCHES == 0 {
+            info!(
+                "Kopiert {} av {} leste meldinger, til og med id {}",
+                summary.rows_copied, summary.rows_read, after_id
+            );
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod backfill_key_hashes;
+pub mod migrate_data_table;
@@ -1,23 +1,52 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use crate::app_state::AppState;
-use axum::extract::State;
+use crate::query_api::{self, QueryApiState};
+use axum::extract::{Request, State};
+use axum::response::{IntoResponse, Response};
 use axum::{Router, http::StatusCode, routing::get};
 use prometheus::{Encoder, TextEncoder};
 use tokio::task::JoinHandle;
+use tower::ServiceExt;
 
+/// The query API routes, set once the database is migrated
+pub type QueryApiRoutes = Arc<OnceLock<Router>>;
+
+/// Serves the health routes right away, so the pod is reported not ready instead of
+/// failing its probes while the database is migrated. Other requests get 503 until
+/// `query_api_routes` is set.
 pub fn register_nais_http_apis(
     app_state: Arc<AppState>,
+    query_api_routes: QueryApiRoutes,
 ) -> JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>> {
     tokio::spawn(async move {
-        let routes = routes(app_state);
+        let routes = routes(app_state)
+            .fallback(query_api)
+            .with_state(query_api_routes);
         let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
         axum::serve(listener, routes).await?;
         Ok(())
     })
 }
 
-fn routes(app_state: Arc<AppState>) -> Router {
+/// The routes of the query API, or no routes when it is not configured
+pub fn query_api_routes(query_api_state: Option<QueryApiState>) -> Router {
+    query_api_state
+        .map(|query_api_state| query_api::routes(Arc::new(query_api_state)))
+        .unwrap_or_default()
+}
+
+async fn query_api(State(query_api_routes): State<QueryApiRoutes>, request: Request) -> Response {
+    match query_api_routes.get() {
+        Some(routes) => match routes.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        },
+        None => (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable").into_response(),
+    }
+}
+
+fn routes<S: Clone + Send + Sync + 'static>(app_state: Arc<AppState>) -> Router<S> {
     Router::new()
         .route("/internal/isAlive", get(is_alive))
         .route("/internal/isReady", get(is_ready))
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, header::AUTHORIZATION};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::query_api::QueryApiState;
+
+pub async fn require_bearer_token(
+    State(state): State<Arc<QueryApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if has_token(&request, &state.api_token) {
+        next.run(request).await
+    } else {
+        (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+    }
+}
+
+/// Admin routes are rejected when no admin token is configured
+pub async fn require_admin_token(
+    State(state): State<Arc<QueryApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = state
+        .admin_token
+        .as_deref()
+        .is_some_and(|admin_token| has_token(&request, admin_token));
+    if authorized {
+        next.run(request).await
+    } else {
+        (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+    }
+}
+
+fn has_token(request: &Request, expected: &str) -> bool {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+/// Compares without returning early, so the response time does not reveal the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
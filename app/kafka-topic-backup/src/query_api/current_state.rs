@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+
+use crate::database::current_state::{begin_snapshot, get_topic_hwms, query_current_state};
+use crate::encoding::ValueEncoding;
+use crate::encryption::key_hash::record_key_hash;
+use crate::query_api::QueryApiState;
+use crate::query_api::records::{DEFAULT_LIMIT, MAX_LIMIT, RecordResponse, internal_error};
+
+#[derive(Debug, Deserialize)]
+pub struct CurrentStateParams {
+    pub topic: String,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub encoding: Option<ValueEncoding>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CurrentStateResponse {
+    /// The HWM of each partition when the page was read
+    pub high_watermarks: BTreeMap<i32, i64>,
+    /// The latest record of each key, tombstoned keys are left out
+    pub records: Vec<RecordResponse>,
+    /// Pass as `cursor` to get the next page, `None` when there are no more keys
+    pub next_cursor: Option<String>,
+}
+
+/// The compacted snapshot of a topic that keeps its current state, ordered by the hash
+/// of the key so pages stay stable while new records arrive. Every page is read in one
+/// snapshot together with the HWMs it corresponds to.
+pub async fn current_state(
+    State(state): State<Arc<QueryApiState>>,
+    Query(params): Query<CurrentStateParams>,
+) -> Result<Json<CurrentStateResponse>, (StatusCode, String)> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let after_key_hash = params
+        .cursor
+        .as_deref()
+        .map(|cursor| general_purpose::URL_SAFE_NO_PAD.decode(cursor))
+        .transpose()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid cursor".to_string()))?;
+    let mut tx = begin_snapshot(&state.pg_pool)
+        .await
+        .map_err(internal_error)?;
+    let high_watermarks = get_topic_hwms(&mut tx, &params.topic)
+        .await
+        .map_err(internal_error)?;
+    let rows = query_current_state(
+        &mut tx,
+        &params.topic,
+        after_key_hash.as_deref(),
+        limit,
+        state.cipher.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    let next_cursor = match rows.last().and_then(|last| last.record_key.as_deref()) {
+        Some(key) if rows.len() as i64 == limit => {
+            Some(general_purpose::URL_SAFE_NO_PAD.encode(record_key_hash(key)))
+        }
+        _ => None,
+    };
+    let encoding = params.encoding.unwrap_or(ValueEncoding::Utf8);
+    let records = rows
+        .into_iter()
+        .map(|row| RecordResponse::from_row(row, encoding))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(internal_error)?;
+    Ok(Json(CurrentStateResponse {
+        high_watermarks,
+        records,
+        next_cursor,
+    }))
+}
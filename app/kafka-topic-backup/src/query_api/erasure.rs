@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::encoding::EncodedBytes;
+use crate::erasure::erase_key::{
+    ErasureFilter, ErasureRequest, HeaderMatch, JsonFieldMatch, erase_key,
+};
+use crate::erasure::hash_chain::{self, ChainVerification, to_hex};
+use crate::query_api::QueryApiState;
+use crate::query_api::records::internal_error;
+
+#[derive(Debug, Deserialize)]
+pub struct HeaderFilter {
+    pub key: String,
+    pub value: EncodedBytes,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonFieldFilter {
+    /// JSON pointer to the field in the payload, like `/person/ident`
+    pub pointer: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErasureApiRequest {
+    pub topic: String,
+    pub key: EncodedBytes,
+    pub header: Option<HeaderFilter>,
+    pub json_field: Option<JsonFieldFilter>,
+    /// Who asked for the erasure, written to the erasure log
+    pub requested_by: String,
+    /// Why, for example a case number, written to the erasure log
+    pub reason: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErasureResponse {
+    /// `None` for a dry run
+    pub erasure_id: Option<i64>,
+    pub dry_run: bool,
+    pub rows: i64,
+    pub rows_per_partition: BTreeMap<i32, i64>,
+    /// Hex-encoded hash of the new erasure log entry
+    pub entry_hash: Option<String>,
+    /// Archived segments the records were erased from, as they were named before the
+    /// erasure rewrote them
+    #[serde(default)]
+    pub archive_segments: Vec<String>,
+}
+
+fn bad_request(message: &str) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.to_string())
+}
+
+/// Deletes every record with the key, narrowed by the optional header and payload field
+/// filters. With `dry_run` only the matching records are counted.
+pub async fn erase(
+    State(state): State<Arc<QueryApiState>>,
+    Json(request): Json<ErasureApiRequest>,
+) -> Result<Json<ErasureResponse>, (StatusCode, String)> {
+    if request.requested_by.trim().is_empty() || request.reason.trim().is_empty() {
+        return Err(bad_request("requested_by and reason are required"));
+    }
+    let key = request
+        .key
+        .decode()
+        .map_err(|_| bad_request("Invalid key encoding"))?;
+    let header = match request.header {
+        Some(header) => Some(HeaderMatch {
+            value: header
+                .value
+                .decode()
+                .map_err(|_| bad_request("Invalid header value encoding"))?,
+            key: header.key,
+        }),
+        None => None,
+    };
+    let json_field = match request.json_field {
+        Some(field) if !field.pointer.starts_with('/') => {
+            return Err(bad_request("json_field.pointer must start with /"));
+        }
+        Some(field) => Some(JsonFieldMatch {
+            pointer: field.pointer,
+            value: field.value,
+        }),
+        None => None,
+    };
+    let result = erase_key(
+        &state.pg_pool,
+        &ErasureRequest {
+            topic: request.topic,
+            key,
+            filter: ErasureFilter { header, json_field },
+            requested_by: request.requested_by,
+            reason: request.reason,
+            dry_run: request.dry_run,
+        },
+        state.cipher.as_deref(),
+        state.archiver.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+    Ok(Json(ErasureResponse {
+        erasure_id: result.erasure_id,
+        dry_run: request.dry_run,
+        rows: result.total(),
+        entry_hash: result.entry_hash.as_deref().map(to_hex),
+        rows_per_partition: result.rows_per_partition,
+        archive_segments: result.archive_segments,
+    }))
+}
+
+/// Recomputes the hash chain of the erasure log
+pub async fn verify_erasure_log(
+    State(state): State<Arc<QueryApiState>>,
+) -> Result<Json<ChainVerification>, (StatusCode, String)> {
+    hash_chain::verify_erasure_log(&state.pg_pool)
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use crate::database::read_data::{RecordCursor, query_records_by_key};
+use crate::encoding::{EncodedBytes, ValueEncoding};
+use crate::query_api::QueryApiState;
+use crate::query_api::records::{
+    DEFAULT_LIMIT, MAX_LIMIT, RecordResponse, RecordsResponse, decode_cursor, encode_cursor,
+    internal_error,
+};
+
+/// The key is sent in the body rather than the URL to keep it out of access logs
+#[derive(Debug, Deserialize)]
+pub struct KeyLookupRequest {
+    pub topic: String,
+    pub key: EncodedBytes,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub encoding: Option<ValueEncoding>,
+}
+
+/// Every stored record with the key, across all partitions, ordered by timestamp and offset
+pub async fn records_by_key(
+    State(state): State<Arc<QueryApiState>>,
+    Json(request): Json<KeyLookupRequest>,
+) -> Result<Json<RecordsResponse>, (StatusCode, String)> {
+    let limit = request.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let key = request
+        .key
+        .decode()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid key encoding".to_string()))?;
+    let cursor = match request.cursor.as_deref() {
+        Some(cursor) => decode_cursor(cursor).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => RecordCursor::start(),
+    };
+    let rows = query_records_by_key(
+        &state.pg_pool,
+        &request.topic,
+        &key,
+        cursor,
+        limit,
+        state.cipher.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+    let next_cursor = match rows.last() {
+        Some(last) if rows.len() as i64 == limit => Some(encode_cursor(RecordCursor {
+            timestamp: Some(last.timestamp),
+            partition: last.kafka_partition as i32,
+            offset: last.kafka_offset,
+        })),
+        _ => None,
+    };
+    let encoding = request.encoding.unwrap_or(ValueEncoding::Utf8);
+    let records = rows
+        .into_iter()
+        .map(|row| RecordResponse::from_row(row, encoding))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(internal_error)?;
+    Ok(Json(RecordsResponse {
+        records,
+        next_cursor,
+    }))
+}
@@ -0,0 +1,96 @@
+pub mod auth;
+pub mod current_state;
+pub mod erasure;
+pub mod key_lookup;
+pub mod offset_gaps;
+pub mod offset_mapping;
+pub mod records;
+
+use std::sync::Arc;
+
+use axum::routing::{get, post};
+use axum::{Router, middleware};
+use log::info;
+use sqlx::PgPool;
+
+use crate::archive::archiver::Archiver;
+use crate::audit::offset_audit::SharedAuditReport;
+use crate::config_utils::get_env::get_env;
+use crate::encryption::data_cipher::DataCipher;
+
+pub const QUERY_API_TOKEN_ENV: &str = "QUERY_API_TOKEN";
+/// Separate token for the admin routes, which delete data
+pub const ADMIN_API_TOKEN_ENV: &str = "ADMIN_API_TOKEN";
+
+pub struct QueryApiState {
+    pub pg_pool: PgPool,
+    pub api_token: String,
+    /// `None` disables the admin routes
+    pub admin_token: Option<String>,
+    pub audit_report: SharedAuditReport,
+    /// Decrypts the stored records, `None` when encryption is disabled
+    pub cipher: Option<Arc<DataCipher>>,
+    /// Erasures rewrite the archived segments with the key, `None` when the archive is
+    /// not configured
+    pub archiver: Option<Arc<Archiver>>,
+}
+
+impl QueryApiState {
+    /// The query API is only enabled when a token is configured
+    pub fn from_env(
+        pg_pool: PgPool,
+        audit_report: SharedAuditReport,
+        cipher: Option<Arc<DataCipher>>,
+        archiver: Option<Arc<Archiver>>,
+    ) -> Option<Self> {
+        match get_env(QUERY_API_TOKEN_ENV) {
+            Ok(api_token) if !api_token.is_empty() => Some(QueryApiState {
+                pg_pool,
+                api_token,
+                admin_token: get_env(ADMIN_API_TOKEN_ENV)
+                    .ok()
+                    .filter(|admin_token| !admin_token.is_empty()),
+                audit_report,
+                cipher,
+                archiver,
+            }),
+            _ => {
+                info!(
+                    "{} er ikke satt, API for oppslag i backup er deaktivert",
+                    QUERY_API_TOKEN_ENV
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Read endpoints for browsing backed up records, every route requires the bearer token.
+/// The admin routes require the admin token instead.
+pub fn routes(state: Arc<QueryApiState>) -> Router {
+    let admin_routes = Router::new()
+        .route("/api/v1/admin/erasures", post(erasure::erase))
+        .route(
+            "/api/v1/admin/erasures/verify",
+            get(erasure::verify_erasure_log),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_admin_token,
+        ));
+    Router::new()
+        .route("/api/v1/records", get(records::list_records))
+        .route("/api/v1/records/by-key", post(key_lookup::records_by_key))
+        .route("/api/v1/state", get(current_state::current_state))
+        .route("/api/v1/audit/offset-gaps", get(offset_gaps::offset_gaps))
+        .route(
+            "/api/v1/restore/offset-mapping",
+            get(offset_mapping::offset_mapping),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_bearer_token,
+        ))
+        .merge(admin_routes)
+        .with_state(state)
+}
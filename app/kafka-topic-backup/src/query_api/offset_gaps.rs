@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use crate::audit::offset_audit::AuditReport;
+use crate::query_api::QueryApiState;
+
+#[derive(Debug, Deserialize)]
+pub struct OffsetGapsParams {
+    pub topic: Option<String>,
+    pub partition: Option<i32>,
+    /// Leave out partitions without gaps
+    #[serde(default)]
+    pub only_gaps: bool,
+}
+
+/// The gaps found by the latest offset audit, 404 until the first audit has completed
+pub async fn offset_gaps(
+    State(state): State<Arc<QueryApiState>>,
+    Query(params): Query<OffsetGapsParams>,
+) -> Result<Json<AuditReport>, (StatusCode, String)> {
+    let latest = state
+        .audit_report
+        .read()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            )
+        })?
+        .clone();
+    let mut report = latest.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "No offset audit has completed yet".to_string(),
+        )
+    })?;
+    report.partitions.retain(|partition| {
+        params
+            .topic
+            .as_ref()
+            .is_none_or(|topic| &partition.topic == topic)
+            && params
+                .partition
+                .is_none_or(|wanted| partition.partition == wanted)
+            && (!params.only_gaps || !partition.gaps.is_empty())
+    });
+    Ok(Json(report))
+}
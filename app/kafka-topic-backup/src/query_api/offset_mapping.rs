@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use crate::database::restore_offsets::{OffsetMapping, translate_position};
+use crate::query_api::QueryApiState;
+use crate::query_api::records::internal_error;
+
+#[derive(Debug, Deserialize)]
+pub struct OffsetMappingParams {
+    pub source_topic: String,
+    pub target_topic: String,
+    pub partition: i32,
+    /// The committed position in the source partition, the offset of the next record to
+    /// read
+    pub offset: i64,
+}
+
+/// Translates a committed position in a restored topic to the target topic. 404 when
+/// nothing at or after the position was restored, the consumer should then start at the
+/// end of the target partition.
+pub async fn offset_mapping(
+    State(state): State<Arc<QueryApiState>>,
+    Query(params): Query<OffsetMappingParams>,
+) -> Result<Json<OffsetMapping>, (StatusCode, String)> {
+    translate_position(
+        &state.pg_pool,
+        &params.source_topic,
+        &params.target_topic,
+        params.partition,
+        params.offset,
+    )
+    .await
+    .map_err(internal_error)?
+    .map(Json)
+    .ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "No restored record at or after the offset".to_string(),
+        )
+    })
+}
@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::database::read_data::{DataRow, RecordCursor, RecordQuery, query_records};
+use crate::encoding::{EncodedBytes, ValueEncoding};
+use crate::kafka::headers::{headers_from_json, headers_to_json};
+use crate::query_api::QueryApiState;
+
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct RecordsParams {
+    pub topic: String,
+    pub partition: Option<i32>,
+    pub from_offset: Option<i64>,
+    pub to_offset: Option<i64>,
+    pub from_timestamp: Option<DateTime<Utc>>,
+    pub to_timestamp: Option<DateTime<Utc>>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    /// Preferred encoding of keys and values, values that are not UTF-8 are always base64
+    pub encoding: Option<ValueEncoding>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordsResponse {
+    pub records: Vec<RecordResponse>,
+    /// Pass as `cursor` to get the next page, `None` when there are no more records
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordResponse {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub timestamp: DateTime<Utc>,
+    pub key: Option<EncodedBytes>,
+    pub value: Option<EncodedBytes>,
+    pub headers: Option<Value>,
+    /// The value was not stored because of a `keys_only` policy, `value` is then `None`
+    /// but the record is not a tombstone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub value_omitted: bool,
+}
+
+impl RecordResponse {
+    pub fn from_row(row: DataRow, encoding: ValueEncoding) -> Result<Self, String> {
+        let headers = match row.headers {
+            Some(headers) => Some(headers_to_json(
+                &headers_from_json(&headers).map_err(|e| e.to_string())?,
+            )),
+            None => None,
+        };
+        Ok(RecordResponse {
+            topic: row.kafka_topic,
+            partition: row.kafka_partition as i32,
+            offset: row.kafka_offset,
+            timestamp: row.timestamp,
+            key: row
+                .record_key
+                .map(|key| EncodedBytes::encode(&key, encoding)),
+            value: row
+                .record_value
+                .map(|value| EncodedBytes::encode(&value, encoding)),
+            headers,
+            value_omitted: row.value_omitted,
+        })
+    }
+
+    /// The stored record again, as read from an export or an archived segment
+    pub fn into_row(self) -> Result<DataRow, String> {
+        let invalid = |e: base64::DecodeError| {
+            format!(
+                "Invalid record {}::{}::{}: {}",
+                self.topic, self.partition, self.offset, e
+            )
+        };
+        let record_key = self
+            .key
+            .map(|key| key.decode())
+            .transpose()
+            .map_err(invalid)?;
+        let record_value = self
+            .value
+            .map(|value| value.decode())
+            .transpose()
+            .map_err(invalid)?;
+        Ok(DataRow {
+            kafka_partition: i16::try_from(self.partition)
+                .map_err(|_| format!("Invalid partition {}", self.partition))?,
+            kafka_topic: self.topic,
+            kafka_offset: self.offset,
+            timestamp: self.timestamp,
+            headers: self.headers,
+            record_key,
+            record_value,
+            key_id: None,
+            value_codec: None,
+            value_omitted: self.value_omitted,
+        })
+    }
+}
+
+/// The cursor is opaque to clients, it is the JSON of the last position base64url-encoded
+#[derive(Debug, Serialize, Deserialize)]
+struct CursorToken {
+    timestamp: Option<DateTime<Utc>>,
+    partition: i32,
+    offset: i64,
+}
+
+pub fn encode_cursor(cursor: RecordCursor) -> String {
+    let token = CursorToken {
+        timestamp: cursor.timestamp,
+        partition: cursor.partition,
+        offset: cursor.offset,
+    };
+    general_purpose::URL_SAFE_NO_PAD
+        .encode(serde_json::to_vec(&token).expect("Cursor is always valid JSON"))
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<RecordCursor, String> {
+    let bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|_| "Invalid cursor".to_string())?;
+    let token: CursorToken =
+        serde_json::from_slice(&bytes).map_err(|_| "Invalid cursor".to_string())?;
+    Ok(RecordCursor {
+        timestamp: token.timestamp,
+        partition: token.partition,
+        offset: token.offset,
+    })
+}
+
+pub async fn list_records(
+    State(state): State<Arc<QueryApiState>>,
+    Query(params): Query<RecordsParams>,
+) -> Result<Json<RecordsResponse>, (StatusCode, String)> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let cursor = match params.cursor.as_deref() {
+        Some(cursor) => decode_cursor(cursor).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => RecordCursor::start(),
+    };
+    let query = RecordQuery {
+        kafka_topic: params.topic,
+        kafka_partition: params.partition,
+        from_offset: params.from_offset,
+        to_offset: params.to_offset,
+        from_timestamp: params.from_timestamp,
+        to_timestamp: params.to_timestamp,
+    };
+    let rows = query_records(
+        &state.pg_pool,
+        &query,
+        cursor,
+        limit,
+        state.cipher.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+    let next_cursor = match rows.last() {
+        Some(last) if rows.len() as i64 == limit => {
+            Some(encode_cursor(RecordCursor::after(last, &query)))
+        }
+        _ => None,
+    };
+    let encoding = params.encoding.unwrap_or(ValueEncoding::Utf8);
+    let records = rows
+        .into_iter()
+        .map(|row| RecordResponse::from_row(row, encoding))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(internal_error)?;
+    Ok(Json(RecordsResponse {
+        records,
+        next_cursor,
+    }))
+}
+
+pub fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    error!("Feil ved oppslag i backup: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
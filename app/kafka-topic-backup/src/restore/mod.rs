@@ -0,0 +1 @@
+pub mod restore_topic;
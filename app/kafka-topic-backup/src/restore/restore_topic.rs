@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::info;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord, Producer};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::database::read_data::{DataPosition, DataRow, get_topic_partitions, read_data_batch};
+use crate::database::restore_offsets::{
+    OffsetMapping, delete_offset_mappings, insert_offset_mappings,
+};
+use crate::encryption::data_cipher::DataCipher;
+use crate::kafka::headers::{headers_from_json, to_kafka_headers};
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct RestoreRequest {
+    pub source_topic: String,
+    pub target_topic: String,
+    /// Only restore these partitions, all partitions when `None`
+    pub partitions: Option<Vec<i32>>,
+    pub batch_size: i64,
+    pub cutoff: RestoreCutoff,
+}
+
+/// Restores the topic as it was at a point in time, optionally without a window of bad
+/// records
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreCutoff {
+    /// Only records with a timestamp at or before this
+    pub until_timestamp: Option<DateTime<Utc>>,
+    /// Only offsets up to and including this in the partition, other partitions are not
+    /// limited
+    pub until_offsets: BTreeMap<i32, i64>,
+    /// Records with a timestamp from the start, inclusive, to the end, exclusive, are
+    /// skipped
+    pub exclude_window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl RestoreCutoff {
+    /// Why the record is not replayed, `None` when it is
+    pub fn skip_reason(&self, row: &DataRow) -> Option<SkipReason> {
+        let partition = row.kafka_partition as i32;
+        if row.value_omitted {
+            Some(SkipReason::ValueOmitted)
+        } else if self
+            .until_timestamp
+            .is_some_and(|until| row.timestamp > until)
+            || self
+                .until_offsets
+                .get(&partition)
+                .is_some_and(|until| row.kafka_offset > *until)
+        {
+            Some(SkipReason::AfterCutoff)
+        } else if self
+            .exclude_window
+            .is_some_and(|(from, to)| row.timestamp >= from && row.timestamp < to)
+        {
+            Some(SkipReason::ExcludedWindow)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    AfterCutoff,
+    ExcludedWindow,
+    /// Stored without its value by a `keys_only` policy, replaying it would produce a
+    /// tombstone
+    ValueOmitted,
+}
+
+/// Consecutive offsets, both inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OffsetRange {
+    pub first: i64,
+    pub last: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SkippedRange {
+    pub first: i64,
+    pub last: i64,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreSummary {
+    pub records_per_partition: BTreeMap<i32, u64>,
+    /// Replayed offsets of each partition, a stored offset that is missing from both
+    /// these and the skipped ranges was not stored
+    pub replayed: BTreeMap<i32, Vec<OffsetRange>>,
+    pub skipped: BTreeMap<i32, Vec<SkippedRange>>,
+}
+
+impl RestoreSummary {
+    pub fn total(&self) -> u64 {
+        self.records_per_partition.values().sum()
+    }
+
+    pub fn skipped_total(&self) -> u64 {
+        self.skipped
+            .values()
+            .flatten()
+            .map(|range| (range.last - range.first + 1) as u64)
+            .sum()
+    }
+
+    fn add_replayed(&mut self, partition: i32, offset: i64) {
+        *self.records_per_partition.entry(partition).or_insert(0) += 1;
+        let ranges = self.replayed.entry(partition).or_default();
+        match ranges.last_mut() {
+            Some(range) if range.last + 1 == offset => range.last = offset,
+            _ => ranges.push(OffsetRange {
+                first: offset,
+                last: offset,
+            }),
+        }
+    }
+
+    fn add_skipped(&mut self, partition: i32, offset: i64, reason: SkipReason) {
+        let ranges = self.skipped.entry(partition).or_default();
+        match ranges.last_mut() {
+            Some(range) if range.last + 1 == offset && range.reason == reason => {
+                range.last = offset
+            }
+            _ => ranges.push(SkippedRange {
+                first: offset,
+                last: offset,
+                reason,
+            }),
+        }
+    }
+}
+
+/// Reads every stored record for the source topic in (partition, offset) order and
+/// produces it to the same partition of the target topic with the original key, value,
+/// headers and timestamp. Records after the cutoff or in the excluded window are skipped,
+/// the summary lists the replayed and skipped offsets.
+///
+/// Each batch read from the database is fully acknowledged by the broker before the
+/// next batch is read, so a failed restore stops at a batch boundary. The offsets the
+/// acknowledged records got are written to the offset mapping table, after the mappings
+/// of an earlier restore of the same partitions to the target topic are removed.
+pub async fn restore_topic(
+    pg_pool: &PgPool,
+    producer: &FutureProducer,
+    request: &RestoreRequest,
+    cipher: Option<&DataCipher>,
+) -> Result<RestoreSummary, Box<dyn Error>> {
+    if let Some((from, to)) = request.cutoff.exclude_window
+        && from >= to
+    {
+        return Err(format!("Excluded window starts at {} after it ends at {}", from, to).into());
+    }
+    check_target_partitions(pg_pool, producer, request).await?;
+    delete_offset_mappings(
+        pg_pool,
+        &request.source_topic,
+        &request.target_topic,
+        request.partitions.as_deref(),
+    )
+    .await?;
+    let mut summary = RestoreSummary::default();
+    let mut position = DataPosition::start();
+    loop {
+        let rows = read_data_batch(
+            pg_pool,
+            &request.source_topic,
+            request.partitions.as_deref(),
+            position,
+            request.batch_size,
+            cipher,
+        )
+        .await?;
+        let Some(last_row) = rows.last() else {
+            break;
+        };
+        position = DataPosition::of(last_row);
+
+        let mut replay = Vec::with_capacity(rows.len());
+        for row in &rows {
+            match request.cutoff.skip_reason(row) {
+                Some(reason) => {
+                    summary.add_skipped(row.kafka_partition as i32, row.kafka_offset, reason)
+                }
+                None => replay.push(row),
+            }
+        }
+        let mut deliveries = Vec::with_capacity(replay.len());
+        for row in &replay {
+            let partition = Some(row.kafka_partition as i32);
+            deliveries.push(send_row(producer, &request.target_topic, partition, row).await?);
+        }
+        let mut mappings = Vec::with_capacity(replay.len());
+        for (row, delivery) in replay.iter().zip(deliveries) {
+            let delivery = delivery.await?.map_err(|(e, _)| {
+                format!(
+                    "Failed to restore {}::{}::{} to {}: {}",
+                    row.kafka_topic, row.kafka_partition, row.kafka_offset, request.target_topic, e
+                )
+            })?;
+            mappings.push(OffsetMapping {
+                source_partition: row.kafka_partition as i32,
+                source_offset: row.kafka_offset,
+                target_partition: delivery.partition,
+                target_offset: delivery.offset,
+            });
+            summary.add_replayed(row.kafka_partition as i32, row.kafka_offset);
+        }
+        insert_offset_mappings(
+            pg_pool,
+            &request.source_topic,
+            &request.target_topic,
+            &mappings,
+        )
+        .await?;
+        info!(
+            "Restore {} -> {}: {} meldinger produsert, {} hoppet over, posisjon {}::{}",
+            request.source_topic,
+            request.target_topic,
+            summary.total(),
+            summary.skipped_total(),
+            position.partition,
+            position.offset
+        );
+    }
+    Ok(summary)
+}
+
+/// Fails before anything is produced when a partition of the source topic that is
+/// restored does not exist in the target topic
+async fn check_target_partitions(
+    pg_pool: &PgPool,
+    producer: &FutureProducer,
+    request: &RestoreRequest,
+) -> Result<(), Box<dyn Error>> {
+    let source_partitions: Vec<i32> = get_topic_partitions(pg_pool, &request.source_topic)
+        .await?
+        .into_iter()
+        .filter(|partition| {
+            request
+                .partitions
+                .as_ref()
+                .is_none_or(|wanted| wanted.contains(partition))
+        })
+        .collect();
+    let Some(highest) = source_partitions.last().copied() else {
+        return Ok(());
+    };
+    let producer = producer.clone();
+    let target_topic = request.target_topic.clone();
+    let target_partitions = tokio::task::spawn_blocking(move || {
+        let metadata = producer
+            .client()
+            .fetch_metadata(Some(&target_topic), METADATA_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        Ok::<_, String>(
+            metadata
+                .topics()
+                .iter()
+                .find(|topic| topic.name() == target_topic)
+                .map_or(0, |topic| topic.partitions().len() as i32),
+        )
+    })
+    .await??;
+    if highest >= target_partitions {
+        return Err(format!(
+            "Target topic {} has {} partitions, records of partition {} of {} are restored to the same partition",
+            request.target_topic, target_partitions, highest, request.source_topic
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Produces the row with the partition chosen by the partitioner unless `partition` is
+/// given
+pub(crate) async fn send_row(
+    producer: &FutureProducer,
+    target_topic: &str,
+    partition: Option<i32>,
+    row: &DataRow,
+) -> Result<DeliveryFuture, Box<dyn Error>> {
+    let mut record: FutureRecord<'_, [u8], [u8]> =
+        FutureRecord::to(target_topic).timestamp(row.timestamp.timestamp_millis());
+    if let Some(partition) = partition {
+        record = record.partition(partition);
+    }
+    if let Some(key) = row.record_key.as_deref() {
+        record = record.key(key);
+    }
+    if let Some(value) = row.record_value.as_deref() {
+        record = record.payload(value);
+    }
+    if let Some(headers) = row.headers.as_ref() {
+        record = record.headers(to_kafka_headers(&headers_from_json(headers)?));
+    }
+    loop {
+        match producer.send_result(record) {
+            Ok(delivery) => return Ok(delivery),
+            Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                // The producer polls in the background, wait for the queue to drain
+                record = returned;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Err((e, _)) => return Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod partition_maintenance;
+pub mod purge_job;
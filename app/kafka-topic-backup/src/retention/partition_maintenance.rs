@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use log::{info, warn};
+use sqlx::PgPool;
+
+use crate::database::partitions::{
+    create_month_partition, drop_month_partition, get_default_partition_months,
+    get_partition_months, get_topics_in_month, lock_data_table, month_bounds, month_of, next_month,
+};
+use crate::kafka::topic_subscription::TopicSelector;
+use crate::metrics;
+
+pub const PARTITION_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Months after the current one that always have a partition
+pub const FUTURE_PARTITIONS: u32 = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceSummary {
+    pub created: Vec<NaiveDate>,
+    /// Records moved from the default partition to the created partitions
+    pub moved_records: u64,
+    pub dropped: Vec<NaiveDate>,
+}
+
+/// Creates the partitions for the current and the next months and for records that
+/// ended up in the default partition, then drops the partitions that only hold expired
+/// records
+pub async fn maintain_partitions(
+    pg_pool: &PgPool,
+    selector: &TopicSelector,
+    now: DateTime<Utc>,
+) -> Result<MaintenanceSummary, Box<dyn Error>> {
+    let mut summary = MaintenanceSummary::default();
+    let existing = get_partition_months(pg_pool).await?;
+    let mut wanted = get_default_partition_months(pg_pool).await?;
+    let mut month = month_of(now);
+    for _ in 0..=FUTURE_PARTITIONS {
+        wanted.push(month);
+        month = next_month(month);
+    }
+    wanted.sort();
+    wanted.dedup();
+    for month in wanted.into_iter().filter(|month| !existing.contains(month)) {
+        summary.moved_records += create_month_partition(pg_pool, month).await?;
+        summary.created.push(month);
+    }
+
+    for month in get_partition_months(pg_pool).await? {
+        if drop_expired_partition(pg_pool, selector, month, now).await? {
+            summary.dropped.push(month);
+        }
+    }
+    Ok(summary)
+}
+
+/// A partition is expired when its whole month is older than the retention of every
+/// topic with records in it, so partitions with records of a topic without retention
+/// are kept, whether the topic is consumed or only imported or restored. Empty
+/// partitions are dropped once the month is over.
+async fn drop_expired_partition(
+    pg_pool: &PgPool,
+    selector: &TopicSelector,
+    month: NaiveDate,
+    now: DateTime<Utc>,
+) -> Result<bool, Box<dyn Error>> {
+    let (_, end) = month_bounds(month);
+    if end > now {
+        return Ok(false);
+    }
+    let is_kept = |topic: &String| {
+        selector
+            .policy_for(topic)
+            .retention_days
+            .is_none_or(|days| end > now - TimeDelta::days(days as i64))
+    };
+    let mut tx = pg_pool.begin().await?;
+    // Checked again after locking, so no record for a kept topic is stored in between
+    if get_topics_in_month(&mut tx, month)
+        .await?
+        .iter()
+        .any(is_kept)
+    {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    lock_data_table(&mut tx).await?;
+    if get_topics_in_month(&mut tx, month)
+        .await?
+        .iter()
+        .any(is_kept)
+    {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    let dropped = drop_month_partition(&mut tx, month).await?;
+    tx.commit().await?;
+    for (topic, partition, count) in &dropped {
+        metrics::increment_retention_rows_deleted(topic, *partition as i32, *count);
+    }
+    info!(
+        "Slettet partisjonen for {} med {} meldinger",
+        month.format("%Y-%m"),
+        dropped.iter().map(|(_, _, count)| count).sum::<i64>()
+    );
+    Ok(true)
+}
+
+/// Runs the partition maintenance periodically, a failed run is logged and retried at
+/// the next interval
+pub async fn run_partition_maintenance(
+    pg_pool: PgPool,
+    selector: Arc<TopicSelector>,
+    interval: Duration,
+) {
+    loop {
+        match maintain_partitions(&pg_pool, &selector, Utc::now()).await {
+            Ok(summary) if !summary.created.is_empty() => info!(
+                "Opprettet partisjoner for {:?}, flyttet {} meldinger fra standardpartisjonen",
+                summary.created, summary.moved_records
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Vedlikehold av partisjoner feilet: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
+use sqlx::PgPool;
+
+use crate::database::purge::{get_hwm_topics, get_oldest_timestamp, purge_data_batch};
+use crate::kafka::topic_subscription::TopicSelector;
+use crate::metrics;
+
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Rows deleted per statement, small enough to keep each delete and its locks short
+pub const PURGE_BATCH_SIZE: i64 = 1000;
+const PAUSE_BETWEEN_BATCHES: Duration = Duration::from_millis(100);
+
+/// Deleted records per partition for one topic
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PurgeSummary {
+    pub rows_per_partition: BTreeMap<i32, i64>,
+}
+
+impl PurgeSummary {
+    pub fn total(&self) -> i64 {
+        self.rows_per_partition.values().sum()
+    }
+}
+
+/// Deletes every record of the topic older than `cutoff`, one bounded batch at a time
+pub async fn purge_topic(
+    pg_pool: &PgPool,
+    topic: &str,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<PurgeSummary, Box<dyn Error>> {
+    let mut summary = PurgeSummary::default();
+    loop {
+        let deleted = purge_data_batch(pg_pool, topic, cutoff, batch_size).await?;
+        let mut deleted_in_batch = 0;
+        for (partition, count) in deleted {
+            let partition = partition as i32;
+            *summary.rows_per_partition.entry(partition).or_default() += count;
+            metrics::increment_retention_rows_deleted(topic, partition, count);
+            deleted_in_batch += count;
+        }
+        if deleted_in_batch < batch_size {
+            return Ok(summary);
+        }
+        tokio::time::sleep(PAUSE_BETWEEN_BATCHES).await;
+    }
+}
+
+/// Applies the retention of every topic with a HWM and updates the oldest timestamp
+/// gauges. Topics without retention are only measured.
+pub async fn apply_retention(
+    pg_pool: &PgPool,
+    selector: &TopicSelector,
+    now: DateTime<Utc>,
+) -> Result<BTreeMap<String, PurgeSummary>, Box<dyn Error>> {
+    let mut summaries = BTreeMap::new();
+    for topic in get_hwm_topics(pg_pool).await? {
+        if let Some(days) = selector.policy_for(&topic).retention_days {
+            let cutoff = now - TimeDelta::days(days as i64);
+            let summary = purge_topic(pg_pool, &topic, cutoff, PURGE_BATCH_SIZE).await?;
+            if summary.total() > 0 {
+                info!(
+                    "Slettet {} meldinger eldre enn {} fra {}: {:?}",
+                    summary.total(),
+                    cutoff,
+                    topic,
+                    summary.rows_per_partition
+                );
+            }
+            summaries.insert(topic.clone(), summary);
+        }
+        let oldest = get_oldest_timestamp(pg_pool, &topic).await?;
+        metrics::set_oldest_stored_record(&topic, oldest.map(|timestamp| timestamp.timestamp()));
+    }
+    Ok(summaries)
+}
+
+/// Runs the retention periodically, a failed run is logged and retried at the next interval
+pub async fn run_retention(pg_pool: PgPool, selector: Arc<TopicSelector>, interval: Duration) {
+    loop {
+        if let Err(e) = apply_retention(&pg_pool, &selector, Utc::now()).await {
+            warn!("Sletting av gamle meldinger feilet: {}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use object_store::ObjectStore;
+use object_store::aws::AmazonS3Builder;
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use sqlx::PgPool;
+use std::error::Error;
+use std::sync::Arc;
+use testcontainers::core::ExecCommand;
+use testcontainers::{ContainerAsync, runners::AsyncRunner};
+use testcontainers_modules::minio::MinIO;
+
+use paw_kafka_topic_backup::archive::archiver::Archiver;
+use paw_kafka_topic_backup::database::archive_catalog::get_archive_segments;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+use paw_kafka_topic_backup::query_api::records::RecordResponse;
+
+mod common;
+use common::setup_test_db;
+
+const TOPIC: &str = "test-topic";
+const BUCKET: &str = "archive";
+
+/// Setup a MinIO container with an empty bucket
+async fn setup_test_bucket() -> Result<(Arc<dyn ObjectStore>, ContainerAsync<MinIO>), Box<dyn Error>>
+{
+    let minio_container = MinIO::default().start().await;
+    // MinIO serves every directory of its data directory as a bucket
+    minio_container
+        .exec(ExecCommand::new([
+            "mkdir",
+            "-p",
+            &format!("/data/{}", BUCKET),
+        ]))
+        .await;
+
+    let host_port = minio_container.get_host_port_ipv4(9000).await;
+    let store = AmazonS3Builder::new()
+        .with_endpoint(format!("http://127.0.0.1:{}", host_port))
+        .with_allow_http(true)
+        .with_region("us-east-1")
+        .with_bucket_name(BUCKET)
+        .with_access_key_id("minioadmin")
+        .with_secret_access_key("minioadmin")
+        .build()?;
+
+    Ok((Arc::new(store), minio_container))
+}
+
+fn day(n: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 3, 1 + n).expect("Valid day")
+}
+
+/// Stores offsets 0 to 5 on partition 0, two records per day starting at `day(0)`
+async fn store_test_messages(pool: &PgPool) {
+    let timestamp = |offset: i64| -> DateTime<Utc> {
+        day(offset as u32 / 2)
+            .and_hms_opt(6, 0, 0)
+            .unwrap()
+            .and_utc()
+            + TimeDelta::hours(offset % 2 * 12)
+    };
+    common::store_test_messages(pool, TOPIC, 0..6, timestamp, |offset| {
+        Some(format!(r#"{{"offset": {}}}"#, offset).into_bytes())
+    })
+    .await;
+}
+
+async fn stored_offsets(pool: &PgPool) -> Vec<i64> {
+    sqlx::query_scalar(
+        "SELECT kafka_offset FROM data_v3 WHERE kafka_topic = $1 ORDER BY kafka_offset",
+    )
+    .bind(TOPIC)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to read stored offsets")
+}
+
+async fn read_segment(store: &dyn ObjectStore, key: &str) -> Vec<RecordResponse> {
+    let compressed = store
+        .get(&Path::from(key))
+        .await
+        .expect("Segment should exist")
+        .bytes()
+        .await
+        .expect("Segment should be readable");
+    let content = zstd::decode_all(compressed.as_ref()).expect("Segment should be zstd");
+    String::from_utf8(content)
+        .expect("Segment should be text")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Line should be a record"))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_archive_topic_uploads_segments_and_deletes_archived_rows() {
+    let (pool, _postgres) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let (store, _minio) = setup_test_bucket()
+        .await
+        .expect("Failed to setup test bucket");
+    store_test_messages(&pool).await;
+    let archiver = Archiver::new(store.clone(), "backup", true);
+
+    let archived = archiver
+        .archive_topic(&pool, TOPIC, day(2), None)
+        .await
+        .expect("Archiving should succeed");
+    let keys: Vec<&str> = archived
+        .iter()
+        .map(|window| window.object_key.as_str())
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            "backup/test-topic/2024/03/test-topic-2024-03-01.jsonl.zst",
+            "backup/test-topic/2024/03/test-topic-2024-03-02.jsonl.zst"
+        ]
+    );
+    assert!(
+        archived
+            .iter()
+            .all(|window| window.row_count == 2 && window.deleted_rows == Some(2))
+    );
+    let offsets: Vec<i64> = read_segment(store.as_ref(), keys[1])
+        .await
+        .iter()
+        .map(|record| record.offset)
+        .collect();
+    assert_eq!(offsets, vec![2, 3]);
+    assert_eq!(stored_offsets(&pool).await, vec![4, 5]);
+
+    let catalog = get_archive_segments(&pool, TOPIC)
+        .await
+        .expect("Failed to read catalog");
+    assert_eq!(catalog.len(), 2);
+    assert_eq!(catalog[0].object_key, keys[0]);
+    assert_eq!(catalog[0].deleted_rows, Some(2));
+    assert!(catalog[0].rows_deleted_at.is_some());
+    let purged: i64 =
+        sqlx::query_scalar("SELECT SUM(row_count)::BIGINT FROM purge_log WHERE kafka_topic = $1")
+            .bind(TOPIC)
+            .fetch_one(&pool)
+            .await
+            .expect("Deleted records should be in the purge log");
+    assert_eq!(purged, 4);
+
+    let archived = archiver
+        .archive_topic(&pool, TOPIC, day(2), None)
+        .await
+        .expect("Archiving should succeed");
+    assert!(archived.is_empty(), "Archived days are not archived again");
+}
+
+#[tokio::test]
+async fn test_archive_closed_windows_keeps_rows_by_default() {
+    let (pool, _postgres) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let archiver = Archiver::new(store.clone(), "", false);
+    let selector = TopicSelector::with_policies(
+        [(
+            TOPIC,
+            TopicPolicy {
+                archive_after_days: Some(10),
+                ..Default::default()
+            },
+        )],
+        &[],
+    )
+    .expect("Valid selector");
+    let now = day(11).and_hms_opt(12, 0, 0).unwrap().and_utc();
+
+    let archived = archiver
+        .archive_closed_windows(&pool, &selector, now, None)
+        .await
+        .expect("Archiving should succeed");
+    assert_eq!(
+        archived.iter().map(|window| window.day).collect::<Vec<_>>(),
+        vec![day(0)],
+        "Only days older than archive_after_days are archived"
+    );
+    assert_eq!(archived[0].deleted_rows, None);
+    assert_eq!(stored_offsets(&pool).await, (0..6).collect::<Vec<i64>>());
+    let records = read_segment(
+        store.as_ref(),
+        "test-topic/2024/03/test-topic-2024-03-01.jsonl.zst",
+    )
+    .await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[1].value.as_ref().expect("Value is archived").data,
+        r#"{"offset": 1}"#
+    );
+    let catalog = get_archive_segments(&pool, TOPIC)
+        .await
+        .expect("Failed to read catalog");
+    assert_eq!(catalog[0].rows_deleted_at, None);
+}
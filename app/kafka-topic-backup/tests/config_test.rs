@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use std::path::Path;
+
+use paw_kafka_topic_backup::compression::Compression;
+use paw_kafka_topic_backup::config::{CONFIG_FILE_ENV, Config};
+use paw_kafka_topic_backup::kafka::config::ApplicationKafkaConfig;
+use paw_kafka_topic_backup::kafka::topic_policy::{StartPosition, TopicPolicy};
+
+#[test]
+fn test_kafka_section_is_optional() {
+    let config = Config::from_string(r#"topics = ["topic-a", "topic-b"]"#).expect("Invalid config");
+
+    assert_eq!(config.topics_as_str_slice(), vec!["topic-a", "topic-b"]);
+    assert!(config.exclude_topics_as_str_slice().is_empty());
+    assert_eq!(config.kafka.group_id.as_str(), "hedelselogg_backup2_v1");
+    assert_eq!(config.kafka.auto_offset_reset.as_str(), "earliest");
+    assert_eq!(config.kafka.security_protocol.as_str(), "ssl");
+    assert!(config.kafka.properties.is_empty());
+}
+
+#[test]
+fn test_kafka_section_with_env_vars() {
+    temp_env::with_vars(
+        [
+            ("TEST_GROUP_ID", Some("group-from-env")),
+            ("TEST_FETCH_MAX_BYTES", Some("262144")),
+        ],
+        || {
+            let config = Config::from_string(
+                r#"
+                topics = ["topic-a"]
+
+                [kafka]
+                group_id = "$TEST_GROUP_ID"
+                security_protocol = "plaintext"
+
+                [kafka.properties]
+                "fetch.max.bytes" = "$TEST_FETCH_MAX_BYTES"
+                "queued.max.messages.kbytes" = "2048"
+                "#,
+            )
+            .expect("Invalid config");
+
+            assert_eq!(config.kafka.group_id.as_str(), "group-from-env");
+            assert_eq!(config.kafka.auto_offset_reset.as_str(), "earliest");
+            assert_eq!(config.kafka.security_protocol.as_str(), "plaintext");
+            let properties = config.kafka.properties();
+            assert_eq!(properties["fetch.max.bytes"], "262144");
+            assert_eq!(properties["queued.max.messages.kbytes"], "2048");
+        },
+    );
+}
+
+#[test]
+fn test_properties_override_consumer_defaults() {
+    let config = Config::from_string(
+        r#"
+        topics = ["topic-a"]
+
+        [kafka]
+        group_id = "test-group"
+        auto_offset_reset = "latest"
+        security_protocol = "plaintext"
+
+        [kafka.properties]
+        "fetch.max.bytes" = "262144"
+        "#,
+    )
+    .expect("Invalid config");
+
+    temp_env::with_var("KAFKA_BROKERS", Some("localhost:9092"), || {
+        let client_config = ApplicationKafkaConfig::from_config(&config.kafka)
+            .rdkafka_config()
+            .expect("Failed to create rdkafka config");
+
+        assert_eq!(client_config.get("group.id"), Some("test-group"));
+        assert_eq!(client_config.get("auto.offset.reset"), Some("latest"));
+        assert_eq!(client_config.get("security.protocol"), Some("plaintext"));
+        assert_eq!(client_config.get("fetch.max.bytes"), Some("262144"));
+        assert_eq!(
+            client_config.get("queued.max.messages.kbytes"),
+            Some("1024")
+        );
+    });
+}
+
+#[test]
+fn test_properties_override_producer_defaults() {
+    let config = Config::from_string(
+        r#"
+        topics = ["topic-a"]
+
+        [kafka]
+        group_id = "test-group"
+        auto_offset_reset = "latest"
+        security_protocol = "plaintext"
+
+        [kafka.properties]
+        "security.protocol" = "sasl_ssl"
+        "sasl.mechanism" = "PLAIN"
+        "message.timeout.ms" = "120000"
+        "#,
+    )
+    .expect("Invalid config");
+
+    temp_env::with_var("KAFKA_BROKERS", Some("localhost:9092"), || {
+        let client_config = ApplicationKafkaConfig::from_config(&config.kafka)
+            .rdkafka_producer_config()
+            .expect("Failed to create rdkafka producer config");
+
+        assert_eq!(client_config.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(client_config.get("sasl.mechanism"), Some("PLAIN"));
+        assert_eq!(client_config.get("message.timeout.ms"), Some("120000"));
+        assert_eq!(client_config.get("enable.idempotence"), Some("true"));
+    });
+}
+
+#[test]
+fn test_load_prefers_cli_path_over_env_and_embedded_config() {
+    let dir = std::env::temp_dir().join(format!("config-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+    let cli_file = dir.join("cli.toml");
+    let env_file = dir.join("env.toml");
+    std::fs::write(&cli_file, r#"topics = ["from-cli"]"#).expect("Failed to write config");
+    std::fs::write(&env_file, r#"topics = ["from-env"]"#).expect("Failed to write config");
+
+    temp_env::with_var(CONFIG_FILE_ENV, Some(&env_file), || {
+        let from_cli = Config::load(Some(&cli_file)).expect("Failed to load config");
+        assert_eq!(from_cli.topics_as_str_slice(), vec!["from-cli"]);
+
+        let from_env = Config::load(None).expect("Failed to load config");
+        assert_eq!(from_env.topics_as_str_slice(), vec!["from-env"]);
+    });
+    temp_env::with_vars(
+        [
+            (CONFIG_FILE_ENV, None),
+            ("PAA_VEGNE_AV_TOPIC", Some("paa-vegne-av")),
+        ],
+        || {
+            let embedded = Config::load(None).expect("Failed to load config");
+            assert!(embedded.topics_as_str_slice().contains(&"paa-vegne-av"));
+        },
+    );
+    assert!(Config::load(Some(Path::new("/does/not/exist.toml"))).is_err());
+
+    std::fs::remove_dir_all(&dir).expect("Failed to remove temp dir");
+}
+
+#[test]
+fn test_topics_with_policies() {
+    temp_env::with_var("TEST_TOPIC", Some("topic-from-env"), || {
+        let config = Config::from_string(
+            r#"
+            topics = [
+                "$TEST_TOPIC",
+                { name = "^paw\\.opplysninger-.*", start_position = "latest", store_headers = false, compression = "zstd" },
+                { name = "keys-only-topic", keys_only = true, retention_days = 30 },
+                { name = "recent-topic", start_position = "2025-01-01T00:00:00+01:00" },
+            ]
+            "#,
+        )
+        .expect("Invalid config");
+
+        assert_eq!(
+            config.topics_as_str_slice(),
+            vec![
+                "topic-from-env",
+                "^paw\\.opplysninger-.*",
+                "keys-only-topic",
+                "recent-topic"
+            ]
+        );
+        let policies = config.topic_policies();
+        assert_eq!(policies[0].1, TopicPolicy::default());
+        assert_eq!(
+            policies[1].1,
+            TopicPolicy {
+                start_position: StartPosition::Latest,
+                store_headers: false,
+                keys_only: false,
+                retention_days: None,
+                compression: Compression::Zstd,
+                archive_after_days: None,
+                current_state: false,
+            }
+        );
+        assert_eq!(
+            policies[2].1,
+            TopicPolicy {
+                start_position: StartPosition::Earliest,
+                store_headers: true,
+                keys_only: true,
+                retention_days: Some(30),
+                compression: Compression::None,
+                archive_after_days: None,
+                current_state: false,
+            }
+        );
+        assert_eq!(
+            policies[3].1.start_position,
+            StartPosition::Timestamp(
+                DateTime::parse_from_rfc3339("2024-12-31T23:00:00Z")
+                    .expect("Valid timestamp")
+                    .with_timezone(&Utc)
+            )
+        );
+    });
+}
+
+#[test]
+fn test_invalid_start_position_is_rejected() {
+    let result =
+        Config::from_string(r#"topics = [{ name = "topic-a", start_position = "yesterday" }]"#);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_unknown_topic_setting_is_rejected() {
+    let result = Config::from_string(r#"topics = [{ name = "topic-a", store_header = false }]"#);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_keys_only_with_current_state_is_rejected() {
+    let result = Config::from_string(
+        r#"topics = [{ name = "topic-a", keys_only = true, current_state = true }]"#,
+    );
+
+    assert!(result.is_err());
+}
@@ -0,0 +1,334 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use object_store::memory::InMemory;
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::archive::archiver::Archiver;
+use paw_kafka_topic_backup::database::current_state::{
+    begin_snapshot, get_topic_hwms, query_current_state, rebuild_current_state,
+};
+use paw_kafka_topic_backup::encoding::ValueEncoding;
+use paw_kafka_topic_backup::erasure::erase_key::{
+    ErasureFilter, ErasureRequest, JsonFieldMatch, erase_key,
+};
+use paw_kafka_topic_backup::export::manifest::ExportManifest;
+use paw_kafka_topic_backup::export::state_export::{StateExportRequest, export_current_state};
+use paw_kafka_topic_backup::kafka::message_batcher::MessageBatch;
+use paw_kafka_topic_backup::kafka::message_processor::prosesser_batch;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+use paw_kafka_topic_backup::query_api::records::RecordResponse;
+use paw_kafka_topic_backup::retention::partition_maintenance::maintain_partitions;
+use paw_kafka_topic_backup::retention::purge_job::purge_topic;
+
+mod common;
+use common::{reset_hwm, setup_test_db, store_messages};
+
+const TOPIC: &str = "test-topic";
+
+fn minute(n: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).expect("Valid timestamp") + TimeDelta::minutes(n)
+}
+
+fn state_policy() -> TopicPolicy {
+    TopicPolicy {
+        current_state: true,
+        ..Default::default()
+    }
+}
+
+/// `None` as value is a tombstone
+fn message(partition: i32, offset: i64, key: &str, value: Option<&str>) -> KafkaMessage {
+    KafkaMessage {
+        topic: TOPIC.to_string(),
+        partition,
+        offset,
+        headers: None,
+        key: Some(key.as_bytes().to_vec()),
+        payload: value.map(|value| value.as_bytes().to_vec()),
+        timestamp: minute(offset),
+    }
+}
+
+async fn init_hwms(pool: &PgPool, partitions: &[i32]) {
+    for partition in partitions {
+        reset_hwm(pool, TOPIC, *partition).await;
+    }
+}
+
+async fn store_batch(pool: &PgPool, partition: i32, messages: Vec<KafkaMessage>) {
+    let batch = MessageBatch {
+        topic: TOPIC.to_string(),
+        partition,
+        messages,
+    };
+    prosesser_batch(pool.clone(), batch, &state_policy(), None)
+        .await
+        .expect("prosesser_batch should succeed");
+}
+
+/// Key and value of every key in the current state, ordered by key
+async fn current_values(pool: &PgPool) -> BTreeMap<String, String> {
+    let mut tx = begin_snapshot(pool)
+        .await
+        .expect("Failed to start snapshot");
+    let rows = query_current_state(&mut tx, TOPIC, None, 100, None)
+        .await
+        .expect("Failed to read current state");
+    tx.commit().await.expect("Failed to commit");
+    rows.into_iter()
+        .map(|row| {
+            (
+                String::from_utf8(row.record_key.unwrap()).unwrap(),
+                String::from_utf8(row.record_value.unwrap()).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_current_state_keeps_latest_record_per_key() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    init_hwms(&pool, &[0, 1]).await;
+
+    store_batch(
+        &pool,
+        0,
+        vec![
+            message(0, 0, "a", Some("a1")),
+            message(0, 1, "b", Some("b1")),
+            message(0, 2, "a", Some("a2")),
+            message(0, 3, "c", Some("c1")),
+        ],
+    )
+    .await;
+    store_batch(
+        &pool,
+        0,
+        vec![message(0, 4, "b", None), message(0, 5, "c", Some("c2"))],
+    )
+    .await;
+    // The key moved to another partition, its latest record there replaces the old one
+    store_batch(&pool, 1, vec![message(1, 0, "a", Some("a3"))]).await;
+
+    assert_eq!(
+        current_values(&pool).await,
+        BTreeMap::from([
+            ("a".to_string(), "a3".to_string()),
+            ("c".to_string(), "c2".to_string()),
+        ]),
+        "Tombstoned keys are removed"
+    );
+    let mut tx = begin_snapshot(&pool)
+        .await
+        .expect("Failed to start snapshot");
+    let hwms = get_topic_hwms(&mut tx, TOPIC)
+        .await
+        .expect("Failed to read HWMs");
+    tx.commit().await.expect("Failed to commit");
+    assert_eq!(hwms, BTreeMap::from([(0, 5), (1, 0)]));
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM current_state")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to count state");
+    assert_eq!(count, 2);
+}
+
+#[tokio::test]
+async fn test_current_state_falls_back_when_latest_record_is_erased() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    init_hwms(&pool, &[0]).await;
+    store_batch(
+        &pool,
+        0,
+        vec![
+            message(0, 0, "a", Some(r#"{"version": 1}"#)),
+            message(0, 1, "a", Some(r#"{"version": 2}"#)),
+            message(0, 2, "b", Some(r#"{"version": 1}"#)),
+        ],
+    )
+    .await;
+
+    let erase = |key: &str, filter: ErasureFilter| ErasureRequest {
+        topic: TOPIC.to_string(),
+        key: key.as_bytes().to_vec(),
+        filter,
+        requested_by: "saksbehandler".to_string(),
+        reason: "sak-123".to_string(),
+        dry_run: false,
+    };
+    erase_key(
+        &pool,
+        &erase(
+            "a",
+            ErasureFilter {
+                header: None,
+                json_field: Some(JsonFieldMatch {
+                    pointer: "/version".to_string(),
+                    value: json!(2),
+                }),
+            },
+        ),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+    assert_eq!(
+        current_values(&pool).await,
+        BTreeMap::from([
+            ("a".to_string(), r#"{"version": 1}"#.to_string()),
+            ("b".to_string(), r#"{"version": 1}"#.to_string()),
+        ]),
+        "The latest remaining record of the key takes over"
+    );
+
+    erase_key(&pool, &erase("b", ErasureFilter::default()), None, None)
+        .await
+        .expect("Erasure should succeed");
+    assert_eq!(
+        current_values(&pool).await.keys().collect::<Vec<_>>(),
+        vec!["a"]
+    );
+}
+
+#[tokio::test]
+async fn test_current_state_follows_retention_and_archiving() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    init_hwms(&pool, &[0]).await;
+    let day = |month: u32, day: u32| {
+        NaiveDate::from_ymd_opt(2023, month, day)
+            .expect("Valid date")
+            .and_hms_opt(12, 0, 0)
+            .expect("Valid time")
+            .and_utc()
+    };
+    let at = |offset: i64, key: &str, timestamp: DateTime<Utc>| KafkaMessage {
+        timestamp,
+        ..message(0, offset, key, Some(key))
+    };
+    store_batch(
+        &pool,
+        0,
+        vec![
+            at(0, "dropped", day(10, 10)),
+            at(1, "purged", day(11, 10)),
+            at(2, "archived", day(11, 20)),
+            at(3, "kept", day(11, 25)),
+        ],
+    )
+    .await;
+
+    // The October partition is dropped, November is still within the retention
+    let selector = TopicSelector::with_policies(
+        [(
+            TOPIC,
+            TopicPolicy {
+                retention_days: Some(30),
+                ..state_policy()
+            },
+        )],
+        &[],
+    )
+    .expect("Valid selector");
+    let summary = maintain_partitions(&pool, &selector, day(12, 15))
+        .await
+        .expect("Maintenance should succeed");
+    assert_eq!(
+        summary.dropped,
+        vec![NaiveDate::from_ymd_opt(2023, 10, 1).unwrap()]
+    );
+    assert_eq!(
+        current_values(&pool).await.into_keys().collect::<Vec<_>>(),
+        vec!["archived", "kept", "purged"]
+    );
+
+    purge_topic(&pool, TOPIC, day(11, 15), 100)
+        .await
+        .expect("Purge should succeed");
+    assert_eq!(
+        current_values(&pool).await.into_keys().collect::<Vec<_>>(),
+        vec!["archived", "kept"]
+    );
+
+    let archiver = Archiver::new(Arc::new(InMemory::new()), "", true);
+    archiver
+        .archive_topic(&pool, TOPIC, day(11, 21).date_naive(), None)
+        .await
+        .expect("Archiving should succeed");
+    assert_eq!(
+        current_values(&pool).await.into_keys().collect::<Vec<_>>(),
+        vec!["kept"]
+    );
+}
+
+#[tokio::test]
+async fn test_rebuild_and_export_current_state() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    // Stored without the policy, so the state has to be rebuilt
+    store_messages(
+        &pool,
+        [
+            message(0, 0, "a", Some("a1")),
+            message(0, 1, "b", Some("b1")),
+            message(0, 2, "a", Some("a2")),
+            message(0, 3, "c", Some("c1")),
+            message(0, 4, "c", None),
+        ],
+    )
+    .await;
+    assert!(current_values(&pool).await.is_empty());
+
+    let keys = rebuild_current_state(&pool, TOPIC)
+        .await
+        .expect("Rebuild should succeed");
+    assert_eq!(keys, 2);
+
+    let nanos = Utc::now().timestamp_nanos_opt().expect("Valid timestamp");
+    let dir: PathBuf = std::env::temp_dir().join(format!("export-state-{}", nanos));
+    let manifest = export_current_state(
+        &pool,
+        &StateExportRequest {
+            topic: TOPIC.to_string(),
+            output_dir: dir.clone(),
+            max_segment_bytes: 1024 * 1024,
+            batch_size: 1,
+            encoding: ValueEncoding::Utf8,
+        },
+        None,
+    )
+    .await
+    .expect("Export should succeed");
+    assert_eq!(manifest.rows, 2);
+    assert_eq!(manifest.high_watermarks, Some(BTreeMap::from([(0, 4)])));
+    assert_eq!(
+        ExportManifest::read(&dir).expect("Manifest should be written"),
+        manifest
+    );
+    let mut offsets: Vec<i64> = fs::read_to_string(dir.join(&manifest.segments[0].file))
+        .expect("Segment should exist")
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<RecordResponse>(line)
+                .expect("Line should be a record")
+                .offset
+        })
+        .collect();
+    offsets.sort();
+    assert_eq!(offsets, vec![1, 2]);
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
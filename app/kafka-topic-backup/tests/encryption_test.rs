@@ -0,0 +1,175 @@
+use chrono::DateTime;
+use sqlx::PgPool;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::database::read_data::{
+    DataPosition, RecordCursor, query_records_by_key, read_data_batch,
+};
+use paw_kafka_topic_backup::encryption::data_cipher::DataCipher;
+use paw_kafka_topic_backup::encryption::key_rotation::{ensure_data_key, rotate_keys};
+use paw_kafka_topic_backup::encryption::master_key::{MasterKey, MasterKeys};
+use paw_kafka_topic_backup::kafka::message_batcher::MessageBatch;
+use paw_kafka_topic_backup::kafka::message_processor::prosesser_batch;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+
+mod common;
+use common::{reset_hwm, setup_test_db};
+
+const TOPIC: &str = "test-topic";
+
+fn master_keys(current: u8, previous: Option<u8>) -> MasterKeys {
+    MasterKeys {
+        current: MasterKey::from_bytes(&[current; 32]).expect("Valid master key"),
+        previous: previous
+            .map(|previous| MasterKey::from_bytes(&[previous; 32]).expect("Valid master key")),
+    }
+}
+
+async fn store_messages(pool: &PgPool, offsets: std::ops::Range<i64>, cipher: &DataCipher) {
+    let messages = offsets
+        .map(|offset| KafkaMessage {
+            topic: TOPIC.to_string(),
+            partition: 0,
+            offset,
+            headers: None,
+            key: Some(format!("key-{}", offset % 2).into_bytes()),
+            payload: Some(format!("value-{}", offset).into_bytes()),
+            timestamp: DateTime::from_timestamp(1_700_000_000 + offset, 0)
+                .expect("Valid timestamp"),
+        })
+        .collect();
+    prosesser_batch(
+        pool.clone(),
+        MessageBatch {
+            topic: TOPIC.to_string(),
+            partition: 0,
+            messages,
+        },
+        &TopicPolicy::default(),
+        Some(cipher),
+    )
+    .await
+    .expect("Failed to store messages");
+}
+
+async fn setup_encrypted_records(pool: &PgPool, keys: &MasterKeys) -> DataCipher {
+    reset_hwm(pool, TOPIC, 0).await;
+    ensure_data_key(pool, keys)
+        .await
+        .expect("Failed to create data key");
+    let cipher = DataCipher::load(pool, keys)
+        .await
+        .expect("Failed to load data keys");
+    store_messages(pool, 0..4, &cipher).await;
+    cipher
+}
+
+async fn read_values(
+    pool: &PgPool,
+    cipher: Option<&DataCipher>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = read_data_batch(pool, TOPIC, None, DataPosition::start(), 100, cipher).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| String::from_utf8(row.record_value.expect("Value should be stored")).unwrap())
+        .collect())
+}
+
+#[tokio::test]
+async fn test_records_are_encrypted_at_rest_and_decrypted_on_read() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let cipher = setup_encrypted_records(&pool, &master_keys(1, None)).await;
+
+    let (stored_key, stored_value, key_id): (Vec<u8>, Vec<u8>, Option<i32>) = sqlx::query_as(
+        "SELECT record_key, record_value, key_id FROM data_v3 WHERE kafka_offset = 0",
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("Record should be stored");
+    assert_ne!(stored_key, b"key-0");
+    assert_ne!(stored_value, b"value-0");
+    assert_eq!(key_id, cipher.active_key_id());
+
+    assert_eq!(
+        read_values(&pool, Some(&cipher))
+            .await
+            .expect("Records should decrypt"),
+        vec!["value-0", "value-1", "value-2", "value-3"]
+    );
+    assert!(read_values(&pool, None).await.is_err());
+
+    let by_key = query_records_by_key(
+        &pool,
+        TOPIC,
+        b"key-1",
+        RecordCursor::start(),
+        100,
+        Some(&cipher),
+    )
+    .await
+    .expect("Lookup by key should succeed");
+    let offsets: Vec<i64> = by_key.iter().map(|row| row.kafka_offset).collect();
+    assert_eq!(offsets, vec![1, 3]);
+}
+
+#[tokio::test]
+async fn test_ciphertext_moved_to_another_record_does_not_decrypt() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let cipher = setup_encrypted_records(&pool, &master_keys(1, None)).await;
+
+    sqlx::query(
+        "UPDATE data_v3 SET record_value = (SELECT record_value FROM data_v3 WHERE kafka_offset = 0) WHERE kafka_offset = 1",
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to copy ciphertext");
+
+    assert!(read_values(&pool, Some(&cipher)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_rotate_keys_rewraps_data_keys_with_new_master_key() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    setup_encrypted_records(&pool, &master_keys(1, None)).await;
+
+    let summary = rotate_keys(&pool, &master_keys(2, Some(1)), true)
+        .await
+        .expect("Rotation should succeed");
+    assert_eq!(summary.rewrapped_key_ids.len(), 1);
+    let new_data_key_id = summary
+        .new_data_key_id
+        .expect("New data key should be created");
+
+    assert!(
+        DataCipher::load(&pool, &master_keys(1, None))
+            .await
+            .is_err()
+    );
+    let cipher = DataCipher::load(&pool, &master_keys(2, None))
+        .await
+        .expect("Data keys should unwrap with the new master key");
+    assert_eq!(cipher.active_key_id(), Some(new_data_key_id));
+
+    store_messages(&pool, 4..6, &cipher).await;
+    assert_eq!(
+        read_values(&pool, Some(&cipher))
+            .await
+            .expect("Records should decrypt"),
+        vec![
+            "value-0", "value-1", "value-2", "value-3", "value-4", "value-5"
+        ]
+    );
+    let key_ids: Vec<Option<i32>> =
+        sqlx::query_scalar("SELECT key_id FROM data_v3 ORDER BY kafka_offset")
+            .fetch_all(&pool)
+            .await
+            .expect("Failed to read key ids");
+    assert_eq!(key_ids[3], Some(summary.rewrapped_key_ids[0]));
+    assert_eq!(key_ids[4], Some(new_data_key_id));
+}
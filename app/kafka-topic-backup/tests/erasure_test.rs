@@ -0,0 +1,575 @@
+use chrono::{DateTime, NaiveDate};
+use object_store::ObjectStore;
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::archive::archiver::Archiver;
+use paw_kafka_topic_backup::audit::offset_audit::{GapKind, OffsetGap, audit_offsets};
+use paw_kafka_topic_backup::compression::Compression;
+use paw_kafka_topic_backup::database::archive_catalog::get_archive_segments;
+use paw_kafka_topic_backup::encryption::key_hash::record_key_hash;
+use paw_kafka_topic_backup::erasure::erase_key::{
+    ErasureFilter, ErasureRequest, HeaderMatch, JsonFieldMatch, erase_key,
+};
+use paw_kafka_topic_backup::erasure::hash_chain::verify_erasure_log;
+use paw_kafka_topic_backup::kafka::headers::KafkaHeader;
+use paw_kafka_topic_backup::kafka::message_batcher::MessageBatch;
+use paw_kafka_topic_backup::kafka::message_processor::prosesser_batch;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+use paw_kafka_topic_backup::migration::backfill_key_hashes::{
+    BackfillSummary, backfill_key_hashes,
+};
+use paw_kafka_topic_backup::migration::migrate_data_table::migrate_data_table;
+use paw_kafka_topic_backup::query_api::records::RecordResponse;
+
+mod common;
+use common::{reset_hwm, setup_test_db, store_messages};
+
+const TOPIC: &str = "test-topic";
+
+/// Even offsets have the key `person-a`, odd offsets `person-b`. The payload holds the
+/// offset as JSON and the header `kilde` alternates between `a` and `b` every second pair.
+fn test_messages(partition: i32) -> Vec<KafkaMessage> {
+    (0..8)
+        .map(|offset| KafkaMessage {
+            topic: TOPIC.to_string(),
+            partition,
+            offset,
+            headers: Some(vec![KafkaHeader {
+                key: "kilde".to_string(),
+                value: Some(if offset % 4 < 2 {
+                    b"a".to_vec()
+                } else {
+                    b"b".to_vec()
+                }),
+            }]),
+            key: Some(if offset % 2 == 0 {
+                b"person-a".to_vec()
+            } else {
+                b"person-b".to_vec()
+            }),
+            payload: Some(json!({ "offset": offset }).to_string().into_bytes()),
+            timestamp: DateTime::from_timestamp(1_700_000_000 + offset, 0)
+                .expect("Valid timestamp"),
+        })
+        .collect()
+}
+
+async fn store_test_messages(pool: &PgPool) {
+    store_messages(pool, (0..2).flat_map(test_messages)).await;
+}
+
+async fn stored_offsets(pool: &PgPool, partition: i32) -> Vec<i64> {
+    sqlx::query_scalar(
+        "SELECT kafka_offset FROM data_v3 WHERE kafka_topic = $1 AND kafka_partition = $2 ORDER BY kafka_offset",
+    )
+    .bind(TOPIC)
+    .bind(partition)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to read stored offsets")
+}
+
+fn erasure_request(key: &[u8], filter: ErasureFilter) -> ErasureRequest {
+    ErasureRequest {
+        topic: TOPIC.to_string(),
+        key: key.to_vec(),
+        filter,
+        requested_by: "saksbehandler".to_string(),
+        reason: "sak-123".to_string(),
+        dry_run: false,
+    }
+}
+
+#[tokio::test]
+async fn test_erase_key_deletes_records_in_all_partitions() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+
+    let result = erase_key(
+        &pool,
+        &erasure_request(b"person-a", ErasureFilter::default()),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+
+    assert_eq!(result.total(), 8);
+    assert_eq!(result.rows_per_partition.get(&0), Some(&4));
+    assert!(result.erasure_id.is_some());
+    for partition in 0..2 {
+        assert_eq!(stored_offsets(&pool, partition).await, vec![1, 3, 5, 7]);
+    }
+    let row_count: i64 = sqlx::query_scalar("SELECT row_count FROM erasure_log")
+        .fetch_one(&pool)
+        .await
+        .expect("Erasure should be logged");
+    assert_eq!(row_count, 8);
+}
+
+#[tokio::test]
+async fn test_erase_key_with_header_and_json_field_filters() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+
+    let header_filter = ErasureFilter {
+        header: Some(HeaderMatch {
+            key: "kilde".to_string(),
+            value: b"b".to_vec(),
+        }),
+        json_field: None,
+    };
+    let result = erase_key(
+        &pool,
+        &erasure_request(b"person-a", header_filter),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+    assert_eq!(result.total(), 4);
+    assert_eq!(stored_offsets(&pool, 0).await, vec![0, 1, 3, 4, 5, 7]);
+
+    let json_filter = ErasureFilter {
+        header: None,
+        json_field: Some(JsonFieldMatch {
+            pointer: "/offset".to_string(),
+            value: json!(5),
+        }),
+    };
+    let result = erase_key(
+        &pool,
+        &erasure_request(b"person-b", json_filter),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+    assert_eq!(result.total(), 2);
+    assert_eq!(stored_offsets(&pool, 0).await, vec![0, 1, 3, 4, 7]);
+}
+
+#[tokio::test]
+async fn test_json_field_filter_matches_compressed_values() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    for compression in [Compression::Zstd, Compression::Gzip] {
+        let topic = format!("{:?}-topic", compression).to_lowercase();
+        reset_hwm(&pool, &topic, 0).await;
+        // Long enough to be stored compressed
+        let messages = test_messages(0)
+            .into_iter()
+            .map(|msg| KafkaMessage {
+                topic: topic.clone(),
+                payload: Some(
+                    json!({ "offset": msg.offset, "hendelser": vec!["periode startet"; 50] })
+                        .to_string()
+                        .into_bytes(),
+                ),
+                ..msg
+            })
+            .collect();
+        let policy = TopicPolicy {
+            compression,
+            ..Default::default()
+        };
+        prosesser_batch(
+            pool.clone(),
+            MessageBatch {
+                topic: topic.clone(),
+                partition: 0,
+                messages,
+            },
+            &policy,
+            None,
+        )
+        .await
+        .expect("prosesser_batch should succeed");
+
+        let json_filter = ErasureFilter {
+            header: None,
+            json_field: Some(JsonFieldMatch {
+                pointer: "/offset".to_string(),
+                value: json!(4),
+            }),
+        };
+        let result = erase_key(
+            &pool,
+            &ErasureRequest {
+                topic: topic.clone(),
+                ..erasure_request(b"person-a", json_filter)
+            },
+            None,
+            None,
+        )
+        .await
+        .expect("Erasure should succeed");
+        assert_eq!(result.total(), 1, "{:?}", compression);
+        let offsets: Vec<i64> = sqlx::query_scalar(
+            "SELECT kafka_offset FROM data_v3 WHERE kafka_topic = $1 AND value_codec IS NOT NULL ORDER BY kafka_offset",
+        )
+        .bind(&topic)
+        .fetch_all(&pool)
+        .await
+        .expect("Failed to read stored offsets");
+        assert_eq!(offsets, vec![0, 1, 2, 3, 5, 6, 7]);
+    }
+}
+
+#[tokio::test]
+async fn test_erase_key_covers_records_not_yet_migrated() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let insert_legacy = |offset: i64, key: &'static [u8]| {
+        sqlx::query(
+            "INSERT INTO data_v2 (kafka_topic, kafka_partition, kafka_offset, timestamp, record_key, record_value, record_key_hash) VALUES ($1, 0, $2, $3, $4, 'value', $5)",
+        )
+        .bind(TOPIC)
+        .bind(offset)
+        .bind(DateTime::from_timestamp(1_700_000_000 + offset, 0).expect("Valid timestamp"))
+        .bind(key)
+        .bind(record_key_hash(key))
+        .execute(&pool)
+    };
+    // Offsets 0 and 1 are copied to data_v3 before the erasure, 2 to 4 are not
+    for offset in 0..2 {
+        insert_legacy(offset, b"person-a")
+            .await
+            .expect("Failed to insert legacy record");
+    }
+    migrate_data_table(&pool, 10)
+        .await
+        .expect("Migration should succeed");
+    insert_legacy(2, b"person-a")
+        .await
+        .expect("Failed to insert legacy record");
+    insert_legacy(3, b"person-b")
+        .await
+        .expect("Failed to insert legacy record");
+    insert_legacy(4, b"person-a")
+        .await
+        .expect("Failed to insert legacy record");
+
+    let result = erase_key(
+        &pool,
+        &erasure_request(b"person-a", ErasureFilter::default()),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+    assert_eq!(result.total(), 4, "Copied records are counted once");
+    let legacy_offsets: Vec<i64> =
+        sqlx::query_scalar("SELECT kafka_offset FROM data_v2 ORDER BY kafka_offset")
+            .fetch_all(&pool)
+            .await
+            .expect("Failed to read legacy offsets");
+    assert_eq!(legacy_offsets, vec![3]);
+
+    migrate_data_table(&pool, 10)
+        .await
+        .expect("Migration should succeed");
+    assert_eq!(
+        stored_offsets(&pool, 0).await,
+        vec![3],
+        "Erased records are not copied"
+    );
+}
+
+#[tokio::test]
+async fn test_erase_key_requires_backfilled_key_hashes() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let insert_legacy = |offset: i64| {
+        sqlx::query(
+            "INSERT INTO data_v2 (kafka_topic, kafka_partition, kafka_offset, timestamp, record_key, record_value) VALUES ($1, 0, $2, $3, $4, 'value')",
+        )
+        .bind(TOPIC)
+        .bind(offset)
+        .bind(DateTime::from_timestamp(1_700_000_000 + offset, 0).expect("Valid timestamp"))
+        .bind(if offset % 2 == 0 { b"person-a" } else { b"person-b" })
+        .execute(&pool)
+    };
+    // Offsets 0 and 1 are copied to data_v3 without a hash, 2 to 4 are only in data_v2
+    for offset in 0..2 {
+        insert_legacy(offset)
+            .await
+            .expect("Failed to insert legacy record");
+    }
+    migrate_data_table(&pool, 10)
+        .await
+        .expect("Migration should succeed");
+    for offset in 2..5 {
+        insert_legacy(offset)
+            .await
+            .expect("Failed to insert legacy record");
+    }
+
+    let request = erasure_request(b"person-a", ErasureFilter::default());
+    assert!(
+        erase_key(&pool, &request, None, None).await.is_err(),
+        "Records without a key hash would not be erased"
+    );
+
+    let summary = backfill_key_hashes(&pool, 2, None)
+        .await
+        .expect("Backfill should succeed");
+    assert_eq!(summary.legacy_rows_updated, 5);
+    assert_eq!(summary.data_rows_updated, 2);
+    let repeated = backfill_key_hashes(&pool, 2, None)
+        .await
+        .expect("Backfill should succeed");
+    assert_eq!(repeated, BackfillSummary::default());
+
+    let result = erase_key(&pool, &request, None, None)
+        .await
+        .expect("Erasure should succeed");
+    assert_eq!(result.total(), 3);
+    let legacy_offsets: Vec<i64> =
+        sqlx::query_scalar("SELECT kafka_offset FROM data_v2 ORDER BY kafka_offset")
+            .fetch_all(&pool)
+            .await
+            .expect("Failed to read legacy offsets");
+    assert_eq!(legacy_offsets, vec![1, 3]);
+    assert_eq!(stored_offsets(&pool, 0).await, vec![1]);
+}
+
+#[tokio::test]
+async fn test_erase_key_rewrites_archived_segments() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let store = Arc::new(InMemory::new());
+    let archiver = Archiver::new(store.clone(), "", true);
+    let archived = archiver
+        .archive_topic(
+            &pool,
+            TOPIC,
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            None,
+        )
+        .await
+        .expect("Archiving should succeed");
+    assert_eq!(archived.len(), 1);
+    let old_key = archived[0].object_key.clone();
+    assert!(stored_offsets(&pool, 0).await.is_empty());
+
+    let request = erasure_request(b"person-a", ErasureFilter::default());
+    assert!(
+        erase_key(&pool, &request, None, None).await.is_err(),
+        "Archived records are not left behind when the archive is not configured"
+    );
+    let result = erase_key(&pool, &request, None, Some(&archiver))
+        .await
+        .expect("Erasure should succeed");
+    assert_eq!(result.total(), 8);
+    assert_eq!(result.archive_segments, vec![old_key.clone()]);
+
+    let segments = get_archive_segments(&pool, TOPIC)
+        .await
+        .expect("Failed to read catalog");
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].row_count, 8);
+    assert_ne!(segments[0].object_key, old_key);
+    assert!(
+        store.head(&Path::from(old_key.as_str())).await.is_err(),
+        "The old segment is deleted"
+    );
+    let content = store
+        .get(&Path::from(segments[0].object_key.as_str()))
+        .await
+        .expect("The rewritten segment exists")
+        .bytes()
+        .await
+        .expect("Failed to read segment");
+    assert_eq!(segments[0].bytes, content.len() as i64);
+    let lines = zstd::decode_all(content.as_ref()).expect("Valid zstd");
+    let records: Vec<RecordResponse> = lines
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).expect("Valid record"))
+        .collect();
+    assert_eq!(records.len(), 8);
+    assert!(records.iter().all(|record| record.offset % 2 == 1));
+
+    let verification = verify_erasure_log(&pool)
+        .await
+        .expect("Verification should succeed");
+    assert_eq!(verification.first_invalid_id, None);
+    let offsets: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM erased_offsets")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to count erased offsets");
+    assert_eq!(offsets, 8, "Archived records are not imported again");
+
+    let repeated = erase_key(&pool, &request, None, None)
+        .await
+        .expect("The segment no longer has the key");
+    assert_eq!(repeated.total(), 0);
+    assert!(repeated.archive_segments.is_empty());
+}
+
+#[tokio::test]
+async fn test_dry_run_deletes_and_logs_nothing() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+
+    let mut request = erasure_request(b"person-a", ErasureFilter::default());
+    request.dry_run = true;
+    let result = erase_key(&pool, &request, None, None)
+        .await
+        .expect("Dry run should succeed");
+
+    assert_eq!(result.total(), 8);
+    assert_eq!(result.erasure_id, None);
+    assert_eq!(stored_offsets(&pool, 0).await.len(), 8);
+    let verification = verify_erasure_log(&pool)
+        .await
+        .expect("Verification should succeed");
+    assert_eq!(verification.entries, 0);
+}
+
+#[tokio::test]
+async fn test_erasure_log_detects_tampering() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    for key in [b"person-a", b"person-b", b"person-c"] {
+        erase_key(
+            &pool,
+            &erasure_request(key, ErasureFilter::default()),
+            None,
+            None,
+        )
+        .await
+        .expect("Erasure should succeed");
+    }
+
+    let verification = verify_erasure_log(&pool)
+        .await
+        .expect("Verification should succeed");
+    assert_eq!(verification.entries, 3);
+    assert_eq!(verification.first_invalid_id, None);
+
+    let second_id: i64 = sqlx::query_scalar("SELECT id FROM erasure_log ORDER BY id OFFSET 1")
+        .fetch_one(&pool)
+        .await
+        .expect("Second entry should exist");
+    sqlx::query("DELETE FROM erased_offsets WHERE erasure_id = $1 AND kafka_offset = 1")
+        .bind(second_id)
+        .execute(&pool)
+        .await
+        .expect("Failed to tamper with erased offsets");
+
+    let verification = verify_erasure_log(&pool)
+        .await
+        .expect("Verification should succeed");
+    assert_eq!(verification.first_invalid_id, Some(second_id));
+}
+
+#[tokio::test]
+async fn test_erased_records_are_not_stored_again_on_replay() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    erase_key(
+        &pool,
+        &erasure_request(b"person-a", ErasureFilter::default()),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+
+    // Consume partition 0 again from the start, as after restoring an older dump
+    sqlx::query("DELETE FROM data_v3 WHERE kafka_partition = 0")
+        .execute(&pool)
+        .await
+        .expect("Failed to delete stored records");
+    store_messages(&pool, test_messages(0)).await;
+
+    assert_eq!(stored_offsets(&pool, 0).await, vec![1, 3, 5, 7]);
+    let hwm: i64 = sqlx::query_scalar("SELECT hwm FROM hwm WHERE topic = $1 AND partition = 0")
+        .bind(TOPIC)
+        .fetch_one(&pool)
+        .await
+        .expect("HWM should exist");
+    assert_eq!(hwm, 7);
+}
+
+#[tokio::test]
+async fn test_audit_reports_erased_offsets_as_expected() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let filter = ErasureFilter {
+        header: Some(HeaderMatch {
+            key: "kilde".to_string(),
+            value: b"a".to_vec(),
+        }),
+        json_field: None,
+    };
+    erase_key(
+        &pool,
+        &erasure_request(b"person-a", filter.clone()),
+        None,
+        None,
+    )
+    .await
+    .expect("Erasure should succeed");
+    erase_key(&pool, &erasure_request(b"person-b", filter), None, None)
+        .await
+        .expect("Erasure should succeed");
+    sqlx::query("DELETE FROM data_v3 WHERE kafka_partition = 0 AND kafka_offset = 6")
+        .execute(&pool)
+        .await
+        .expect("Failed to delete record");
+
+    let report = audit_offsets(&pool, None, 10)
+        .await
+        .expect("Audit should succeed");
+
+    let partition = report
+        .partitions
+        .iter()
+        .find(|partition| partition.partition == 0)
+        .expect("Partition 0 should be audited");
+    assert_eq!(
+        partition.gaps,
+        vec![
+            OffsetGap {
+                start: 0,
+                end: 1,
+                kind: GapKind::Erased,
+            },
+            OffsetGap {
+                start: 4,
+                end: 5,
+                kind: GapKind::Erased,
+            },
+            OffsetGap {
+                start: 6,
+                end: 6,
+                kind: GapKind::Unverified,
+            },
+        ]
+    );
+}
@@ -0,0 +1,143 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use paw_kafka_topic_backup::encoding::ValueEncoding;
+use paw_kafka_topic_backup::export::jsonl_export::{ExportRequest, export_jsonl};
+use paw_kafka_topic_backup::export::manifest::ExportManifest;
+use paw_kafka_topic_backup::query_api::records::RecordResponse;
+
+mod common;
+use common::setup_test_db;
+
+const TOPIC: &str = "test-topic";
+
+fn minute(n: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).expect("Valid timestamp") + TimeDelta::minutes(n)
+}
+
+fn output_dir(name: &str) -> PathBuf {
+    let nanos = Utc::now().timestamp_nanos_opt().expect("Valid timestamp");
+    std::env::temp_dir().join(format!("{}-{}", name, nanos))
+}
+
+/// Stores offsets 0 to 9 on partition 0, offset n has the timestamp of `minute(n)`.
+/// Offset 5 has a value that is not UTF-8.
+async fn store_test_messages(pool: &PgPool) {
+    common::store_test_messages(pool, TOPIC, 0..10, minute, |offset| {
+        Some(if offset == 5 {
+            vec![0xff, 0xfe, 0x00]
+        } else {
+            format!(r#"{{"offset": {}}}"#, offset).into_bytes()
+        })
+    })
+    .await;
+}
+
+fn export_request(output_dir: PathBuf) -> ExportRequest {
+    ExportRequest {
+        topic: TOPIC.to_string(),
+        from_timestamp: None,
+        to_timestamp: None,
+        output_dir,
+        max_segment_bytes: 500,
+        batch_size: 3,
+        encoding: ValueEncoding::Utf8,
+    }
+}
+
+fn read_records(output_dir: &Path, manifest: &ExportManifest) -> Vec<RecordResponse> {
+    manifest
+        .segments
+        .iter()
+        .flat_map(|segment| {
+            let content =
+                fs::read_to_string(output_dir.join(&segment.file)).expect("Segment should exist");
+            content
+                .lines()
+                .map(|line| serde_json::from_str(line).expect("Line should be a record"))
+                .collect::<Vec<RecordResponse>>()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_export_jsonl_writes_segments_and_manifest() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let dir = output_dir("export-jsonl");
+
+    let manifest = export_jsonl(&pool, &export_request(dir.clone()), None)
+        .await
+        .expect("Export should succeed");
+    assert_eq!(manifest.rows, 10);
+    assert!(manifest.segments.len() > 1, "Segments should be split");
+    assert_eq!(
+        ExportManifest::read(&dir).expect("Manifest should be written"),
+        manifest
+    );
+    for segment in &manifest.segments {
+        let content = fs::read(dir.join(&segment.file)).expect("Segment should exist");
+        assert!(segment.bytes <= 500);
+        assert_eq!(segment.bytes, content.len() as u64);
+        let sha256: String = Sha256::digest(&content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(segment.sha256, sha256);
+    }
+
+    let records = read_records(&dir, &manifest);
+    let offsets: Vec<i64> = records.iter().map(|record| record.offset).collect();
+    assert_eq!(offsets, (0..10).collect::<Vec<i64>>());
+    let value = |offset: usize| records[offset].value.as_ref().expect("Value is exported");
+    assert_eq!(value(1).encoding, ValueEncoding::Utf8);
+    assert_eq!(value(1).data, r#"{"offset": 1}"#);
+    assert_eq!(value(5).encoding, ValueEncoding::Base64);
+    assert_eq!(value(5).decode().unwrap(), vec![0xff, 0xfe, 0x00]);
+    assert_eq!(records[3].timestamp, minute(3));
+
+    assert!(
+        export_jsonl(&pool, &export_request(dir.clone()), None)
+            .await
+            .is_err(),
+        "An existing export is not overwritten"
+    );
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
+
+#[tokio::test]
+async fn test_export_jsonl_limits_time_range() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let dir = output_dir("export-jsonl-range");
+
+    let manifest = export_jsonl(
+        &pool,
+        &ExportRequest {
+            from_timestamp: Some(minute(2)),
+            to_timestamp: Some(minute(6)),
+            max_segment_bytes: 1024 * 1024,
+            ..export_request(dir.clone())
+        },
+        None,
+    )
+    .await
+    .expect("Export should succeed");
+    assert_eq!(manifest.rows, 4);
+    assert_eq!(manifest.segments.len(), 1);
+    assert_eq!(manifest.segments[0].min_timestamp, Some(minute(2)));
+    assert_eq!(manifest.segments[0].max_timestamp, Some(minute(5)));
+    let offsets: Vec<i64> = read_records(&dir, &manifest)
+        .iter()
+        .map(|record| record.offset)
+        .collect();
+    assert_eq!(offsets, vec![2, 3, 4, 5]);
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use chrono::Utc;
+use common::setup_test_db;
+use paw_kafka_topic_backup::database::insert_data::{InsertDataRow, insert_data_batch};
+use paw_kafka_topic_backup::database::read_data::{DataPosition, read_data_batch};
+use paw_kafka_topic_backup::kafka::headers::{KafkaHeader, headers_from_json, headers_to_json};
+
+fn header(key: &str, value: Option<&[u8]>) -> KafkaHeader {
+    KafkaHeader {
+        key: key.to_string(),
+        value: value.map(|value| value.to_vec()),
+    }
+}
+
+#[test]
+fn test_headers_roundtrip_keeps_order_and_repeated_keys() {
+    let headers = vec![
+        header("trace", Some(b"first")),
+        header("source", Some(b"integration-test")),
+        header("trace", Some(b"second")),
+    ];
+
+    let restored = headers_from_json(&headers_to_json(&headers)).expect("Valid headers");
+
+    assert_eq!(restored, headers, "Order and repeated keys should be kept");
+}
+
+#[test]
+fn test_headers_roundtrip_binary_and_null_values() {
+    let headers = vec![
+        header("binary", Some(&[0xff, 0x00, 0xfe])),
+        header("empty", Some(&[])),
+        header("null", None),
+    ];
+
+    let json = headers_to_json(&headers);
+    let restored = headers_from_json(&json).expect("Valid headers");
+
+    assert_eq!(
+        restored, headers,
+        "Binary, empty and null values should be byte exact"
+    );
+    assert_eq!(json[0]["encoding"], "base64");
+    assert_eq!(json[1]["encoding"], "utf8");
+    assert!(json[2]["value"].is_null());
+}
+
+#[test]
+fn test_headers_roundtrip_base64_looking_utf8_value() {
+    // A UTF-8 value that happens to be valid base64 must not be decoded on read
+    let headers = vec![header("looks-like-base64", Some(b"aGVsbG8="))];
+
+    let restored = headers_from_json(&headers_to_json(&headers)).expect("Valid headers");
+
+    assert_eq!(restored, headers);
+}
+
+#[test]
+fn test_headers_from_legacy_json_object() {
+    let legacy = serde_json::json!({"source": "integration-test", "missing": null});
+
+    let restored = headers_from_json(&legacy).expect("Legacy headers should be readable");
+
+    assert_eq!(restored.len(), 2);
+    assert!(restored.contains(&header("source", Some(b"integration-test"))));
+    assert!(restored.contains(&header("missing", None)));
+}
+
+#[test]
+fn test_headers_from_json_rejects_unknown_shape() {
+    assert!(headers_from_json(&serde_json::json!("not headers")).is_err());
+}
+
+#[tokio::test]
+async fn test_headers_with_nul_are_stored_and_read_back() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    // Valid UTF-8, but Postgres rejects \u0000 in JSONB
+    let headers = vec![
+        header("nul", Some(b"before\0after")),
+        header("plain", Some(b"value")),
+    ];
+    let json = headers_to_json(&headers);
+    assert_eq!(json[0]["encoding"], "base64");
+    assert_eq!(json[1]["encoding"], "utf8");
+
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_data_batch(
+        &mut tx,
+        vec![InsertDataRow {
+            kafka_topic: "headers-topic".to_string(),
+            kafka_partition: 0,
+            kafka_offset: 0,
+            timestamp: Utc::now(),
+            headers: Some(json),
+            record_key: None,
+            record_value: Some(b"payload".to_vec()),
+            value_codec: None,
+            value_omitted: false,
+            record_key_hash: None,
+        }],
+        None,
+    )
+    .await
+    .expect("Headers with NUL should be stored");
+    tx.commit().await.expect("Failed to commit");
+
+    let rows = read_data_batch(
+        &pool,
+        "headers-topic",
+        None,
+        DataPosition::start(),
+        10,
+        None,
+    )
+    .await
+    .expect("Failed to read rows");
+    assert_eq!(rows.len(), 1);
+    let restored = headers_from_json(rows[0].headers.as_ref().expect("Stored headers"))
+        .expect("Valid headers");
+    assert_eq!(restored, headers, "Header values should be byte exact");
+}
@@ -75,7 +75,7 @@ async fn test_get_nonexistent_hwm() {
 
     // Query non-existent data using hwm_statements function
     let mut tx = pool.begin().await.expect("Failed to start transaction");
-    let result = get_hwm(&mut tx, &"nonexistent-topic".to_string(), 999i32)
+    let result = get_hwm(&mut tx, "nonexistent-topic", 999i32)
         .await
         .expect("Failed to query HWM data");
     tx.commit().await.expect("Failed to commit transaction");
@@ -111,9 +111,8 @@ async fn test_update_hwm_data() {
         "Failed to update HWM: {:?}",
         update_result.err()
     );
-    assert_eq!(
+    assert!(
         update_result.unwrap(),
-        true,
         "Update should return true when rows are affected"
     );
 
@@ -151,9 +150,8 @@ async fn test_update_hwm_no_change_when_lower() {
     tx.commit().await.expect("Failed to commit transaction");
 
     assert!(update_result.is_ok());
-    assert_eq!(
-        update_result.unwrap(),
-        false,
+    assert!(
+        !update_result.unwrap(),
         "Should return false when no rows are affected"
     );
 
@@ -187,7 +185,7 @@ async fn test_multiple_topics_and_partitions() {
 
     for (topic, partition, hwm) in &test_data {
         let mut tx = pool.begin().await.expect("Failed to start transaction");
-        insert_hwm(&mut tx, &topic, *partition, *hwm)
+        insert_hwm(&mut tx, topic, *partition, *hwm)
             .await
             .expect("Failed to insert test data");
         tx.commit().await.expect("Failed to commit transaction");
@@ -0,0 +1,221 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use object_store::ObjectStore;
+use object_store::memory::InMemory;
+use sqlx::PgPool;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use paw_kafka_topic_backup::archive::archiver::Archiver;
+use paw_kafka_topic_backup::encoding::ValueEncoding;
+use paw_kafka_topic_backup::encryption::key_hash::record_key_hash;
+use paw_kafka_topic_backup::export::jsonl_export::{ExportRequest, export_jsonl};
+use paw_kafka_topic_backup::import::import_segments::{
+    ImportRequest, ImportSummary, ImportTarget, import_segments,
+};
+use paw_kafka_topic_backup::import::segment_source::SegmentSource;
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+
+mod common;
+use common::setup_test_db;
+
+const TOPIC: &str = "test-topic";
+
+/// 2023-11-14T22:13:20Z, offset n is stored `n` hours later
+fn hour(n: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).expect("Valid timestamp") + TimeDelta::hours(n)
+}
+
+fn output_dir(name: &str) -> PathBuf {
+    let nanos = Utc::now().timestamp_nanos_opt().expect("Valid timestamp");
+    std::env::temp_dir().join(format!("{}-{}", name, nanos))
+}
+
+/// Stores offsets 0 to 5 on partition 0, offset 5 is a tombstone
+async fn store_test_messages(pool: &PgPool) {
+    common::store_test_messages(pool, TOPIC, 0..6, hour, |offset| {
+        (offset != 5).then(|| format!(r#"{{"offset": {}}}"#, offset).into_bytes())
+    })
+    .await;
+}
+
+async fn export_test_messages(pool: &PgPool) -> PathBuf {
+    let dir = output_dir("import");
+    export_jsonl(
+        pool,
+        &ExportRequest {
+            topic: TOPIC.to_string(),
+            from_timestamp: None,
+            to_timestamp: None,
+            output_dir: dir.clone(),
+            max_segment_bytes: 300,
+            batch_size: 10,
+            encoding: ValueEncoding::Utf8,
+        },
+        None,
+    )
+    .await
+    .expect("Export should succeed");
+    dir
+}
+
+async fn delete_stored_records(pool: &PgPool) {
+    sqlx::query("DELETE FROM data_v3 WHERE kafka_topic = $1")
+        .bind(TOPIC)
+        .execute(pool)
+        .await
+        .expect("Failed to delete records");
+}
+
+async fn stored_records(pool: &PgPool) -> Vec<(i64, Option<Vec<u8>>)> {
+    sqlx::query_as(
+        "SELECT kafka_offset, record_value FROM data_v3 WHERE kafka_topic = $1 ORDER BY kafka_offset",
+    )
+    .bind(TOPIC)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to read stored records")
+}
+
+async fn import_to_database(
+    pool: &PgPool,
+    source: SegmentSource,
+    from_timestamp: Option<DateTime<Utc>>,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    let selector = TopicSelector::new(&[TOPIC], &[]).expect("Valid selector");
+    import_segments(
+        pool,
+        &ImportRequest {
+            source,
+            from_timestamp,
+            to_timestamp: None,
+            batch_size: 2,
+        },
+        &ImportTarget::Database {
+            selector: &selector,
+            cipher: None,
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_import_export_to_database_is_idempotent_and_skips_erased() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let dir = export_test_messages(&pool).await;
+    delete_stored_records(&pool).await;
+    let erasure_id: i64 = sqlx::query_scalar(
+        "INSERT INTO erasure_log (erased_at, kafka_topic, record_key_hash, requested_by, reason, row_count, prev_hash, entry_hash) VALUES (NOW(), $1, $2, 'test', 'test', 1, '\\x00', '\\x01') RETURNING id",
+    )
+    .bind(TOPIC)
+    .bind(record_key_hash(b"key-2"))
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to insert erasure");
+    sqlx::query(
+        "INSERT INTO erased_offsets (kafka_topic, kafka_partition, kafka_offset, erasure_id) VALUES ($1, 0, 2, $2)",
+    )
+    .bind(TOPIC)
+    .bind(erasure_id)
+    .execute(&pool)
+    .await
+    .expect("Failed to insert erased offset");
+
+    let summary = import_to_database(&pool, SegmentSource::ExportDir(dir.clone()), None)
+        .await
+        .expect("Import should succeed");
+    assert!(
+        summary.segments > 1,
+        "The export should have several segments"
+    );
+    assert_eq!(
+        (
+            summary.records_read,
+            summary.records_imported,
+            summary.records_existing,
+            summary.records_erased
+        ),
+        (6, 5, 0, 1)
+    );
+    let records = stored_records(&pool).await;
+    assert_eq!(
+        records
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>(),
+        vec![0, 1, 3, 4, 5]
+    );
+    assert_eq!(records[1].1.as_deref(), Some(&br#"{"offset": 1}"#[..]));
+    assert_eq!(records[4].1, None, "Tombstones stay tombstones");
+
+    let summary = import_to_database(&pool, SegmentSource::ExportDir(dir.clone()), Some(hour(3)))
+        .await
+        .expect("Import should succeed");
+    assert_eq!(
+        (
+            summary.records_read,
+            summary.records_imported,
+            summary.records_existing
+        ),
+        (3, 0, 3),
+        "Stored records are not imported again"
+    );
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
+
+#[tokio::test]
+async fn test_import_rejects_segment_with_wrong_checksum() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let dir = export_test_messages(&pool).await;
+    delete_stored_records(&pool).await;
+    let first_segment = dir.join(format!("{}-00000.jsonl", TOPIC));
+    let content = fs::read_to_string(&first_segment).expect("Segment should exist");
+    fs::write(&first_segment, content.replace("offset", "OFFSET")).expect("Failed to write");
+
+    let result = import_to_database(&pool, SegmentSource::ExportDir(dir.clone()), None).await;
+    assert!(result.is_err(), "A changed segment is not imported");
+    assert!(stored_records(&pool).await.is_empty());
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
+
+#[tokio::test]
+async fn test_import_archived_segments_to_database() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let archived = Archiver::new(store.clone(), "backup", true)
+        .archive_topic(&pool, TOPIC, hour(48).date_naive(), None)
+        .await
+        .expect("Archiving should succeed");
+    assert_eq!(archived.len(), 2);
+    assert!(stored_records(&pool).await.is_empty());
+
+    let summary = import_to_database(
+        &pool,
+        SegmentSource::Archive {
+            store,
+            topic: TOPIC.to_string(),
+        },
+        Some(hour(2)),
+    )
+    .await
+    .expect("Import should succeed");
+    assert_eq!((summary.segments, summary.records_imported), (1, 4));
+    assert_eq!(
+        stored_records(&pool)
+            .await
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>(),
+        vec![2, 3, 4, 5]
+    );
+}
@@ -0,0 +1,90 @@
+use chrono::{DateTime, Duration, Utc};
+
+use paw_kafka_topic_backup::database::hwm_statements::{HwmRow, get_all_hwms, insert_hwm};
+use paw_kafka_topic_backup::kafka::lag_monitor::PartitionLag;
+use paw_kafka_topic_backup::{KafkaMessage, prosesser_melding};
+
+mod common;
+use common::setup_test_db;
+
+fn hwm_row(hwm: i64, last_timestamp: Option<DateTime<Utc>>) -> HwmRow {
+    HwmRow {
+        topic: "test-topic".to_string(),
+        partition: 3,
+        hwm,
+        start_offset: Some(0),
+        last_timestamp,
+    }
+}
+
+#[test]
+fn test_lag_is_offsets_above_stored_hwm() {
+    let now = Utc::now();
+    let lag = PartitionLag::new(&hwm_row(41, Some(now - Duration::seconds(90))), 50, now);
+
+    assert_eq!(lag.partition, 3);
+    assert_eq!(lag.high_watermark, 50);
+    assert_eq!(lag.stored_hwm, 41);
+    assert_eq!(lag.lag, 8);
+    assert_eq!(lag.seconds_since_last_record, Some(90.0));
+}
+
+#[test]
+fn test_no_lag_when_everything_is_stored() {
+    let now = Utc::now();
+    assert_eq!(PartitionLag::new(&hwm_row(49, None), 50, now).lag, 0);
+    assert_eq!(PartitionLag::new(&hwm_row(-1, None), 0, now).lag, 0);
+}
+
+#[test]
+fn test_lag_for_partition_without_stored_records() {
+    let lag = PartitionLag::new(&hwm_row(-1, None), 10, Utc::now());
+
+    assert_eq!(lag.lag, 10);
+    assert_eq!(lag.seconds_since_last_record, None);
+}
+
+#[tokio::test]
+async fn test_get_all_hwms_includes_timestamp_of_last_stored_record() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let timestamp = DateTime::from_timestamp_millis(1_700_000_000_000).expect("Valid timestamp");
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_hwm(&mut tx, "test-topic", 0, -1)
+        .await
+        .expect("Failed to insert HWM");
+    insert_hwm(&mut tx, "test-topic", 1, -1)
+        .await
+        .expect("Failed to insert HWM");
+    tx.commit().await.expect("Failed to commit");
+    for offset in 0..3 {
+        prosesser_melding(
+            pool.clone(),
+            KafkaMessage {
+                topic: "test-topic".to_string(),
+                partition: 0,
+                offset,
+                headers: None,
+                key: None,
+                payload: Some(b"value".to_vec()),
+                timestamp: timestamp + Duration::seconds(offset),
+            },
+        )
+        .await
+        .expect("Failed to store message");
+    }
+
+    let hwms = get_all_hwms(&pool).await.expect("Failed to read HWMs");
+
+    assert_eq!(hwms.len(), 2);
+    assert_eq!(hwms[0].partition, 0);
+    assert_eq!(hwms[0].hwm, 2);
+    assert_eq!(
+        hwms[0].last_timestamp,
+        Some(timestamp + Duration::seconds(2))
+    );
+    assert_eq!(hwms[1].partition, 1);
+    assert_eq!(hwms[1].hwm, -1);
+    assert_eq!(hwms[1].last_timestamp, None);
+}
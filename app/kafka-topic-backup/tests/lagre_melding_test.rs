@@ -5,7 +5,14 @@ use testcontainers::{ContainerAsync, runners::AsyncRunner};
 use testcontainers_modules::postgres::Postgres;
 
 // Import modules from the main crate
+use paw_kafka_topic_backup::compression::Compression;
 use paw_kafka_topic_backup::database::hwm_statements::{get_hwm, insert_hwm};
+use paw_kafka_topic_backup::database::read_data::{DataPosition, read_data_batch};
+use paw_kafka_topic_backup::encryption::key_hash::init_key_hash_secret;
+use paw_kafka_topic_backup::kafka::headers::KafkaHeader;
+use paw_kafka_topic_backup::kafka::message_batcher::MessageBatch;
+use paw_kafka_topic_backup::kafka::message_processor::prosesser_batch;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
 use paw_kafka_topic_backup::{KafkaMessage, prosesser_melding};
 
 /// Setup a test database container
@@ -32,6 +39,7 @@ async fn setup_test_db() -> Result<(PgPool, ContainerAsync<Postgres>), Box<dyn E
 
     // Create necessary tables
     sqlx::migrate!("./migrations").run(&pool).await?;
+    init_key_hash_secret(vec![7; 32])?;
 
     Ok((pool, postgres_container))
 }
@@ -44,9 +52,20 @@ fn create_test_kafka_message(topic: &str, partition: i32, offset: i64) -> KafkaM
         topic: topic.to_string(),
         partition,
         offset,
-        headers: Some(serde_json::json!({"test": "header", "source": "integration-test"})),
-        key: format!("test-key-{}", offset).into_bytes(),
-        payload: format!(r#"{{"message": "test payload", "offset": {}}}"#, offset).into_bytes(),
+        headers: Some(vec![
+            KafkaHeader {
+                key: "test".to_string(),
+                value: Some(b"header".to_vec()),
+            },
+            KafkaHeader {
+                key: "source".to_string(),
+                value: Some(b"integration-test".to_vec()),
+            },
+        ]),
+        key: Some(format!("test-key-{}", offset).into_bytes()),
+        payload: Some(
+            format!(r#"{{"message": "test payload", "offset": {}}}"#, offset).into_bytes(),
+        ),
         timestamp,
     }
 }
@@ -59,7 +78,7 @@ async fn test_lagre_melding_i_db_new_message() {
 
     // Insert initial HWM record with a lower offset so our test message will be processed
     let mut tx = pool.begin().await.expect("Failed to start transaction");
-    let _ = insert_hwm(&mut tx, "test-topic", 0, 50)
+    insert_hwm(&mut tx, "test-topic", 0, 50)
         .await
         .expect("Failed to insert initial HWM");
     tx.commit().await.expect("Failed to commit initial HWM");
@@ -81,7 +100,7 @@ async fn test_lagre_melding_i_db_new_message() {
 
     // Verify data was inserted
     let count: (i64,) = sqlx::query_as(
-        "SELECT COUNT(*) FROM data_v2 WHERE kafka_topic = $1 AND kafka_partition = $2",
+        "SELECT COUNT(*) FROM data_v3 WHERE kafka_topic = $1 AND kafka_partition = $2",
     )
     .bind("test-topic")
     .bind(0i32)
@@ -102,7 +121,7 @@ async fn test_lagre_melding_i_db_duplicate_message() {
 
     // Insert initial HWM record with the SAME offset as our test message
     let mut tx = pool.begin().await.expect("Failed to start transaction");
-    let _ = insert_hwm(&mut tx, "test-topic", 0, 100)
+    insert_hwm(&mut tx, "test-topic", 0, 100)
         .await
         .expect("Failed to insert initial HWM");
     tx.commit().await.expect("Failed to commit initial HWM");
@@ -118,7 +137,7 @@ async fn test_lagre_melding_i_db_duplicate_message() {
     // Verify no additional data was inserted
     let mut tx = pool.begin().await.expect("Failed to start transaction");
     let count: (i64,) = sqlx::query_as(
-        "SELECT COUNT(*) FROM data_v2 WHERE kafka_topic = $1 AND kafka_partition = $2",
+        "SELECT COUNT(*) FROM data_v3 WHERE kafka_topic = $1 AND kafka_partition = $2",
     )
     .bind("test-topic")
     .bind(0i32)
@@ -144,7 +163,7 @@ async fn test_lagre_melding_i_db_lower_offset_message() {
 
     // Insert initial HWM record with a HIGHER offset than our test message
     let mut tx = pool.begin().await.expect("Failed to start transaction");
-    let _ = insert_hwm(&mut tx, "test-topic", 0, higher_hwm)
+    insert_hwm(&mut tx, "test-topic", 0, higher_hwm)
         .await
         .expect("Failed to insert initial HWM");
     tx.commit().await.expect("Failed to commit initial HWM");
@@ -166,7 +185,7 @@ async fn test_lagre_melding_i_db_lower_offset_message() {
 
     // Verify no data was inserted
     let count: (i64,) = sqlx::query_as(
-        "SELECT COUNT(*) FROM data_v2 WHERE kafka_topic = $1 AND kafka_partition = $2",
+        "SELECT COUNT(*) FROM data_v3 WHERE kafka_topic = $1 AND kafka_partition = $2",
     )
     .bind("test-topic")
     .bind(0i32)
@@ -181,3 +200,279 @@ async fn test_lagre_melding_i_db_lower_offset_message() {
         "Should not have inserted any data records for lower offset"
     );
 }
+
+#[tokio::test]
+async fn test_lagre_melding_i_db_tombstone_with_null_key() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_hwm(&mut tx, "test-topic", 0, 50)
+        .await
+        .expect("Failed to insert initial HWM");
+    tx.commit().await.expect("Failed to commit initial HWM");
+
+    // A tombstone without key: both key and payload are null in Kafka
+    let test_message = KafkaMessage {
+        key: None,
+        payload: None,
+        ..create_test_kafka_message("test-topic", 0, 100)
+    };
+
+    prosesser_melding(pool.clone(), test_message)
+        .await
+        .expect("lagre_melding_i_db should succeed for tombstones");
+
+    let (record_key, record_value): (Option<Vec<u8>>, Option<Vec<u8>>) = sqlx::query_as(
+        "SELECT record_key, record_value FROM data_v3 WHERE kafka_topic = $1 AND kafka_offset = $2",
+    )
+    .bind("test-topic")
+    .bind(100i64)
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to read stored record");
+
+    assert_eq!(record_key, None, "Null key should be stored as SQL NULL");
+    assert_eq!(record_value, None, "Tombstone should be stored as SQL NULL");
+}
+
+#[tokio::test]
+async fn test_lagre_melding_i_db_empty_key_and_payload() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_hwm(&mut tx, "test-topic", 0, 50)
+        .await
+        .expect("Failed to insert initial HWM");
+    tx.commit().await.expect("Failed to commit initial HWM");
+
+    // Empty key and payload are valid values and must not be confused with null
+    let test_message = KafkaMessage {
+        key: Some(Vec::new()),
+        payload: Some(Vec::new()),
+        ..create_test_kafka_message("test-topic", 0, 100)
+    };
+
+    prosesser_melding(pool.clone(), test_message)
+        .await
+        .expect("lagre_melding_i_db should succeed for empty key and payload");
+
+    let (record_key, record_value): (Option<Vec<u8>>, Option<Vec<u8>>) = sqlx::query_as(
+        "SELECT record_key, record_value FROM data_v3 WHERE kafka_topic = $1 AND kafka_offset = $2",
+    )
+    .bind("test-topic")
+    .bind(100i64)
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to read stored record");
+
+    assert_eq!(
+        record_key,
+        Some(Vec::new()),
+        "Empty key should be stored as empty bytes"
+    );
+    assert_eq!(
+        record_value,
+        Some(Vec::new()),
+        "Empty payload should be stored as empty bytes"
+    );
+}
+
+#[tokio::test]
+async fn test_prosesser_batch_stores_messages_above_hwm() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_hwm(&mut tx, "test-topic", 0, 101)
+        .await
+        .expect("Failed to insert initial HWM");
+    tx.commit().await.expect("Failed to commit initial HWM");
+
+    // Offsets 100 and 101 are at or below the HWM, 102 to 104 should be stored
+    let batch = MessageBatch {
+        topic: "test-topic".to_string(),
+        partition: 0,
+        messages: (100..=104)
+            .map(|offset| create_test_kafka_message("test-topic", 0, offset))
+            .collect(),
+    };
+
+    prosesser_batch(pool.clone(), batch, &TopicPolicy::default(), None)
+        .await
+        .expect("prosesser_batch should succeed");
+
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    let hwm = get_hwm(&mut tx, "test-topic", 0)
+        .await
+        .expect("Failed to get HWM");
+    tx.commit().await.expect("Failed to commit");
+    assert_eq!(hwm, Some(104), "HWM should be the last offset in the batch");
+
+    let offsets: Vec<i64> = sqlx::query_scalar(
+        "SELECT kafka_offset FROM data_v3 WHERE kafka_topic = $1 ORDER BY kafka_offset",
+    )
+    .bind("test-topic")
+    .fetch_all(&pool)
+    .await
+    .expect("Failed to read stored offsets");
+    assert_eq!(offsets, vec![102, 103, 104]);
+}
+
+#[tokio::test]
+async fn test_concurrent_batches_for_same_partition_are_serialized() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+
+    for partition in 0..10 {
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        insert_hwm(&mut tx, "test-topic", partition, -1)
+            .await
+            .expect("Failed to insert initial HWM");
+        tx.commit().await.expect("Failed to commit initial HWM");
+    }
+
+    // Overlapping batches, as when two consumers hold the same partition during a rebalance
+    let batch = |partition: i32, offsets: std::ops::RangeInclusive<i64>| MessageBatch {
+        topic: "test-topic".to_string(),
+        partition,
+        messages: offsets
+            .map(|offset| create_test_kafka_message("test-topic", partition, offset))
+            .collect(),
+    };
+    for partition in 0..10 {
+        let policy = TopicPolicy::default();
+        let (first, second) = tokio::join!(
+            prosesser_batch(pool.clone(), batch(partition, 0..=20), &policy, None),
+            prosesser_batch(pool.clone(), batch(partition, 10..=30), &policy, None),
+        );
+        first.expect("First batch should succeed");
+        second.expect("Second batch should succeed");
+
+        // Whichever batch is stored last only adds what is above the HWM of the first
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let hwm = get_hwm(&mut tx, "test-topic", partition)
+            .await
+            .expect("Failed to get HWM");
+        tx.commit().await.expect("Failed to commit");
+        assert_eq!(hwm, Some(30));
+    }
+}
+
+#[tokio::test]
+async fn test_prosesser_batch_honours_topic_policy() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_hwm(&mut tx, "keys-only-topic", 0, -1)
+        .await
+        .expect("Failed to insert initial HWM");
+    tx.commit().await.expect("Failed to commit initial HWM");
+
+    let batch = MessageBatch {
+        topic: "keys-only-topic".to_string(),
+        partition: 0,
+        messages: vec![
+            create_test_kafka_message("keys-only-topic", 0, 0),
+            KafkaMessage {
+                payload: None,
+                ..create_test_kafka_message("keys-only-topic", 0, 1)
+            },
+        ],
+    };
+    let policy = TopicPolicy {
+        store_headers: false,
+        keys_only: true,
+        ..Default::default()
+    };
+
+    prosesser_batch(pool.clone(), batch, &policy, None)
+        .await
+        .expect("prosesser_batch should succeed");
+
+    let rows = read_data_batch(
+        &pool,
+        "keys-only-topic",
+        None,
+        DataPosition::start(),
+        10,
+        None,
+    )
+    .await
+    .expect("Failed to read stored records");
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].headers, None, "Headers should not be stored");
+    assert_eq!(rows[0].record_key, Some(b"test-key-0".to_vec()));
+    assert_eq!(rows[0].record_value, None, "Only the key should be stored");
+    assert!(rows[0].value_omitted, "The omitted value should be marked");
+    assert_eq!(rows[1].record_value, None);
+    assert!(!rows[1].value_omitted, "A tombstone has no value to omit");
+}
+
+#[tokio::test]
+async fn test_prosesser_batch_compresses_values() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let verbose_json = serde_json::json!({ "hendelser": vec!["periode startet"; 50] })
+        .to_string()
+        .into_bytes();
+
+    for (topic, compression) in [
+        ("plain-topic", Compression::None),
+        ("zstd-topic", Compression::Zstd),
+        ("gzip-topic", Compression::Gzip),
+    ] {
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        insert_hwm(&mut tx, topic, 0, -1)
+            .await
+            .expect("Failed to insert initial HWM");
+        tx.commit().await.expect("Failed to commit initial HWM");
+        let mut verbose = create_test_kafka_message(topic, 0, 0);
+        verbose.payload = Some(verbose_json.clone());
+        let batch = MessageBatch {
+            topic: topic.to_string(),
+            partition: 0,
+            messages: vec![verbose, create_test_kafka_message(topic, 0, 1)],
+        };
+        let policy = TopicPolicy {
+            compression,
+            ..Default::default()
+        };
+        prosesser_batch(pool.clone(), batch, &policy, None)
+            .await
+            .expect("prosesser_batch should succeed");
+
+        let stored: Vec<(Option<String>, i32)> = sqlx::query_as(
+            "SELECT value_codec, LENGTH(record_value) FROM data_v3 WHERE kafka_topic = $1 ORDER BY kafka_offset",
+        )
+        .bind(topic)
+        .fetch_all(&pool)
+        .await
+        .expect("Failed to read stored records");
+        let expected_codec = compression.codec().map(|codec| codec.as_str().to_string());
+        assert_eq!(stored[0].0, expected_codec);
+        assert_eq!(
+            stored[0].1 < verbose_json.len() as i32,
+            expected_codec.is_some(),
+            "Only compressed values should be smaller"
+        );
+        assert_eq!(stored[1].0, None, "Short values are stored uncompressed");
+
+        let rows = read_data_batch(&pool, topic, None, DataPosition::start(), 10, None)
+            .await
+            .expect("Failed to read records");
+        assert_eq!(rows[0].record_value, Some(verbose_json.clone()));
+        assert_eq!(
+            rows[1].record_value,
+            Some(br#"{"message": "test payload", "offset": 1}"#.to_vec())
+        );
+    }
+}
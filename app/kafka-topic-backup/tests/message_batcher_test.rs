@@ -0,0 +1,123 @@
+use chrono::DateTime;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::kafka::hwm::Topic;
+use paw_kafka_topic_backup::kafka::message_batcher::{BatchConfig, MessageBatcher};
+
+fn create_test_kafka_message(partition: i32, offset: i64, payload_size: usize) -> KafkaMessage {
+    KafkaMessage {
+        topic: "test-topic".to_string(),
+        partition,
+        offset,
+        headers: None,
+        key: None,
+        payload: Some(vec![0u8; payload_size]),
+        timestamp: DateTime::from_timestamp_millis(1234567890000).expect("Valid timestamp"),
+    }
+}
+
+fn batch_config(max_messages: usize, max_bytes: usize) -> BatchConfig {
+    BatchConfig {
+        max_messages,
+        max_bytes,
+        linger: Duration::from_secs(60),
+    }
+}
+
+#[tokio::test]
+async fn test_batch_is_returned_when_max_messages_is_reached() {
+    let mut batcher = MessageBatcher::new(batch_config(3, usize::MAX));
+
+    assert!(batcher.add(create_test_kafka_message(0, 1, 10)).is_none());
+    assert!(batcher.add(create_test_kafka_message(0, 2, 10)).is_none());
+    let batch = batcher
+        .add(create_test_kafka_message(0, 3, 10))
+        .expect("Batch should be full");
+
+    assert_eq!(batch.topic, "test-topic");
+    assert_eq!(batch.partition, 0);
+    let offsets: Vec<i64> = batch.messages.iter().map(|msg| msg.offset).collect();
+    assert_eq!(
+        offsets,
+        vec![1, 2, 3],
+        "Messages should keep consumed order"
+    );
+    assert!(
+        batcher.next_deadline().is_none(),
+        "No batches should be pending"
+    );
+}
+
+#[tokio::test]
+async fn test_batch_is_returned_when_max_bytes_is_reached() {
+    let mut batcher = MessageBatcher::new(batch_config(100, 1000));
+
+    assert!(batcher.add(create_test_kafka_message(0, 1, 600)).is_none());
+    let batch = batcher
+        .add(create_test_kafka_message(0, 2, 600))
+        .expect("Batch should be full");
+
+    assert_eq!(batch.messages.len(), 2);
+}
+
+#[tokio::test]
+async fn test_batches_are_kept_per_partition() {
+    let mut batcher = MessageBatcher::new(batch_config(2, usize::MAX));
+
+    assert!(batcher.add(create_test_kafka_message(0, 1, 10)).is_none());
+    assert!(batcher.add(create_test_kafka_message(1, 1, 10)).is_none());
+    let batch = batcher
+        .add(create_test_kafka_message(1, 2, 10))
+        .expect("Batch for partition 1 should be full");
+
+    assert_eq!(batch.partition, 1);
+    assert_eq!(batch.messages.len(), 2);
+    assert!(
+        batcher.next_deadline().is_some(),
+        "Partition 0 should still be pending"
+    );
+}
+
+#[tokio::test]
+async fn test_batches_are_returned_after_linger_time() {
+    let mut batcher = MessageBatcher::new(batch_config(100, usize::MAX));
+
+    assert!(batcher.add(create_test_kafka_message(0, 1, 10)).is_none());
+    assert!(batcher.add(create_test_kafka_message(1, 1, 10)).is_none());
+
+    assert!(
+        batcher.take_expired(Instant::now()).is_empty(),
+        "Batches should wait for the linger time"
+    );
+    let expired = batcher.take_expired(Instant::now() + Duration::from_secs(61));
+
+    assert_eq!(expired.len(), 2, "Both partitions should be written");
+    assert!(batcher.next_deadline().is_none());
+}
+
+#[tokio::test]
+async fn test_revoked_partitions_are_dropped() {
+    let mut batcher = MessageBatcher::new(batch_config(100, usize::MAX));
+
+    assert!(batcher.add(create_test_kafka_message(0, 1, 10)).is_none());
+    assert!(batcher.add(create_test_kafka_message(0, 2, 10)).is_none());
+    assert!(batcher.add(create_test_kafka_message(1, 1, 10)).is_none());
+
+    let revoked = vec![
+        Topic {
+            name: "test-topic".to_string(),
+            partition: 0,
+        },
+        Topic {
+            name: "test-topic".to_string(),
+            partition: 2,
+        },
+    ];
+    assert_eq!(batcher.drop_partitions(&revoked), 2);
+
+    let expired = batcher.take_expired(Instant::now() + Duration::from_secs(61));
+    assert_eq!(expired.len(), 1, "Only partition 1 should be left");
+    assert_eq!(expired[0].partition, 1);
+}
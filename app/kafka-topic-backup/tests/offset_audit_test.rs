@@ -0,0 +1,249 @@
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode, header::AUTHORIZATION};
+use chrono::DateTime;
+use sqlx::PgPool;
+use std::error::Error;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::audit::broker_offsets::BrokerOffsets;
+use paw_kafka_topic_backup::audit::offset_audit::{
+    AuditReport, GapKind, OffsetGap, SharedAuditReport, audit_offsets,
+};
+use paw_kafka_topic_backup::database::hwm_statements::insert_hwm;
+use paw_kafka_topic_backup::kafka::message_batcher::MessageBatch;
+use paw_kafka_topic_backup::kafka::message_processor::prosesser_batch;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+use paw_kafka_topic_backup::query_api::{self, QueryApiState};
+
+mod common;
+use common::{setup_test_db, store_messages};
+
+/// A broker that has deleted everything below `low_watermark` and still has `offsets`
+struct FakeBroker {
+    low_watermark: i64,
+    offsets: Vec<i64>,
+}
+
+impl BrokerOffsets for FakeBroker {
+    fn low_watermark(&self, _topic: &str, _partition: i32) -> Result<i64, Box<dyn Error>> {
+        Ok(self.low_watermark)
+    }
+
+    fn first_offset_from(
+        &self,
+        _topic: &str,
+        _partition: i32,
+        offset: i64,
+    ) -> Result<Option<i64>, Box<dyn Error>> {
+        Ok(self.offsets.iter().copied().find(|o| *o >= offset))
+    }
+}
+
+fn message(partition: i32, offset: i64) -> KafkaMessage {
+    KafkaMessage {
+        topic: "test-topic".to_string(),
+        partition,
+        offset,
+        headers: None,
+        key: Some(b"key".to_vec()),
+        payload: Some(b"value".to_vec()),
+        timestamp: DateTime::from_timestamp_millis(1_700_000_000_000).expect("Valid timestamp"),
+    }
+}
+
+async fn store_offsets(pool: &PgPool, partition: i32, offsets: &[i64]) {
+    store_messages(
+        pool,
+        offsets.iter().map(|offset| message(partition, *offset)),
+    )
+    .await;
+}
+
+fn gap(start: i64, end: i64, kind: GapKind) -> OffsetGap {
+    OffsetGap { start, end, kind }
+}
+
+#[tokio::test]
+async fn test_audit_without_gaps() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_offsets(&pool, 0, &[0, 1, 2, 3]).await;
+
+    let report = audit_offsets(&pool, None, 10).await.expect("Audit failed");
+
+    assert_eq!(report.partitions.len(), 1);
+    assert_eq!(report.partitions[0].hwm, 3);
+    assert!(report.partitions[0].gaps.is_empty());
+}
+
+#[tokio::test]
+async fn test_audit_without_broker_reports_unverified_gaps() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_offsets(&pool, 0, &[2, 3, 6, 7, 10]).await;
+
+    let report = audit_offsets(&pool, None, 10).await.expect("Audit failed");
+
+    let partition = &report.partitions[0];
+    assert_eq!(partition.low_watermark, None);
+    assert_eq!(
+        partition.gaps,
+        vec![
+            gap(0, 1, GapKind::Unverified),
+            gap(4, 5, GapKind::Unverified),
+            gap(8, 9, GapKind::Unverified),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_audit_expects_gap_before_start_offset() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    // Started from a timestamp or the latest offset, with 10 as the first offset to read
+    let mut tx = pool.begin().await.expect("Failed to start transaction");
+    insert_hwm(&mut tx, "test-topic", 0, 9)
+        .await
+        .expect("Failed to insert start HWM");
+    tx.commit().await.expect("Failed to commit start HWM");
+    let batch = MessageBatch {
+        topic: "test-topic".to_string(),
+        partition: 0,
+        messages: [10, 11, 13]
+            .into_iter()
+            .map(|offset| message(0, offset))
+            .collect(),
+    };
+    prosesser_batch(pool.clone(), batch, &TopicPolicy::default(), None)
+        .await
+        .expect("prosesser_batch should succeed");
+    let broker = FakeBroker {
+        low_watermark: 0,
+        offsets: (0..=13).collect(),
+    };
+
+    let report = audit_offsets(&pool, Some(Arc::new(broker)), 10)
+        .await
+        .expect("Audit failed");
+
+    assert_eq!(
+        report.partitions[0].gaps,
+        vec![
+            gap(0, 9, GapKind::BeforeStart),
+            gap(12, 12, GapKind::Missing),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_audit_classifies_gaps_with_broker() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_offsets(&pool, 0, &[3, 4, 8, 12, 13]).await;
+    // 0..=4 are deleted, 5..=7 are compacted away, 9 is on the broker but not stored
+    let broker = FakeBroker {
+        low_watermark: 5,
+        offsets: vec![8, 9, 12, 13],
+    };
+
+    let report = audit_offsets(&pool, Some(Arc::new(broker)), 10)
+        .await
+        .expect("Audit failed");
+
+    let partition = &report.partitions[0];
+    assert_eq!(partition.low_watermark, Some(5));
+    assert_eq!(
+        partition.gaps,
+        vec![
+            gap(0, 2, GapKind::BelowLowWatermark),
+            gap(5, 7, GapKind::NoRecordsOnBroker),
+            gap(9, 11, GapKind::Missing),
+        ]
+    );
+    assert_eq!(report.missing_gaps(), 1);
+}
+
+#[tokio::test]
+async fn test_audit_splits_gap_at_low_watermark_and_respects_budget() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_offsets(&pool, 0, &[0, 6, 9]).await;
+    let broker = FakeBroker {
+        low_watermark: 3,
+        offsets: vec![4, 6, 9],
+    };
+
+    let report = audit_offsets(&pool, Some(Arc::new(broker)), 1)
+        .await
+        .expect("Audit failed");
+
+    assert_eq!(
+        report.partitions[0].gaps,
+        vec![
+            gap(1, 2, GapKind::BelowLowWatermark),
+            gap(3, 5, GapKind::Missing),
+            gap(7, 8, GapKind::Unverified),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_offset_gaps_endpoint() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_offsets(&pool, 0, &[0, 1, 2]).await;
+    store_offsets(&pool, 1, &[0, 2]).await;
+    let audit_report = SharedAuditReport::default();
+    let routes = query_api::routes(Arc::new(QueryApiState {
+        pg_pool: pool.clone(),
+        api_token: "test-token".to_string(),
+        admin_token: None,
+        audit_report: audit_report.clone(),
+        cipher: None,
+        archiver: None,
+    }));
+    let get = |uri: &'static str| {
+        let routes = routes.clone();
+        async move {
+            let response = routes
+                .oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .header(AUTHORIZATION, "Bearer test-token")
+                        .body(Body::empty())
+                        .expect("Valid request"),
+                )
+                .await
+                .expect("Request failed");
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("Failed to read body");
+            (status, body)
+        }
+    };
+
+    let (status, _) = get("/api/v1/audit/offset-gaps").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let report = audit_offsets(&pool, None, 10).await.expect("Audit failed");
+    *audit_report.write().expect("Lock poisoned") = Some(report);
+
+    let (status, body) = get("/api/v1/audit/offset-gaps?topic=test-topic&only_gaps=true").await;
+    assert_eq!(status, StatusCode::OK);
+    let report: AuditReport = serde_json::from_slice(&body).expect("Invalid JSON");
+    assert_eq!(report.partitions.len(), 1);
+    assert_eq!(report.partitions[0].partition, 1);
+    assert_eq!(
+        report.partitions[0].gaps,
+        vec![gap(1, 1, GapKind::Unverified)]
+    );
+}
@@ -0,0 +1,164 @@
+use arrow_array::{Array, BinaryArray, Int64Array, RecordBatch, StringArray};
+use chrono::{DateTime, TimeDelta, Utc};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use sqlx::PgPool;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use paw_kafka_topic_backup::export::manifest::{ExportManifest, file_sha256};
+use paw_kafka_topic_backup::export::parquet_export::{ParquetExportRequest, export_parquet};
+
+mod common;
+use common::setup_test_db;
+
+const TOPIC: &str = "test-topic";
+
+/// 2023-11-14T22:13:20Z, offset n is stored `n` hours later so the records span two days
+fn hour(n: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).expect("Valid timestamp") + TimeDelta::hours(n)
+}
+
+fn output_dir(name: &str) -> PathBuf {
+    let nanos = Utc::now().timestamp_nanos_opt().expect("Valid timestamp");
+    std::env::temp_dir().join(format!("{}-{}", name, nanos))
+}
+
+async fn store_test_messages(pool: &PgPool) {
+    common::store_test_messages(pool, TOPIC, 0..4, hour, |offset| {
+        Some(if offset == 3 {
+            b"not json".to_vec()
+        } else {
+            format!(
+                r#"{{"person": {{"ident": "id-{}"}}, "count": {}}}"#,
+                offset, offset
+            )
+            .into_bytes()
+        })
+    })
+    .await;
+}
+
+fn read_parquet(path: PathBuf) -> RecordBatch {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+        .expect("Valid Parquet file")
+        .build()
+        .expect("Valid Parquet file");
+    let mut batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+    assert_eq!(batches.len(), 1, "Small files are read as one batch");
+    batches.remove(0)
+}
+
+fn strings(batch: &RecordBatch, column: &str) -> Vec<Option<String>> {
+    let array = batch
+        .column_by_name(column)
+        .expect("Column should exist")
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("Text column")
+        .clone();
+    (0..array.len())
+        .map(|i| (!array.is_null(i)).then(|| array.value(i).to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_export_parquet_writes_one_file_per_day() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let dir = output_dir("export-parquet");
+
+    let manifest = export_parquet(
+        &pool,
+        &ParquetExportRequest {
+            topic: TOPIC.to_string(),
+            from_timestamp: None,
+            to_timestamp: None,
+            output_dir: dir.clone(),
+            batch_size: 3,
+            json_columns: vec![
+                "/person/ident".parse().unwrap(),
+                "count=/count".parse().unwrap(),
+            ],
+        },
+        None,
+    )
+    .await
+    .expect("Export should succeed");
+    assert_eq!(manifest.rows, 4);
+    let files: Vec<&str> = manifest
+        .segments
+        .iter()
+        .map(|segment| segment.file.as_str())
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            "test-topic-2023-11-14.parquet",
+            "test-topic-2023-11-15.parquet"
+        ]
+    );
+    assert_eq!(
+        ExportManifest::read(&dir).expect("Manifest should be written"),
+        manifest
+    );
+    for segment in &manifest.segments {
+        assert_eq!(
+            segment.sha256,
+            file_sha256(&dir.join(&segment.file)).unwrap()
+        );
+    }
+
+    let first_day = read_parquet(dir.join(files[0]));
+    assert_eq!(first_day.num_rows(), 2);
+    let second_day = read_parquet(dir.join(files[1]));
+    let offsets = second_day
+        .column_by_name("offset")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap()
+        .values()
+        .to_vec();
+    assert_eq!(offsets, vec![2, 3]);
+    let keys = second_day
+        .column_by_name("key")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<BinaryArray>()
+        .unwrap()
+        .value(0)
+        .to_vec();
+    assert_eq!(keys, b"key-2");
+    assert_eq!(
+        strings(&second_day, "person.ident"),
+        vec![Some("id-2".to_string()), None]
+    );
+    assert_eq!(
+        strings(&second_day, "count"),
+        vec![Some("2".to_string()), None]
+    );
+    fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
+
+#[tokio::test]
+async fn test_export_parquet_rejects_duplicate_columns() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let result = export_parquet(
+        &pool,
+        &ParquetExportRequest {
+            topic: TOPIC.to_string(),
+            from_timestamp: None,
+            to_timestamp: None,
+            output_dir: output_dir("export-parquet-invalid"),
+            batch_size: 10,
+            json_columns: vec!["value=/value".parse().unwrap()],
+        },
+        None,
+    )
+    .await;
+    assert!(result.is_err());
+}
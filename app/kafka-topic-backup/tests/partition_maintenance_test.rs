@@ -0,0 +1,231 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use sqlx::PgPool;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::database::partitions::get_partition_months;
+use paw_kafka_topic_backup::encryption::key_hash::record_key_hash;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+use paw_kafka_topic_backup::migration::migrate_data_table::migrate_data_table;
+use paw_kafka_topic_backup::retention::partition_maintenance::maintain_partitions;
+
+mod common;
+use common::{setup_test_db, store_messages, test_message};
+
+fn month(year: i32, month: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, 1).expect("Valid month")
+}
+
+fn now() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2025-10-18T12:00:00Z")
+        .expect("Valid timestamp")
+        .to_utc()
+}
+
+/// Stores offsets 0 to 2 of partition 0, starting at `first`, one day apart
+async fn store_test_messages(pool: &PgPool, topic: &str, first: DateTime<Utc>) {
+    store_messages(
+        pool,
+        (0..3).map(|offset| KafkaMessage {
+            key: Some(b"key".to_vec()),
+            ..test_message(
+                topic,
+                0,
+                offset,
+                first + TimeDelta::days(offset),
+                Some(b"value".to_vec()),
+            )
+        }),
+    )
+    .await;
+}
+
+async fn stored_partitions(pool: &PgPool, topic: &str) -> Vec<String> {
+    sqlx::query_scalar(
+        "SELECT tableoid::regclass::TEXT FROM data_v3 WHERE kafka_topic = $1 ORDER BY kafka_offset",
+    )
+    .bind(topic)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to read stored partitions")
+}
+
+#[tokio::test]
+async fn test_maintain_partitions_creates_months_and_moves_default_records() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(
+        &pool,
+        "test-topic",
+        month(2023, 11).and_hms_opt(0, 0, 0).unwrap().and_utc(),
+    )
+    .await;
+    assert_eq!(
+        stored_partitions(&pool, "test-topic").await,
+        vec!["data_v3_default"; 3]
+    );
+
+    let selector = TopicSelector::new(&["test-topic"], &[]).expect("Valid selector");
+    let summary = maintain_partitions(&pool, &selector, now())
+        .await
+        .expect("Maintenance should succeed");
+    let expected_months = vec![
+        month(2023, 11),
+        month(2025, 10),
+        month(2025, 11),
+        month(2025, 12),
+        month(2026, 1),
+    ];
+    assert_eq!(summary.created, expected_months);
+    assert_eq!(summary.moved_records, 3);
+    assert!(summary.dropped.is_empty());
+    assert_eq!(
+        stored_partitions(&pool, "test-topic").await,
+        vec!["data_v3_p202311"; 3]
+    );
+    assert_eq!(
+        get_partition_months(&pool)
+            .await
+            .expect("Failed to read partitions"),
+        expected_months
+    );
+
+    let summary = maintain_partitions(&pool, &selector, now())
+        .await
+        .expect("Maintenance should succeed");
+    assert!(summary.created.is_empty(), "All partitions already exist");
+}
+
+#[tokio::test]
+async fn test_maintain_partitions_drops_months_expired_for_every_topic() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let november = month(2023, 11).and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let december = month(2023, 12).and_hms_opt(0, 0, 0).unwrap().and_utc();
+    store_test_messages(&pool, "short-retention", november).await;
+    store_test_messages(&pool, "no-retention", december).await;
+    store_test_messages(&pool, "recent", now() - TimeDelta::days(5)).await;
+
+    let selector = TopicSelector::with_policies(
+        [
+            (
+                "short-retention",
+                TopicPolicy {
+                    retention_days: Some(30),
+                    ..Default::default()
+                },
+            ),
+            ("no-retention", TopicPolicy::default()),
+            (
+                "recent",
+                TopicPolicy {
+                    retention_days: Some(30),
+                    ..Default::default()
+                },
+            ),
+        ],
+        &[],
+    )
+    .expect("Valid selector");
+    let summary = maintain_partitions(&pool, &selector, now())
+        .await
+        .expect("Maintenance should succeed");
+    assert_eq!(summary.dropped, vec![month(2023, 11)]);
+    assert!(stored_partitions(&pool, "short-retention").await.is_empty());
+    assert_eq!(stored_partitions(&pool, "no-retention").await.len(), 3);
+    assert_eq!(stored_partitions(&pool, "recent").await.len(), 3);
+
+    let (row_count, max_offset): (i64, i64) = sqlx::query_as(
+        "SELECT row_count, max_offset FROM purge_log WHERE kafka_topic = 'short-retention'",
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("Dropped records should be in the purge log");
+    assert_eq!((row_count, max_offset), (3, 2));
+}
+
+#[tokio::test]
+async fn test_maintain_partitions_keeps_months_of_topics_without_hwm() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let november = month(2023, 11).and_hms_opt(0, 0, 0).unwrap().and_utc();
+    store_test_messages(&pool, "imported-topic", november).await;
+    // Imported and restored topics are not consumed, so they have no HWM
+    sqlx::query("DELETE FROM hwm")
+        .execute(&pool)
+        .await
+        .expect("Failed to delete HWMs");
+
+    let selector = TopicSelector::new(&["test-topic"], &[]).expect("Valid selector");
+    let summary = maintain_partitions(&pool, &selector, now())
+        .await
+        .expect("Maintenance should succeed");
+    assert!(summary.dropped.is_empty(), "The topic has no retention");
+    assert_eq!(stored_partitions(&pool, "imported-topic").await.len(), 3);
+}
+
+#[tokio::test]
+async fn test_migrate_data_table_copies_legacy_records() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let january = month(2024, 1).and_hms_opt(0, 0, 0).unwrap().and_utc();
+    for offset in 0..5i64 {
+        sqlx::query(
+            "INSERT INTO data_v2 (kafka_topic, kafka_partition, kafka_offset, timestamp, record_key, record_value, record_key_hash) VALUES ('legacy-topic', 0, $1, $2, 'key', 'value', $3)",
+        )
+        .bind(offset)
+        .bind(january + TimeDelta::days(offset * 10))
+        .bind(record_key_hash(b"key"))
+        .execute(&pool)
+        .await
+        .expect("Failed to insert legacy record");
+    }
+    let erasure_id: i64 = sqlx::query_scalar(
+        "INSERT INTO erasure_log (erased_at, kafka_topic, record_key_hash, requested_by, reason, row_count, prev_hash, entry_hash) VALUES (NOW(), 'legacy-topic', $1, 'test', 'test', 1, '\\x00', '\\x01') RETURNING id",
+    )
+    .bind(record_key_hash(b"key"))
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to insert erasure");
+    sqlx::query(
+        "INSERT INTO erased_offsets (kafka_topic, kafka_partition, kafka_offset, erasure_id) VALUES ('legacy-topic', 0, 2, $1)",
+    )
+    .bind(erasure_id)
+    .execute(&pool)
+    .await
+    .expect("Failed to insert erased offset");
+
+    let summary = migrate_data_table(&pool, 2)
+        .await
+        .expect("Migration should succeed");
+    assert_eq!((summary.rows_read, summary.rows_copied), (5, 4));
+    let offsets: Vec<i64> = sqlx::query_scalar(
+        "SELECT kafka_offset FROM data_v3 WHERE kafka_topic = 'legacy-topic' ORDER BY kafka_offset",
+    )
+    .fetch_all(&pool)
+    .await
+    .expect("Failed to read copied offsets");
+    assert_eq!(offsets, vec![0, 1, 3, 4]);
+    assert_eq!(
+        stored_partitions(&pool, "legacy-topic").await,
+        vec![
+            "data_v3_p202401",
+            "data_v3_p202401",
+            "data_v3_p202401",
+            "data_v3_p202402"
+        ]
+    );
+
+    let summary = migrate_data_table(&pool, 2)
+        .await
+        .expect("Migration should succeed");
+    assert_eq!(
+        (summary.rows_read, summary.rows_copied),
+        (0, 0),
+        "Nothing is copied twice"
+    );
+}
@@ -0,0 +1,454 @@
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode, header::AUTHORIZATION};
+use chrono::DateTime;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::database::current_state::rebuild_current_state;
+use paw_kafka_topic_backup::database::restore_offsets::{OffsetMapping, insert_offset_mappings};
+use paw_kafka_topic_backup::encoding::ValueEncoding;
+use paw_kafka_topic_backup::encryption::key_hash::record_key_hash;
+use paw_kafka_topic_backup::query_api::current_state::CurrentStateResponse;
+use paw_kafka_topic_backup::query_api::erasure::ErasureResponse;
+use paw_kafka_topic_backup::query_api::records::RecordsResponse;
+use paw_kafka_topic_backup::query_api::{self, QueryApiState};
+
+mod common;
+use common::{setup_test_db, store_messages};
+
+const TEST_TOKEN: &str = "test-token";
+const TEST_ADMIN_TOKEN: &str = "test-admin-token";
+
+fn create_test_kafka_message(partition: i32, offset: i64) -> KafkaMessage {
+    KafkaMessage {
+        topic: "test-topic".to_string(),
+        partition,
+        offset,
+        headers: None,
+        key: Some(format!("key-{}", offset).into_bytes()),
+        payload: Some(format!("value-{}", offset).into_bytes()),
+        timestamp: DateTime::from_timestamp_millis(1_700_000_000_000 + offset * 1000)
+            .expect("Valid timestamp"),
+    }
+}
+
+/// Stores offsets 0 to 4 on partition 0 and 1
+async fn store_test_messages(pool: &PgPool) {
+    store_messages(
+        pool,
+        (0..2).flat_map(|partition| {
+            (0..5).map(move |offset| create_test_kafka_message(partition, offset))
+        }),
+    )
+    .await;
+}
+
+fn test_routes(pool: PgPool) -> Router {
+    query_api::routes(Arc::new(QueryApiState {
+        pg_pool: pool,
+        api_token: TEST_TOKEN.to_string(),
+        admin_token: Some(TEST_ADMIN_TOKEN.to_string()),
+        audit_report: Default::default(),
+        cipher: None,
+        archiver: None,
+    }))
+}
+
+async fn get(routes: &Router, uri: &str, token: Option<&str>) -> (StatusCode, Vec<u8>) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let response = routes
+        .clone()
+        .oneshot(request.body(Body::empty()).expect("Valid request"))
+        .await
+        .expect("Request should complete");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read body");
+    (status, body.to_vec())
+}
+
+async fn get_records(routes: &Router, uri: &str) -> RecordsResponse {
+    let (status, body) = get(routes, uri, Some(TEST_TOKEN)).await;
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    serde_json::from_slice(&body).expect("Valid records response")
+}
+
+#[tokio::test]
+async fn test_records_require_token() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let routes = test_routes(pool);
+
+    let (status, _) = get(&routes, "/api/v1/records?topic=test-topic", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = get(&routes, "/api/v1/records?topic=test-topic", Some("wrong")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_records_by_offset_range_with_pagination() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+
+    let base_uri = "/api/v1/records?topic=test-topic&partition=1&from_offset=1&to_offset=4&limit=2";
+    let first_page = get_records(&routes, base_uri).await;
+    let cursor = first_page
+        .next_cursor
+        .clone()
+        .expect("First page should have a cursor");
+    let second_page = get_records(&routes, &format!("{}&cursor={}", base_uri, cursor)).await;
+
+    let offsets: Vec<(i32, i64)> = first_page
+        .records
+        .iter()
+        .chain(second_page.records.iter())
+        .map(|record| (record.partition, record.offset))
+        .collect();
+    assert_eq!(offsets, vec![(1, 1), (1, 2), (1, 3), (1, 4)]);
+
+    let record = &first_page.records[0];
+    let value = record.value.as_ref().expect("Record should have a value");
+    assert_eq!(value.encoding, ValueEncoding::Utf8);
+    assert_eq!(value.data, "value-1");
+}
+
+#[tokio::test]
+async fn test_records_by_timestamp_range_across_partitions() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+
+    // Offsets 3 and 4 have timestamps from 1_700_000_003_000 ms
+    let response = get_records(
+        &routes,
+        "/api/v1/records?topic=test-topic&from_timestamp=2023-11-14T22:13:23Z&to_timestamp=2023-11-14T22:13:25Z",
+    )
+    .await;
+
+    let offsets: Vec<(i32, i64)> = response
+        .records
+        .iter()
+        .map(|record| (record.partition, record.offset))
+        .collect();
+    assert_eq!(offsets, vec![(0, 3), (1, 3), (0, 4), (1, 4)]);
+    assert!(response.next_cursor.is_none());
+}
+
+#[tokio::test]
+async fn test_records_as_base64() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+
+    let response = get_records(
+        &routes,
+        "/api/v1/records?topic=test-topic&partition=0&from_offset=0&to_offset=0&encoding=base64",
+    )
+    .await;
+
+    let key = response.records[0]
+        .key
+        .as_ref()
+        .expect("Record should have a key");
+    assert_eq!(key.encoding, ValueEncoding::Base64);
+    assert_eq!(key.decode().expect("Valid base64"), b"key-0");
+}
+
+async fn post_records(routes: &Router, uri: &str, body: serde_json::Value) -> RecordsResponse {
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(AUTHORIZATION, format!("Bearer {}", TEST_TOKEN))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("Valid request");
+    let response = routes
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("Request should complete");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read body");
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    serde_json::from_slice(&body).expect("Valid records response")
+}
+
+#[tokio::test]
+async fn test_records_by_key_across_partitions() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+
+    // Both partitions have a record with key-2
+    let response = post_records(
+        &routes,
+        "/api/v1/records/by-key",
+        serde_json::json!({
+            "topic": "test-topic",
+            "key": {"encoding": "utf8", "data": "key-2"}
+        }),
+    )
+    .await;
+
+    let offsets: Vec<(i32, i64)> = response
+        .records
+        .iter()
+        .map(|record| (record.partition, record.offset))
+        .collect();
+    assert_eq!(offsets, vec![(0, 2), (1, 2)]);
+}
+
+#[tokio::test]
+async fn test_records_by_key_with_pagination() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+
+    let first_page = post_records(
+        &routes,
+        "/api/v1/records/by-key",
+        serde_json::json!({
+            "topic": "test-topic",
+            "key": {"encoding": "base64", "data": "a2V5LTQ="},
+            "limit": 1
+        }),
+    )
+    .await;
+    let second_page = post_records(
+        &routes,
+        "/api/v1/records/by-key",
+        serde_json::json!({
+            "topic": "test-topic",
+            "key": {"encoding": "base64", "data": "a2V5LTQ="},
+            "limit": 1,
+            "cursor": first_page.next_cursor.clone().expect("First page should have a cursor")
+        }),
+    )
+    .await;
+
+    assert_eq!(first_page.records[0].partition, 0);
+    assert_eq!(first_page.records[0].offset, 4);
+    assert_eq!(second_page.records[0].partition, 1);
+    assert_eq!(second_page.records[0].offset, 4);
+}
+
+#[tokio::test]
+async fn test_records_by_key_pages_past_colliding_hashes() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    // The records of key-1 come first and share the hash of key-2
+    sqlx::query("UPDATE data_v3 SET record_key_hash = $1 WHERE record_key = 'key-1'")
+        .bind(record_key_hash(b"key-2"))
+        .execute(&pool)
+        .await
+        .expect("Failed to update key hashes");
+    let routes = test_routes(pool);
+
+    let response = post_records(
+        &routes,
+        "/api/v1/records/by-key",
+        serde_json::json!({
+            "topic": "test-topic",
+            "key": {"encoding": "utf8", "data": "key-2"},
+            "limit": 1
+        }),
+    )
+    .await;
+
+    assert_eq!(response.records.len(), 1);
+    assert_eq!(response.records[0].partition, 0);
+    assert_eq!(response.records[0].offset, 2);
+    assert!(response.next_cursor.is_some());
+}
+
+#[tokio::test]
+async fn test_records_by_unknown_key() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+
+    let response = post_records(
+        &routes,
+        "/api/v1/records/by-key",
+        serde_json::json!({
+            "topic": "test-topic",
+            "key": {"encoding": "utf8", "data": "unknown"}
+        }),
+    )
+    .await;
+
+    assert!(response.records.is_empty());
+    assert!(response.next_cursor.is_none());
+}
+
+async fn post_erasure(
+    routes: &Router,
+    token: &str,
+    body: serde_json::Value,
+) -> (StatusCode, Vec<u8>) {
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/v1/admin/erasures")
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("Valid request");
+    let response = routes
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("Request should complete");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Failed to read body");
+    (status, body.to_vec())
+}
+
+#[tokio::test]
+async fn test_current_state_with_pagination() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    rebuild_current_state(&pool, "test-topic")
+        .await
+        .expect("Rebuild should succeed");
+    let routes = test_routes(pool);
+
+    let base_uri = "/api/v1/state?topic=test-topic&limit=3";
+    let mut keys = Vec::new();
+    let mut uri = base_uri.to_string();
+    loop {
+        let (status, body) = get(&routes, &uri, Some(TEST_TOKEN)).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let page: CurrentStateResponse =
+            serde_json::from_slice(&body).expect("Valid state response");
+        assert_eq!(
+            page.high_watermarks.into_iter().collect::<Vec<_>>(),
+            vec![(0, 4), (1, 4)]
+        );
+        keys.extend(
+            page.records
+                .into_iter()
+                .map(|record| record.key.expect("Record should have a key").data),
+        );
+        match page.next_cursor {
+            Some(cursor) => uri = format!("{}&cursor={}", base_uri, cursor),
+            None => break,
+        }
+    }
+    keys.sort();
+    assert_eq!(keys, vec!["key-0", "key-1", "key-2", "key-3", "key-4"]);
+
+    let (status, _) = get(
+        &routes,
+        &format!("{}&cursor=not*valid", base_uri),
+        Some(TEST_TOKEN),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_restore_offset_mapping() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let mapping = OffsetMapping {
+        source_partition: 1,
+        source_offset: 40,
+        target_partition: 1,
+        target_offset: 2,
+    };
+    insert_offset_mappings(&pool, "test-topic", "restored-topic", &[mapping])
+        .await
+        .expect("Failed to insert mapping");
+    let routes = test_routes(pool);
+
+    let base_uri = "/api/v1/restore/offset-mapping?source_topic=test-topic&target_topic=restored-topic&partition=1";
+    let (status, body) = get(
+        &routes,
+        &format!("{}&offset=35", base_uri),
+        Some(TEST_TOKEN),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    let translated: OffsetMapping = serde_json::from_slice(&body).expect("Valid mapping");
+    assert_eq!(translated, mapping);
+
+    let (status, _) = get(
+        &routes,
+        &format!("{}&offset=41", base_uri),
+        Some(TEST_TOKEN),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_erasure_requires_admin_token() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool).await;
+    let routes = test_routes(pool);
+    let body = serde_json::json!({
+        "topic": "test-topic",
+        "key": {"encoding": "utf8", "data": "key-3"},
+        "requested_by": "saksbehandler",
+        "reason": "sak-123"
+    });
+
+    let (status, _) = post_erasure(&routes, TEST_TOKEN, body.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, response) = post_erasure(&routes, TEST_ADMIN_TOKEN, body).await;
+    assert_eq!(
+        status,
+        StatusCode::OK,
+        "{}",
+        String::from_utf8_lossy(&response)
+    );
+    let response: ErasureResponse =
+        serde_json::from_slice(&response).expect("Valid erasure response");
+    assert_eq!(response.rows, 2);
+    assert!(response.erasure_id.is_some());
+
+    let remaining = post_records(
+        &routes,
+        "/api/v1/records/by-key",
+        serde_json::json!({
+            "topic": "test-topic",
+            "key": {"encoding": "utf8", "data": "key-3"}
+        }),
+    )
+    .await;
+    assert!(remaining.records.is_empty());
+}
@@ -0,0 +1,519 @@
+use chrono::{DateTime, Utc};
+use rdkafka::ClientConfig;
+use rdkafka::Message;
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::Headers;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use testcontainers::{ContainerAsync, runners::AsyncRunner};
+use testcontainers_modules::kafka::{KAFKA_PORT, Kafka};
+use tokio::sync::Mutex;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::database::read_data::{DataRow, get_topic_partitions};
+use paw_kafka_topic_backup::database::restore_offsets::{
+    OffsetMapping, delete_offset_mappings, insert_offset_mappings, translate_position,
+};
+use paw_kafka_topic_backup::kafka::config::ApplicationKafkaConfig;
+use paw_kafka_topic_backup::kafka::headers::KafkaHeader;
+use paw_kafka_topic_backup::kafka::kafka_connection::create_kafka_producer;
+use paw_kafka_topic_backup::restore::restore_topic::{
+    OffsetRange, RestoreCutoff, RestoreRequest, SkipReason, SkippedRange, restore_topic,
+};
+
+mod common;
+use common::{setup_test_db, store_messages, test_message};
+
+/// KAFKA_BROKERS is process wide, so tests using a broker must not run concurrently
+static KAFKA_ENV_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Setup a local Kafka broker and point KAFKA_BROKERS to it
+async fn setup_test_kafka() -> ContainerAsync<Kafka> {
+    let kafka_container = Kafka::default().start().await;
+    let brokers = format!(
+        "127.0.0.1:{}",
+        kafka_container.get_host_port_ipv4(KAFKA_PORT).await
+    );
+    unsafe {
+        std::env::set_var("KAFKA_BROKERS", &brokers);
+    }
+    kafka_container
+}
+
+/// Restored records keep their partition, so the target topic needs as many partitions
+async fn create_topic(topic: &str, partitions: i32) {
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set(
+            "bootstrap.servers",
+            std::env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS"),
+        )
+        .create()
+        .expect("Failed to create admin client");
+    let results = admin
+        .create_topics(
+            &[NewTopic::new(topic, partitions, TopicReplication::Fixed(1))],
+            &AdminOptions::new(),
+        )
+        .await
+        .expect("Failed to create topic");
+    for result in results {
+        result.expect("Topic should be created");
+    }
+}
+
+fn plaintext_kafka_config() -> ApplicationKafkaConfig {
+    ApplicationKafkaConfig {
+        security_protocol: "plaintext".to_string(),
+        ..Default::default()
+    }
+}
+
+fn create_test_kafka_message(topic: &str, partition: i32, offset: i64) -> KafkaMessage {
+    let timestamp =
+        DateTime::from_timestamp_millis(1234567890000 + offset).expect("Valid timestamp");
+
+    KafkaMessage {
+        topic: topic.to_string(),
+        partition,
+        offset,
+        headers: Some(vec![KafkaHeader {
+            key: "source".to_string(),
+            value: Some(b"restore-test".to_vec()),
+        }]),
+        key: Some(format!("test-key-{}-{}", partition, offset).into_bytes()),
+        payload: Some(
+            format!(r#"{{"message": "test payload", "offset": {}}}"#, offset).into_bytes(),
+        ),
+        timestamp,
+    }
+}
+
+async fn store_test_messages(pool: &PgPool, topic: &str, partition: i32, offsets: &[i64]) {
+    store_messages(
+        pool,
+        offsets
+            .iter()
+            .map(|offset| create_test_kafka_message(topic, partition, *offset)),
+    )
+    .await;
+}
+
+async fn consume_all(topic: &str, expected: usize) -> Vec<(Vec<u8>, Vec<u8>, i64, String)> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set(
+            "bootstrap.servers",
+            std::env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS"),
+        )
+        .set("group.id", "restore-test-verifier")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("Failed to create verifying consumer");
+    consumer
+        .subscribe(&[topic])
+        .expect("Failed to subscribe to restored topic");
+    let mut received = Vec::new();
+    while received.len() < expected {
+        let msg = tokio::time::timeout(Duration::from_secs(60), consumer.recv())
+            .await
+            .expect("Timed out waiting for restored message")
+            .expect("Failed to consume restored message");
+        let source_header = msg
+            .headers()
+            .and_then(|headers| headers.iter().find(|header| header.key == "source"))
+            .and_then(|header| header.value)
+            .map(|value| String::from_utf8_lossy(value).to_string())
+            .unwrap_or_default();
+        received.push((
+            msg.key().unwrap_or_default().to_vec(),
+            msg.payload().unwrap_or_default().to_vec(),
+            msg.timestamp().to_millis().unwrap_or_default(),
+            source_header,
+        ));
+    }
+    received
+}
+
+#[tokio::test]
+async fn test_restore_topic_produces_stored_records() {
+    let (pool, _pg_container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let _kafka_lock = KAFKA_ENV_LOCK.lock().await;
+    let _kafka_container = setup_test_kafka().await;
+
+    store_test_messages(&pool, "source-topic", 0, &[10, 11, 12]).await;
+
+    let producer =
+        create_kafka_producer(plaintext_kafka_config()).expect("Failed to create producer");
+    let summary = restore_topic(
+        &pool,
+        &producer,
+        &RestoreRequest {
+            source_topic: "source-topic".to_string(),
+            target_topic: "restored-topic".to_string(),
+            partitions: None,
+            batch_size: 2,
+            cutoff: RestoreCutoff::default(),
+        },
+        None,
+    )
+    .await
+    .expect("Restore should succeed");
+
+    assert_eq!(summary.total(), 3, "Should have restored 3 records");
+
+    let received = consume_all("restored-topic", 3).await;
+    for (index, offset) in [10i64, 11, 12].iter().enumerate() {
+        let expected = create_test_kafka_message("source-topic", 0, *offset);
+        let (key, payload, timestamp, source_header) = &received[index];
+        assert_eq!(Some(key), expected.key.as_ref(), "Key should be restored");
+        assert_eq!(
+            Some(payload),
+            expected.payload.as_ref(),
+            "Payload should be restored"
+        );
+        assert_eq!(
+            *timestamp,
+            expected.timestamp.timestamp_millis(),
+            "Timestamp should be restored"
+        );
+        assert_eq!(source_header, "restore-test", "Headers should be restored");
+    }
+}
+
+#[tokio::test]
+async fn test_restore_topic_only_selected_partitions() {
+    let (pool, _pg_container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let _kafka_lock = KAFKA_ENV_LOCK.lock().await;
+    let _kafka_container = setup_test_kafka().await;
+
+    store_test_messages(&pool, "source-topic", 0, &[1, 2]).await;
+    store_test_messages(&pool, "source-topic", 1, &[5, 6, 7]).await;
+    create_topic("restored-partition-topic", 2).await;
+
+    let producer =
+        create_kafka_producer(plaintext_kafka_config()).expect("Failed to create producer");
+    let summary = restore_topic(
+        &pool,
+        &producer,
+        &RestoreRequest {
+            source_topic: "source-topic".to_string(),
+            target_topic: "restored-partition-topic".to_string(),
+            partitions: Some(vec![1]),
+            batch_size: 500,
+            cutoff: RestoreCutoff::default(),
+        },
+        None,
+    )
+    .await
+    .expect("Restore should succeed");
+
+    assert_eq!(summary.total(), 3, "Should only restore partition 1");
+    assert_eq!(summary.records_per_partition.get(&0), None);
+    assert_eq!(summary.records_per_partition.get(&1), Some(&3));
+}
+
+fn message_timestamp(offset: i64) -> DateTime<Utc> {
+    create_test_kafka_message("source-topic", 0, offset).timestamp
+}
+
+#[test]
+fn test_records_without_value_are_never_replayed() {
+    let row = |offset: i64, value_omitted: bool| DataRow {
+        kafka_topic: "source-topic".to_string(),
+        kafka_partition: 0,
+        kafka_offset: offset,
+        timestamp: message_timestamp(offset),
+        headers: None,
+        record_key: Some(b"key".to_vec()),
+        record_value: None,
+        key_id: None,
+        value_codec: None,
+        value_omitted,
+    };
+    let cutoff = RestoreCutoff::default();
+
+    assert_eq!(
+        cutoff.skip_reason(&row(0, true)),
+        Some(SkipReason::ValueOmitted),
+        "A keys_only record would be replayed as a tombstone"
+    );
+    assert_eq!(
+        cutoff.skip_reason(&row(1, false)),
+        None,
+        "Tombstones are replayed"
+    );
+}
+
+#[tokio::test]
+async fn test_restore_topic_until_cutoff_without_excluded_window() {
+    let (pool, _pg_container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let _kafka_lock = KAFKA_ENV_LOCK.lock().await;
+    let _kafka_container = setup_test_kafka().await;
+
+    store_test_messages(&pool, "source-topic", 0, &[0, 1, 2, 3, 4, 5]).await;
+    store_test_messages(&pool, "source-topic", 1, &[0, 1, 2]).await;
+    create_topic("restored-cutoff-topic", 2).await;
+
+    let producer =
+        create_kafka_producer(plaintext_kafka_config()).expect("Failed to create producer");
+    let summary = restore_topic(
+        &pool,
+        &producer,
+        &RestoreRequest {
+            source_topic: "source-topic".to_string(),
+            target_topic: "restored-cutoff-topic".to_string(),
+            partitions: None,
+            batch_size: 4,
+            cutoff: RestoreCutoff {
+                until_timestamp: Some(message_timestamp(4)),
+                until_offsets: BTreeMap::from([(1, 0)]),
+                exclude_window: Some((message_timestamp(1), message_timestamp(3))),
+            },
+        },
+        None,
+    )
+    .await
+    .expect("Restore should succeed");
+
+    assert_eq!(summary.total(), 4);
+    assert_eq!(summary.skipped_total(), 5);
+    assert_eq!(
+        summary.replayed,
+        BTreeMap::from([
+            (
+                0,
+                vec![
+                    OffsetRange { first: 0, last: 0 },
+                    OffsetRange { first: 3, last: 4 }
+                ]
+            ),
+            (1, vec![OffsetRange { first: 0, last: 0 }]),
+        ])
+    );
+    assert_eq!(
+        summary.skipped,
+        BTreeMap::from([
+            (
+                0,
+                vec![
+                    SkippedRange {
+                        first: 1,
+                        last: 2,
+                        reason: SkipReason::ExcludedWindow
+                    },
+                    SkippedRange {
+                        first: 5,
+                        last: 5,
+                        reason: SkipReason::AfterCutoff
+                    }
+                ]
+            ),
+            (
+                1,
+                vec![SkippedRange {
+                    first: 1,
+                    last: 2,
+                    reason: SkipReason::AfterCutoff
+                }]
+            ),
+        ])
+    );
+
+    let mut keys: Vec<String> = consume_all("restored-cutoff-topic", 4)
+        .await
+        .into_iter()
+        .map(|(key, _, _, _)| String::from_utf8(key).expect("Key should be text"))
+        .collect();
+    keys.sort();
+    assert_eq!(
+        keys,
+        vec![
+            "test-key-0-0",
+            "test-key-0-3",
+            "test-key-0-4",
+            "test-key-1-0"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_restore_topic_keeps_partitions_and_maps_offsets() {
+    let (pool, _pg_container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let _kafka_lock = KAFKA_ENV_LOCK.lock().await;
+    let _kafka_container = setup_test_kafka().await;
+
+    store_test_messages(&pool, "source-topic", 0, &[3, 4]).await;
+    store_test_messages(&pool, "source-topic", 1, &[10, 12]).await;
+    create_topic("restored-single-partition-topic", 1).await;
+    create_topic("restored-mapped-topic", 2).await;
+
+    let producer =
+        create_kafka_producer(plaintext_kafka_config()).expect("Failed to create producer");
+    let request = |target_topic: &str| RestoreRequest {
+        source_topic: "source-topic".to_string(),
+        target_topic: target_topic.to_string(),
+        partitions: None,
+        batch_size: 500,
+        cutoff: RestoreCutoff::default(),
+    };
+    assert!(
+        restore_topic(
+            &pool,
+            &producer,
+            &request("restored-single-partition-topic"),
+            None
+        )
+        .await
+        .is_err(),
+        "Partition 1 does not exist in the target topic"
+    );
+
+    let summary = restore_topic(&pool, &producer, &request("restored-mapped-topic"), None)
+        .await
+        .expect("Restore should succeed");
+    assert_eq!(summary.total(), 4);
+
+    let translate = |partition: i32, offset: i64| {
+        translate_position(
+            &pool,
+            "source-topic",
+            "restored-mapped-topic",
+            partition,
+            offset,
+        )
+    };
+    let mapping = |source_partition, source_offset, target_offset| OffsetMapping {
+        source_partition,
+        source_offset,
+        target_partition: source_partition,
+        target_offset,
+    };
+    assert_eq!(translate(0, 4).await.unwrap(), Some(mapping(0, 4, 1)));
+    assert_eq!(translate(1, 0).await.unwrap(), Some(mapping(1, 10, 0)));
+    assert_eq!(translate(1, 11).await.unwrap(), Some(mapping(1, 12, 1)));
+    assert_eq!(translate(1, 13).await.unwrap(), None);
+
+    // Restoring partition 1 again up to offset 10 leaves no mapping for offset 12
+    let summary = restore_topic(
+        &pool,
+        &producer,
+        &RestoreRequest {
+            partitions: Some(vec![1]),
+            cutoff: RestoreCutoff {
+                until_offsets: BTreeMap::from([(1, 10)]),
+                ..Default::default()
+            },
+            ..request("restored-mapped-topic")
+        },
+        None,
+    )
+    .await
+    .expect("Restore should succeed");
+    assert_eq!(summary.total(), 1);
+    assert_eq!(translate(1, 0).await.unwrap(), Some(mapping(1, 10, 2)));
+    assert_eq!(translate(1, 11).await.unwrap(), None);
+    assert_eq!(
+        translate(0, 4).await.unwrap(),
+        Some(mapping(0, 4, 1)),
+        "Partitions that were not restored again keep their mappings"
+    );
+}
+
+#[tokio::test]
+async fn test_translate_position_uses_latest_restore() {
+    let (pool, _pg_container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let mapping = |source_offset, target_offset| OffsetMapping {
+        source_partition: 0,
+        source_offset,
+        target_partition: 0,
+        target_offset,
+    };
+    insert_offset_mappings(
+        &pool,
+        "source-topic",
+        "target-topic",
+        &[mapping(5, 0), mapping(7, 1)],
+    )
+    .await
+    .expect("Failed to insert mappings");
+    insert_offset_mappings(
+        &pool,
+        "source-topic",
+        "other-target-topic",
+        &[mapping(6, 0)],
+    )
+    .await
+    .expect("Failed to insert mappings");
+    let translate = |offset| translate_position(&pool, "source-topic", "target-topic", 0, offset);
+    assert_eq!(translate(0).await.unwrap(), Some(mapping(5, 0)));
+    assert_eq!(translate(6).await.unwrap(), Some(mapping(7, 1)));
+    assert_eq!(translate(8).await.unwrap(), None);
+
+    // A narrower restore to the same target removes the mappings of the earlier restore
+    // first, so positions after the restored records are not mapped to stale offsets
+    delete_offset_mappings(&pool, "source-topic", "target-topic", Some(&[0]))
+        .await
+        .expect("Failed to delete mappings");
+    insert_offset_mappings(&pool, "source-topic", "target-topic", &[mapping(5, 4)])
+        .await
+        .expect("Failed to insert mappings");
+    assert_eq!(translate(0).await.unwrap(), Some(mapping(5, 4)));
+    assert_eq!(translate(6).await.unwrap(), None);
+    assert_eq!(
+        translate_position(&pool, "source-topic", "other-target-topic", 0, 0)
+            .await
+            .unwrap(),
+        Some(mapping(6, 0)),
+        "Mappings to other targets are kept"
+    );
+}
+
+#[tokio::test]
+async fn test_topic_partitions_include_partitions_without_hwm() {
+    let (pool, _pg_container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    let topic = "partitions-topic";
+    store_messages(
+        &pool,
+        [0, 3].map(|partition| test_message(topic, partition, 0, Utc::now(), None)),
+    )
+    .await;
+    // Stored records of partition 3 without a HWM, and records of partition 5 still only
+    // in the legacy table
+    sqlx::query("DELETE FROM hwm WHERE topic = $1 AND partition = 3")
+        .bind(topic)
+        .execute(&pool)
+        .await
+        .expect("Failed to delete HWM");
+    sqlx::query(
+        "INSERT INTO data_v2 (kafka_topic, kafka_partition, kafka_offset, timestamp) VALUES ($1, 5, 0, NOW())",
+    )
+    .bind(topic)
+    .execute(&pool)
+    .await
+    .expect("Failed to insert legacy record");
+
+    assert_eq!(
+        get_topic_partitions(&pool, topic).await.unwrap(),
+        vec![0, 3, 5]
+    );
+    assert!(
+        get_topic_partitions(&pool, "other-topic")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
@@ -0,0 +1,225 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use object_store::ObjectStore;
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use paw_kafka_topic_backup::KafkaMessage;
+use paw_kafka_topic_backup::archive::archiver::Archiver;
+use paw_kafka_topic_backup::audit::offset_audit::{GapKind, OffsetGap, audit_offsets};
+use paw_kafka_topic_backup::database::archive_catalog::get_archive_segments;
+use paw_kafka_topic_backup::kafka::topic_policy::TopicPolicy;
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+use paw_kafka_topic_backup::retention::purge_job::{apply_retention, purge_topic};
+
+mod common;
+use common::{setup_test_db, store_messages, test_message};
+
+fn day(n: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).expect("Valid timestamp") + TimeDelta::days(n)
+}
+
+/// Stores offsets 0 to 9 on both partitions, offset n has the timestamp of `day(n)`
+async fn store_test_messages(pool: &PgPool, topic: &str) {
+    store_messages(
+        pool,
+        (0..2).flat_map(|partition| {
+            (0..10).map(move |offset| KafkaMessage {
+                key: Some(b"key".to_vec()),
+                ..test_message(
+                    topic,
+                    partition,
+                    offset,
+                    day(offset),
+                    Some(b"value".to_vec()),
+                )
+            })
+        }),
+    )
+    .await;
+}
+
+async fn stored_offsets(pool: &PgPool, topic: &str, partition: i32) -> Vec<i64> {
+    sqlx::query_scalar(
+        "SELECT kafka_offset FROM data_v3 WHERE kafka_topic = $1 AND kafka_partition = $2 ORDER BY kafka_offset",
+    )
+    .bind(topic)
+    .bind(partition)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to read stored offsets")
+}
+
+#[tokio::test]
+async fn test_purge_topic_deletes_old_records_in_batches() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool, "test-topic").await;
+
+    let summary = purge_topic(&pool, "test-topic", day(4), 3)
+        .await
+        .expect("Purge failed");
+
+    assert_eq!(summary.total(), 8);
+    assert_eq!(summary.rows_per_partition.get(&0), Some(&4));
+    assert_eq!(summary.rows_per_partition.get(&1), Some(&4));
+    assert_eq!(
+        stored_offsets(&pool, "test-topic", 0).await,
+        (4..10).collect::<Vec<_>>()
+    );
+
+    let logged: Vec<(i16, i64, i64, i64)> = sqlx::query_as(
+        "SELECT kafka_partition, SUM(row_count)::BIGINT, MIN(min_offset), MAX(max_offset) FROM purge_log WHERE kafka_topic = $1 GROUP BY kafka_partition ORDER BY kafka_partition",
+    )
+    .bind("test-topic")
+    .fetch_all(&pool)
+    .await
+    .expect("Failed to read purge log");
+    assert_eq!(logged, vec![(0, 4, 0, 3), (1, 4, 0, 3)]);
+}
+
+#[tokio::test]
+async fn test_apply_retention_uses_topic_policy() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool, "short-retention").await;
+    store_test_messages(&pool, "kept-forever").await;
+    let selector = TopicSelector::with_policies(
+        [
+            (
+                "short-retention",
+                TopicPolicy {
+                    retention_days: Some(3),
+                    ..Default::default()
+                },
+            ),
+            ("kept-forever", TopicPolicy::default()),
+        ],
+        &[],
+    )
+    .expect("Invalid topic selector");
+
+    let summaries = apply_retention(&pool, &selector, day(10))
+        .await
+        .expect("Retention failed");
+
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries["short-retention"].total(), 14);
+    assert_eq!(
+        stored_offsets(&pool, "short-retention", 1).await,
+        vec![7, 8, 9]
+    );
+    assert_eq!(stored_offsets(&pool, "kept-forever", 1).await.len(), 10);
+}
+
+#[tokio::test]
+async fn test_audit_reports_purged_offsets_as_expected() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool, "test-topic").await;
+    purge_topic(&pool, "test-topic", day(3), 100)
+        .await
+        .expect("Purge failed");
+
+    let report = audit_offsets(&pool, None, 10).await.expect("Audit failed");
+
+    assert_eq!(
+        report.partitions[0].gaps,
+        vec![OffsetGap {
+            start: 0,
+            end: 2,
+            kind: GapKind::Purged
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_audit_only_expects_gaps_in_purged_ranges() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool, "test-topic").await;
+    // Offset 4 of partition 0 was never stored, and offset 8 has an older timestamp than
+    // the offsets before it
+    sqlx::query(
+        "DELETE FROM data_v3 WHERE kafka_topic = $1 AND kafka_partition = 0 AND kafka_offset = 4",
+    )
+    .bind("test-topic")
+    .execute(&pool)
+    .await
+    .expect("Failed to delete record");
+    sqlx::query("UPDATE data_v3 SET timestamp = $2 WHERE kafka_topic = $1 AND kafka_offset = 8")
+        .bind("test-topic")
+        .bind(day(-1))
+        .execute(&pool)
+        .await
+        .expect("Failed to update timestamp");
+    purge_topic(&pool, "test-topic", day(1), 1)
+        .await
+        .expect("Purge failed");
+
+    let report = audit_offsets(&pool, None, 10).await.expect("Audit failed");
+
+    let gap = |start, end, kind| OffsetGap { start, end, kind };
+    assert_eq!(
+        report.partitions[0].gaps,
+        vec![
+            gap(0, 0, GapKind::Purged),
+            gap(4, 4, GapKind::Unverified),
+            gap(8, 8, GapKind::Purged),
+        ],
+        "A purged offset above a missing gap must not make it expected"
+    );
+}
+
+#[tokio::test]
+async fn test_archived_segments_follow_retention() {
+    let (pool, _container) = setup_test_db()
+        .await
+        .expect("Failed to setup test database");
+    store_test_messages(&pool, "archived-topic").await;
+    let store = Arc::new(InMemory::new());
+    let archiver = Archiver::new(store.clone(), "", false);
+    let archived = archiver
+        .archive_topic(&pool, "archived-topic", day(10).date_naive(), None)
+        .await
+        .expect("Archiving should succeed");
+    assert_eq!(archived.len(), 10);
+
+    let selector = TopicSelector::with_policies(
+        [(
+            "archived-topic",
+            TopicPolicy {
+                retention_days: Some(5),
+                ..Default::default()
+            },
+        )],
+        &[],
+    )
+    .expect("Valid selector");
+    // The segments of the records at day(0) to day(3) end before the cutoff, the one of
+    // day(4) ends at midnight after it
+    let cutoff = day(4);
+    let expired = archiver
+        .expire_segments(&pool, &selector, cutoff + TimeDelta::days(5))
+        .await
+        .expect("Expiry should succeed");
+
+    let expected: Vec<String> = archived[..4]
+        .iter()
+        .map(|window| window.object_key.clone())
+        .collect();
+    assert_eq!(expired, expected);
+    for key in &expected {
+        assert!(store.head(&Path::from(key.as_str())).await.is_err());
+    }
+    let remaining = get_archive_segments(&pool, "archived-topic")
+        .await
+        .expect("Failed to read catalog");
+    assert_eq!(remaining.len(), 6);
+    assert!(remaining.iter().all(|segment| segment.window_end > cutoff));
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeSet;
+
+use paw_kafka_topic_backup::kafka::topic_policy::{StartPosition, TopicPolicy};
+use paw_kafka_topic_backup::kafka::topic_subscription::TopicSelector;
+
+const EXISTING_TOPICS: [&str; 6] = [
+    "__consumer_offsets",
+    "paw.arbeidssoker-hendelseslogg-v1",
+    "paw.arbeidssoker-bekreftelse-v1",
+    "paw.arbeidssoker-test-dlq",
+    "paw.opplysninger-v1",
+    "team-annet.topic-v1",
+];
+
+fn topics(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn test_names_are_selected_without_metadata() {
+    let selector = TopicSelector::new(&["topic-a", "topic-b"], &[]).expect("Invalid selector");
+
+    assert!(!selector.has_patterns());
+    assert_eq!(selector.select([]), topics(&["topic-a", "topic-b"]));
+}
+
+#[test]
+fn test_patterns_match_existing_topics() {
+    let selector = TopicSelector::new(&["^paw\\.arbeidssoker-.*", "team-annet.topic-v1"], &[])
+        .expect("Invalid selector");
+
+    assert!(selector.has_patterns());
+    assert_eq!(
+        selector.select(EXISTING_TOPICS),
+        topics(&[
+            "paw.arbeidssoker-bekreftelse-v1",
+            "paw.arbeidssoker-hendelseslogg-v1",
+            "paw.arbeidssoker-test-dlq",
+            "team-annet.topic-v1",
+        ])
+    );
+}
+
+#[test]
+fn test_excludes_win_over_names_and_patterns() {
+    let selector = TopicSelector::new(
+        &["^paw\\..*", "team-annet.topic-v1"],
+        &["^.*-dlq$", "paw.opplysninger-v1", "team-annet.topic-v1"],
+    )
+    .expect("Invalid selector");
+
+    assert_eq!(
+        selector.select(EXISTING_TOPICS),
+        topics(&[
+            "paw.arbeidssoker-bekreftelse-v1",
+            "paw.arbeidssoker-hendelseslogg-v1",
+        ])
+    );
+}
+
+#[test]
+fn test_internal_topics_are_never_matched_by_patterns() {
+    let selector = TopicSelector::new(&["^.*"], &[]).expect("Invalid selector");
+
+    assert!(
+        !selector
+            .select(EXISTING_TOPICS)
+            .contains("__consumer_offsets")
+    );
+}
+
+#[test]
+fn test_new_topics_are_picked_up() {
+    let selector = TopicSelector::new(&["^paw\\.opplysninger-.*"], &[]).expect("Invalid selector");
+
+    let before = selector.select(EXISTING_TOPICS);
+    let after = selector.select(EXISTING_TOPICS.into_iter().chain(["paw.opplysninger-v2"]));
+
+    assert_eq!(before, topics(&["paw.opplysninger-v1"]));
+    assert_eq!(
+        after,
+        topics(&["paw.opplysninger-v1", "paw.opplysninger-v2"])
+    );
+}
+
+#[test]
+fn test_invalid_pattern_is_rejected() {
+    assert!(TopicSelector::new(&["^paw.(unclosed"], &[]).is_err());
+}
+
+#[test]
+fn test_policy_for_prefers_names_over_patterns() {
+    let latest = TopicPolicy {
+        start_position: StartPosition::Latest,
+        ..Default::default()
+    };
+    let keys_only = TopicPolicy {
+        keys_only: true,
+        ..Default::default()
+    };
+    let selector = TopicSelector::with_policies(
+        [
+            ("^paw\\..*", latest.clone()),
+            ("paw.opplysninger-v1", keys_only.clone()),
+        ],
+        &[],
+    )
+    .expect("Invalid selector");
+
+    assert_eq!(selector.policy_for("paw.opplysninger-v1"), &keys_only);
+    assert_eq!(
+        selector.policy_for("paw.arbeidssoker-hendelseslogg-v1"),
+        &latest
+    );
+    assert_eq!(
+        selector.policy_for("team-annet.topic-v1"),
+        &TopicPolicy::default()
+    );
+}